@@ -0,0 +1,38 @@
+# Compare per-document match_tokens calls with the batch APIs on many tiny
+# documents, where per-call overhead dominates.
+#
+# Usage: ruby -Ilib examples/batch_matching_benchmark.rb <artifacts_dir> [documents]
+
+require 'phrasekit'
+require 'json'
+
+artifacts_dir = ARGV[0] || 'examples/demo_output/artifacts'
+documents = (ARGV[1] || 100_000).to_i
+
+paths = {
+  automaton_path: File.join(artifacts_dir, 'phrases.daac'),
+  payloads_path: File.join(artifacts_dir, 'payloads.bin'),
+  manifest_path: File.join(artifacts_dir, 'manifest.json'),
+  vocab_path: File.join(artifacts_dir, 'vocab.json')
+}
+PhraseKit.load!(**paths)
+
+vocab_ids = JSON.parse(File.read(paths[:vocab_path]))['tokens'].values
+random = Random.new(42)
+batch = Array.new(documents) { vocab_ids.sample(4, random: random) }
+
+def measure
+  start = Process.clock_gettime(Process::CLOCK_MONOTONIC, :microsecond)
+  yield
+  Process.clock_gettime(Process::CLOCK_MONOTONIC, :microsecond) - start
+end
+
+timings = {
+  'match_tokens loop' => measure { batch.each { |token_ids| PhraseKit.match_tokens(token_ids: token_ids) } },
+  'match_tokens_batch' => measure { PhraseKit.match_tokens_batch(batch: batch) },
+  'match_tokens_batch_each' => measure { PhraseKit.match_tokens_batch_each(batch: batch) { |_, _| } }
+}
+
+timings.each do |name, total_us|
+  puts format('%-24s total=%.1fms per_doc=%.2fµs', name, total_us / 1000.0, total_us.to_f / documents)
+end
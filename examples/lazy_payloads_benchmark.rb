@@ -0,0 +1,41 @@
+# Compare eager vs lazy payload loading on a built artifact directory.
+#
+# Usage: ruby -Ilib examples/lazy_payloads_benchmark.rb <artifacts_dir> [iterations]
+
+require 'phrasekit'
+require 'json'
+
+artifacts_dir = ARGV[0] || 'examples/demo_output/artifacts'
+iterations = (ARGV[1] || 10_000).to_i
+
+paths = {
+  automaton_path: File.join(artifacts_dir, 'phrases.daac'),
+  payloads_path: File.join(artifacts_dir, 'payloads.bin'),
+  manifest_path: File.join(artifacts_dir, 'manifest.json'),
+  vocab_path: File.join(artifacts_dir, 'vocab.json')
+}
+
+vocab = JSON.parse(File.read(paths[:vocab_path]))
+token_ids = vocab['tokens'].values.sample(20, random: Random.new(42))
+
+def measure(iterations, token_ids)
+  times = iterations.times.map do
+    start = Process.clock_gettime(Process::CLOCK_MONOTONIC, :microsecond)
+    PhraseKit.match_tokens(token_ids: token_ids)
+    Process.clock_gettime(Process::CLOCK_MONOTONIC, :microsecond) - start
+  end.sort
+
+  {p50: times[times.length / 2], p95: times[(times.length * 0.95).floor], p99: times[(times.length * 0.99).floor]}
+end
+
+[false, true].each do |lazy|
+  load_start = Process.clock_gettime(Process::CLOCK_MONOTONIC, :millisecond)
+  PhraseKit.load!(**paths, lazy_payloads: lazy)
+  load_ms = Process.clock_gettime(Process::CLOCK_MONOTONIC, :millisecond) - load_start
+
+  latency = measure(iterations, token_ids)
+  stats = PhraseKit.stats
+
+  puts "#{lazy ? 'lazy ' : 'eager'}: load=#{load_ms}ms heap=#{stats[:heap_mb].round(2)}MB " \
+       "p50=#{latency[:p50]}µs p95=#{latency[:p95]}µs p99=#{latency[:p99]}µs"
+end
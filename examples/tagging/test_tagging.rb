@@ -23,10 +23,6 @@ output_dir = File.join(script_dir, "output")
 Dir.mkdir(output_dir) unless Dir.exist?(output_dir)
 
 build_config = {
-  automaton_path: File.join(output_dir, "phrases.daac"),
-  payloads_path: File.join(output_dir, "payloads.bin"),
-  manifest_path: File.join(output_dir, "manifest.json"),
-  vocab_path: File.join(output_dir, "vocab.json"),
   tokenizer: "whitespace",
   version: "test-v1",
   separator_id: 4294967294
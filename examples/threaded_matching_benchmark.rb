@@ -0,0 +1,42 @@
+# Measure match_tokens throughput on long documents from 1 and N Ruby threads.
+# Long documents are matched with the GVL released, so throughput should grow
+# with the thread count up to the number of cores.
+#
+# Usage: ruby -Ilib examples/threaded_matching_benchmark.rb <artifacts_dir> [threads] [documents] [tokens]
+
+require 'phrasekit'
+require 'json'
+
+artifacts_dir = ARGV[0] || 'examples/demo_output/artifacts'
+threads = (ARGV[1] || 4).to_i
+documents = (ARGV[2] || 2_000).to_i
+tokens = (ARGV[3] || 10_000).to_i
+
+paths = {
+  automaton_path: File.join(artifacts_dir, 'phrases.daac'),
+  payloads_path: File.join(artifacts_dir, 'payloads.bin'),
+  manifest_path: File.join(artifacts_dir, 'manifest.json'),
+  vocab_path: File.join(artifacts_dir, 'vocab.json')
+}
+PhraseKit.load!(**paths)
+
+vocab_ids = JSON.parse(File.read(paths[:vocab_path]))['tokens'].values
+random = Random.new(42)
+docs = Array.new(documents) { Array.new(tokens) { vocab_ids.sample(random: random) } }
+
+def measure
+  start = Process.clock_gettime(Process::CLOCK_MONOTONIC)
+  yield
+  Process.clock_gettime(Process::CLOCK_MONOTONIC) - start
+end
+
+def run(docs, threads)
+  docs.each_slice((docs.size / threads.to_f).ceil).map do |slice|
+    Thread.new { slice.each { |token_ids| PhraseKit.match_tokens(token_ids: token_ids, max: 1_000) } }
+  end.each(&:join)
+end
+
+[1, threads].uniq.each do |count|
+  seconds = measure { run(docs, count) }
+  puts format('%2d thread(s): %.2fs, %.0f docs/s', count, seconds, docs.size / seconds)
+end
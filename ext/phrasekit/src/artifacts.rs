@@ -0,0 +1,2040 @@
+//! Artifact building from scored phrases: `phrasekit_build` without the CLI.
+
+use crate::compression;
+use crate::config::Validate;
+use crate::manifest::{decode_pattern_tokens, encode_pattern_tokens, sha256_hex, Manifest, NegativeRule, Normalization, TokenEncoding, UnicodeNormalization, AUTOMATON_FORMAT, EXTERNAL_VOCAB, FORMAT_VERSION, PAYLOAD_FORMAT_VERSION, U24_TOKEN_LIMIT};
+use crate::payload::{load_payloads, Payload, PayloadFormat, PAYLOAD_SIZE};
+use crate::progress::Progress;
+use daachorse::DoubleArrayAhoCorasick;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+pub struct PhraseInput {
+    #[serde(default)]
+    pub tokens: Vec<TokenSlot>,
+    /// Pre-tokenized IDs from an external tokenizer, used as-is instead of
+    /// `tokens`. A build takes either form for every phrase, never a mix.
+    #[serde(default)]
+    pub token_ids: Option<Vec<u32>>,
+    pub phrase_id: u64,
+    pub salience: f32,
+    #[serde(alias = "domain_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub negative: bool,
+    #[serde(default)]
+    pub suppresses: Vec<u64>,
+    #[serde(default)]
+    pub window: usize,
+    /// Per-slot case flags; slots flagged `true` keep their case and only match
+    /// input with the exact same case. Empty means every slot is case-insensitive.
+    /// Ignored by `case_sensitive` builds, where every slot keeps its case.
+    #[serde(default)]
+    pub case_sensitive: Vec<bool>,
+    /// Entity type reported on the phrase's matches (e.g. "skill"); mapped to a
+    /// category_id in categories.json.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Concrete token sequences after slot expansion, filled in during validation.
+    #[serde(skip)]
+    variants: Vec<Vec<String>>,
+    /// 1-based line in the input JSONL, filled in during validation.
+    #[serde(skip)]
+    source_line: usize,
+}
+
+impl PhraseInput {
+    fn is_case_sensitive(&self, slot: usize) -> bool {
+        self.case_sensitive.get(slot).copied().unwrap_or(false)
+    }
+}
+
+/// One token position: either a literal token or a set of interchangeable alternatives.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TokenSlot {
+    Token(String),
+    AnyOf(Vec<String>),
+}
+
+impl TokenSlot {
+    fn alternatives(&self, case_sensitive: bool, normalization: Normalization, unicode: UnicodeNormalization) -> Vec<String> {
+        let normalize = |t: &String| {
+            let t = unicode.apply(t);
+            if case_sensitive { t.into_owned() } else { normalization.apply(&t).into_owned() }
+        };
+        let mut alternatives: Vec<String> = match self {
+            TokenSlot::Token(token) => vec![normalize(token)],
+            TokenSlot::AnyOf(tokens) => tokens.iter().map(normalize).collect(),
+        };
+        let mut seen = HashSet::new();
+        alternatives.retain(|t| seen.insert(t.clone()));
+        alternatives
+    }
+}
+
+/// Distinct tokens seen across all phrases, split by how they are matched.
+#[derive(Debug, Default)]
+pub struct VocabTokens {
+    /// Tokens from case-insensitive slots, normalized per the build's
+    /// `Normalization`.
+    insensitive: HashSet<String>,
+    /// Tokens from case-sensitive slots, with their original case.
+    case_sensitive: HashSet<String>,
+}
+
+struct ProcessedPhrase {
+    token_ids: Vec<u32>,
+    source_line: usize,
+    phrase_id: u64,
+    salience: f32,
+    count: u32,
+    length: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildConfig {
+    pub version: String,
+    pub tokenizer: String,
+    #[serde(default)]
+    pub separator_id: u32,
+    #[serde(default)]
+    pub min_count: Option<u32>,
+    #[serde(default)]
+    pub salience_threshold: Option<f32>,
+    #[serde(default = "default_max_slot_expansions")]
+    pub max_slot_expansions: usize,
+    /// Choose a separator above the built token ID range instead of using `separator_id`.
+    #[serde(default)]
+    pub auto_separator: bool,
+    /// Special tokens beyond `<UNK>` (e.g. `<NUM>`), given IDs above the regular range.
+    #[serde(default)]
+    pub special_tokens: Vec<String>,
+    /// What to do when a phrase uses a special token's name as a regular token.
+    #[serde(default)]
+    pub special_token_collision: SpecialTokenCollision,
+    /// Zero `built_at` and sort patterns, so identical input builds
+    /// byte-identical artifacts.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Keep every token's case instead of lowercasing, so "BRCA1" and "brca1"
+    /// are different tokens; recorded as the manifest's `normalization`.
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Unicode form every token is brought to before case normalization;
+    /// recorded as the manifest's `unicode_normalization`.
+    #[serde(default)]
+    pub unicode_normalization: UnicodeNormalization,
+    /// Automaton input layout; `u24_sep1` halves it for token IDs below
+    /// `U24_TOKEN_LIMIT`. Recorded as the manifest's `encoding`.
+    #[serde(default)]
+    pub encoding: TokenEncoding,
+}
+
+impl BuildConfig {
+    pub fn normalization(&self) -> Normalization {
+        Normalization::from_case_sensitive(self.case_sensitive)
+    }
+}
+
+/// Resolution for a special token whose name also appears in a phrase.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecialTokenCollision {
+    /// Abort the build, naming every colliding token.
+    #[default]
+    Error,
+    /// Encode the phrase token as the special token's ID and record the merge in vocab.json.
+    Merge,
+}
+
+/// A phrase token that spells a special token's name.
+#[derive(Debug, PartialEq)]
+struct SpecialTokenClash {
+    special: String,
+    /// The form as collected from phrases (normalized unless case-sensitive).
+    form: String,
+    case_sensitive: bool,
+}
+
+/// Conventional separator, used by `--auto-separator` whenever it is free.
+const DEFAULT_SEPARATOR_ID: u32 = 4294967294;
+
+/// `built_at` of deterministic builds: the Unix epoch, in `to_rfc3339` form.
+const DETERMINISTIC_BUILT_AT: &str = "1970-01-01T00:00:00+00:00";
+
+fn default_max_slot_expansions() -> usize {
+    256
+}
+
+/// Where document frequencies for the payload `idf` come from, if anywhere.
+#[derive(Debug, Default)]
+pub struct IdfSource {
+    /// JSONL of `{"phrase_id": ..., "df": ...}`; needs `num_docs`.
+    pub df_file: Option<String>,
+    /// Pre-tokenized corpus (`{"tokens": [...]}` per line) to count df from.
+    pub df_corpus: Option<String>,
+    pub num_docs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DocumentFrequency {
+    phrase_id: u64,
+    df: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct CorpusDocument {
+    tokens: Vec<String>,
+}
+
+/// Optional caps checked before automaton construction; all unlimited by default.
+#[derive(Debug, Default)]
+pub struct BuildLimits {
+    pub max_patterns: Option<usize>,
+    pub max_total_pattern_bytes: Option<usize>,
+    /// Soft cap on construction memory, compared against an estimate rather than measured.
+    pub max_build_memory_mb: Option<usize>,
+}
+
+/// Rough peak construction memory per encoded pattern byte, measured on a
+/// 200K-pattern build; used only for the `--max-build-memory-mb` estimate.
+const ESTIMATED_BUILD_BYTES_PER_PATTERN_BYTE: usize = 160;
+
+#[derive(Debug)]
+pub struct BuildStats {
+    pub total_input: usize,
+    pub filtered_low_count: usize,
+    pub filtered_low_salience: usize,
+    pub duplicate_phrase_ids: usize,
+    pub invalid_tokens: usize,
+    /// Negative phrases skipped because their `suppresses` list is empty.
+    pub empty_suppressions: usize,
+    pub expansion_limit_exceeded: usize,
+    pub negative_phrases: usize,
+    pub built: usize,
+}
+
+/// Sidecar mapping each pattern index (the matcher's `pattern_id`) to the input
+/// line it was built from. Slot alternatives share their phrase's line.
+#[derive(Debug, Serialize)]
+struct PatternSources {
+    input: String,
+    source_lines: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Vocabulary {
+    /// Artifact version, matching manifest.json, so consumers can detect a
+    /// vocabulary paired with the wrong build.
+    #[serde(default)]
+    pub version: String,
+    #[serde(serialize_with = "serialize_sorted")]
+    pub tokens: HashMap<String, u32>,
+    #[serde(serialize_with = "serialize_sorted")]
+    pub special_tokens: HashMap<String, u32>,
+    /// Exact-case forms from case-sensitive slots. Encoders look a token up here
+    /// first, then fall back to its normalized form (per the manifest's
+    /// `normalization`) in `tokens`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "serialize_sorted")]
+    pub case_sensitive_tokens: HashMap<String, u32>,
+    pub vocab_size: usize,
+    pub separator_id: u32,
+    /// Special tokens that phrases also used as regular tokens, merged under
+    /// `special_token_collision: "merge"`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_special_tokens: Vec<String>,
+}
+
+/// Phrases of a previous build, reconstructed from its artifacts for `--merge`.
+pub struct MergeBase {
+    /// The base build's settings, as recorded in its manifest and vocabulary.
+    pub config: BuildConfig,
+    /// One entry per phrase_id in base pattern order, with `variants` (or
+    /// `token_ids`) filled in.
+    pub phrases: Vec<PhraseInput>,
+}
+
+/// How a merge changed the base build's phrase set.
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeStats {
+    /// Base phrases carried over unchanged.
+    pub kept: usize,
+    /// New phrase_ids.
+    pub added: usize,
+    /// Base phrases replaced by an input phrase with the same phrase_id.
+    pub updated: usize,
+    /// Base phrases dropped because they no longer pass `min_count` or
+    /// `salience_threshold`.
+    pub removed: usize,
+}
+
+/// Checked after env and CLI overrides are applied, since they can supply
+/// `separator_id`.
+impl Validate for BuildConfig {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.separator_id == 0 && !self.auto_separator {
+            problems.push("separator_id must be non-zero (set it in the config, --separator-id, or PHRASEKIT_SEPARATOR_ID)".to_string());
+        }
+
+        if let Some(threshold) = self.salience_threshold {
+            if !threshold.is_finite() {
+                problems.push(format!("salience_threshold must be finite, got {}", threshold));
+            }
+        }
+
+        if self.max_slot_expansions == 0 {
+            problems.push("max_slot_expansions must be >= 1".to_string());
+        }
+
+        problems
+    }
+}
+
+/// A build held in memory; `write` puts it on disk.
+pub struct Artifacts {
+    /// Serialized automaton (phrases.daac).
+    pub automaton: Vec<u8>,
+    /// Payload table (payloads.bin), one entry per pattern.
+    pub payloads: Vec<u8>,
+    /// Token IDs of every pattern (phrases.bin), for `--merge`.
+    pub pattern_tokens: Vec<u8>,
+    pub manifest: Manifest,
+    /// Input line of each pattern (pattern_sources.json).
+    pub source_lines: Vec<usize>,
+    /// `None` for `token_ids` input.
+    pub vocabulary: Option<Vocabulary>,
+    pub categories: BTreeMap<String, u16>,
+}
+
+/// Where `Artifacts::write` put each file; optional files are `None` when not written.
+#[derive(Debug)]
+pub struct ArtifactPaths {
+    pub automaton: PathBuf,
+    pub payloads: PathBuf,
+    pub patterns: PathBuf,
+    pub manifest: PathBuf,
+    pub sources: PathBuf,
+    pub vocab: Option<PathBuf>,
+    pub vocab_txt: Option<PathBuf>,
+    pub categories: Option<PathBuf>,
+}
+
+/// Builds the automaton, payloads, vocabulary, and manifest for `text_phrases`, the
+/// output of `load_and_validate_phrases` (and `merge_phrases`) along with its
+/// `unique_tokens`. `config` must already be validated.
+pub fn build_artifacts(
+    text_phrases: &[PhraseInput],
+    unique_tokens: VocabTokens,
+    config: &BuildConfig,
+    limits: &BuildLimits,
+    idf: &IdfSource,
+    progress: &dyn Fn(Progress),
+) -> Result<Artifacts, Box<dyn std::error::Error>> {
+    prepare_build(text_phrases, unique_tokens, config, progress)?.build(config, limits, idf, progress)
+}
+
+/// A build up to the automaton: the vocabulary is built, the separator chosen,
+/// and every phrase resolved to token IDs, so everything that can reject the
+/// input has run. `phrasekit_build --check` stops here.
+pub struct PreparedBuild {
+    pub vocabulary: Option<Vocabulary>,
+    pub separator_id: u32,
+    pub categories: BTreeMap<String, u16>,
+    phrases: Vec<ProcessedPhrase>,
+    phrase_categories: HashMap<u64, u16>,
+    negative_rules: Vec<NegativeRule>,
+    external_ids: bool,
+}
+
+/// The checks of `build_artifacts`, without building anything.
+pub fn prepare_build(
+    text_phrases: &[PhraseInput],
+    unique_tokens: VocabTokens,
+    config: &BuildConfig,
+    progress: &dyn Fn(Progress),
+) -> Result<PreparedBuild, Box<dyn std::error::Error>> {
+    if text_phrases.is_empty() {
+        return Err("No valid phrases to build".into());
+    }
+
+    let external_ids = text_phrases.iter().any(|p| p.token_ids.is_some());
+    let (mut vocabulary, max_token_id) = if external_ids {
+        progress(Progress::Stage("📚 Using external token IDs (no vocabulary built)".into()));
+        if !config.special_tokens.is_empty() {
+            return Err("special_tokens need a built vocabulary and cannot be used with token_ids input".into());
+        }
+        let max_token_id = text_phrases.iter().flat_map(|p| p.token_ids.iter().flatten()).copied().max().unwrap_or(0);
+        (None, max_token_id)
+    } else {
+        let vocabulary = build_phrase_vocabulary(unique_tokens, config, progress)?;
+        let max_token_id = vocabulary.max_token_id();
+        (Some(vocabulary), max_token_id)
+    };
+
+    let mut separator_id = config.separator_id;
+    if config.auto_separator {
+        separator_id = auto_separator(max_token_id).ok_or("No separator_id available above the token ID range")?;
+        if let Some(vocabulary) = &mut vocabulary {
+            vocabulary.separator_id = separator_id;
+        }
+        progress(Progress::Done(format!("Auto-selected separator_id {}", separator_id)));
+    }
+    if !config.auto_separator && separator_id <= max_token_id {
+        progress(Progress::Warning(format!(
+            "separator_id {} is within the token ID range (max {}); it collides with a token and matching will break. Use --auto-separator.",
+            separator_id, max_token_id
+        )));
+    }
+
+    // Collect negative rules
+    let negative_rules = collect_negative_rules(text_phrases, progress);
+
+    let categories = assign_categories(text_phrases)?;
+    let phrase_categories: HashMap<u64, u16> = text_phrases
+        .iter()
+        .filter_map(|p| Some((p.phrase_id, categories[p.category.as_ref()?])))
+        .collect();
+    if !categories.is_empty() {
+        progress(Progress::Done(format!("Assigned {} categories", categories.len())));
+    }
+
+    // Convert text tokens to IDs
+    let mut phrases = match &vocabulary {
+        Some(vocabulary) => process_phrases(text_phrases, vocabulary)?,
+        None => process_token_id_phrases(text_phrases),
+    };
+    check_separator_unused(&phrases, vocabulary.as_ref(), separator_id)?;
+    if let Some(token_id) = config.encoding.unencodable(phrases.iter().flat_map(|p| p.token_ids.iter())) {
+        return Err(format!(
+            "Token ID {} does not fit the {} encoding, which needs IDs below {}; use \"encoding\": \"{}\"",
+            token_id,
+            config.encoding.as_str(),
+            U24_TOKEN_LIMIT,
+            TokenEncoding::U32Sep.as_str()
+        )
+        .into());
+    }
+    if config.deterministic {
+        sort_patterns(&mut phrases);
+    }
+
+    Ok(PreparedBuild {
+        vocabulary,
+        separator_id,
+        categories,
+        phrases,
+        phrase_categories,
+        negative_rules,
+        external_ids,
+    })
+}
+
+impl PreparedBuild {
+    /// Patterns the automaton will hold: one per phrase, or per slot expansion.
+    pub fn num_patterns(&self) -> usize {
+        self.phrases.len()
+    }
+
+    pub fn build(
+        self,
+        config: &BuildConfig,
+        limits: &BuildLimits,
+        idf: &IdfSource,
+        progress: &dyn Fn(Progress),
+    ) -> Result<Artifacts, Box<dyn std::error::Error>> {
+        let PreparedBuild {
+            vocabulary,
+            separator_id,
+            categories,
+            phrases,
+            phrase_categories,
+            negative_rules,
+            external_ids,
+        } = self;
+
+        // Build automaton
+        progress(Progress::Stage("🔨 Building automaton...".into()));
+        let patterns: Vec<Vec<u8>> = phrases.iter()
+            .map(|p| config.encoding.encode(&p.token_ids, separator_id))
+            .collect();
+
+        check_limits(&patterns, limits)?;
+
+        let automaton: DoubleArrayAhoCorasick<u32> = DoubleArrayAhoCorasick::new(patterns)
+            .map_err(|e| format!("Failed to build automaton: {:?}", e))?;
+
+        let idf = match (&idf.df_file, &idf.df_corpus) {
+            (Some(path), _) => {
+                let dfs = load_document_frequencies(compression::open_input(path)?)?;
+                let num_docs = idf.num_docs.ok_or("--df-file requires --num-docs")?;
+                progress(Progress::Done(format!("Loaded {} document frequencies ({} documents)", dfs.len(), num_docs)));
+                Some((dfs, num_docs))
+            }
+            (None, Some(path)) => {
+                let vocabulary = vocabulary
+                    .as_ref()
+                    .ok_or("--df-corpus needs a vocabulary to encode the corpus; use --df-file with token_ids input")?;
+                let (dfs, num_docs) = count_document_frequencies(
+                    compression::open_input(path)?,
+                    &automaton,
+                    &phrases,
+                    vocabulary,
+                    config,
+                    separator_id,
+                )?;
+                progress(Progress::Done(format!("Counted document frequencies over {} documents", num_docs)));
+                Some((dfs, num_docs))
+            }
+            (None, None) => None,
+        };
+
+        let automaton_bytes = automaton.serialize();
+
+        let payloads: Vec<Payload> = phrases.iter()
+            .map(|p| {
+                let payload = Payload::new(p.phrase_id, p.salience, p.count, p.length)
+                    .with_category(phrase_categories.get(&p.phrase_id).copied().unwrap_or(0));
+                match &idf {
+                    Some((dfs, num_docs)) => {
+                        payload.with_idf(smoothed_idf(*num_docs, dfs.get(&p.phrase_id).copied().unwrap_or(0)))
+                    }
+                    None => payload,
+                }
+            })
+            .collect();
+
+        let mut payloads_bytes = Vec::with_capacity(payloads.len() * PAYLOAD_SIZE);
+        for payload in &payloads {
+            payload.write_to(&mut payloads_bytes)?;
+        }
+
+        // Manifest with checksums
+        let manifest = Manifest {
+            version: config.version.clone(),
+            tokenizer: config.tokenizer.clone(),
+            num_patterns: phrases.len(),
+            min_count: config.min_count,
+            salience_threshold: config.salience_threshold,
+            built_at: if config.deterministic {
+                DETERMINISTIC_BUILT_AT.to_string()
+            } else {
+                chrono::Utc::now().to_rfc3339()
+            },
+            separator_id,
+            max_token_id: phrases.iter().flat_map(|p| p.token_ids.iter()).copied().max(),
+            min_pattern_len: payloads.iter().map(|p| p.n as usize).min(),
+            max_pattern_len: payloads.iter().map(|p| p.n as usize).max(),
+            format_version: FORMAT_VERSION,
+            payload_format_version: PAYLOAD_FORMAT_VERSION,
+            vocab: external_ids.then(|| EXTERNAL_VOCAB.to_string()),
+            negative_rules,
+            normalization: config.normalization(),
+            unicode_normalization: config.unicode_normalization,
+            encoding: config.encoding,
+            automaton_format: Some(AUTOMATON_FORMAT.to_string()),
+            automaton_sha256: Some(sha256_hex(&automaton_bytes)),
+            payloads_sha256: Some(sha256_hex(&payloads_bytes)),
+        };
+
+        Ok(Artifacts {
+            pattern_tokens: encode_pattern_tokens(phrases.iter().map(|p| p.token_ids.as_slice())),
+            source_lines: phrases.iter().map(|p| p.source_line).collect(),
+            automaton: automaton_bytes,
+            payloads: payloads_bytes,
+            manifest,
+            vocabulary,
+            categories,
+        })
+    }
+}
+
+impl Artifacts {
+    /// Writes every artifact into `output_dir`, creating it if needed.
+    /// `input_path` is recorded in pattern_sources.json; `vocab_txt` also
+    /// writes vocab.txt.
+    pub fn write(
+        &self,
+        output_dir: &Path,
+        input_path: &str,
+        vocab_txt: bool,
+        progress: &dyn Fn(Progress),
+    ) -> Result<ArtifactPaths, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(output_dir)?;
+
+        let automaton_path = output_dir.join("phrases.daac");
+        std::fs::write(&automaton_path, &self.automaton)?;
+        progress(Progress::Done(format!("Wrote automaton ({} bytes) to {}", self.automaton.len(), automaton_path.display())));
+
+        // Write payloads
+        progress(Progress::Stage("💾 Writing payloads...".into()));
+        let payloads_path = output_dir.join("payloads.bin");
+        std::fs::write(&payloads_path, &self.payloads)?;
+        progress(Progress::Done(format!(
+            "Wrote {} payloads ({} bytes) to {}",
+            self.manifest.num_patterns,
+            self.payloads.len(),
+            payloads_path.display()
+        )));
+
+        let patterns_path = output_dir.join("phrases.bin");
+        std::fs::write(&patterns_path, &self.pattern_tokens)?;
+        progress(Progress::Done(format!("Wrote pattern token IDs to {}", patterns_path.display())));
+
+        progress(Progress::Stage("📝 Generating manifest...".into()));
+        let manifest_path = output_dir.join("manifest.json");
+        let manifest_json = serde_json::to_string_pretty(&self.manifest)?;
+        std::fs::write(&manifest_path, manifest_json)?;
+        progress(Progress::Done(format!("Wrote manifest to {}", manifest_path.display())));
+
+        // Write pattern provenance (pattern_index -> input line)
+        let sources_path = output_dir.join("pattern_sources.json");
+        let sources = PatternSources {
+            input: input_path.to_string(),
+            source_lines: self.source_lines.clone(),
+        };
+        std::fs::write(&sources_path, serde_json::to_string(&sources)?)?;
+        progress(Progress::Done(format!("Wrote pattern sources to {}", sources_path.display())));
+
+        // Write vocabulary
+        let mut vocab_path = None;
+        let mut vocab_txt_path = None;
+        if let Some(vocabulary) = &self.vocabulary {
+            progress(Progress::Stage("💾 Writing vocabulary...".into()));
+            let path = output_dir.join("vocab.json");
+            std::fs::write(&path, serde_json::to_string_pretty(vocabulary)?)?;
+            progress(Progress::Done(format!("Wrote vocabulary ({} tokens) to {}", vocabulary.vocab_size, path.display())));
+            vocab_path = Some(path);
+
+            if vocab_txt {
+                let path = output_dir.join("vocab.txt");
+                std::fs::write(&path, vocab_text(vocabulary))?;
+                progress(Progress::Done(format!("Wrote plain-text vocabulary to {}", path.display())));
+                vocab_txt_path = Some(path);
+            }
+        } else if vocab_txt {
+            progress(Progress::Warning("--vocab-txt ignored: token_ids input has no vocabulary".into()));
+        }
+
+        let mut categories_path = None;
+        if !self.categories.is_empty() {
+            let path = output_dir.join("categories.json");
+            std::fs::write(&path, serde_json::to_string_pretty(&self.categories)?)?;
+            progress(Progress::Done(format!("Wrote {} categories to {}", self.categories.len(), path.display())));
+            categories_path = Some(path);
+        }
+
+        Ok(ArtifactPaths {
+            automaton: automaton_path,
+            payloads: payloads_path,
+            patterns: patterns_path,
+            manifest: manifest_path,
+            sources: sources_path,
+            vocab: vocab_path,
+            vocab_txt: vocab_txt_path,
+            categories: categories_path,
+        })
+    }
+}
+
+pub fn load_and_validate_phrases(
+    path: &str,
+    config: &BuildConfig,
+    progress: &dyn Fn(Progress),
+) -> Result<(Vec<PhraseInput>, BuildStats, VocabTokens), Box<dyn std::error::Error>> {
+    let reader = compression::open_input(path)?;
+
+    let mut phrases = Vec::new();
+    let mut seen_ids = HashSet::new();
+    let mut unique_tokens = VocabTokens::default();
+    let mut external_ids: Option<bool> = None;
+    let mut stats = BuildStats {
+        total_input: 0,
+        filtered_low_count: 0,
+        filtered_low_salience: 0,
+        duplicate_phrase_ids: 0,
+        invalid_tokens: 0,
+        empty_suppressions: 0,
+        expansion_limit_exceeded: 0,
+        negative_phrases: 0,
+        built: 0,
+    };
+
+    progress(Progress::Stage("📖 Loading phrases...".into()));
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        stats.total_input += 1;
+
+        let mut phrase: PhraseInput = match serde_json::from_str(&line) {
+            Ok(p) => p,
+            Err(e) => {
+                progress(Progress::Warning(format!("Line {}: Failed to parse: {}", line_num + 1, e)));
+                continue;
+            }
+        };
+
+        // Validate (negative phrases are rules, not candidates, so thresholds don't apply)
+        if let Some(min_count) = config.min_count {
+            if !phrase.negative && phrase.count < min_count {
+                stats.filtered_low_count += 1;
+                continue;
+            }
+        }
+
+        if let Some(threshold) = config.salience_threshold {
+            if !phrase.negative && phrase.salience < threshold {
+                stats.filtered_low_salience += 1;
+                continue;
+            }
+        }
+
+        if phrase.negative && phrase.suppresses.is_empty() {
+            progress(Progress::Warning(format!("Line {}: Negative phrase {} suppresses nothing", line_num + 1, phrase.phrase_id)));
+            stats.empty_suppressions += 1;
+            continue;
+        }
+
+        let slots: Vec<Vec<String>> = if let Some(token_ids) = &phrase.token_ids {
+            if !phrase.tokens.is_empty() || !phrase.case_sensitive.is_empty() {
+                progress(Progress::Warning(format!("Line {}: token_ids cannot be combined with tokens or case_sensitive", line_num + 1)));
+                stats.invalid_tokens += 1;
+                continue;
+            }
+            if token_ids.is_empty() {
+                progress(Progress::Warning(format!("Line {}: Empty token sequence", line_num + 1)));
+                stats.invalid_tokens += 1;
+                continue;
+            }
+            Vec::new()
+        } else {
+            if phrase.tokens.is_empty() {
+                progress(Progress::Warning(format!("Line {}: Empty token sequence", line_num + 1)));
+                stats.invalid_tokens += 1;
+                continue;
+            }
+
+            if !phrase.case_sensitive.is_empty() && phrase.case_sensitive.len() != phrase.tokens.len() {
+                progress(Progress::Warning(format!(
+                    "Line {}: case_sensitive has {} flags for {} tokens",
+                    line_num + 1,
+                    phrase.case_sensitive.len(),
+                    phrase.tokens.len()
+                )));
+                stats.invalid_tokens += 1;
+                continue;
+            }
+
+            // Every token already keeps its case; flagged slots would only
+            // give the same form a second ID
+            if config.case_sensitive {
+                phrase.case_sensitive.clear();
+            }
+
+            let slots: Vec<Vec<String>> = phrase
+                .tokens
+                .iter()
+                .enumerate()
+                .map(|(i, slot)| slot.alternatives(phrase.is_case_sensitive(i), config.normalization(), config.unicode_normalization))
+                .collect();
+
+            if slots.iter().any(|alternatives| alternatives.is_empty() || alternatives.iter().any(|t| t.is_empty())) {
+                progress(Progress::Warning(format!("Line {}: Empty token", line_num + 1)));
+                stats.invalid_tokens += 1;
+                continue;
+            }
+
+            phrase.variants = match expand_slots(&slots, config.max_slot_expansions) {
+                Some(variants) => variants,
+                None => {
+                    progress(Progress::Warning(format!(
+                        "Line {}: Slot alternatives expand to more than {} patterns",
+                        line_num + 1,
+                        config.max_slot_expansions
+                    )));
+                    stats.expansion_limit_exceeded += 1;
+                    continue;
+                }
+            };
+            slots
+        };
+
+        let uses_ids = phrase.token_ids.is_some();
+        if *external_ids.get_or_insert(uses_ids) != uses_ids {
+            return Err(format!(
+                "Line {}: phrases mix token_ids and tokens; use one form for the whole input",
+                line_num + 1
+            )
+            .into());
+        }
+
+        if !seen_ids.insert(phrase.phrase_id) {
+            progress(Progress::Warning(format!("Line {}: Duplicate phrase_id {}", line_num + 1, phrase.phrase_id)));
+            stats.duplicate_phrase_ids += 1;
+            continue;
+        }
+
+        for (i, alternatives) in slots.into_iter().enumerate() {
+            if phrase.is_case_sensitive(i) {
+                unique_tokens.case_sensitive.extend(alternatives);
+            } else {
+                unique_tokens.insensitive.extend(alternatives);
+            }
+        }
+
+        if phrase.negative {
+            stats.negative_phrases += 1;
+        }
+
+        phrase.source_line = line_num + 1;
+        phrases.push(phrase);
+        stats.built += 1;
+
+        if stats.total_input % 10000 == 0 {
+            progress(Progress::Processed { count: stats.total_input, unit: "lines" });
+        }
+    }
+
+    progress(Progress::Done(format!("Loaded {} phrases", stats.total_input)));
+
+    Ok((phrases, stats, unique_tokens))
+}
+
+/// A build read back from its output directory: manifest.json, payloads.bin,
+/// phrases.bin, and vocab.json unless the build used external token IDs.
+struct StoredBuild {
+    manifest: Manifest,
+    payloads: Vec<Payload>,
+    /// Token IDs of each pattern, parallel to `payloads`.
+    patterns: Vec<Vec<u32>>,
+    vocabulary: Option<Vocabulary>,
+}
+
+fn load_stored_build(dir: &Path) -> Result<StoredBuild, Box<dyn std::error::Error>> {
+    let manifest = Manifest::load(dir.join("manifest.json"))?;
+    let format = PayloadFormat::from_version(manifest.payload_format_version)
+        .ok_or_else(|| format!("Unsupported payload_format_version {}", manifest.payload_format_version))?;
+    let payloads = load_payloads(BufReader::new(File::open(dir.join("payloads.bin"))?), format)?;
+
+    let patterns_path = dir.join("phrases.bin");
+    if !patterns_path.exists() {
+        return Err(format!(
+            "{} not found; rebuild the artifacts once with this version of phrasekit_build",
+            patterns_path.display()
+        )
+        .into());
+    }
+    let patterns = decode_pattern_tokens(&std::fs::read(&patterns_path)?)?;
+    if patterns.len() != payloads.len() {
+        return Err(format!("phrases.bin has {} patterns but payloads.bin has {}", patterns.len(), payloads.len()).into());
+    }
+
+    let vocabulary: Option<Vocabulary> = if manifest.vocab.as_deref() == Some(EXTERNAL_VOCAB) {
+        None
+    } else {
+        Some(serde_json::from_reader(BufReader::new(File::open(dir.join("vocab.json"))?))?)
+    };
+    Ok(StoredBuild { manifest, payloads, patterns, vocabulary })
+}
+
+/// One pattern of a built phrase, as `phrasekit_inspect` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredPattern {
+    /// Index in payloads.bin; the matcher's `pattern_id`.
+    pub pattern_id: usize,
+    pub token_ids: Vec<u32>,
+    /// Token strings under the build's vocabulary, `None` for a build from
+    /// `token_ids`. An ID the vocabulary lacks reads as `"?"`.
+    pub tokens: Option<Vec<String>>,
+    pub payload: Payload,
+}
+
+/// Every pattern each of `phrase_ids` was built into (one per slot
+/// expansion) in the build at `dir`, in the order of `phrase_ids`; a phrase the
+/// build lacks gets an empty list. The build is read once for all of them.
+pub fn inspect_phrases(dir: &Path, phrase_ids: &[u64]) -> Result<Vec<Vec<StoredPattern>>, Box<dyn std::error::Error>> {
+    let StoredBuild { payloads, patterns, vocabulary, .. } = load_stored_build(dir)?;
+    let token_names = vocabulary.as_ref().map(base_token_names);
+
+    let mut found: Vec<Vec<StoredPattern>> = phrase_ids.iter().map(|_| Vec::new()).collect();
+    let mut slots: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (slot, &phrase_id) in phrase_ids.iter().enumerate() {
+        slots.entry(phrase_id).or_default().push(slot);
+    }
+    for (pattern_id, (token_ids, payload)) in patterns.into_iter().zip(payloads).enumerate() {
+        let Some(indices) = slots.get(&payload.phrase_id) else { continue };
+        let pattern = StoredPattern {
+            pattern_id,
+            tokens: token_names.as_ref().map(|names| {
+                token_ids
+                    .iter()
+                    .map(|id| names.get(id).map_or_else(|| "?".to_string(), |(token, _)| token.clone()))
+                    .collect()
+            }),
+            token_ids,
+            payload,
+        };
+        for &index in indices {
+            found[index].push(pattern.clone());
+        }
+    }
+    Ok(found)
+}
+
+/// Reconstructs a previous build's phrases and settings from its manifest.json,
+/// payloads.bin, phrases.bin, and, when present, vocab.json and categories.json.
+pub fn load_merge_base(dir: &Path) -> Result<MergeBase, Box<dyn std::error::Error>> {
+    let StoredBuild { manifest, payloads, patterns, vocabulary } = load_stored_build(dir)?;
+    let categories: HashMap<u16, String> = match std::fs::read_to_string(dir.join("categories.json")) {
+        Ok(json) => serde_json::from_str::<HashMap<String, u16>>(&json)?.into_iter().map(|(name, id)| (id, name)).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let rules: HashMap<u64, &NegativeRule> = manifest.negative_rules.iter().map(|rule| (rule.phrase_id, rule)).collect();
+    let token_names = vocabulary.as_ref().map(base_token_names);
+
+    let mut phrases: Vec<PhraseInput> = Vec::new();
+    let mut phrase_index: HashMap<u64, usize> = HashMap::new();
+    for (token_ids, payload) in patterns.into_iter().zip(&payloads) {
+        // (token, case_sensitive) per slot; None for pre-tokenized builds
+        let slots = match &token_names {
+            Some(names) => Some(
+                token_ids
+                    .iter()
+                    .map(|id| {
+                        names.get(id).cloned().ok_or_else(|| {
+                            format!("Phrase {} uses token ID {}, which is not in the base vocab.json", payload.phrase_id, id)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        // Slot alternatives were expanded into one pattern each; regroup them
+        if let Some(&index) = phrase_index.get(&payload.phrase_id) {
+            if let Some(slots) = slots {
+                phrases[index].variants.push(slots.into_iter().map(|(token, _)| token).collect());
+            }
+            continue;
+        }
+
+        let rule = rules.get(&payload.phrase_id);
+        let (case_sensitive, variants) = match slots {
+            Some(slots) => (
+                slots.iter().map(|(_, case_sensitive)| *case_sensitive).collect(),
+                vec![slots.into_iter().map(|(token, _)| token).collect()],
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        phrase_index.insert(payload.phrase_id, phrases.len());
+        phrases.push(PhraseInput {
+            tokens: Vec::new(),
+            token_ids: token_names.is_none().then_some(token_ids),
+            phrase_id: payload.phrase_id,
+            salience: payload.salience,
+            count: payload.count,
+            negative: rule.is_some(),
+            suppresses: rule.map_or_else(Vec::new, |rule| rule.suppresses.clone()),
+            window: rule.map_or(0, |rule| rule.window),
+            case_sensitive,
+            category: categories.get(&payload.category_id).cloned(),
+            variants,
+            source_line: 0,
+        });
+    }
+
+    let (special_tokens, special_token_collision) = match &vocabulary {
+        Some(vocabulary) => {
+            let mut special: Vec<(&String, u32)> = vocabulary
+                .special_tokens
+                .iter()
+                .filter(|(name, _)| name.as_str() != "<UNK>")
+                .map(|(name, &id)| (name, id))
+                .collect();
+            special.sort_by_key(|&(_, id)| id);
+            let collision = if vocabulary.merged_special_tokens.is_empty() {
+                SpecialTokenCollision::Error
+            } else {
+                SpecialTokenCollision::Merge
+            };
+            (special.into_iter().map(|(name, _)| name.clone()).collect(), collision)
+        }
+        None => (Vec::new(), SpecialTokenCollision::Error),
+    };
+
+    let config = BuildConfig {
+        version: manifest.version,
+        tokenizer: manifest.tokenizer,
+        separator_id: manifest.separator_id,
+        min_count: manifest.min_count,
+        salience_threshold: manifest.salience_threshold,
+        max_slot_expansions: default_max_slot_expansions(),
+        auto_separator: false,
+        special_tokens,
+        special_token_collision,
+        deterministic: manifest.built_at == DETERMINISTIC_BUILT_AT,
+        case_sensitive: manifest.normalization.is_case_sensitive(),
+        unicode_normalization: manifest.unicode_normalization,
+        encoding: manifest.encoding,
+    };
+
+    Ok(MergeBase { config, phrases })
+}
+
+/// Token and case-sensitivity for every ID of a base vocabulary. Forms merged
+/// into a special token shadow the special token's own name.
+fn base_token_names(vocabulary: &Vocabulary) -> HashMap<u32, (String, bool)> {
+    let mut names = HashMap::new();
+    for (token, &id) in &vocabulary.special_tokens {
+        names.insert(id, (token.clone(), false));
+    }
+    for (token, &id) in &vocabulary.tokens {
+        names.insert(id, (token.clone(), false));
+    }
+    for (token, &id) in &vocabulary.case_sensitive_tokens {
+        names.insert(id, (token.clone(), true));
+    }
+    names
+}
+
+/// Unions base phrases into `phrases`, the validated input of a `--merge`
+/// build. An input phrase replaces the base phrase with its phrase_id, and base
+/// phrases that no longer pass `min_count` or `salience_threshold` are dropped.
+/// Kept base phrases come first, in base order, and their tokens are added to
+/// `unique_tokens`.
+pub fn merge_phrases(
+    base: Vec<PhraseInput>,
+    phrases: &mut Vec<PhraseInput>,
+    unique_tokens: &mut VocabTokens,
+    config: &BuildConfig,
+) -> Result<MergeStats, String> {
+    if let (Some(first_base), Some(first_input)) = (base.first(), phrases.first()) {
+        if first_base.token_ids.is_some() != first_input.token_ids.is_some() {
+            return Err("--merge input must use the same form (tokens or token_ids) as the base build".to_string());
+        }
+    }
+
+    let input_ids: HashSet<u64> = phrases.iter().map(|p| p.phrase_id).collect();
+    let mut stats = MergeStats::default();
+    let mut merged = Vec::with_capacity(base.len() + phrases.len());
+    for phrase in base {
+        let filtered = !phrase.negative
+            && (config.min_count.is_some_and(|min_count| phrase.count < min_count)
+                || config.salience_threshold.is_some_and(|threshold| phrase.salience < threshold));
+        if input_ids.contains(&phrase.phrase_id) {
+            stats.updated += 1;
+        } else if filtered {
+            stats.removed += 1;
+        } else {
+            for variant in &phrase.variants {
+                for (i, token) in variant.iter().enumerate() {
+                    if phrase.is_case_sensitive(i) {
+                        unique_tokens.case_sensitive.insert(token.clone());
+                    } else {
+                        unique_tokens.insensitive.insert(token.clone());
+                    }
+                }
+            }
+            merged.push(phrase);
+        }
+    }
+    stats.kept = merged.len();
+    stats.added = phrases.len() - stats.updated;
+
+    merged.append(phrases);
+    *phrases = merged;
+    Ok(stats)
+}
+
+/// Expands per-slot alternatives into their cross product, or `None` if that
+/// would produce more than `limit` token sequences.
+fn expand_slots(slots: &[Vec<String>], limit: usize) -> Option<Vec<Vec<String>>> {
+    let total = slots
+        .iter()
+        .try_fold(1usize, |acc, alternatives| acc.checked_mul(alternatives.len()))?;
+    if total > limit {
+        return None;
+    }
+
+    let mut variants: Vec<Vec<String>> = vec![Vec::with_capacity(slots.len())];
+    for alternatives in slots {
+        variants = variants
+            .into_iter()
+            .flat_map(|prefix| {
+                alternatives.iter().map(move |token| {
+                    let mut variant = prefix.clone();
+                    variant.push(token.clone());
+                    variant
+                })
+            })
+            .collect();
+    }
+
+    Some(variants)
+}
+
+/// Category names mapped to IDs from 1 in name order; 0 is left for
+/// uncategorized phrases.
+fn assign_categories(phrases: &[PhraseInput]) -> Result<BTreeMap<String, u16>, String> {
+    let names: BTreeSet<&String> = phrases.iter().filter_map(|p| p.category.as_ref()).collect();
+    if names.len() > u16::MAX as usize {
+        return Err(format!("{} categories exceed the limit of {}", names.len(), u16::MAX));
+    }
+    if let Some(phrase) = phrases.iter().find(|p| p.category.as_ref().is_some_and(|c| c.trim().is_empty())) {
+        return Err(format!("Line {}: phrase {} has an empty category", phrase.source_line, phrase.phrase_id));
+    }
+    Ok(names.into_iter().zip(1..).map(|(name, id)| (name.clone(), id)).collect())
+}
+
+fn collect_negative_rules(phrases: &[PhraseInput], progress: &dyn Fn(Progress)) -> Vec<NegativeRule> {
+    let known_ids: HashSet<u64> = phrases.iter().map(|p| p.phrase_id).collect();
+
+    phrases
+        .iter()
+        .filter(|p| p.negative)
+        .map(|p| {
+            for target in &p.suppresses {
+                if !known_ids.contains(target) {
+                    progress(Progress::Warning(format!("Negative phrase {} suppresses unknown phrase_id {}", p.phrase_id, target)));
+                }
+            }
+
+            NegativeRule {
+                phrase_id: p.phrase_id,
+                suppresses: p.suppresses.clone(),
+                window: p.window,
+            }
+        })
+        .collect()
+}
+
+/// Maps each expanded variant to token IDs, rejecting any pattern that ends up
+/// with no tokens (it would match nowhere and carry an `n == 0` payload).
+fn process_phrases(text_phrases: &[PhraseInput], vocabulary: &Vocabulary) -> Result<Vec<ProcessedPhrase>, String> {
+    let mut phrases = Vec::new();
+    for phrase in text_phrases {
+        for variant in &phrase.variants {
+            let token_ids: Vec<u32> = variant.iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let ids = if phrase.is_case_sensitive(i) {
+                        &vocabulary.case_sensitive_tokens
+                    } else {
+                        &vocabulary.tokens
+                    };
+                    *ids.get(t).unwrap_or(&0)
+                })
+                .collect();
+
+            if token_ids.is_empty() {
+                return Err(format!("Phrase {} produced a zero-length pattern", phrase.phrase_id));
+            }
+
+            phrases.push(ProcessedPhrase {
+                token_ids,
+                source_line: phrase.source_line,
+                phrase_id: phrase.phrase_id,
+                salience: phrase.salience,
+                count: phrase.count,
+                length: variant.len() as u8,
+            });
+        }
+    }
+    Ok(phrases)
+}
+
+/// One pattern per phrase, straight from its supplied `token_ids`.
+fn process_token_id_phrases(text_phrases: &[PhraseInput]) -> Vec<ProcessedPhrase> {
+    text_phrases
+        .iter()
+        .filter_map(|phrase| {
+            let token_ids = phrase.token_ids.clone()?;
+            Some(ProcessedPhrase {
+                length: token_ids.len() as u8,
+                token_ids,
+                source_line: phrase.source_line,
+                phrase_id: phrase.phrase_id,
+                salience: phrase.salience,
+                count: phrase.count,
+            })
+        })
+        .collect()
+}
+
+/// Rejects token IDs equal to the separator, assigned or supplied. Patterns
+/// are encoded as `token, separator` pairs, so such a token would let matches
+/// start or end inside another token's encoding.
+fn check_separator_unused(
+    phrases: &[ProcessedPhrase],
+    vocabulary: Option<&Vocabulary>,
+    separator_id: u32,
+) -> Result<(), String> {
+    if let Some(phrase) = phrases.iter().find(|p| p.token_ids.contains(&separator_id)) {
+        return Err(format!(
+            "Line {}: phrase {} uses token ID {}, which is the separator_id; pick another separator_id or use --auto-separator",
+            phrase.source_line, phrase.phrase_id, separator_id
+        ));
+    }
+    if let Some(vocabulary) = vocabulary {
+        let entries = vocabulary
+            .special_tokens
+            .iter()
+            .chain(&vocabulary.tokens)
+            .chain(&vocabulary.case_sensitive_tokens);
+        if let Some((token, _)) = entries.filter(|(_, &id)| id == separator_id).min() {
+            return Err(format!(
+                "Vocabulary token {:?} has ID {}, which is the separator_id; pick another separator_id or use --auto-separator",
+                token, separator_id
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Orders patterns by `(token_ids, phrase_id)`, making pattern IDs and the
+/// payload table independent of input line order.
+fn sort_patterns(phrases: &mut [ProcessedPhrase]) {
+    phrases.sort_by(|a, b| a.token_ids.cmp(&b.token_ids).then(a.phrase_id.cmp(&b.phrase_id)));
+}
+
+/// Writes a token map in key order, so vocab.json does not depend on hash order.
+fn serialize_sorted<S: Serializer>(map: &HashMap<String, u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Enforces `BuildLimits` on the encoded pattern set, naming the limit and the
+/// counts that exceeded it.
+fn check_limits(patterns: &[Vec<u8>], limits: &BuildLimits) -> Result<(), String> {
+    if let Some(max) = limits.max_patterns {
+        if patterns.len() > max {
+            return Err(format!("Pattern limit exceeded: {} patterns > --max-patterns {}", patterns.len(), max));
+        }
+    }
+
+    let total_bytes: usize = patterns.iter().map(|p| p.len()).sum();
+    if let Some(max) = limits.max_total_pattern_bytes {
+        if total_bytes > max {
+            return Err(format!(
+                "Pattern size limit exceeded: {} bytes across {} patterns > --max-total-pattern-bytes {}",
+                total_bytes,
+                patterns.len(),
+                max
+            ));
+        }
+    }
+
+    if let Some(max_mb) = limits.max_build_memory_mb {
+        let estimated_mb = total_bytes.saturating_mul(ESTIMATED_BUILD_BYTES_PER_PATTERN_BYTE) / 1_048_576;
+        if estimated_mb > max_mb {
+            return Err(format!(
+                "Estimated build memory {} MB ({} pattern bytes) > --max-build-memory-mb {}",
+                estimated_mb, total_bytes, max_mb
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// `ln((1 + num_docs) / (1 + df)) + 1`: always at least 1, so a stored 0.0
+/// unambiguously means "no idf", and phrases missing from the df source (df 0)
+/// get the rarest weight.
+fn smoothed_idf(num_docs: u64, df: u32) -> f32 {
+    (((1 + num_docs) as f64 / (1 + df as u64) as f64).ln() + 1.0) as f32
+}
+
+fn load_document_frequencies<R: BufRead>(reader: R) -> Result<HashMap<u64, u32>, String> {
+    let mut dfs = HashMap::new();
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: DocumentFrequency =
+            serde_json::from_str(&line).map_err(|e| format!("df file line {}: {}", line_num + 1, e))?;
+        dfs.insert(entry.phrase_id, entry.df);
+    }
+    Ok(dfs)
+}
+
+/// Counts, for each phrase_id, how many corpus documents contain it at least
+/// once, using the automaton being built. Returns the counts and the number of
+/// documents.
+fn count_document_frequencies<R: BufRead>(
+    reader: R,
+    automaton: &DoubleArrayAhoCorasick<u32>,
+    phrases: &[ProcessedPhrase],
+    vocabulary: &Vocabulary,
+    config: &BuildConfig,
+    separator: u32,
+) -> Result<(HashMap<u64, u32>, u64), String> {
+    let normalization = config.normalization();
+    let unk = vocabulary.special_tokens.get("<UNK>").copied().unwrap_or(0);
+    let mut dfs: HashMap<u64, u32> = HashMap::new();
+    let mut num_docs = 0;
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let doc: CorpusDocument =
+            serde_json::from_str(&line).map_err(|e| format!("df corpus line {}: {}", line_num + 1, e))?;
+        num_docs += 1;
+
+        let token_ids: Vec<u32> = doc.tokens.iter()
+            .map(|t| {
+                let t = config.unicode_normalization.apply(t);
+                vocabulary.case_sensitive_tokens.get(t.as_ref())
+                    .or_else(|| vocabulary.tokens.get(normalization.apply(&t).as_ref()))
+                    .copied()
+                    .unwrap_or(unk)
+            })
+            .collect();
+
+        let seen: HashSet<u64> = automaton
+            .find_overlapping_iter(config.encoding.encode(&token_ids, separator))
+            .map(|m| phrases[m.value() as usize].phrase_id)
+            .collect();
+        for phrase_id in seen {
+            *dfs.entry(phrase_id).or_insert(0) += 1;
+        }
+    }
+
+    Ok((dfs, num_docs))
+}
+
+impl Vocabulary {
+    fn max_token_id(&self) -> u32 {
+        self.special_tokens
+            .values()
+            .chain(self.tokens.values())
+            .chain(self.case_sensitive_tokens.values())
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Separator guaranteed not to collide with any assigned token ID: the
+/// conventional default when it lies above the range, otherwise `max + 1`.
+fn auto_separator(max_token_id: u32) -> Option<u32> {
+    if max_token_id < DEFAULT_SEPARATOR_ID {
+        Some(DEFAULT_SEPARATOR_ID)
+    } else {
+        max_token_id.checked_add(1)
+    }
+}
+
+/// Renders every vocabulary entry (special, case-insensitive, and case-sensitive)
+/// as `token<TAB>id` lines sorted by id, so vocabularies diff cleanly across builds.
+fn vocab_text(vocabulary: &Vocabulary) -> String {
+    let mut entries: Vec<(&String, u32)> = vocabulary
+        .special_tokens
+        .iter()
+        .chain(&vocabulary.tokens)
+        .chain(&vocabulary.case_sensitive_tokens)
+        .map(|(token, &id)| (token, id))
+        .collect();
+    entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut text = String::new();
+    for (token, id) in entries {
+        text.push_str(token);
+        text.push('\t');
+        text.push_str(&id.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+/// Every phrase token that spells `<UNK>` or a declared special token. Tokens
+/// from case-insensitive slots are compared normalized.
+fn find_special_token_clashes(
+    unique_tokens: &VocabTokens,
+    special_tokens: &[String],
+    normalization: Normalization,
+) -> Vec<SpecialTokenClash> {
+    let mut clashes = Vec::new();
+    for special in std::iter::once("<UNK>").chain(special_tokens.iter().map(String::as_str)) {
+        let normalized = normalization.apply(special).into_owned();
+        if unique_tokens.insensitive.contains(&normalized) {
+            clashes.push(SpecialTokenClash {
+                special: special.to_string(),
+                form: normalized,
+                case_sensitive: false,
+            });
+        }
+        if unique_tokens.case_sensitive.contains(special) {
+            clashes.push(SpecialTokenClash {
+                special: special.to_string(),
+                form: special.to_string(),
+                case_sensitive: true,
+            });
+        }
+    }
+    clashes
+}
+
+/// Assigns declared special tokens IDs above the regular range, then points
+/// each clashing phrase form at its special token's ID.
+fn add_special_tokens(vocabulary: &mut Vocabulary, special_tokens: &[String], clashes: &[SpecialTokenClash]) {
+    let mut next_id = vocabulary.max_token_id() + 1;
+    for special in special_tokens {
+        if !vocabulary.special_tokens.contains_key(special) {
+            vocabulary.special_tokens.insert(special.clone(), next_id);
+            next_id += 1;
+        }
+    }
+
+    for clash in clashes {
+        let id = vocabulary.special_tokens[&clash.special];
+        if clash.case_sensitive {
+            vocabulary.case_sensitive_tokens.insert(clash.form.clone(), id);
+        } else {
+            vocabulary.tokens.insert(clash.form.clone(), id);
+        }
+        if !vocabulary.merged_special_tokens.contains(&clash.special) {
+            vocabulary.merged_special_tokens.push(clash.special.clone());
+        }
+    }
+
+    // Merged forms share their special token's ID, so they don't add to the size
+    vocabulary.vocab_size = vocabulary.tokens.len() + vocabulary.case_sensitive_tokens.len()
+        + vocabulary.special_tokens.len() - clashes.len();
+}
+
+/// Builds the vocabulary for string-token phrases, resolving special token
+/// clashes per `special_token_collision`.
+fn build_phrase_vocabulary(
+    mut unique_tokens: VocabTokens,
+    config: &BuildConfig,
+    progress: &dyn Fn(Progress),
+) -> Result<Vocabulary, String> {
+    progress(Progress::Stage("📚 Building vocabulary...".into()));
+    let clashes = find_special_token_clashes(&unique_tokens, &config.special_tokens, config.normalization());
+    if !clashes.is_empty() && config.special_token_collision == SpecialTokenCollision::Error {
+        let names: Vec<String> = clashes.iter().map(|c| format!("{} (as \"{}\")", c.special, c.form)).collect();
+        return Err(format!(
+            "Phrases use special token names as regular tokens: {}. Rename them or set special_token_collision to \"merge\"",
+            names.join(", ")
+        ));
+    }
+    for clash in &clashes {
+        if clash.case_sensitive {
+            unique_tokens.case_sensitive.remove(&clash.form);
+        } else {
+            unique_tokens.insensitive.remove(&clash.form);
+        }
+    }
+    let mut vocabulary = build_vocabulary(unique_tokens, config.separator_id);
+    vocabulary.version = config.version.clone();
+    add_special_tokens(&mut vocabulary, &config.special_tokens, &clashes);
+    for clash in &clashes {
+        progress(Progress::Warning(format!("Merged phrase token \"{}\" into special token {}", clash.form, clash.special)));
+    }
+    progress(Progress::Done(format!("Built vocabulary ({} tokens)", vocabulary.vocab_size)));
+    Ok(vocabulary)
+}
+
+fn build_vocabulary(unique_tokens: VocabTokens, separator_id: u32) -> Vocabulary {
+    let mut sorted_tokens: Vec<String> = unique_tokens.insensitive.into_iter().collect();
+    sorted_tokens.sort();
+    let mut sorted_case_sensitive: Vec<String> = unique_tokens.case_sensitive.into_iter().collect();
+    sorted_case_sensitive.sort();
+
+    // Case-sensitive forms get their own IDs after the case-insensitive ones, so
+    // "COVID" (exact) and "covid" (any case) never share an ID.
+    let mut tokens = HashMap::new();
+    for (idx, token) in sorted_tokens.iter().enumerate() {
+        tokens.insert(token.clone(), (idx + 1) as u32);
+    }
+
+    let mut case_sensitive_tokens = HashMap::new();
+    for (idx, token) in sorted_case_sensitive.iter().enumerate() {
+        case_sensitive_tokens.insert(token.clone(), (sorted_tokens.len() + idx + 1) as u32);
+    }
+
+    let mut special_tokens = HashMap::new();
+    special_tokens.insert("<UNK>".to_string(), 0);
+
+    let vocab_size = tokens.len() + case_sensitive_tokens.len() + special_tokens.len();
+
+    Vocabulary {
+        version: String::new(),
+        tokens,
+        special_tokens,
+        case_sensitive_tokens,
+        vocab_size,
+        separator_id,
+        merged_special_tokens: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::silent;
+    use crate::manifest::encode_token_ids;
+    use std::io::Write;
+
+    fn slots(json: &str) -> Vec<Vec<String>> {
+        let parsed: Vec<TokenSlot> = serde_json::from_str(json).unwrap();
+        parsed.iter().map(|slot| slot.alternatives(false, Normalization::Lowercase, UnicodeNormalization::None)).collect()
+    }
+
+    #[test]
+    fn test_expand_two_alternative_slot() {
+        let variants = expand_slots(&slots(r#"[["large", "Small"], "cancer"]"#), 256).unwrap();
+        assert_eq!(
+            variants,
+            vec![
+                vec!["large".to_string(), "cancer".to_string()],
+                vec!["small".to_string(), "cancer".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_dedups_alternatives() {
+        let variants = expand_slots(&slots(r#"[["Large", "large"], "cancer"]"#), 256).unwrap();
+        assert_eq!(variants.len(), 1);
+    }
+
+    #[test]
+    fn test_expand_respects_limit() {
+        let input = slots(r#"[["a", "b"], ["c", "d"], ["e", "f"]]"#);
+        assert_eq!(expand_slots(&input, 8).unwrap().len(), 8);
+        assert!(expand_slots(&input, 7).is_none());
+    }
+
+    #[test]
+    fn test_zero_length_pattern_is_rejected() {
+        let mut phrase: PhraseInput = serde_json::from_str(
+            r#"{"tokens": ["lung"], "phrase_id": 7, "salience": 1.0, "count": 5}"#,
+        )
+        .unwrap();
+        phrase.variants = vec![vec![]];
+
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["lung".to_string()]),
+            ..VocabTokens::default()
+        };
+        let vocabulary = build_vocabulary(tokens, 4294967294);
+        let err = process_phrases(&[phrase], &vocabulary).err().unwrap();
+        assert!(err.contains("Phrase 7"));
+    }
+
+    #[test]
+    fn test_assign_categories() {
+        let phrase = |json: &str| serde_json::from_str::<PhraseInput>(json).unwrap();
+        let phrases = [
+            phrase(r#"{"tokens": ["rust"], "phrase_id": 1, "salience": 1.0, "count": 5, "category": "skill"}"#),
+            phrase(r#"{"tokens": ["acme"], "phrase_id": 2, "salience": 1.0, "count": 5, "category": "company"}"#),
+            phrase(r#"{"tokens": ["ruby"], "phrase_id": 3, "salience": 1.0, "count": 5, "category": "skill"}"#),
+            phrase(r#"{"tokens": ["the"], "phrase_id": 4, "salience": 1.0, "count": 5}"#),
+        ];
+        let categories = assign_categories(&phrases).unwrap();
+        assert_eq!(categories, BTreeMap::from([("company".to_string(), 1), ("skill".to_string(), 2)]));
+
+        let blank = phrase(r#"{"tokens": ["x"], "phrase_id": 5, "salience": 1.0, "count": 5, "category": " "}"#);
+        assert!(assign_categories(&[blank]).unwrap_err().contains("phrase 5 has an empty category"));
+    }
+
+    #[test]
+    fn test_mixed_case_phrase() {
+        let mut phrase: PhraseInput = serde_json::from_str(
+            r#"{"tokens": ["COVID", "Testing"], "case_sensitive": [true, false], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(phrase.tokens[0].alternatives(true, Normalization::Lowercase, UnicodeNormalization::None), vec!["COVID".to_string()]);
+        assert_eq!(phrase.tokens[1].alternatives(false, Normalization::Lowercase, UnicodeNormalization::None), vec!["testing".to_string()]);
+        phrase.variants = vec![vec!["COVID".to_string(), "testing".to_string()]];
+
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["covid".to_string(), "testing".to_string()]),
+            case_sensitive: HashSet::from(["COVID".to_string()]),
+        };
+        let vocabulary = build_vocabulary(tokens, 4294967294);
+        assert_eq!(vocabulary.vocab_size, 4);
+
+        let exact_id = vocabulary.case_sensitive_tokens["COVID"];
+        assert_ne!(exact_id, vocabulary.tokens["covid"]);
+
+        let processed = process_phrases(&[phrase], &vocabulary).unwrap();
+        assert_eq!(processed[0].token_ids, vec![exact_id, vocabulary.tokens["testing"]]);
+    }
+
+    #[test]
+    fn test_vocab_text_sorted_by_id() {
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["lung".to_string(), "cancer".to_string()]),
+            case_sensitive: HashSet::from(["BRCA1".to_string()]),
+        };
+        let vocabulary = build_vocabulary(tokens, 4294967294);
+
+        assert_eq!(vocab_text(&vocabulary), "<UNK>\t0\ncancer\t1\nlung\t2\nBRCA1\t3\n");
+    }
+
+    #[test]
+    fn test_special_token_clashes() {
+        let mut tokens = VocabTokens {
+            insensitive: HashSet::from(["<num>".to_string(), "mg".to_string()]),
+            case_sensitive: HashSet::new(),
+        };
+        let specials = vec!["<NUM>".to_string(), "<DATE>".to_string()];
+
+        let clashes = find_special_token_clashes(&tokens, &specials, Normalization::Lowercase);
+        assert_eq!(
+            clashes,
+            vec![SpecialTokenClash {
+                special: "<NUM>".to_string(),
+                form: "<num>".to_string(),
+                case_sensitive: false,
+            }]
+        );
+
+        // Merge: the phrase token encodes as the special token's ID
+        tokens.insensitive.remove("<num>");
+        let mut vocabulary = build_vocabulary(tokens, 4294967294);
+        add_special_tokens(&mut vocabulary, &specials, &clashes);
+
+        let num_id = vocabulary.special_tokens["<NUM>"];
+        assert_eq!(num_id, 2);
+        assert_eq!(vocabulary.special_tokens["<DATE>"], 3);
+        assert_eq!(vocabulary.tokens["<num>"], num_id);
+        assert_eq!(vocabulary.merged_special_tokens, vec!["<NUM>".to_string()]);
+        assert_eq!(vocabulary.vocab_size, 4);
+
+        let no_clash = VocabTokens {
+            insensitive: HashSet::from(["mg".to_string()]),
+            case_sensitive: HashSet::from(["<num>".to_string()]),
+        };
+        assert!(find_special_token_clashes(&no_clash, &specials, Normalization::Lowercase).is_empty());
+    }
+
+    #[test]
+    fn test_auto_separator() {
+        assert_eq!(auto_separator(42), Some(DEFAULT_SEPARATOR_ID));
+        assert_eq!(auto_separator(DEFAULT_SEPARATOR_ID), Some(u32::MAX));
+        assert_eq!(auto_separator(u32::MAX), None);
+
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["lung".to_string(), "cancer".to_string()]),
+            case_sensitive: HashSet::from(["BRCA1".to_string()]),
+        };
+        assert_eq!(build_vocabulary(tokens, 0).max_token_id(), 3);
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let patterns = vec![encode_token_ids(&[1, 2], 4294967294), encode_token_ids(&[3], 4294967294)];
+
+        assert!(check_limits(&patterns, &BuildLimits::default()).is_ok());
+
+        let limits = BuildLimits { max_patterns: Some(1), ..BuildLimits::default() };
+        let err = check_limits(&patterns, &limits).unwrap_err();
+        assert!(err.contains("2 patterns"));
+
+        let limits = BuildLimits { max_total_pattern_bytes: Some(23), ..BuildLimits::default() };
+        let err = check_limits(&patterns, &limits).unwrap_err();
+        assert!(err.contains("24 bytes"));
+
+        let limits = BuildLimits { max_build_memory_mb: Some(0), ..BuildLimits::default() };
+        assert!(check_limits(&patterns, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_smoothed_idf() {
+        assert_eq!(smoothed_idf(9, 9), 1.0);
+        assert!((smoothed_idf(9, 0) - (10.0f32.ln() + 1.0)).abs() < 1e-6);
+        assert!(smoothed_idf(1000, 10) > smoothed_idf(1000, 100));
+    }
+
+    #[test]
+    fn test_count_document_frequencies() {
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["machine".to_string(), "learning".to_string(), "deep".to_string()]),
+            case_sensitive: HashSet::new(),
+        };
+        let vocabulary = build_vocabulary(tokens, 4294967294);
+        let phrase = |words: &[&str], phrase_id: u64| ProcessedPhrase {
+            token_ids: words.iter().map(|w| vocabulary.tokens[*w]).collect(),
+            source_line: 1,
+            phrase_id,
+            salience: 1.0,
+            count: 1,
+            length: words.len() as u8,
+        };
+        let phrases = vec![phrase(&["machine", "learning"], 7), phrase(&["deep", "learning"], 8)];
+        let automaton = DoubleArrayAhoCorasick::<u32>::new(
+            phrases.iter().map(|p| encode_token_ids(&p.token_ids, 4294967294)),
+        )
+        .unwrap();
+
+        // Repeats within a document count once
+        let corpus = concat!(
+            r#"{"tokens": ["Machine", "learning", "and", "machine", "learning"]}"#, "\n",
+            r#"{"tokens": ["deep", "learning"]}"#, "\n",
+            "\n",
+            r#"{"tokens": ["nothing", "here"]}"#, "\n",
+        );
+        let (dfs, num_docs) =
+            count_document_frequencies(corpus.as_bytes(), &automaton, &phrases, &vocabulary, &build_config(), 4294967294).unwrap();
+
+        assert_eq!(num_docs, 3);
+        assert_eq!(dfs[&7], 1);
+        assert_eq!(dfs[&8], 1);
+    }
+
+    fn build_config() -> BuildConfig {
+        serde_json::from_str(r#"{"version": "v1", "tokenizer": "external", "separator_id": 4294967294}"#).unwrap()
+    }
+
+    fn input_file(lines: &[&str]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_token_ids_are_used_as_is() {
+        let file = input_file(&[
+            r#"{"token_ids": [5012, 77], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [], "phrase_id": 2, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [9], "tokens": ["nine"], "phrase_id": 3, "salience": 2.0, "count": 10}"#,
+        ]);
+        let (phrases, stats, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &build_config(), &silent).unwrap();
+        assert_eq!(stats.invalid_tokens, 2);
+        assert!(unique_tokens.insensitive.is_empty());
+
+        let processed = process_token_id_phrases(&phrases);
+        assert_eq!(processed.len(), 1);
+        assert_eq!(processed[0].token_ids, vec![5012, 77]);
+        assert_eq!((processed[0].length, processed[0].source_line), (2, 1));
+    }
+
+    #[test]
+    fn test_negative_phrase_without_targets_is_counted_separately() {
+        let file = input_file(&[
+            r#"{"token_ids": [5012], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [77], "phrase_id": 2, "salience": 0.0, "count": 0, "negative": true, "suppresses": []}"#,
+            r#"{"token_ids": [78], "phrase_id": 3, "salience": 0.0, "count": 0, "negative": true, "suppresses": [1]}"#,
+        ]);
+        let (phrases, stats, _) = load_and_validate_phrases(file.path().to_str().unwrap(), &build_config(), &silent).unwrap();
+        assert_eq!(stats.empty_suppressions, 1);
+        assert_eq!(stats.invalid_tokens, 0);
+        assert_eq!(stats.negative_phrases, 1);
+        assert_eq!(phrases.iter().map(|p| p.phrase_id).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_token_ids_and_tokens_do_not_mix() {
+        let file = input_file(&[
+            r#"{"token_ids": [5012, 77], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+            r#"{"tokens": ["lysis", "buffer"], "phrase_id": 2, "salience": 2.0, "count": 10}"#,
+        ]);
+        let err = load_and_validate_phrases(file.path().to_str().unwrap(), &build_config(), &silent).err().unwrap();
+        assert!(err.to_string().contains("Line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_token_id_equal_to_separator_is_rejected() {
+        let file = input_file(&[
+            r#"{"token_ids": [5012, 77], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [3, 4294967294], "phrase_id": 2, "salience": 2.0, "count": 10}"#,
+        ]);
+        let (phrases, _, _) = load_and_validate_phrases(file.path().to_str().unwrap(), &build_config(), &silent).unwrap();
+        let phrases = process_token_id_phrases(&phrases);
+        let err = check_separator_unused(&phrases, None, 4294967294).unwrap_err();
+        assert!(err.contains("Line 2: phrase 2 uses token ID 4294967294"), "{}", err);
+        assert!(check_separator_unused(&phrases, None, 4294967295).is_ok());
+    }
+
+    #[test]
+    fn test_compact_encoding_is_recorded_and_rejects_wide_token_ids() {
+        let file = input_file(&[
+            r#"{"token_ids": [5012, 77], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [3, 16581375], "phrase_id": 2, "salience": 2.0, "count": 10}"#,
+        ]);
+        let mut config = build_config();
+        config.encoding = TokenEncoding::U24Sep1;
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &config, &silent).unwrap();
+        let err = prepare_build(&phrases, unique_tokens, &config, &silent).err().unwrap();
+        assert!(err.to_string().contains("Token ID 16581375 does not fit the u24_sep1 encoding"), "{}", err);
+
+        let file = input_file(&[r#"{"token_ids": [5012, 77], "phrase_id": 1, "salience": 2.0, "count": 10}"#]);
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &config, &silent).unwrap();
+        let artifacts = build_artifacts(&phrases, unique_tokens, &config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+        assert_eq!(artifacts.manifest.encoding, TokenEncoding::U24Sep1);
+    }
+
+    #[test]
+    fn test_vocabulary_entry_equal_to_separator_is_rejected() {
+        let tokens = VocabTokens {
+            insensitive: HashSet::from(["buffer".to_string(), "lysis".to_string()]),
+            ..VocabTokens::default()
+        };
+        // IDs are assigned from 1 in sorted order, so "lysis" gets the separator
+        let mut vocabulary = build_vocabulary(tokens, 2);
+        let phrase = ProcessedPhrase {
+            token_ids: vec![vocabulary.tokens["buffer"]],
+            source_line: 1,
+            phrase_id: 7,
+            salience: 1.0,
+            count: 5,
+            length: 1,
+        };
+
+        let err = check_separator_unused(&[phrase], Some(&vocabulary), 2).unwrap_err();
+        assert!(err.contains(r#"Vocabulary token "lysis" has ID 2"#), "{}", err);
+
+        vocabulary.tokens.remove("lysis");
+        let phrase = ProcessedPhrase {
+            token_ids: vec![1, 2],
+            source_line: 3,
+            phrase_id: 8,
+            salience: 1.0,
+            count: 5,
+            length: 2,
+        };
+        let err = check_separator_unused(&[phrase], Some(&vocabulary), 2).unwrap_err();
+        assert!(err.starts_with("Line 3: phrase 8"), "{}", err);
+    }
+
+    #[test]
+    fn test_deterministic_pattern_order_and_vocab_json() {
+        let phrase = |token_ids: Vec<u32>, phrase_id: u64| ProcessedPhrase {
+            length: token_ids.len() as u8,
+            token_ids,
+            source_line: 1,
+            phrase_id,
+            salience: 1.0,
+            count: 5,
+        };
+        let mut phrases = vec![phrase(vec![2, 1], 9), phrase(vec![1, 3], 8), phrase(vec![1], 7), phrase(vec![1, 3], 6)];
+        sort_patterns(&mut phrases);
+        let order: Vec<u64> = phrases.iter().map(|p| p.phrase_id).collect();
+        assert_eq!(order, vec![7, 6, 8, 9]);
+
+        let tokens = VocabTokens {
+            insensitive: (0..50).map(|i| format!("token{}", i)).collect(),
+            ..VocabTokens::default()
+        };
+        let vocabulary = build_vocabulary(tokens, 4294967294);
+        let sorted = serde_json::to_string(&vocabulary.tokens.iter().collect::<BTreeMap<_, _>>()).unwrap();
+        assert!(serde_json::to_string(&vocabulary).unwrap().contains(&sorted));
+    }
+
+    #[test]
+    fn test_merge_phrases() {
+        let phrase = |json: &str| {
+            let mut phrase: PhraseInput = serde_json::from_str(json).unwrap();
+            phrase.variants = vec![phrase.tokens.iter().map(|slot| slot.alternatives(false, Normalization::Lowercase, UnicodeNormalization::None)[0].clone()).collect()];
+            phrase
+        };
+        let base = vec![
+            phrase(r#"{"tokens": ["lung", "cancer"], "phrase_id": 1, "salience": 2.0, "count": 50}"#),
+            phrase(r#"{"tokens": ["old", "name"], "phrase_id": 2, "salience": 2.0, "count": 50}"#),
+            phrase(r#"{"tokens": ["rare"], "phrase_id": 3, "salience": 2.0, "count": 2}"#),
+        ];
+        let mut input = vec![
+            phrase(r#"{"tokens": ["new", "name"], "phrase_id": 2, "salience": 3.0, "count": 60}"#),
+            phrase(r#"{"tokens": ["fresh"], "phrase_id": 4, "salience": 1.0, "count": 20}"#),
+        ];
+        let config: BuildConfig =
+            serde_json::from_str(r#"{"version": "v2", "tokenizer": "t", "separator_id": 4294967294, "min_count": 10}"#).unwrap();
+
+        let mut unique_tokens = VocabTokens::default();
+        let stats = merge_phrases(base, &mut input, &mut unique_tokens, &config).unwrap();
+        assert_eq!(stats, MergeStats { kept: 1, added: 1, updated: 1, removed: 1 });
+
+        let order: Vec<(u64, f32)> = input.iter().map(|p| (p.phrase_id, p.salience)).collect();
+        assert_eq!(order, vec![(1, 2.0), (2, 3.0), (4, 1.0)]);
+        // Only kept base phrases contribute tokens; the input's come from validation
+        assert_eq!(unique_tokens.insensitive, HashSet::from(["lung".to_string(), "cancer".to_string()]));
+    }
+
+    #[test]
+    fn test_build_artifacts_writes_a_mergeable_build() {
+        let file = input_file(&[
+            r#"{"tokens": ["lysis", "buffer"], "phrase_id": 1, "salience": 2.0, "count": 10, "category": "reagent"}"#,
+            r#"{"tokens": [["large", "small"], "cells"], "phrase_id": 2, "salience": 3.0, "count": 12}"#,
+        ]);
+        let config = build_config();
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &config, &silent).unwrap();
+        let artifacts =
+            build_artifacts(&phrases, unique_tokens, &config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+        assert_eq!(artifacts.manifest.num_patterns, 3);
+        assert_eq!((artifacts.manifest.min_pattern_len, artifacts.manifest.max_pattern_len), (Some(2), Some(2)));
+        assert_eq!(artifacts.payloads.len(), 3 * PAYLOAD_SIZE);
+        assert_eq!(artifacts.source_lines, vec![1, 2, 2]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let paths = artifacts.write(dir.path(), "phrases.jsonl", false, &silent).unwrap();
+        assert!(paths.vocab.is_some() && paths.categories.is_some() && paths.vocab_txt.is_none());
+
+        let base = load_merge_base(dir.path()).unwrap();
+        assert_eq!(base.config.version, "v1");
+        let ids: Vec<(u64, usize)> = base.phrases.iter().map(|p| (p.phrase_id, p.variants.len())).collect();
+        assert_eq!(ids, vec![(1, 1), (2, 2)]);
+        assert_eq!(base.phrases[0].category.as_deref(), Some("reagent"));
+
+        let inspected = inspect_phrases(dir.path(), &[2, 99, 1]).unwrap();
+        assert_eq!(inspected.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 0, 1]);
+        let stored = &inspected[0];
+        let tokens: Vec<Option<Vec<String>>> = stored.iter().map(|p| p.tokens.clone()).collect();
+        assert_eq!(stored.iter().map(|p| p.pattern_id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(tokens, vec![Some(vec!["large".to_string(), "cells".to_string()]), Some(vec!["small".to_string(), "cells".to_string()])]);
+        assert_eq!(stored[0].token_ids.len(), 2);
+        assert_eq!(stored[0].payload.salience, 3.0);
+        assert_eq!(inspected[2][0].pattern_id, 0);
+    }
+
+    #[test]
+    fn test_load_merge_base_round_trips_a_build() {
+        let file = input_file(&[
+            r#"{"tokens": ["BRCA1", "gene"], "case_sensitive": [true, false], "phrase_id": 1, "salience": 2.0, "count": 10, "category": "gene"}"#,
+            r#"{"tokens": [["large", "small"], "cells"], "phrase_id": 2, "salience": 3.0, "count": 12, "category": "cell"}"#,
+            r#"{"tokens": ["knockout", "mice"], "phrase_id": 3, "salience": 0.0, "count": 0, "negative": true, "suppresses": [1], "window": 2}"#,
+        ]);
+        let mut config = build_config();
+        config.special_tokens = vec!["<NUM>".to_string(), "<DATE>".to_string()];
+        let build = |phrases: &[PhraseInput], unique_tokens: VocabTokens, config: &BuildConfig| {
+            let artifacts =
+                build_artifacts(phrases, unique_tokens, config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+            let dir = tempfile::tempdir().unwrap();
+            artifacts.write(dir.path(), "phrases.jsonl", false, &silent).unwrap();
+            (artifacts, dir)
+        };
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &config, &silent).unwrap();
+        let (first, first_dir) = build(&phrases, unique_tokens, &config);
+
+        let base = load_merge_base(first_dir.path()).unwrap();
+        assert_eq!(base.config.special_tokens, vec!["<NUM>".to_string(), "<DATE>".to_string()]);
+        assert_eq!(base.config.separator_id, config.separator_id);
+        let summary: Vec<_> = base
+            .phrases
+            .iter()
+            .map(|p| (p.phrase_id, p.variants.clone(), p.case_sensitive.clone(), p.category.clone()))
+            .collect();
+        let names = |tokens: &[&str]| tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (1, vec![names(&["BRCA1", "gene"])], vec![true, false], Some("gene".to_string())),
+                (2, vec![names(&["large", "cells"]), names(&["small", "cells"])], vec![false, false], Some("cell".to_string())),
+                (3, vec![names(&["knockout", "mice"])], vec![false, false], None),
+            ]
+        );
+        let rules: Vec<_> = base.phrases.iter().map(|p| (p.negative, p.suppresses.clone(), p.window)).collect();
+        assert_eq!(rules, vec![(false, vec![], 0), (false, vec![], 0), (true, vec![1], 2)]);
+
+        // Merging nothing into the base rebuilds the same artifacts
+        let mut merged = Vec::new();
+        let mut unique_tokens = VocabTokens::default();
+        merge_phrases(base.phrases, &mut merged, &mut unique_tokens, &base.config).unwrap();
+        let (second, _second_dir) = build(&merged, unique_tokens, &base.config);
+        assert_eq!(second.pattern_tokens, first.pattern_tokens);
+        assert_eq!(second.payloads, first.payloads);
+        assert_eq!(second.categories, first.categories);
+        let rules = |manifest: &Manifest| {
+            manifest.negative_rules.iter().map(|r| (r.phrase_id, r.suppresses.clone(), r.window)).collect::<Vec<_>>()
+        };
+        assert_eq!(rules(&first.manifest), vec![(3, vec![1], 2)]);
+        assert_eq!(rules(&second.manifest), rules(&first.manifest));
+        let (first_vocab, second_vocab) = (first.vocabulary.unwrap(), second.vocabulary.unwrap());
+        assert_eq!(second_vocab.tokens, first_vocab.tokens);
+        assert_eq!(second_vocab.case_sensitive_tokens, first_vocab.case_sensitive_tokens);
+        assert_eq!(second_vocab.special_tokens, first_vocab.special_tokens);
+    }
+
+    #[test]
+    fn test_prepare_build_checks_without_building() {
+        let file = input_file(&[
+            r#"{"tokens": [["large", "small"], "cells"], "phrase_id": 2, "salience": 3.0, "count": 12}"#,
+        ]);
+        let config = build_config();
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), &config, &silent).unwrap();
+        let prepared = prepare_build(&phrases, unique_tokens, &config, &silent).unwrap();
+        assert_eq!(prepared.num_patterns(), 2);
+        assert_eq!(prepared.vocabulary.as_ref().map(|v| v.tokens.len()), Some(3));
+
+        let err = prepare_build(&[], VocabTokens::default(), &config, &silent).err().unwrap();
+        assert_eq!(err.to_string(), "No valid phrases to build");
+    }
+
+    #[test]
+    fn test_case_sensitive_build_keeps_case() {
+        let file = input_file(&[
+            r#"{"tokens": ["BRCA1", "gene"], "phrase_id": 1, "salience": 3.0, "count": 12}"#,
+            r#"{"tokens": ["brca1", "Gene"], "case_sensitive": [true, false], "phrase_id": 2, "salience": 2.0, "count": 12}"#,
+        ]);
+        let build = |config: &BuildConfig| {
+            let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), config, &silent).unwrap();
+            build_artifacts(&phrases, unique_tokens, config, &BuildLimits::default(), &IdfSource::default(), &silent)
+        };
+
+        let mut config = build_config();
+        config.case_sensitive = true;
+        let artifacts = build(&config).unwrap();
+        assert_eq!(artifacts.manifest.normalization, Normalization::None);
+        let vocabulary = artifacts.vocabulary.unwrap();
+        let mut tokens: Vec<&str> = vocabulary.tokens.keys().map(String::as_str).collect();
+        tokens.sort();
+        assert_eq!(tokens, vec!["BRCA1", "Gene", "brca1", "gene"]);
+        assert!(vocabulary.case_sensitive_tokens.is_empty());
+
+        let artifacts = build(&build_config()).unwrap();
+        assert_eq!(artifacts.manifest.normalization, Normalization::Lowercase);
+        let vocabulary = artifacts.vocabulary.unwrap();
+        let mut tokens: Vec<&str> = vocabulary.tokens.keys().map(String::as_str).collect();
+        tokens.sort();
+        assert_eq!(tokens, vec!["brca1", "gene"]);
+        assert!(vocabulary.case_sensitive_tokens.contains_key("brca1"));
+    }
+
+    #[test]
+    fn test_unicode_normalization_merges_composed_and_decomposed_tokens() {
+        let file = input_file(&[
+            r#"{"tokens": ["caf\u00e9", "menu"], "phrase_id": 1, "salience": 3.0, "count": 12}"#,
+            r#"{"tokens": ["Cafe\u0301", "au", "lait"], "case_sensitive": [true, false, false], "phrase_id": 2, "salience": 2.0, "count": 12}"#,
+        ]);
+        let build = |config: &BuildConfig| {
+            let (phrases, _, unique_tokens) = load_and_validate_phrases(file.path().to_str().unwrap(), config, &silent).unwrap();
+            build_artifacts(&phrases, unique_tokens, config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap()
+        };
+
+        let artifacts = build(&build_config());
+        assert_eq!(artifacts.manifest.unicode_normalization, UnicodeNormalization::None);
+        let vocabulary = artifacts.vocabulary.unwrap();
+        assert!(vocabulary.tokens.contains_key("caf\u{e9}"));
+        assert!(vocabulary.case_sensitive_tokens.contains_key("Cafe\u{301}"));
+
+        let mut config = build_config();
+        config.unicode_normalization = UnicodeNormalization::Nfc;
+        let artifacts = build(&config);
+        assert_eq!(artifacts.manifest.unicode_normalization, UnicodeNormalization::Nfc);
+        let vocabulary = artifacts.vocabulary.unwrap();
+        assert!(vocabulary.tokens.contains_key("caf\u{e9}"));
+        assert!(vocabulary.case_sensitive_tokens.contains_key("Caf\u{e9}"));
+        assert!(vocabulary.tokens.keys().chain(vocabulary.case_sensitive_tokens.keys()).all(|t| !t.contains('\u{301}')));
+    }
+
+    #[test]
+    fn test_load_document_frequencies() {
+        let dfs = load_document_frequencies("{\"phrase_id\": 7, \"df\": 12}\n\n".as_bytes()).unwrap();
+        assert_eq!(dfs[&7], 12);
+        assert!(load_document_frequencies("{\"phrase_id\": 7}".as_bytes()).is_err());
+    }
+}
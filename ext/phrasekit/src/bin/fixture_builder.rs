@@ -1,20 +1,14 @@
 use daachorse::DoubleArrayAhoCorasick;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::File;
 use std::path::PathBuf;
 
-#[path = "../payload.rs"]
-mod payload;
-
-#[path = "../manifest.rs"]
-mod manifest;
-
-use manifest::Manifest;
-use payload::Payload;
+use phrasekit::manifest::{encode_token_ids, sha256_hex, Manifest, Normalization, TokenEncoding, UnicodeNormalization, AUTOMATON_FORMAT, FORMAT_VERSION, PAYLOAD_FORMAT_VERSION};
+use phrasekit::payload::Payload;
 
 #[derive(Debug, Serialize)]
 struct Vocabulary {
+    version: String,
     tokens: HashMap<String, u32>,
     special_tokens: HashMap<String, u32>,
     vocab_size: usize,
@@ -37,13 +31,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let separator: u32 = 4294967294;
 
     // Pattern 0: [100, 101] - "machine learning"
-    let pattern0 = encode_tokens(&[100, 101], separator);
+    let pattern0 = encode_token_ids(&[100, 101], separator);
 
     // Pattern 1: [200, 101] - "deep learning"
-    let pattern1 = encode_tokens(&[200, 101], separator);
+    let pattern1 = encode_token_ids(&[200, 101], separator);
 
     // Pattern 2: [100, 101, 102] - "machine learning algorithms"
-    let pattern2 = encode_tokens(&[100, 101, 102], separator);
+    let pattern2 = encode_token_ids(&[100, 101, 102], separator);
 
     let patterns = vec![pattern0, pattern1, pattern2];
     let num_patterns = patterns.len();
@@ -63,15 +57,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let payloads = vec![
         Payload::new(100, 2.5, 150, 2),  // "machine learning" - [100, 101]
         Payload::new(200, 2.0, 100, 2),  // "deep learning" - [200, 101]
-        Payload::new(300, 3.0, 200, 3),  // "machine learning algorithms" - [100, 101, 102]
+        Payload::new(300, 3.0, 200, 3).with_category(1),  // "machine learning algorithms" - [100, 101, 102]
     ];
 
     // Write payloads
     let payloads_path = output_dir.join("payloads.bin");
-    let mut payloads_file = File::create(&payloads_path)?;
+    let mut payloads_bytes = Vec::new();
     for payload in &payloads {
-        payload.write_to(&mut payloads_file)?;
+        payload.write_to(&mut payloads_bytes)?;
     }
+    std::fs::write(&payloads_path, &payloads_bytes)?;
     println!("✓ Wrote {} payloads to {}", payloads.len(), payloads_path.display());
 
     // Create manifest
@@ -83,6 +78,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         salience_threshold: Some(1.0),
         built_at: "2025-09-25T00:00:00Z".to_string(),
         separator_id: separator,
+        max_token_id: Some(200),
+        min_pattern_len: payloads.iter().map(|p| p.n as usize).min(),
+        max_pattern_len: payloads.iter().map(|p| p.n as usize).max(),
+        format_version: FORMAT_VERSION,
+        payload_format_version: PAYLOAD_FORMAT_VERSION,
+        vocab: None,
+        negative_rules: Vec::new(),
+        normalization: Normalization::Lowercase,
+        unicode_normalization: UnicodeNormalization::None,
+        encoding: TokenEncoding::U32Sep,
+        automaton_format: Some(AUTOMATON_FORMAT.to_string()),
+        automaton_sha256: Some(sha256_hex(&automaton_bytes)),
+        payloads_sha256: Some(sha256_hex(&payloads_bytes)),
     };
 
     let manifest_path = output_dir.join("manifest.json");
@@ -101,6 +109,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     special_tokens.insert("<UNK>".to_string(), 0);
 
     let vocabulary = Vocabulary {
+        version: "test-v1".to_string(),
         tokens,
         special_tokens,
         vocab_size: 5,
@@ -112,20 +121,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::fs::write(&vocab_path, vocab_json)?;
     println!("✓ Wrote vocabulary to {}", vocab_path.display());
 
+    // Only phrase 300 is categorized
+    let categories_path = output_dir.join("categories.json");
+    std::fs::write(&categories_path, r#"{"method": 1}"#)?;
+    println!("✓ Wrote categories to {}", categories_path.display());
+
     println!("\n✅ Test fixtures generated successfully!");
     println!("\nTest patterns:");
     println!("  Pattern 0: tokens [100, 101] → phrase_id 100 (salience 2.5) - 'machine learning'");
     println!("  Pattern 1: tokens [200, 101] → phrase_id 200 (salience 2.0) - 'deep learning'");
-    println!("  Pattern 2: tokens [100, 101, 102] → phrase_id 300 (salience 3.0) - 'machine learning algorithms'");
+    println!("  Pattern 2: tokens [100, 101, 102] → phrase_id 300 (salience 3.0, category \"method\") - 'machine learning algorithms'");
 
     Ok(())
 }
-
-fn encode_tokens(tokens: &[u32], separator: u32) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    for &token in tokens {
-        bytes.extend_from_slice(&token.to_le_bytes());
-        bytes.extend_from_slice(&separator.to_le_bytes());
-    }
-    bytes
-}
\ No newline at end of file
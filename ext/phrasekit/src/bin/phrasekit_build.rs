@@ -1,326 +1,511 @@
-use daachorse::DoubleArrayAhoCorasick;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::path::{Path, PathBuf};
-
-#[path = "../payload.rs"]
-mod payload;
-
-#[path = "../manifest.rs"]
-mod manifest;
-
-use manifest::Manifest;
-use payload::Payload;
-
-#[derive(Debug, Deserialize)]
-struct PhraseInput {
-    tokens: Vec<String>,
-    phrase_id: u32,
-    salience: f32,
-    #[serde(alias = "domain_count")]
-    count: u32,
-}
+use std::path::PathBuf;
 
-struct ProcessedPhrase {
-    token_ids: Vec<u32>,
-    phrase_id: u32,
-    salience: f32,
-    count: u32,
-    length: u8,
-}
+use phrasekit::config;
+use phrasekit::artifacts::{load_and_validate_phrases, load_merge_base, merge_phrases, prepare_build, BuildConfig, BuildLimits, IdfSource, MergeBase};
+use phrasekit::manifest::{TokenEncoding, UnicodeNormalization};
+use phrasekit::progress::Progress;
 
-#[derive(Debug, Deserialize)]
-struct BuildConfig {
-    version: String,
-    tokenizer: String,
-    separator_id: u32,
-    #[serde(default)]
+/// Config values supplied outside the JSON file (CLI flags or environment).
+#[derive(Debug, Default)]
+struct ConfigOverrides {
+    version: Option<String>,
     min_count: Option<u32>,
-    #[serde(default)]
     salience_threshold: Option<f32>,
+    separator_id: Option<u32>,
+    auto_separator: bool,
+    deterministic: bool,
+}
+
+/// Where the build configuration comes from.
+enum ConfigSource {
+    File(String),
+    /// `--merge`: the manifest and vocabulary of the base build in this directory.
+    Merge(PathBuf),
 }
 
-#[derive(Debug)]
-struct BuildStats {
-    total_input: usize,
-    filtered_low_count: usize,
-    filtered_low_salience: usize,
-    duplicate_phrase_ids: usize,
-    invalid_tokens: usize,
-    built: usize,
+struct CliArgs {
+    input_path: String,
+    config: ConfigSource,
+    output_dir: PathBuf,
+    overrides: ConfigOverrides,
+    /// Also write `vocab.txt` (`token<TAB>id` per line, sorted by id).
+    vocab_txt: bool,
+    limits: BuildLimits,
+    idf: IdfSource,
+    /// `--check`: validate the input and report what would be built, writing nothing.
+    check: bool,
 }
 
-#[derive(Debug, Serialize)]
-struct Vocabulary {
-    tokens: HashMap<String, u32>,
-    special_tokens: HashMap<String, u32>,
-    vocab_size: usize,
-    separator_id: u32,
+fn print_usage() {
+    eprintln!("Usage: phrasekit_build <input.jsonl> <config.json> <output_dir> [options]");
+    eprintln!("       phrasekit_build --merge <base_dir> <new_phrases.jsonl> <output_dir> [options]");
+    eprintln!("\nOptions (override config.json; flags take precedence over env vars):");
+    eprintln!("  --min-count <n>            PHRASEKIT_MIN_COUNT");
+    eprintln!("  --salience-threshold <f>   PHRASEKIT_SALIENCE_THRESHOLD");
+    eprintln!("  --separator-id <id>        PHRASEKIT_SEPARATOR_ID");
+    eprintln!("  --auto-separator           Pick a separator that cannot collide with token IDs");
+    eprintln!("  --deterministic            Zero built_at and sort patterns for byte-identical rebuilds");
+    eprintln!("  --version <v>              Artifact version (default for --merge: the base build's)");
+    eprintln!("  --check                    Validate phrases and vocabulary, then exit without building");
+    eprintln!("\nResource limits (unlimited by default):");
+    eprintln!("  --max-patterns <n>              Abort if more patterns would be built");
+    eprintln!("  --max-total-pattern-bytes <n>   Abort if encoded patterns exceed n bytes");
+    eprintln!("  --max-build-memory-mb <n>       Abort if estimated construction memory exceeds n MB");
+    eprintln!("  --vocab-txt                Also write vocab.txt (token<TAB>id, sorted by id)");
+    eprintln!("\nDocument frequency (stores idf in payloads):");
+    eprintln!("  --df-file <path> --num-docs <n>   Read phrase_id/df pairs from JSONL");
+    eprintln!("  --df-corpus <corpus.jsonl>        Count df by matching a pre-tokenized corpus");
+    eprintln!("\nExample:");
+    eprintln!("  phrasekit_build phrases.jsonl config.json ./artifacts/ --min-count 20");
+    eprintln!("  phrasekit_build --merge ./artifacts/ new_phrases.jsonl ./artifacts-next/ --version v2");
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
 
-    if args.len() < 4 {
-        eprintln!("Usage: phrasekit_build <input.jsonl> <config.json> <output_dir>");
-        eprintln!("\nExample:");
-        eprintln!("  phrasekit_build phrases.jsonl config.json ./artifacts/");
-        std::process::exit(1);
-    }
+    let cli = match parse_args(&args[1..]) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {}\n", e);
+            print_usage();
+            std::process::exit(1);
+        }
+    };
 
-    let input_path = &args[1];
-    let config_path = &args[2];
-    let output_dir = PathBuf::from(&args[3]);
+    let input_path = &cli.input_path;
+    let output_dir = cli.output_dir.clone();
 
     println!("📦 PhraseKit Artifact Builder");
     println!("════════════════════════════════════════");
     println!("Input:  {}", input_path);
-    println!("Config: {}", config_path);
+    match &cli.config {
+        ConfigSource::File(path) => println!("Config: {}", path),
+        ConfigSource::Merge(base_dir) => println!("Base:   {}", base_dir.display()),
+    }
     println!("Output: {}", output_dir.display());
     println!();
 
-    // Load config
-    let config = load_config(config_path)?;
+    // Load config, then apply env and CLI overrides
+    let (mut config, merge_base) = match &cli.config {
+        ConfigSource::File(path) => (load_config(path)?, None),
+        ConfigSource::Merge(base_dir) => {
+            if base_dir == &output_dir {
+                return Err("--merge output directory must differ from the base directory".into());
+            }
+            let MergeBase { config, phrases } = load_merge_base(base_dir)?;
+            println!("✓ Loaded {} base phrases", phrases.len());
+            (config, Some(phrases))
+        }
+    };
+    env_overrides()?.apply_to(&mut config);
+    cli.overrides.apply_to(&mut config);
+    config::validate(&config)?;
     println!("✓ Loaded config: {} (tokenizer: {})", config.version, config.tokenizer);
+    if config.auto_separator {
+        println!("  separator_id:       auto");
+    } else {
+        println!("  separator_id:       {}", config.separator_id);
+    }
+    println!("  min_count:          {}", config.min_count.map_or("none".to_string(), |v| v.to_string()));
+    println!("  salience_threshold: {}", config.salience_threshold.map_or("none".to_string(), |v| v.to_string()));
+    if config.deterministic {
+        println!("  deterministic:      yes");
+    }
+    if config.case_sensitive {
+        println!("  case_sensitive:     yes");
+    }
+    if config.unicode_normalization != UnicodeNormalization::None {
+        println!("  unicode_normalization: {}", config.unicode_normalization.as_str());
+    }
+    if config.encoding != TokenEncoding::U32Sep {
+        println!("  encoding:           {}", config.encoding.as_str());
+    }
 
     // Create output directory
-    std::fs::create_dir_all(&output_dir)?;
+    if !cli.check {
+        std::fs::create_dir_all(&output_dir)?;
+    }
 
     // Load and validate phrases
-    let (text_phrases, stats, unique_tokens) = load_and_validate_phrases(input_path, &config)?;
+    let (mut text_phrases, stats, mut unique_tokens) = load_and_validate_phrases(input_path, &config, &print_progress)?;
+    let merge_stats = match merge_base {
+        Some(base_phrases) => Some(merge_phrases(base_phrases, &mut text_phrases, &mut unique_tokens, &config)?),
+        None => None,
+    };
 
     println!("\n📊 Build Statistics:");
     println!("  Total input phrases:     {}", stats.total_input);
-    if stats.filtered_low_count > 0 {
+    if cli.check || stats.filtered_low_count > 0 {
         println!("  Filtered (low count):    {}", stats.filtered_low_count);
     }
-    if stats.filtered_low_salience > 0 {
+    if cli.check || stats.filtered_low_salience > 0 {
         println!("  Filtered (low salience): {}", stats.filtered_low_salience);
     }
-    if stats.duplicate_phrase_ids > 0 {
+    if cli.check || stats.duplicate_phrase_ids > 0 {
         println!("  Skipped (duplicate IDs): {}", stats.duplicate_phrase_ids);
     }
-    if stats.invalid_tokens > 0 {
+    if cli.check || stats.invalid_tokens > 0 {
         println!("  Skipped (invalid tokens): {}", stats.invalid_tokens);
     }
+    if cli.check || stats.empty_suppressions > 0 {
+        println!("  Skipped (empty suppresses): {}", stats.empty_suppressions);
+    }
+    if cli.check || stats.expansion_limit_exceeded > 0 {
+        println!("  Skipped (too many slot expansions): {}", stats.expansion_limit_exceeded);
+    }
+    if cli.check || stats.negative_phrases > 0 {
+        println!("  Negative phrases:        {}", stats.negative_phrases);
+    }
     println!("  Built patterns:          {}", stats.built);
+    if let Some(merge) = &merge_stats {
+        println!("\n🔀 Merge Statistics:");
+        println!("  Kept from base:          {}", merge.kept);
+        println!("  Added:                   {}", merge.added);
+        println!("  Updated:                 {}", merge.updated);
+        println!("  Removed:                 {}", merge.removed);
+    }
 
-    if text_phrases.is_empty() {
-        return Err("No valid phrases to build".into());
-    }
-
-    // Build vocabulary and assign token IDs
-    println!("\n📚 Building vocabulary...");
-    let vocabulary = build_vocabulary(unique_tokens, config.separator_id);
-    println!("  ✓ Built vocabulary ({} tokens)", vocabulary.vocab_size);
-
-    // Convert text tokens to IDs
-    let mut phrases: Vec<ProcessedPhrase> = Vec::new();
-    for phrase in text_phrases {
-        let token_ids: Vec<u32> = phrase.tokens.iter()
-            .map(|t| *vocabulary.tokens.get(&t.to_lowercase()).unwrap_or(&0))
-            .collect();
-
-        phrases.push(ProcessedPhrase {
-            token_ids,
-            phrase_id: phrase.phrase_id,
-            salience: phrase.salience,
-            count: phrase.count,
-            length: phrase.tokens.len() as u8,
-        });
-    }
-
-    // Build automaton
-    println!("\n🔨 Building automaton...");
-    let patterns: Vec<Vec<u8>> = phrases.iter()
-        .map(|p| encode_tokens(&p.token_ids, config.separator_id))
-        .collect();
-
-    let automaton: DoubleArrayAhoCorasick<u32> = DoubleArrayAhoCorasick::new(patterns)
-        .map_err(|e| format!("Failed to build automaton: {:?}", e))?;
-
-    let automaton_bytes = automaton.serialize();
-    let automaton_path = output_dir.join("phrases.daac");
-    std::fs::write(&automaton_path, &automaton_bytes)?;
-    println!("  ✓ Wrote automaton ({} bytes) to {}", automaton_bytes.len(), automaton_path.display());
-
-    // Write payloads
-    println!("\n💾 Writing payloads...");
-    let payloads: Vec<Payload> = phrases.iter()
-        .map(|p| Payload::new(p.phrase_id, p.salience, p.count, p.length))
-        .collect();
-
-    let payloads_path = output_dir.join("payloads.bin");
-    let mut payloads_file = File::create(&payloads_path)?;
-    for payload in &payloads {
-        payload.write_to(&mut payloads_file)?;
-    }
-    let payloads_size = payloads.len() * 17;
-    println!("  ✓ Wrote {} payloads ({} bytes) to {}", payloads.len(), payloads_size, payloads_path.display());
-
-    // Generate manifest with checksums
-    println!("\n📝 Generating manifest...");
-    let manifest = Manifest {
-        version: config.version.clone(),
-        tokenizer: config.tokenizer.clone(),
-        num_patterns: phrases.len(),
-        min_count: config.min_count,
-        salience_threshold: config.salience_threshold,
-        built_at: chrono::Utc::now().to_rfc3339(),
-        separator_id: config.separator_id,
-    };
-
-    let manifest_path = output_dir.join("manifest.json");
-    let manifest_json = serde_json::to_string_pretty(&manifest)?;
-    std::fs::write(&manifest_path, manifest_json)?;
-    println!("  ✓ Wrote manifest to {}", manifest_path.display());
+    // Zero valid phrases and separator collisions are rejected here
+    let prepared = prepare_build(&text_phrases, unique_tokens, &config, &print_progress)?;
+    if cli.check {
+        println!("\n✅ Check passed");
+        println!("  Patterns:                {}", prepared.num_patterns());
+        match &prepared.vocabulary {
+            Some(vocabulary) => println!("  Vocabulary size:         {}", vocabulary.vocab_size),
+            None => println!("  Vocabulary size:         none (external token IDs)"),
+        }
+        println!("  separator_id:            {}", prepared.separator_id);
+        return Ok(());
+    }
 
-    // Write vocabulary
-    println!("\n💾 Writing vocabulary...");
-    let vocab_path = output_dir.join("vocab.json");
-    let vocab_json = serde_json::to_string_pretty(&vocabulary)?;
-    std::fs::write(&vocab_path, vocab_json)?;
-    println!("  ✓ Wrote vocabulary ({} tokens) to {}", vocabulary.vocab_size, vocab_path.display());
+    let artifacts = prepared.build(&config, &cli.limits, &cli.idf, &print_progress)?;
+    let paths = artifacts.write(&output_dir, input_path, cli.vocab_txt, &print_progress)?;
 
     // Summary
     println!("\n✅ Build complete!");
     println!("\nArtifacts:");
-    println!("  {} ({} bytes)", automaton_path.display(), automaton_bytes.len());
-    println!("  {} ({} bytes)", payloads_path.display(), payloads_size);
-    println!("  {}", manifest_path.display());
-    println!("  {}", vocab_path.display());
+    println!("  {} ({} bytes)", paths.automaton.display(), artifacts.automaton.len());
+    println!("  {} ({} bytes)", paths.payloads.display(), artifacts.payloads.len());
+    println!("  {}", paths.manifest.display());
+    if let Some(path) = &paths.vocab {
+        println!("  {}", path.display());
+    }
+    println!("  {}", paths.patterns.display());
+    println!("  {}", paths.sources.display());
+    if let Some(path) = &paths.vocab_txt {
+        println!("  {}", path.display());
+    }
+    if let Some(path) = &paths.categories {
+        println!("  {}", path.display());
+    }
 
     println!("\n🚀 To use in PhraseKit:");
     println!("  PhraseKit.load!(");
-    println!("    automaton_path: {:?},", automaton_path.to_str().unwrap());
-    println!("    payloads_path: {:?},", payloads_path.to_str().unwrap());
-    println!("    manifest_path: {:?},", manifest_path.to_str().unwrap());
-    println!("    vocab_path: {:?}", vocab_path.to_str().unwrap());
+    let mut load_args = vec![
+        ("automaton_path", &paths.automaton),
+        ("payloads_path", &paths.payloads),
+        ("manifest_path", &paths.manifest),
+    ];
+    if let Some(path) = &paths.vocab {
+        load_args.push(("vocab_path", path));
+    }
+    if let Some(path) = &paths.categories {
+        load_args.push(("categories_path", path));
+    }
+    for (index, (name, path)) in load_args.iter().enumerate() {
+        let comma = if index + 1 < load_args.len() { "," } else { "" };
+        println!("    {}: {:?}{}", name, path.to_str().unwrap(), comma);
+    }
     println!("  )");
 
     Ok(())
 }
 
-fn load_config(path: &str) -> Result<BuildConfig, Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let config: BuildConfig = serde_json::from_reader(file)?;
-    Ok(config)
+/// Prints library progress the way the tool always has: stages set off by a
+/// blank line, warnings on stderr.
+fn print_progress(progress: Progress) {
+    match progress {
+        Progress::Stage(_) => println!("\n{}", progress),
+        Progress::Warning(_) => eprintln!("{}", progress),
+        _ => println!("{}", progress),
+    }
 }
 
-fn load_and_validate_phrases(
-    path: &str,
-    config: &BuildConfig,
-) -> Result<(Vec<PhraseInput>, BuildStats, HashSet<String>), Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let mut phrases = Vec::new();
-    let mut seen_ids = HashSet::new();
-    let mut unique_tokens: HashSet<String> = HashSet::new();
-    let mut stats = BuildStats {
-        total_input: 0,
-        filtered_low_count: 0,
-        filtered_low_salience: 0,
-        duplicate_phrase_ids: 0,
-        invalid_tokens: 0,
-        built: 0,
-    };
+fn load_config(path: &str) -> Result<BuildConfig, config::ConfigError> {
+    config::load(path)
+}
 
-    println!("\n📖 Loading phrases...");
+impl ConfigOverrides {
+    /// Overwrites the fields of `config` that these overrides set.
+    fn apply_to(self, config: &mut BuildConfig) {
+        if let Some(version) = self.version {
+            config.version = version;
+        }
+        if let Some(min_count) = self.min_count {
+            config.min_count = Some(min_count);
+        }
+        if let Some(threshold) = self.salience_threshold {
+            config.salience_threshold = Some(threshold);
+        }
+        if let Some(separator_id) = self.separator_id {
+            config.separator_id = separator_id;
+        }
+        if self.auto_separator {
+            config.auto_separator = true;
+        }
+        if self.deterministic {
+            config.deterministic = true;
+        }
+    }
+}
 
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line?;
-        stats.total_input += 1;
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {:?}", name, value))
+}
 
-        let phrase: PhraseInput = match serde_json::from_str(&line) {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("⚠️  Line {}: Failed to parse: {}", line_num + 1, e);
-                continue;
-            }
-        };
+fn env_overrides() -> Result<ConfigOverrides, String> {
+    overrides_from_env(|name| std::env::var(name).ok())
+}
 
-        // Validate
-        if let Some(min_count) = config.min_count {
-            if phrase.count < min_count {
-                stats.filtered_low_count += 1;
-                continue;
-            }
-        }
+/// Overrides from the variables `var` looks up; empty values count as unset.
+fn overrides_from_env(var: impl Fn(&str) -> Option<String>) -> Result<ConfigOverrides, String> {
+    let read = |name: &str| var(name).filter(|v| !v.is_empty());
+
+    Ok(ConfigOverrides {
+        version: None,
+        min_count: read("PHRASEKIT_MIN_COUNT")
+            .map(|v| parse_value("PHRASEKIT_MIN_COUNT", &v))
+            .transpose()?,
+        salience_threshold: read("PHRASEKIT_SALIENCE_THRESHOLD")
+            .map(|v| parse_value("PHRASEKIT_SALIENCE_THRESHOLD", &v))
+            .transpose()?,
+        separator_id: read("PHRASEKIT_SEPARATOR_ID")
+            .map(|v| parse_value("PHRASEKIT_SEPARATOR_ID", &v))
+            .transpose()?,
+        auto_separator: false,
+        deterministic: false,
+    })
+}
 
-        if let Some(threshold) = config.salience_threshold {
-            if phrase.salience < threshold {
-                stats.filtered_low_salience += 1;
-                continue;
-            }
+fn parse_args(args: &[String]) -> Result<CliArgs, String> {
+    let mut positional = Vec::new();
+    let mut overrides = ConfigOverrides::default();
+    let mut vocab_txt = false;
+    let mut check = false;
+    let mut limits = BuildLimits::default();
+    let mut idf = IdfSource::default();
+    let mut merge_base = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
         }
 
-        if phrase.tokens.is_empty() {
-            eprintln!("⚠️  Line {}: Empty token sequence", line_num + 1);
-            stats.invalid_tokens += 1;
+        if arg == "--vocab-txt" {
+            vocab_txt = true;
             continue;
         }
 
-        for token in &phrase.tokens {
-            if token.is_empty() {
-                eprintln!("⚠️  Line {}: Empty token", line_num + 1);
-                stats.invalid_tokens += 1;
-                continue;
-            }
+        if arg == "--check" {
+            check = true;
+            continue;
         }
 
-        if !seen_ids.insert(phrase.phrase_id) {
-            eprintln!("⚠️  Line {}: Duplicate phrase_id {}", line_num + 1, phrase.phrase_id);
-            stats.duplicate_phrase_ids += 1;
+        if arg == "--auto-separator" {
+            overrides.auto_separator = true;
             continue;
         }
 
-        for token in &phrase.tokens {
-            unique_tokens.insert(token.to_lowercase());
+        if arg == "--deterministic" {
+            overrides.deterministic = true;
+            continue;
         }
 
-        phrases.push(phrase);
-        stats.built += 1;
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| iter.next().cloned())
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
 
-        if stats.total_input % 10000 == 0 {
-            println!("  Processed {} lines...", stats.total_input);
+        match name {
+            "--merge" => merge_base = Some(PathBuf::from(value()?)),
+            "--version" => overrides.version = Some(value()?),
+            "--min-count" => overrides.min_count = Some(parse_value(name, &value()?)?),
+            "--salience-threshold" => overrides.salience_threshold = Some(parse_value(name, &value()?)?),
+            "--separator-id" => overrides.separator_id = Some(parse_value(name, &value()?)?),
+            "--max-patterns" => limits.max_patterns = Some(parse_value(name, &value()?)?),
+            "--max-total-pattern-bytes" => limits.max_total_pattern_bytes = Some(parse_value(name, &value()?)?),
+            "--max-build-memory-mb" => limits.max_build_memory_mb = Some(parse_value(name, &value()?)?),
+            "--df-file" => idf.df_file = Some(value()?),
+            "--df-corpus" => idf.df_corpus = Some(value()?),
+            "--num-docs" => idf.num_docs = Some(parse_value(name, &value()?)?),
+            _ => return Err(format!("Unknown option: {}", name)),
         }
     }
 
-    println!("  ✓ Loaded {} phrases", stats.total_input);
+    if idf.df_file.is_some() && idf.df_corpus.is_some() {
+        return Err("--df-file and --df-corpus are mutually exclusive".to_string());
+    }
+    if idf.df_file.is_some() && idf.num_docs.is_none() {
+        return Err("--df-file requires --num-docs".to_string());
+    }
+
+    // --merge takes the config from the base build instead of a config.json
+    let expected = if merge_base.is_some() { 2 } else { 3 };
+    if positional.len() != expected {
+        return Err(format!("Expected {} positional arguments, got {}", expected, positional.len()));
+    }
 
-    Ok((phrases, stats, unique_tokens))
+    let mut positional = positional.into_iter();
+    let input_path = positional.next().unwrap();
+    let config = match merge_base {
+        Some(base_dir) => ConfigSource::Merge(base_dir),
+        None => ConfigSource::File(positional.next().unwrap()),
+    };
+    Ok(CliArgs {
+        input_path,
+        config,
+        output_dir: PathBuf::from(positional.next().unwrap()),
+        overrides,
+        vocab_txt,
+        limits,
+        idf,
+        check,
+    })
 }
 
-fn encode_tokens(tokens: &[u32], separator: u32) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    for &token in tokens {
-        bytes.extend_from_slice(&token.to_le_bytes());
-        bytes.extend_from_slice(&separator.to_le_bytes());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args_deterministic() {
+        let cli = parse_args(&["in.jsonl", "config.json", "out", "--deterministic"].map(String::from)).unwrap();
+        assert!(cli.overrides.deterministic);
+    }
+
+    #[test]
+    fn test_parse_args_check() {
+        let cli = parse_args(&["--check", "in.jsonl", "config.json", "out"].map(String::from)).unwrap();
+        assert!(cli.check);
+        let cli = parse_args(&["in.jsonl", "config.json", "out"].map(String::from)).unwrap();
+        assert!(!cli.check);
+    }
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn json_config() -> BuildConfig {
+        let json = r#"{"version": "v1", "tokenizer": "external", "separator_id": 7, "min_count": 5, "salience_threshold": 1.5}"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Result<ConfigOverrides, String> {
+        let vars: Vec<(String, String)> = vars.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        overrides_from_env(|name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()))
     }
-    bytes
-}
 
-fn build_vocabulary(unique_tokens: HashSet<String>, separator_id: u32) -> Vocabulary {
-    let mut tokens = HashMap::new();
-    let mut sorted_tokens: Vec<String> = unique_tokens.into_iter().collect();
-    sorted_tokens.sort();
+    #[test]
+    fn test_parse_args_overrides() {
+        let cli = parse_args(&args(&[
+            "in.jsonl",
+            "config.json",
+            "out",
+            "--min-count",
+            "20",
+            "--salience-threshold=2.5",
+            "--separator-id=9",
+            "--version",
+            "v2",
+        ]))
+        .unwrap();
+        assert_eq!(cli.overrides.min_count, Some(20));
+        assert_eq!(cli.overrides.salience_threshold, Some(2.5));
+        assert_eq!(cli.overrides.separator_id, Some(9));
+        assert_eq!(cli.overrides.version.as_deref(), Some("v2"));
+        assert_eq!(cli.input_path, "in.jsonl");
+        assert_eq!(cli.output_dir, PathBuf::from("out"));
+    }
 
-    for (idx, token) in sorted_tokens.iter().enumerate() {
-        tokens.insert(token.clone(), (idx + 1) as u32);
+    #[test]
+    fn test_parse_args_rejects_unknown_and_malformed_flags() {
+        let base = ["in.jsonl", "config.json", "out"];
+        let err = |extra: &[&str]| parse_args(&args(&[&base[..], extra].concat())).err().unwrap();
+
+        assert_eq!(err(&["--min-cnt", "20"]), "Unknown option: --min-cnt");
+        assert_eq!(err(&["--min-cnt=20"]), "Unknown option: --min-cnt");
+        assert_eq!(err(&["--min-count", "twenty"]), r#"Invalid value for --min-count: "twenty""#);
+        assert_eq!(err(&["--min-count=-1"]), r#"Invalid value for --min-count: "-1""#);
+        assert_eq!(err(&["--separator-id=4294967296"]), r#"Invalid value for --separator-id: "4294967296""#);
+        assert_eq!(err(&["--salience-threshold"]), "Missing value for --salience-threshold");
+        assert!(err(&["extra"]).contains("Expected 3 positional arguments"));
     }
 
-    let mut special_tokens = HashMap::new();
-    special_tokens.insert("<UNK>".to_string(), 0);
+    #[test]
+    fn test_env_overrides() {
+        let overrides = env(&[("PHRASEKIT_MIN_COUNT", "10"), ("PHRASEKIT_SALIENCE_THRESHOLD", "0.5"), ("PHRASEKIT_SEPARATOR_ID", "")]).unwrap();
+        assert_eq!(overrides.min_count, Some(10));
+        assert_eq!(overrides.salience_threshold, Some(0.5));
+        // Empty values are treated as unset
+        assert_eq!(overrides.separator_id, None);
+
+        assert_eq!(
+            env(&[("PHRASEKIT_MIN_COUNT", "ten")]).err().unwrap(),
+            r#"Invalid value for PHRASEKIT_MIN_COUNT: "ten""#
+        );
+        assert_eq!(
+            env(&[("PHRASEKIT_SEPARATOR_ID", "-5")]).err().unwrap(),
+            r#"Invalid value for PHRASEKIT_SEPARATOR_ID: "-5""#
+        );
+    }
+
+    #[test]
+    fn test_flags_take_precedence_over_env_over_json() {
+        let merged = |env_vars: &[(&str, &str)], flags: &[&str]| {
+            let mut config = json_config();
+            env(env_vars).unwrap().apply_to(&mut config);
+            let cli = parse_args(&args(&[&["in.jsonl", "config.json", "out"], flags].concat())).unwrap();
+            cli.overrides.apply_to(&mut config);
+            config
+        };
+
+        let config = merged(&[], &[]);
+        assert_eq!((config.min_count, config.salience_threshold, config.separator_id), (Some(5), Some(1.5), 7));
 
-    let vocab_size = tokens.len() + special_tokens.len();
+        let config = merged(&[("PHRASEKIT_MIN_COUNT", "10"), ("PHRASEKIT_SEPARATOR_ID", "8")], &[]);
+        assert_eq!((config.min_count, config.salience_threshold, config.separator_id), (Some(10), Some(1.5), 8));
 
-    Vocabulary {
-        tokens,
-        special_tokens,
-        vocab_size,
-        separator_id,
+        let config = merged(&[("PHRASEKIT_MIN_COUNT", "10"), ("PHRASEKIT_SEPARATOR_ID", "8")], &["--min-count=20"]);
+        assert_eq!((config.min_count, config.salience_threshold, config.separator_id), (Some(20), Some(1.5), 8));
+        assert_eq!(config.version, "v1");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_merged_config_is_validated() {
+        let mut config = json_config();
+        env(&[("PHRASEKIT_SALIENCE_THRESHOLD", "NaN")]).unwrap().apply_to(&mut config);
+        let err = config::validate(&config).err().unwrap();
+        assert!(err.to_string().contains("salience_threshold must be finite"), "{}", err);
+
+        let mut config = json_config();
+        let cli = parse_args(&args(&["in.jsonl", "config.json", "out", "--separator-id", "0"])).unwrap();
+        cli.overrides.apply_to(&mut config);
+        assert!(config::validate(&config).is_err());
+        ConfigOverrides {
+            auto_separator: true,
+            ..ConfigOverrides::default()
+        }
+        .apply_to(&mut config);
+        assert!(config::validate(&config).is_ok());
+    }
+}
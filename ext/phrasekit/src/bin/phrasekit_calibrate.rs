@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use phrasekit::compression;
+
+/// Fitted mapping from raw salience to a calibrated probability in `[0, 1]`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Calibration {
+    /// Non-decreasing step function: salience in `[min_salience, next block's
+    /// min_salience)` maps to `probability`.
+    Isotonic { blocks: Vec<IsotonicBlock> },
+    /// `probability = 1 / (1 + exp(-(slope * salience + intercept)))`.
+    Logistic { slope: f64, intercept: f64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IsotonicBlock {
+    min_salience: f64,
+    max_salience: f64,
+    probability: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CalibrationModel {
+    #[serde(flatten)]
+    calibration: Calibration,
+    /// Labeled examples the model was fitted on.
+    examples: usize,
+    positives: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabeledPhrase {
+    tokens: Vec<String>,
+    label: Value,
+}
+
+struct CliArgs {
+    scored_path: String,
+    labels_path: String,
+    output_path: String,
+    method: String,
+    model_output: PathBuf,
+}
+
+/// Ridge penalty that keeps the logistic fit finite on separable labels.
+const LOGISTIC_L2: f64 = 1e-3;
+const LOGISTIC_ITERATIONS: usize = 50;
+
+fn print_usage() {
+    eprintln!("Usage: phrasekit_calibrate <scored.jsonl> <labels.jsonl> <output.jsonl> [options]");
+    eprintln!("\nOptions:");
+    eprintln!("  --method <isotonic|logistic>   Calibration method (default: isotonic)");
+    eprintln!("  --model-output <path>          Where to write the model (default: calibration.json next to output)");
+    eprintln!("\nExample:");
+    eprintln!("  phrasekit_calibrate phrases.jsonl labels.jsonl calibrated_phrases.jsonl --method logistic");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    let cli = match parse_args(&args[1..]) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {}\n", e);
+            print_usage();
+            std::process::exit(1);
+        }
+    };
+
+    println!("📐 PhraseKit Salience Calibration");
+    println!("════════════════════════════════════════");
+    println!("Scored: {}", cli.scored_path);
+    println!("Labels: {}", cli.labels_path);
+    println!("Output: {}", cli.output_path);
+    println!("Method: {}", cli.method);
+    println!();
+
+    // Load inputs
+    println!("📊 Loading phrases...");
+    let scored = load_scored(&cli.scored_path)?;
+    println!("  ✓ Loaded {} scored phrases", scored.len());
+
+    let labels = load_labels(&cli.labels_path)?;
+    println!("  ✓ Loaded {} labels", labels.len());
+
+    // Pair raw salience with labels
+    let mut examples: Vec<(f64, bool)> = Vec::new();
+    for phrase in &scored {
+        if let (Some(tokens), Some(salience)) = (phrase_key(phrase), phrase["salience"].as_f64()) {
+            if let Some(&label) = labels.get(&tokens) {
+                examples.push((salience, label));
+            }
+        }
+    }
+
+    if examples.is_empty() {
+        return Err("No labeled phrases found in the scored input".into());
+    }
+    let positives = examples.iter().filter(|(_, label)| *label).count();
+    println!("  ✓ Matched {} labeled phrases ({} positive)", examples.len(), positives);
+
+    // Fit
+    println!("\n🔧 Fitting {} calibration...", cli.method);
+    let calibration = match cli.method.as_str() {
+        "isotonic" => fit_isotonic(&examples),
+        "logistic" => fit_logistic(&examples),
+        other => return Err(format!("Invalid method: {}. Must be 'isotonic' or 'logistic'", other).into()),
+    };
+    let model = CalibrationModel {
+        calibration,
+        examples: examples.len(),
+        positives,
+    };
+
+    // Write calibrated phrases
+    println!("\n💾 Writing results...");
+    let mut writer = compression::create_output(&cli.output_path)?;
+    for mut phrase in scored {
+        if let Some(raw) = phrase["salience"].as_f64() {
+            phrase["raw_salience"] = Value::from(raw);
+            phrase["salience"] = Value::from(model.calibration.predict(raw));
+        }
+        writeln!(writer, "{}", serde_json::to_string(&phrase)?)?;
+    }
+    writer.finish()?;
+    println!("  ✓ Wrote calibrated phrases to {}", cli.output_path);
+
+    std::fs::write(&cli.model_output, serde_json::to_string_pretty(&model)?)?;
+    println!("  ✓ Wrote calibration model to {}", cli.model_output.display());
+
+    println!("\n✅ Calibration complete!");
+    println!("\n💡 Next step: Build matching artifacts from {} with phrasekit_build", cli.output_path);
+
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Result<CliArgs, String> {
+    let mut positional = Vec::new();
+    let mut method = "isotonic".to_string();
+    let mut model_output = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let value = inline_value
+            .or_else(|| iter.next().cloned())
+            .ok_or_else(|| format!("Missing value for {}", name))?;
+
+        match name {
+            "--method" => method = value,
+            "--model-output" => model_output = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+    }
+
+    if positional.len() != 3 {
+        return Err(format!("Expected 3 positional arguments, got {}", positional.len()));
+    }
+
+    let mut positional = positional.into_iter();
+    let scored_path = positional.next().unwrap();
+    let labels_path = positional.next().unwrap();
+    let output_path = positional.next().unwrap();
+    let model_output = model_output.unwrap_or_else(|| {
+        PathBuf::from(&output_path)
+            .parent()
+            .map(|dir| dir.join("calibration.json"))
+            .unwrap_or_else(|| PathBuf::from("calibration.json"))
+    });
+
+    Ok(CliArgs {
+        scored_path,
+        labels_path,
+        output_path,
+        method,
+        model_output,
+    })
+}
+
+/// Scored phrases are kept as raw JSON so fields other than `salience` pass through unchanged.
+fn load_scored(path: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let reader = compression::open_input(path)?;
+    let mut phrases = Vec::new();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<Value>(&line) {
+            Ok(phrase) if phrase.is_object() => phrases.push(phrase),
+            Ok(_) => eprintln!("⚠️  Line {}: Expected a JSON object", line_num + 1),
+            Err(e) => eprintln!("⚠️  Line {}: Failed to parse: {}", line_num + 1, e),
+        }
+    }
+
+    Ok(phrases)
+}
+
+fn load_labels(path: &str) -> Result<HashMap<Vec<String>, bool>, Box<dyn std::error::Error>> {
+    let reader = compression::open_input(path)?;
+    let mut labels = HashMap::new();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let labeled: LabeledPhrase = match serde_json::from_str(&line) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("⚠️  Line {}: Failed to parse: {}", line_num + 1, e);
+                continue;
+            }
+        };
+
+        let label = match &labeled.label {
+            Value::Bool(b) => *b,
+            Value::Number(n) if n.as_u64() == Some(0) => false,
+            Value::Number(n) if n.as_u64() == Some(1) => true,
+            other => {
+                eprintln!("⚠️  Line {}: label must be true/false or 0/1, got {}", line_num + 1, other);
+                continue;
+            }
+        };
+
+        let tokens: Vec<String> = labeled.tokens.iter().map(|t| t.to_lowercase()).collect();
+        labels.insert(tokens, label);
+    }
+
+    Ok(labels)
+}
+
+fn phrase_key(phrase: &Value) -> Option<Vec<String>> {
+    phrase["tokens"]
+        .as_array()?
+        .iter()
+        .map(|t| t.as_str().map(|s| s.to_lowercase()))
+        .collect()
+}
+
+/// Pool-adjacent-violators over examples sorted by salience. Equal saliences are
+/// pooled before fitting, so the result does not depend on input order.
+fn fit_isotonic(examples: &[(f64, bool)]) -> Calibration {
+    let mut sorted = examples.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // (min, max, positive weight, total weight)
+    let mut blocks: Vec<(f64, f64, f64, f64)> = Vec::new();
+    for (salience, label) in sorted {
+        let positive = if label { 1.0 } else { 0.0 };
+        match blocks.last_mut() {
+            Some(last) if last.1 == salience => {
+                last.2 += positive;
+                last.3 += 1.0;
+            }
+            _ => blocks.push((salience, salience, positive, 1.0)),
+        }
+
+        while blocks.len() > 1 {
+            let n = blocks.len();
+            let (prev, last) = (blocks[n - 2], blocks[n - 1]);
+            if prev.2 / prev.3 <= last.2 / last.3 {
+                break;
+            }
+            blocks[n - 2] = (prev.0, last.1, prev.2 + last.2, prev.3 + last.3);
+            blocks.pop();
+        }
+    }
+
+    Calibration::Isotonic {
+        blocks: blocks
+            .into_iter()
+            .map(|(min, max, positive, total)| IsotonicBlock {
+                min_salience: min,
+                max_salience: max,
+                probability: positive / total,
+            })
+            .collect(),
+    }
+}
+
+/// L2-regularized logistic regression fitted by Newton's method from a fixed
+/// starting point, so the same examples always give the same coefficients.
+fn fit_logistic(examples: &[(f64, bool)]) -> Calibration {
+    let n = examples.len() as f64;
+    let positives = examples.iter().filter(|(_, label)| *label).count() as f64;
+    let base_rate = ((positives + 0.5) / (n + 1.0)).clamp(1e-6, 1.0 - 1e-6);
+
+    let mut slope = 0.0;
+    let mut intercept = (base_rate / (1.0 - base_rate)).ln();
+
+    for _ in 0..LOGISTIC_ITERATIONS {
+        // Gradient and Hessian of the penalized negative log-likelihood
+        let (mut g_slope, mut g_intercept) = (LOGISTIC_L2 * slope, 0.0);
+        let (mut h_ss, mut h_si, mut h_ii) = (LOGISTIC_L2, 0.0, 0.0);
+
+        for &(x, label) in examples {
+            let p = sigmoid(slope * x + intercept);
+            let residual = p - if label { 1.0 } else { 0.0 };
+            let w = p * (1.0 - p);
+            g_slope += residual * x;
+            g_intercept += residual;
+            h_ss += w * x * x;
+            h_si += w * x;
+            h_ii += w;
+        }
+
+        let det = h_ss * h_ii - h_si * h_si;
+        if det.abs() < 1e-12 {
+            break;
+        }
+
+        let d_slope = (h_ii * g_slope - h_si * g_intercept) / det;
+        let d_intercept = (h_ss * g_intercept - h_si * g_slope) / det;
+        slope -= d_slope;
+        intercept -= d_intercept;
+
+        if d_slope.abs() < 1e-10 && d_intercept.abs() < 1e-10 {
+            break;
+        }
+    }
+
+    Calibration::Logistic { slope, intercept }
+}
+
+fn sigmoid(z: f64) -> f64 {
+    1.0 / (1.0 + (-z).exp())
+}
+
+impl Calibration {
+    fn predict(&self, salience: f64) -> f64 {
+        match self {
+            Calibration::Isotonic { blocks } => {
+                let index = blocks
+                    .partition_point(|block| block.min_salience <= salience)
+                    .saturating_sub(1);
+                blocks.get(index).map_or(0.0, |block| block.probability)
+            }
+            Calibration::Logistic { slope, intercept } => sigmoid(slope * salience + intercept),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isotonic_pools_violators() {
+        let examples = [(1.0, false), (2.0, true), (3.0, false), (4.0, true)];
+
+        let model = fit_isotonic(&examples);
+        match &model {
+            Calibration::Isotonic { blocks } => {
+                let probabilities: Vec<f64> = blocks.iter().map(|b| b.probability).collect();
+                assert_eq!(probabilities, vec![0.0, 0.5, 1.0]);
+            }
+            Calibration::Logistic { .. } => unreachable!(),
+        }
+
+        assert_eq!(model.predict(0.5), 0.0);
+        assert_eq!(model.predict(2.5), 0.5);
+        assert_eq!(model.predict(10.0), 1.0);
+    }
+
+    #[test]
+    fn test_isotonic_is_order_independent() {
+        let forward = [(1.0, true), (1.0, false), (2.0, true)];
+        let reversed = [(2.0, true), (1.0, false), (1.0, true)];
+
+        let a = serde_json::to_string(&fit_isotonic(&forward)).unwrap();
+        let b = serde_json::to_string(&fit_isotonic(&reversed)).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_logistic_is_monotonic_and_bounded() {
+        let examples = [(0.5, false), (1.0, false), (1.5, true), (2.0, false), (3.0, true), (4.0, true)];
+
+        let model = fit_logistic(&examples);
+        let low = model.predict(0.0);
+        let high = model.predict(5.0);
+        assert!(low < 0.5 && high > 0.5);
+        assert!((0.0..=1.0).contains(&low) && (0.0..=1.0).contains(&high));
+
+        let refit = fit_logistic(&examples);
+        assert_eq!(model.predict(2.0), refit.predict(2.0));
+    }
+}
@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use phrasekit::manifest::{decode_pattern_tokens, sha256_hex, Manifest, AUTOMATON_FORMAT, FORMAT_VERSION, PAYLOAD_FORMAT_VERSION};
+use phrasekit::matcher::Matcher;
+use phrasekit::payload::{load_payloads, Payload, PayloadFormat, PAYLOAD_SIZE};
+use phrasekit::policy::MatchPolicy;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("Usage: phrasekit_convert <old_artifacts_dir> <new_artifacts_dir>");
+        eprintln!("\nExample:");
+        eprintln!("  phrasekit_convert ./artifacts-v0/ ./artifacts/");
+        std::process::exit(1);
+    }
+
+    let old_dir = PathBuf::from(&args[1]);
+    let new_dir = PathBuf::from(&args[2]);
+
+    if old_dir == new_dir {
+        return Err("Output directory must differ from the input directory".into());
+    }
+
+    println!("🔄 PhraseKit Artifact Converter");
+    println!("════════════════════════════════════════");
+    println!("From: {}", old_dir.display());
+    println!("To:   {}", new_dir.display());
+    println!();
+
+    let old_format_version = convert(&old_dir, &new_dir)?;
+
+    println!("\n✅ Conversion complete!");
+    println!("  format_version: {} → {}", old_format_version, FORMAT_VERSION);
+
+    Ok(())
+}
+
+/// Rewrites the artifacts in `old_dir` into `new_dir` in the current format
+/// and checks the result loads. Returns the original format_version.
+fn convert(old_dir: &Path, new_dir: &Path) -> Result<u32, Box<dyn std::error::Error>> {
+    // Load legacy artifacts
+    println!("📖 Reading artifacts...");
+    let mut manifest = Manifest::load(old_dir.join("manifest.json"))?;
+    println!("  ✓ Manifest {} (format_version {})", manifest.version, manifest.format_version);
+
+    // The automaton is copied verbatim, so it must already be in the current format
+    if let Some(format) = manifest.automaton_format.as_deref().filter(|&f| f != AUTOMATON_FORMAT) {
+        return Err(format!(
+            "automaton_format {} cannot be converted to {}; rebuild from phrases.jsonl",
+            format, AUTOMATON_FORMAT
+        )
+        .into());
+    }
+
+    let automaton_bytes = std::fs::read(old_dir.join("phrases.daac"))?;
+    println!("  ✓ Automaton ({} bytes)", automaton_bytes.len());
+
+    let payloads = read_payloads(&old_dir.join("payloads.bin"), &manifest)?;
+    if payloads.len() != manifest.num_patterns {
+        return Err(format!(
+            "Payload count mismatch: manifest says {}, got {}",
+            manifest.num_patterns,
+            payloads.len()
+        )
+        .into());
+    }
+    println!("  ✓ {} payloads (payload_format_version {})", payloads.len(), manifest.payload_format_version);
+
+    if manifest.format_version == FORMAT_VERSION {
+        println!("  ℹ️  Already at format_version {}; rewriting anyway", FORMAT_VERSION);
+    }
+
+    // Write current-format artifacts
+    println!("\n💾 Writing artifacts...");
+    std::fs::create_dir_all(new_dir)?;
+
+    let automaton_path = new_dir.join("phrases.daac");
+    std::fs::write(&automaton_path, &automaton_bytes)?;
+    println!("  ✓ Copied automaton to {}", automaton_path.display());
+
+    let payloads_path = new_dir.join("payloads.bin");
+    let mut payloads_bytes = Vec::with_capacity(payloads.len() * PAYLOAD_SIZE);
+    for payload in &payloads {
+        payload.write_to(&mut payloads_bytes)?;
+    }
+    std::fs::write(&payloads_path, &payloads_bytes)?;
+    println!("  ✓ Wrote {} payloads to {}", payloads.len(), payloads_path.display());
+
+    let old_format_version = manifest.format_version;
+    manifest.format_version = FORMAT_VERSION;
+    manifest.payload_format_version = PAYLOAD_FORMAT_VERSION;
+    manifest.min_pattern_len = payloads.iter().map(|p| p.n as usize).min();
+    manifest.max_pattern_len = payloads.iter().map(|p| p.n as usize).max();
+    manifest.automaton_format = Some(AUTOMATON_FORMAT.to_string());
+    manifest.automaton_sha256 = Some(sha256_hex(&automaton_bytes));
+    manifest.payloads_sha256 = Some(sha256_hex(&payloads_bytes));
+    let manifest_path = new_dir.join("manifest.json");
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    println!("  ✓ Wrote manifest to {}", manifest_path.display());
+
+    let old_vocab = old_dir.join("vocab.json");
+    if old_vocab.exists() {
+        let vocab_path = new_dir.join("vocab.json");
+        std::fs::copy(&old_vocab, &vocab_path)?;
+        println!("  ✓ Copied vocabulary to {}", vocab_path.display());
+    }
+
+    for (name, label) in [
+        ("categories.json", "categories"),
+        ("phrases.bin", "pattern token IDs"),
+        ("pattern_sources.json", "pattern sources"),
+    ] {
+        let old_path = old_dir.join(name);
+        if old_path.exists() {
+            let new_path = new_dir.join(name);
+            std::fs::copy(&old_path, &new_path)?;
+            println!("  ✓ Copied {} to {}", label, new_path.display());
+        }
+    }
+
+    // Validate the converted artifact
+    println!("\n🔍 Validating converted artifacts...");
+    verify_converted(new_dir, &automaton_bytes, &payloads)?;
+    println!("  ✓ Manifest, payloads, and automaton verified");
+
+    Ok(old_format_version)
+}
+
+/// Reads a payload table in the layout its manifest records.
+fn read_payloads(path: &Path, manifest: &Manifest) -> Result<Vec<Payload>, Box<dyn std::error::Error>> {
+    let format = PayloadFormat::from_version(manifest.payload_format_version)
+        .ok_or_else(|| format!("Unsupported payload_format_version {}", manifest.payload_format_version))?;
+    let file = File::open(path)?;
+    Ok(load_payloads(BufReader::new(file), format)?)
+}
+
+fn verify_converted(
+    dir: &Path,
+    automaton_bytes: &[u8],
+    original: &[Payload],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = Manifest::load(dir.join("manifest.json"))?;
+    if manifest.format_version != FORMAT_VERSION {
+        return Err(format!("Converted manifest has format_version {}", manifest.format_version).into());
+    }
+
+    if manifest.payload_format_version != PAYLOAD_FORMAT_VERSION {
+        return Err(format!("Converted manifest has payload_format_version {}", manifest.payload_format_version).into());
+    }
+
+    let payloads = read_payloads(&dir.join("payloads.bin"), &manifest)?;
+    if payloads.len() != manifest.num_patterns {
+        return Err(format!(
+            "Converted payload count mismatch: manifest says {}, got {}",
+            manifest.num_patterns,
+            payloads.len()
+        )
+        .into());
+    }
+
+    for (index, (before, after)) in original.iter().zip(&payloads).enumerate() {
+        let unchanged = before.phrase_id == after.phrase_id
+            && before.salience.to_bits() == after.salience.to_bits()
+            && before.count == after.count
+            && before.idf.to_bits() == after.idf.to_bits()
+            && before.n == after.n
+            && before.category_id == after.category_id;
+        if !unchanged {
+            return Err(format!("Payload {} changed during conversion", index).into());
+        }
+    }
+
+    if std::fs::read(dir.join("phrases.daac"))? != automaton_bytes {
+        return Err("Converted automaton differs from the original".into());
+    }
+
+    // Loaded the way a service would, so the checksums and the automaton,
+    // payload, and manifest consistency checks all run
+    let matcher = Matcher::load_checked(dir.join("phrases.daac"), dir.join("payloads.bin"), dir.join("manifest.json"))?;
+    let patterns_path = dir.join("phrases.bin");
+    if patterns_path.exists() {
+        let patterns = decode_pattern_tokens(&std::fs::read(&patterns_path)?)?;
+        if let Some(tokens) = patterns.first() {
+            let matches = matcher.match_tokens(tokens, MatchPolicy::All, usize::MAX);
+            if !matches.iter().any(|m| m.pattern_id == 0 && m.payload.phrase_id == original[0].phrase_id) {
+                return Err("Converted artifacts do not match their first pattern".into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phrasekit::artifacts::{build_artifacts, load_and_validate_phrases, BuildConfig, BuildLimits, IdfSource};
+    use phrasekit::progress::silent;
+    use std::io::Write;
+
+    /// Builds three phrases into `dir`, then rewrites the build as a
+    /// format_version 1 artifact with `format` payloads and no checksums.
+    fn legacy_build(dir: &Path, format: PayloadFormat) {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        for line in [
+            r#"{"token_ids": [1, 2], "phrase_id": 10, "salience": 2.0, "count": 10}"#,
+            r#"{"token_ids": [2, 3], "phrase_id": 20, "salience": 3.5, "count": 4}"#,
+            r#"{"token_ids": [1, 2, 3], "phrase_id": 30, "salience": 1.0, "count": 7}"#,
+        ] {
+            writeln!(input, "{}", line).unwrap();
+        }
+        input.flush().unwrap();
+
+        let config: BuildConfig =
+            serde_json::from_str(r#"{"version": "v1", "tokenizer": "external", "separator_id": 4294967294}"#).unwrap();
+        let (phrases, _, tokens) = load_and_validate_phrases(input.path().to_str().unwrap(), &config, &silent).unwrap();
+        let artifacts =
+            build_artifacts(&phrases, tokens, &config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+        artifacts.write(dir, "phrases.jsonl", false, &silent).unwrap();
+
+        let mut legacy = Vec::new();
+        for payload in read_payloads(&dir.join("payloads.bin"), &artifacts.manifest).unwrap() {
+            payload.write_as(&mut legacy, format).unwrap();
+        }
+        std::fs::write(dir.join("payloads.bin"), legacy).unwrap();
+
+        let mut manifest = artifacts.manifest.clone();
+        manifest.format_version = 1;
+        manifest.payload_format_version = format.version();
+        manifest.min_pattern_len = None;
+        manifest.max_pattern_len = None;
+        manifest.automaton_sha256 = None;
+        manifest.payloads_sha256 = None;
+        std::fs::write(dir.join("manifest.json"), serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+    }
+
+    fn load(dir: &Path) -> Matcher {
+        Matcher::load(dir.join("phrases.daac"), dir.join("payloads.bin"), dir.join("manifest.json")).unwrap()
+    }
+
+    #[test]
+    fn test_convert_legacy_artifacts_round_trip() {
+        for format in [PayloadFormat::V1, PayloadFormat::V2] {
+            let old_dir = tempfile::tempdir().unwrap();
+            let new_dir = tempfile::tempdir().unwrap();
+            legacy_build(old_dir.path(), format);
+
+            assert_eq!(convert(old_dir.path(), new_dir.path()).unwrap(), 1);
+
+            let manifest = Manifest::load(new_dir.path().join("manifest.json")).unwrap();
+            assert_eq!(manifest.format_version, FORMAT_VERSION);
+            assert_eq!(manifest.payload_format_version, PAYLOAD_FORMAT_VERSION);
+            assert_eq!((manifest.min_pattern_len, manifest.max_pattern_len), (Some(2), Some(3)));
+            assert_eq!(
+                std::fs::read(new_dir.path().join("pattern_sources.json")).unwrap(),
+                std::fs::read(old_dir.path().join("pattern_sources.json")).unwrap()
+            );
+
+            let (before, after) = (load(old_dir.path()), load(new_dir.path()));
+            let spans = |matcher: &Matcher, tokens: &[u32], policy: MatchPolicy| -> Vec<(usize, usize, usize, u64, u32, u32)> {
+                matcher
+                    .match_tokens(tokens, policy, usize::MAX)
+                    .iter()
+                    .map(|m| (m.start, m.end, m.pattern_id, m.payload.phrase_id, m.payload.salience.to_bits(), m.payload.count))
+                    .collect()
+            };
+            for tokens in [&[1, 2, 3][..], &[2, 3, 1, 2], &[4, 4]] {
+                for policy in [MatchPolicy::LeftmostLongest, MatchPolicy::SalienceMax, MatchPolicy::All] {
+                    assert_eq!(spans(&after, tokens, policy), spans(&before, tokens, policy));
+                }
+            }
+            assert_eq!(spans(&after, &[1, 2, 3], MatchPolicy::All).len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_verify_converted_rejects_a_checksum_mismatch() {
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+        legacy_build(old_dir.path(), PayloadFormat::V2);
+        convert(old_dir.path(), new_dir.path()).unwrap();
+
+        let manifest_path = new_dir.path().join("manifest.json");
+        let mut manifest = Manifest::load(manifest_path.clone()).unwrap();
+        let automaton = std::fs::read(new_dir.path().join("phrases.daac")).unwrap();
+        let payloads = read_payloads(&new_dir.path().join("payloads.bin"), &manifest).unwrap();
+        manifest.payloads_sha256 = Some("0".repeat(64));
+        std::fs::write(&manifest_path, serde_json::to_string(&manifest).unwrap()).unwrap();
+
+        assert!(verify_converted(new_dir.path(), &automaton, &payloads).is_err());
+    }
+}
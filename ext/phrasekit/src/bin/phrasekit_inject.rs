@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+use phrasekit::compression;
+
+#[derive(Debug, Deserialize)]
+struct TaggedDocument {
+    doc_id: String,
+    #[serde(default)]
+    text: Option<String>,
+    spans: Vec<TaggedSpan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaggedSpan {
+    #[serde(default)]
+    char_start: Option<usize>,
+    #[serde(default)]
+    char_end: Option<usize>,
+    phrase_id: u64,
+    label: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AnnotatedDocument {
+    doc_id: String,
+    text: String,
+}
+
+/// Marker templates; `{label}` and `{phrase_id}` are substituted per span.
+struct Markers {
+    open: String,
+    close: String,
+}
+
+impl Markers {
+    fn render(template: &str, span: &TaggedSpan) -> String {
+        template
+            .replace("{label}", &span.label)
+            .replace("{phrase_id}", &span.phrase_id.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+struct InjectStats {
+    documents: usize,
+    injected: usize,
+    skipped_overlaps: usize,
+}
+
+/// Wraps each span of `text` in markers. Offsets count characters (Unicode
+/// scalar values), not bytes. Spans overlapping an earlier one are skipped and
+/// counted in the returned total.
+fn inject(text: &str, spans: &[TaggedSpan], markers: &Markers) -> Result<(String, usize), String> {
+    // Byte position of every character boundary, including the end of the text
+    let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+    let char_len = boundaries.len() - 1;
+
+    let mut ordered = Vec::with_capacity(spans.len());
+    for span in spans {
+        let (Some(start), Some(end)) = (span.char_start, span.char_end) else {
+            return Err("span has no char_start/char_end; tag with token offsets".to_string());
+        };
+        if start >= end || end > char_len {
+            return Err(format!(
+                "span [{}, {}) is out of bounds for text of {} characters",
+                start, end, char_len
+            ));
+        }
+        ordered.push((start, end, span));
+    }
+    ordered.sort_by_key(|&(start, end, _)| (start, end));
+
+    let mut out = String::with_capacity(text.len() + spans.len() * (markers.open.len() + markers.close.len()));
+    let mut cursor = 0;
+    let mut skipped = 0;
+    for (start, end, span) in ordered {
+        if start < cursor {
+            skipped += 1;
+            continue;
+        }
+        out.push_str(&text[boundaries[cursor]..boundaries[start]]);
+        out.push_str(&Markers::render(&markers.open, span));
+        out.push_str(&text[boundaries[start]..boundaries[end]]);
+        out.push_str(&Markers::render(&markers.close, span));
+        cursor = end;
+    }
+    out.push_str(&text[boundaries[cursor]..]);
+
+    Ok((out, skipped))
+}
+
+fn parse_markers(args: &[String]) -> Result<Markers, String> {
+    let mut markers = Markers {
+        open: "[".to_string(),
+        close: "]".to_string(),
+    };
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--open" => markers.open = iter.next().ok_or("Missing value for --open")?.clone(),
+            "--close" => markers.close = iter.next().ok_or("Missing value for --close")?.clone(),
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    Ok(markers)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("Usage: phrasekit_inject <tagged.jsonl> <output.jsonl> [--open <marker>] [--close <marker>]");
+        eprintln!("\nMarkers may contain {{label}} and {{phrase_id}}. Defaults: \"[\" and \"]\".");
+        eprintln!("\nExample:");
+        eprintln!("  phrasekit_inject tagged.jsonl annotated.jsonl --open '<mark data-id=\"{{phrase_id}}\">' --close '</mark>'");
+        std::process::exit(1);
+    }
+
+    let tagged_path = &args[1];
+    let output_path = &args[2];
+    let markers = parse_markers(&args[3..])?;
+
+    println!("🖍️  PhraseKit Span Injection");
+    println!("════════════════════════════════════════");
+    println!("Tagged:  {}", tagged_path);
+    println!("Output:  {}", output_path);
+    println!();
+
+    let reader = compression::open_input(tagged_path)?;
+    let mut writer = compression::create_output(output_path)?;
+    let mut stats = InjectStats::default();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let doc: TaggedDocument =
+            serde_json::from_str(&line).map_err(|e| format!("Line {}: {}", line_num + 1, e))?;
+        let text = doc
+            .text
+            .as_deref()
+            .ok_or_else(|| format!("Line {}: document {} has no text", line_num + 1, doc.doc_id))?;
+
+        let (annotated, skipped) =
+            inject(text, &doc.spans, &markers).map_err(|e| format!("Line {}: {}", line_num + 1, e))?;
+
+        stats.documents += 1;
+        stats.injected += doc.spans.len() - skipped;
+        stats.skipped_overlaps += skipped;
+
+        let output = AnnotatedDocument { doc_id: doc.doc_id, text: annotated };
+        writeln!(writer, "{}", serde_json::to_string(&output)?)?;
+    }
+
+    writer.finish()?;
+
+    println!("✅ Injection complete!");
+    println!("  Documents:          {}", stats.documents);
+    println!("  Spans injected:     {}", stats.injected);
+    println!("  Overlaps skipped:   {}", stats.skipped_overlaps);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(char_start: usize, char_end: usize, phrase_id: u64) -> TaggedSpan {
+        TaggedSpan {
+            char_start: Some(char_start),
+            char_end: Some(char_end),
+            phrase_id,
+            label: "PHRASE".to_string(),
+        }
+    }
+
+    fn markers(open: &str, close: &str) -> Markers {
+        Markers {
+            open: open.to_string(),
+            close: close.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_inject_multibyte_text() {
+        // "café" and "naïve" contain two-byte characters; offsets count characters
+        let text = "Le café naïve über Straße";
+        let spans = [span(3, 7, 1), span(8, 13, 2), span(19, 25, 3)];
+
+        let (annotated, skipped) = inject(text, &spans, &markers("[", "]")).unwrap();
+        assert_eq!(annotated, "Le [café] [naïve] über [Straße]");
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_inject_templates_and_overlaps() {
+        let text = "machine learning rate";
+        let spans = [span(8, 21, 2), span(0, 16, 1)];
+
+        let (annotated, skipped) =
+            inject(text, &spans, &markers("<m id={phrase_id}>", "</m:{label}>")).unwrap();
+        assert_eq!(annotated, "<m id=1>machine learning</m:PHRASE> rate");
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_inject_rejects_bad_spans() {
+        let m = markers("[", "]");
+        assert!(inject("héllo", &[span(0, 6, 1)], &m).is_err());
+        assert!(inject("héllo", &[span(2, 2, 1)], &m).is_err());
+
+        let no_offsets = TaggedSpan {
+            char_start: None,
+            char_end: None,
+            phrase_id: 1,
+            label: "PHRASE".to_string(),
+        };
+        assert!(inject("héllo", &[no_offsets], &m).is_err());
+    }
+}
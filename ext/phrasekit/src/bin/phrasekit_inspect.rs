@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use phrasekit::artifacts::inspect_phrases;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("Usage: phrasekit_inspect <artifacts_dir> <phrase_id>...");
+        eprintln!("\nPrints the token IDs, token strings (when the build has a vocabulary), and payload");
+        eprintln!("of every pattern each phrase_id was built into.");
+        eprintln!("\nExample:");
+        eprintln!("  phrasekit_inspect ./artifacts/ 12345");
+        std::process::exit(1);
+    }
+
+    let dir = PathBuf::from(&args[1]);
+    let phrase_ids = args[2..]
+        .iter()
+        .map(|arg| arg.parse::<u64>().map_err(|_| format!("Invalid phrase_id: {}", arg)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut missing = Vec::new();
+    for (phrase_id, patterns) in phrase_ids.iter().copied().zip(inspect_phrases(&dir, &phrase_ids)?) {
+        if patterns.is_empty() {
+            missing.push(phrase_id);
+            continue;
+        }
+
+        println!("phrase_id {} ({} pattern{})", phrase_id, patterns.len(), if patterns.len() == 1 { "" } else { "s" });
+        for pattern in &patterns {
+            println!("  pattern_id: {}", pattern.pattern_id);
+            println!("  token_ids:  {:?}", pattern.token_ids);
+            if let Some(tokens) = &pattern.tokens {
+                println!("  tokens:     {}", tokens.join(" "));
+            }
+            println!("  payload:    {}", serde_json::to_string(&pattern.payload)?);
+        }
+    }
+
+    if !missing.is_empty() {
+        let ids: Vec<String> = missing.iter().map(u64::to_string).collect();
+        return Err(format!("No phrase with phrase_id {} in {}", ids.join(", "), dir.display()).into());
+    }
+
+    Ok(())
+}
@@ -1,65 +1,53 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-
-#[derive(Debug, Deserialize)]
-struct Document {
-    tokens: Vec<String>,
-    #[serde(default)]
-    doc_id: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct MineConfig {
-    #[serde(default = "default_min_n")]
-    min_n: usize,
-    #[serde(default = "default_max_n")]
-    max_n: usize,
-    #[serde(default = "default_min_count")]
-    min_count: u32,
-}
-
-fn default_min_n() -> usize {
-    2
-}
-
-fn default_max_n() -> usize {
-    5
-}
-
-fn default_min_count() -> u32 {
-    10
-}
-
-#[derive(Debug, Serialize)]
-struct Ngram {
-    tokens: Vec<String>,
-    count: u32,
-}
+use phrasekit::mining::{mine_ngrams, select_ngrams, write_ngrams, DedupMode, MineConfig, DEFAULT_NEAR_DUP_THRESHOLD};
+use phrasekit::progress::Progress;
+use phrasekit::{config, manifest};
+
+fn parse_dedup(args: &[String]) -> Result<Option<DedupMode>, Box<dyn std::error::Error>> {
+    let mut near = None;
+    let mut threshold = DEFAULT_NEAR_DUP_THRESHOLD;
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        // Only the flags that take a value may consume the next argument
+        let value = || args.get(i + 1).ok_or_else(|| format!("{} requires a value", flag));
+        match flag {
+            "--dedup" => {
+                near = match value()?.as_str() {
+                    "exact" => Some(false),
+                    "near" => Some(true),
+                    other => return Err(format!("Unknown --dedup mode: {} (expected exact or near)", other).into()),
+                }
+            }
+            "--dedup-threshold" => {
+                let value = value()?;
+                threshold = value.parse().map_err(|_| format!("Invalid --dedup-threshold: {}", value))?;
+                if !(0.0..=1.0).contains(&threshold) {
+                    return Err("--dedup-threshold must be between 0 and 1".into());
+                }
+            }
+            other => return Err(format!("Unknown option: {}", other).into()),
+        }
+        i += 2;
+    }
 
-#[derive(Debug)]
-struct MiningStats {
-    total_docs: usize,
-    total_tokens: usize,
-    total_ngrams_extracted: usize,
-    unique_ngrams: usize,
-    ngrams_after_filter: usize,
+    Ok(near.map(|near| if near { DedupMode::Near { threshold } } else { DedupMode::Exact }))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() < 4 {
-        eprintln!("Usage: phrasekit_mine <corpus.jsonl> <config.json> <output.jsonl>");
+        eprintln!("Usage: phrasekit_mine <corpus.jsonl> <config.json> <output.jsonl> [--dedup exact|near] [--dedup-threshold <f>]");
         eprintln!("\nExample:");
         eprintln!("  phrasekit_mine corpus.jsonl mine_config.json candidate_phrases.jsonl");
+        eprintln!("  phrasekit_mine corpus.jsonl mine_config.json candidate_phrases.jsonl --dedup near");
         std::process::exit(1);
     }
 
     let corpus_path = &args[1];
     let config_path = &args[2];
     let output_path = &args[3];
+    let dedup = parse_dedup(&args[4..])?;
 
     println!("🔍 PhraseKit N-gram Miner");
     println!("════════════════════════════════════════");
@@ -74,126 +62,125 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("  min_n: {}", config.min_n);
     println!("  max_n: {}", config.max_n);
     println!("  min_count: {}", config.min_count);
-
-    if config.min_n < 1 || config.max_n > 10 || config.min_n > config.max_n {
-        return Err("Invalid config: min_n must be >= 1, max_n must be <= 10, and min_n <= max_n".into());
+    if let Some(max_count) = config.max_count {
+        println!("  max_count: {}", max_count);
+    }
+    if let Some(top_k) = config.top_k {
+        println!("  top_k: {}", top_k);
+    }
+    if config.max_skip > 0 {
+        println!("  max_skip: {}", config.max_skip);
+    }
+    if let Some(path) = &config.stopwords_path {
+        println!("  stopwords_path: {}", path);
+    }
+    if config.threads > 0 {
+        println!("  threads: {}", config.threads);
+    }
+    if config.sketch {
+        println!("  sketch: {} x {}", config.sketch_depth, config.sketch_width);
+    }
+    if config.case_sensitive {
+        println!("  case_sensitive: yes");
+    }
+    if config.unicode_normalization != manifest::UnicodeNormalization::None {
+        println!("  unicode_normalization: {}", config.unicode_normalization.as_str());
+    }
+    match dedup {
+        Some(DedupMode::Exact) => println!("  dedup: exact"),
+        Some(DedupMode::Near { threshold }) => println!("  dedup: near (threshold {})", threshold),
+        None => {}
     }
 
     // Mine n-grams
     println!("\n📊 Mining n-grams...");
-    let (ngram_counts, mut stats) = mine_ngrams(corpus_path, &config)?;
+    let (ngram_counts, mut stats) = mine_ngrams(corpus_path, &config, dedup, &print_progress)?;
 
     // Write results
     println!("\n💾 Writing results...");
-    stats.ngrams_after_filter = write_ngrams(output_path, ngram_counts, config.min_count)?;
+    let ngrams = select_ngrams(ngram_counts, &config, &mut stats);
+    let written = write_ngrams(output_path, ngrams)?;
+    println!("  ✓ Wrote {} n-grams to {}", written, output_path);
 
     // Summary
     println!("\n✅ Mining complete!");
     println!("\n📈 Statistics:");
     println!("  Total documents:     {}", stats.total_docs);
+    if dedup.is_some() {
+        println!("  Duplicates dropped:  {}", stats.duplicate_docs);
+    }
     println!("  Total tokens:        {}", stats.total_tokens);
     println!("  N-grams extracted:   {}", stats.total_ngrams_extracted);
-    println!("  Unique n-grams:      {}", stats.unique_ngrams);
+    if config.max_skip > 0 {
+        println!(
+            "  Skip-grams extracted: {} ({:.1}x the contiguous n-grams)",
+            stats.skip_grams_extracted,
+            stats.skip_grams_extracted as f64 / stats.total_ngrams_extracted.max(1) as f64
+        );
+    }
+    if config.stopwords_path.is_some() {
+        println!("  Boundary stopwords:  {}", stats.filtered_boundary_stopword);
+    }
+    if config.sketch {
+        println!("  Sketch candidates:   {}", stats.unique_ngrams);
+    } else {
+        println!("  Unique n-grams:      {}", stats.unique_ngrams);
+    }
+    if let Some(max_count) = config.max_count {
+        println!("  Over max_count={}:  {}", max_count, stats.filtered_max_count);
+    }
+    if let Some(top_k) = config.top_k {
+        println!("  Beyond top_k={}:  {}", top_k, stats.filtered_top_k);
+    }
     println!("  After min_count={}:  {}", config.min_count, stats.ngrams_after_filter);
     println!("\n💡 Next step: Run salience scoring on {}", output_path);
 
     Ok(())
 }
 
-fn load_config(path: &str) -> Result<MineConfig, Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let config: MineConfig = serde_json::from_reader(file)?;
-    Ok(config)
-}
-
-fn mine_ngrams(
-    corpus_path: &str,
-    config: &MineConfig,
-) -> Result<(HashMap<Vec<String>, u32>, MiningStats), Box<dyn std::error::Error>> {
-    let file = File::open(corpus_path)?;
-    let reader = BufReader::new(file);
-
-    let mut ngram_counts: HashMap<Vec<String>, u32> = HashMap::new();
-    let mut stats = MiningStats {
-        total_docs: 0,
-        total_tokens: 0,
-        total_ngrams_extracted: 0,
-        unique_ngrams: 0,
-        ngrams_after_filter: 0,
-    };
-
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line?;
-
-        if line.trim().is_empty() {
-            continue;
-        }
-
-        let doc: Document = match serde_json::from_str(&line) {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("⚠️  Line {}: Failed to parse: {}", line_num + 1, e);
-                continue;
-            }
-        };
-
-        stats.total_docs += 1;
-        stats.total_tokens += doc.tokens.len();
-
-        // Extract n-grams from document
-        for n in config.min_n..=config.max_n {
-            if doc.tokens.len() < n {
-                continue;
-            }
-
-            for i in 0..=(doc.tokens.len() - n) {
-                let ngram: Vec<String> = doc.tokens[i..i + n]
-                    .iter()
-                    .map(|t| t.to_lowercase())
-                    .collect();
-
-                *ngram_counts.entry(ngram).or_insert(0) += 1;
-                stats.total_ngrams_extracted += 1;
-            }
-        }
-
-        if stats.total_docs % 10000 == 0 {
-            println!("  Processed {} documents...", stats.total_docs);
-        }
+/// Prints library progress the way the tool always has: stages set off by a
+/// blank line, warnings on stderr.
+fn print_progress(progress: Progress) {
+    match progress {
+        Progress::Stage(_) => println!("\n{}", progress),
+        Progress::Warning(_) => eprintln!("{}", progress),
+        _ => println!("{}", progress),
     }
-
-    stats.unique_ngrams = ngram_counts.len();
-    println!("  ✓ Processed {} documents", stats.total_docs);
-    println!("  ✓ Extracted {} unique n-grams", stats.unique_ngrams);
-
-    Ok((ngram_counts, stats))
 }
 
-fn write_ngrams(
-    output_path: &str,
-    ngram_counts: HashMap<Vec<String>, u32>,
-    min_count: u32,
-) -> Result<usize, Box<dyn std::error::Error>> {
-    let file = File::create(output_path)?;
-    let mut writer = BufWriter::new(file);
-
-    // Sort by count (descending) for better readability
-    let mut ngrams: Vec<(Vec<String>, u32)> = ngram_counts
-        .into_iter()
-        .filter(|(_, count)| *count >= min_count)
-        .collect();
-
-    ngrams.sort_by(|a, b| b.1.cmp(&a.1));
+fn load_config(path: &str) -> Result<MineConfig, config::ConfigError> {
+    let config: MineConfig = config::load(path)?;
+    config::validate(&config)?;
+    Ok(config)
+}
 
-    let count = ngrams.len();
-    for (tokens, count) in ngrams {
-        let ngram = Ngram { tokens, count };
-        let json = serde_json::to_string(&ngram)?;
-        writeln!(writer, "{}", json)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dedup() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(parse_dedup(&[]).unwrap(), None);
+        assert_eq!(parse_dedup(&args(&["--dedup", "exact"])).unwrap(), Some(DedupMode::Exact));
+        assert_eq!(
+            parse_dedup(&args(&["--dedup", "near", "--dedup-threshold", "0.9"])).unwrap(),
+            Some(DedupMode::Near { threshold: 0.9 })
+        );
+        assert!(parse_dedup(&args(&["--dedup", "fuzzy"])).is_err());
+        assert!(parse_dedup(&args(&["--dedup"])).is_err());
     }
 
-    writer.flush()?;
-    println!("  ✓ Wrote {} n-grams to {}", count, output_path);
+    #[test]
+    fn test_parse_dedup_rejects_unknown_flag_without_consuming_a_value() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
 
-    Ok(count)
-}
\ No newline at end of file
+        let err = parse_dedup(&args(&["--dedup", "exact", "--verbose"])).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown option: --verbose");
+        let err = parse_dedup(&args(&["--verbose", "--dedup", "exact"])).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown option: --verbose");
+        let err = parse_dedup(&args(&["--dedup", "near", "--dedup-threshold"])).unwrap_err();
+        assert_eq!(err.to_string(), "--dedup-threshold requires a value");
+    }
+}
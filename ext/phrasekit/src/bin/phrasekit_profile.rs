@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+use phrasekit::compression;
+use phrasekit::manifest::{Normalization, UnicodeNormalization};
+use phrasekit::matcher::{Matcher, ProfiledMatch};
+use phrasekit::policy::MatchPolicy;
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    tokens: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Vocabulary {
+    tokens: HashMap<String, u32>,
+    special_tokens: HashMap<String, u32>,
+    #[serde(default)]
+    case_sensitive_tokens: HashMap<String, u32>,
+}
+
+impl Vocabulary {
+    /// Same lookup as `phrasekit_tag`: after `unicode`, exact-case forms
+    /// first, then normalized as the build was, then `<UNK>`.
+    fn encode(&self, tokens: &[String], normalization: Normalization, unicode: UnicodeNormalization) -> Vec<u32> {
+        let unk_id = self.special_tokens.get("<UNK>").copied().unwrap_or(0);
+        tokens
+            .iter()
+            .map(|token| {
+                let token = unicode.apply(token);
+                self.case_sensitive_tokens
+                    .get(token.as_ref())
+                    .or_else(|| self.tokens.get(normalization.apply(&token).as_ref()))
+                    .copied()
+                    .unwrap_or(unk_id)
+            })
+            .collect()
+    }
+}
+
+struct ProfileOptions {
+    policy: MatchPolicy,
+    max: usize,
+    top: usize,
+    output: Option<String>,
+}
+
+/// Candidate and kept counts plus the match time attributed to them.
+#[derive(Debug, Default, Clone, Serialize)]
+struct Cost {
+    /// Automaton hits that reached overlap resolution.
+    candidates: u64,
+    /// Hits that survived resolution and `max`.
+    kept: u64,
+    /// Share of document match time, split evenly across the document's
+    /// candidates. An estimate: automaton cost is not strictly per pattern.
+    attributed_us: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct PhraseCost {
+    phrase_id: u64,
+    n: u8,
+    #[serde(flatten)]
+    cost: Cost,
+}
+
+#[derive(Debug, Serialize)]
+struct ProfileReport {
+    documents: u64,
+    tokens: u64,
+    candidate_generation_us: f64,
+    resolution_us: f64,
+    /// Keyed by phrase length in tokens.
+    by_length: BTreeMap<u8, Cost>,
+    /// Most frequent candidates first; ties by phrase_id.
+    top_phrases: Vec<PhraseCost>,
+}
+
+#[derive(Debug, Default)]
+struct Profile {
+    documents: u64,
+    tokens: u64,
+    candidate_time: Duration,
+    resolution_time: Duration,
+    by_length: BTreeMap<u8, Cost>,
+    by_phrase: HashMap<u64, (u8, Cost)>,
+}
+
+impl Profile {
+    fn record(&mut self, token_count: usize, profiled: &ProfiledMatch) {
+        self.documents += 1;
+        self.tokens += token_count as u64;
+        self.candidate_time += profiled.candidate_time;
+        self.resolution_time += profiled.resolution_time;
+
+        if profiled.candidates.is_empty() {
+            return;
+        }
+        let elapsed_us = (profiled.candidate_time + profiled.resolution_time).as_secs_f64() * 1e6;
+        let share = elapsed_us / profiled.candidates.len() as f64;
+
+        for m in &profiled.candidates {
+            let n = m.payload.n;
+            for cost in [
+                self.by_length.entry(n).or_default(),
+                &mut self.by_phrase.entry(m.payload.phrase_id).or_insert((n, Cost::default())).1,
+            ] {
+                cost.candidates += 1;
+                cost.attributed_us += share;
+            }
+        }
+        for m in &profiled.resolution.matches {
+            self.by_length.entry(m.payload.n).or_default().kept += 1;
+            if let Some((_, cost)) = self.by_phrase.get_mut(&m.payload.phrase_id) {
+                cost.kept += 1;
+            }
+        }
+    }
+
+    fn report(&self, top: usize) -> ProfileReport {
+        let mut phrases: Vec<PhraseCost> = self
+            .by_phrase
+            .iter()
+            .map(|(&phrase_id, (n, cost))| PhraseCost {
+                phrase_id,
+                n: *n,
+                cost: cost.clone(),
+            })
+            .collect();
+        phrases.sort_by(|a, b| b.cost.candidates.cmp(&a.cost.candidates).then(a.phrase_id.cmp(&b.phrase_id)));
+        phrases.truncate(top);
+
+        ProfileReport {
+            documents: self.documents,
+            tokens: self.tokens,
+            candidate_generation_us: self.candidate_time.as_secs_f64() * 1e6,
+            resolution_us: self.resolution_time.as_secs_f64() * 1e6,
+            by_length: self.by_length.clone(),
+            top_phrases: phrases,
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<ProfileOptions, String> {
+    let mut options = ProfileOptions {
+        policy: MatchPolicy::LeftmostLongest,
+        max: 100,
+        top: 20,
+        output: None,
+    };
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--policy" => {
+                let value = iter.next().ok_or("Missing value for --policy")?;
+                options.policy = MatchPolicy::from_str(value).ok_or_else(|| format!("Invalid policy: {}", value))?;
+            }
+            "--max" => {
+                let value = iter.next().ok_or("Missing value for --max")?;
+                options.max = value.parse().map_err(|_| format!("Invalid value for --max: {:?}", value))?;
+            }
+            "--top" => {
+                let value = iter.next().ok_or("Missing value for --top")?;
+                options.top = value.parse().map_err(|_| format!("Invalid value for --top: {:?}", value))?;
+            }
+            "--output" => options.output = Some(iter.next().ok_or("Missing value for --output")?.clone()),
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("Usage: phrasekit_profile <artifact_dir> <corpus.jsonl> [options]");
+        eprintln!("\nOptions:");
+        eprintln!("  --policy <policy>  - Overlap policy (default leftmost_longest)");
+        eprintln!("  --max <n>          - Matches kept per document (default 100)");
+        eprintln!("  --top <n>          - Phrases listed in the report (default 20)");
+        eprintln!("  --output <path>    - Also write the report as JSON");
+        std::process::exit(1);
+    }
+
+    let artifact_dir = Path::new(&args[1]);
+    let corpus_path = &args[2];
+    let options = parse_options(&args[3..])?;
+
+    println!("⏱️  PhraseKit Match Profile");
+    println!("════════════════════════════════════════");
+    println!("Artifacts: {}", artifact_dir.display());
+    println!("Corpus:    {}", corpus_path);
+    println!();
+
+    let matcher = Matcher::load(
+        artifact_dir.join("phrases.daac"),
+        artifact_dir.join("payloads.bin"),
+        artifact_dir.join("manifest.json"),
+    )?;
+    let vocab: Vocabulary = serde_json::from_reader(BufReader::new(File::open(artifact_dir.join("vocab.json"))?))?;
+
+    let mut profile = Profile::default();
+    for (line_num, line) in compression::open_input(corpus_path)?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let doc: Document = serde_json::from_str(&line).map_err(|e| format!("Line {}: {}", line_num + 1, e))?;
+        let token_ids = vocab.encode(&doc.tokens, matcher.manifest().normalization, matcher.manifest().unicode_normalization);
+        profile.record(token_ids.len(), &matcher.match_tokens_profiled(&token_ids, options.policy, options.max));
+    }
+
+    let report = profile.report(options.top);
+
+    println!("📈 Profile:");
+    println!("  Documents:              {}", report.documents);
+    println!("  Tokens:                 {}", report.tokens);
+    println!("  Candidate generation:   {:.0} µs", report.candidate_generation_us);
+    println!("  Resolution:             {:.0} µs", report.resolution_us);
+    println!();
+    println!("  {:>6} {:>12} {:>12} {:>14}", "length", "candidates", "kept", "attributed µs");
+    for (n, cost) in &report.by_length {
+        println!("  {:>6} {:>12} {:>12} {:>14.0}", n, cost.candidates, cost.kept, cost.attributed_us);
+    }
+    if !report.top_phrases.is_empty() {
+        println!();
+        println!("  {:>10} {:>6} {:>12} {:>12} {:>14}", "phrase_id", "length", "candidates", "kept", "attributed µs");
+        for phrase in &report.top_phrases {
+            let cost = &phrase.cost;
+            println!(
+                "  {:>10} {:>6} {:>12} {:>12} {:>14.0}",
+                phrase.phrase_id, phrase.n, cost.candidates, cost.kept, cost.attributed_us
+            );
+        }
+    }
+
+    if let Some(path) = &options.output {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        println!();
+        println!("  ✓ Wrote profile report to {}", path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phrasekit::payload::Payload;
+    use phrasekit::policy::{Match, Resolution};
+
+    fn hit(start: usize, end: usize, phrase_id: u64) -> Match {
+        Match::new(start, end, 0, Payload::new(phrase_id, 1.0, 10, (end - start) as u8))
+    }
+
+    #[test]
+    fn test_profile_attributes_time_to_candidates() {
+        let mut profile = Profile::default();
+        let profiled = ProfiledMatch {
+            candidates: vec![hit(0, 2, 7), hit(1, 4, 9), hit(3, 5, 7)],
+            resolution: Resolution {
+                matches: vec![hit(0, 2, 7), hit(3, 5, 7)],
+                degraded: false,
+            },
+            candidate_time: Duration::from_micros(20),
+            resolution_time: Duration::from_micros(10),
+        };
+        profile.record(5, &profiled);
+        profile.record(0, &ProfiledMatch {
+            candidates: Vec::new(),
+            resolution: Resolution {
+                matches: Vec::new(),
+                degraded: false,
+            },
+            candidate_time: Duration::from_micros(1),
+            resolution_time: Duration::ZERO,
+        });
+
+        let report = profile.report(1);
+        assert_eq!((report.documents, report.tokens), (2, 5));
+        assert!((report.candidate_generation_us - 21.0).abs() < 1e-6);
+
+        let two = &report.by_length[&2];
+        assert_eq!((two.candidates, two.kept), (2, 2));
+        assert!((two.attributed_us - 20.0).abs() < 1e-6);
+        assert_eq!(report.by_length[&3].kept, 0);
+
+        assert_eq!(report.top_phrases.len(), 1);
+        assert_eq!(report.top_phrases[0].phrase_id, 7);
+        assert_eq!(report.top_phrases[0].cost.candidates, 2);
+    }
+}
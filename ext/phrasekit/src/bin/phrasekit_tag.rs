@@ -1,28 +1,79 @@
+use daachorse::DoubleArrayAhoCorasick;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
 use std::process;
+use std::sync::{mpsc, Mutex};
 
-#[path = "../payload.rs"]
-mod payload;
-
-use payload::Payload;
+use phrasekit::config::Validate;
+use phrasekit::manifest::{NegativeRule, Normalization, TokenEncoding, UnicodeNormalization};
+use phrasekit::payload::{Payload, PayloadFormat};
+use phrasekit::{compression, config, payload};
+use phrasekit::policy::{apply_negative_rules, resolve_overlaps_bounded, Match, MatchPolicy, DEFAULT_MAX_CLUSTER};
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct TagConfig {
     automaton_path: String,
     payloads_path: String,
     manifest_path: String,
     vocab_path: String,
+    /// categories.json from the build; spans of categorized phrases are
+    /// labelled with their category instead of `label`.
+    #[serde(default)]
+    categories_path: Option<String>,
     #[serde(default = "default_policy")]
     policy: String,
     #[serde(default = "default_max_spans")]
     max_spans: usize,
     #[serde(default = "default_label")]
     label: String,
+    #[serde(default = "default_threads")]
+    threads: usize,
+    #[serde(default = "default_read_ahead")]
+    read_ahead: usize,
+    /// Report span `end` as the last token index instead of the exclusive boundary.
+    #[serde(default)]
+    inclusive_end: bool,
+    /// Documents with more tokens than this are not matched; unlimited when unset.
+    #[serde(default)]
+    max_doc_tokens: Option<usize>,
+    /// Drop oversized documents from the output instead of writing them with no spans.
+    #[serde(default)]
+    omit_oversized: bool,
+    /// Add each span's tokens, space-joined in their original case, as `text`.
+    #[serde(default)]
+    emit_text: bool,
+    /// `spans`, or `bio` to also write per-token BIO tags.
+    #[serde(default = "default_output_format")]
+    output_format: String,
+    /// What out-of-vocabulary tokens encode as: `unk`, `skip`, or `error`.
+    #[serde(default = "default_unk_mode")]
+    unk_mode: String,
+    /// Whether the artifacts are expected to be case-sensitive. Tokens are
+    /// always encoded per the manifest's `normalization`; when set, tagging
+    /// fails if that disagrees.
+    #[serde(default)]
+    case_sensitive: Option<bool>,
+}
+
+impl Validate for TagConfig {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        match parse_policy(&self.policy) {
+            Ok(policy) => problems.extend(parse_output_format(&self.output_format, policy).err()),
+            Err(problem) => {
+                problems.push(problem);
+                // Without a policy only the format name itself can be checked
+                problems.extend(parse_output_format(&self.output_format, MatchPolicy::LeftmostLongest).err());
+            }
+        }
+        problems.extend(parse_unk_mode(&self.unk_mode).err());
+        problems
+    }
 }
 
 fn default_policy() -> String {
@@ -37,31 +88,153 @@ fn default_label() -> String {
     "PHRASE".to_string()
 }
 
+fn default_threads() -> usize {
+    1
+}
+
+fn default_read_ahead() -> usize {
+    1024
+}
+
+fn default_output_format() -> String {
+    "spans".to_string()
+}
+
+fn default_unk_mode() -> String {
+    "unk".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnkMode {
+    /// Encode as `<UNK>`, which phrases containing `<UNK>` can match.
+    Unk,
+    /// Encode as the separator, which no pattern contains, so no phrase
+    /// matches across the token.
+    Skip,
+    /// Fail the run on the first out-of-vocabulary token.
+    Error,
+}
+
+impl UnkMode {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "unk" => Some(Self::Unk),
+            "skip" => Some(Self::Skip),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Spans,
+    /// Spans plus `tags` and `tag_ids` aligned to `tokens`.
+    Bio,
+}
+
+impl OutputFormat {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "spans" => Some(Self::Spans),
+            "bio" => Some(Self::Bio),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct InputDocument {
     doc_id: String,
     tokens: Vec<String>,
+    /// Original text, passed through to the output for `phrasekit_inject`.
+    #[serde(default)]
+    text: Option<String>,
+    /// Per-token `[char_start, char_end)` offsets into `text`, in characters.
+    #[serde(default, alias = "token_offsets")]
+    offsets: Option<Vec<(usize, usize)>>,
 }
 
 #[derive(Debug, Serialize)]
 struct OutputDocument {
     doc_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
     tokens: Vec<String>,
     spans: Vec<Span>,
+    /// `B-{label}`, `I-{label}` or `O` per token; only with `output_format: "bio"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+    /// The phrase_id on each span's `B-` token, null elsewhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag_ids: Option<Vec<Option<u64>>>,
 }
 
 #[derive(Debug, Serialize)]
 struct Span {
     start: usize,
     end: usize,
-    phrase_id: u32,
+    /// Character offsets of the span, present when the input had `offsets`;
+    /// `char_end` is always exclusive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    char_start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    char_end: Option<usize>,
+    phrase_id: u64,
     label: String,
+    salience: f32,
+    count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Vocabulary {
+    /// Build version; absent in vocabularies written before it was recorded.
+    #[serde(default)]
+    version: Option<String>,
     tokens: HashMap<String, u32>,
     special_tokens: HashMap<String, u32>,
+    #[serde(default)]
+    case_sensitive_tokens: HashMap<String, u32>,
+    /// The manifest's, set once it is loaded.
+    #[serde(skip)]
+    normalization: Normalization,
+    #[serde(skip)]
+    unicode_normalization: UnicodeNormalization,
+}
+
+impl Vocabulary {
+    /// After the build's Unicode normalization, exact-case forms from
+    /// case-sensitive slots win; everything else is looked up normalized as
+    /// the build was.
+    fn lookup(&self, token: &str) -> Option<u32> {
+        let token = self.unicode_normalization.apply(token);
+        self.case_sensitive_tokens
+            .get(token.as_ref())
+            .or_else(|| self.tokens.get(self.normalization.apply(&token).as_ref()))
+            .copied()
+    }
+
+    /// The form `lookup` lands on in `tokens`.
+    fn normalized(&self, token: &str) -> String {
+        self.normalization.apply(&self.unicode_normalization.apply(token)).into_owned()
+    }
+
+    /// `encode_tokens` only emits vocabulary IDs (and, for `unk_mode: "skip"`,
+    /// the separator itself), so checking them once at load
+    /// keeps a vocab.json from another build from encoding a token as the
+    /// manifest's separator and producing phantom matches.
+    fn check_separator(&self, separator_id: u32) -> Result<(), String> {
+        let entries = self.special_tokens.iter().chain(&self.tokens).chain(&self.case_sensitive_tokens);
+        match entries.filter(|(_, &id)| id == separator_id).min() {
+            Some((token, _)) => Err(format!(
+                "vocab.json token {:?} has ID {}, the manifest's separator_id; the vocabulary and manifest are from different builds",
+                token, separator_id
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -69,24 +242,416 @@ struct TaggingStats {
     documents: usize,
     total_spans: usize,
     docs_with_spans: usize,
+    skipped_oversized: usize,
+    oov: Option<OovStats>,
+}
+
+/// Command-line options that are not part of the tagging config.
+struct TagOptions {
+    oov_report: bool,
+    oov_output: Option<String>,
+    oov_top: usize,
+    /// Flush the output every this many documents; `None` keeps the writer
+    /// fully buffered.
+    flush_every: Option<usize>,
+    /// Warn instead of failing when vocab.json and manifest.json versions differ.
+    allow_version_mismatch: bool,
+}
+
+/// Checks the config's `case_sensitive` expectation, if any, against the
+/// normalization the artifacts were built with.
+fn check_normalization(expected_case_sensitive: Option<bool>, normalization: Normalization) -> Result<(), String> {
+    match expected_case_sensitive {
+        Some(expected) if expected != normalization.is_case_sensitive() => Err(format!(
+            "config sets case_sensitive: {}, but the artifacts were built with normalization {:?}",
+            expected,
+            normalization.as_str()
+        )),
+        _ => Ok(()),
+    }
 }
 
-fn encode_tokens(tokens: &[String], vocab: &Vocabulary) -> Vec<u32> {
-    let unk_id = vocab.special_tokens.get("<UNK>").copied().unwrap_or(0);
+/// Compares the vocabulary's build version with the manifest's. A mismatch
+/// means the vocabulary encodes tokens for a different automaton, so it is an
+/// error unless `allow_mismatch` is set; then, like a vocabulary without a
+/// version, it is returned as a warning.
+fn check_versions(vocab_version: Option<&str>, manifest_version: &str, allow_mismatch: bool) -> Result<Option<String>, String> {
+    match vocab_version {
+        None => Ok(Some("vocab.json records no version; cannot check it against the manifest".to_string())),
+        Some(version) if version == manifest_version => Ok(None),
+        Some(version) => {
+            let message = format!(
+                "vocab.json version {:?} does not match manifest version {:?}",
+                version, manifest_version
+            );
+            if allow_mismatch {
+                Ok(Some(message))
+            } else {
+                Err(format!("{} (pass --allow-version-mismatch to continue anyway)", message))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct OovStats {
+    total_tokens: u64,
+    unk_tokens: u64,
+    forms: HashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct OovReport {
+    total_tokens: u64,
+    unk_tokens: u64,
+    oov_rate: f64,
+    top_oov: Vec<OovForm>,
+}
+
+#[derive(Debug, Serialize)]
+struct OovForm {
+    token: String,
+    count: u64,
+}
+
+impl OovStats {
+    fn record(&mut self, tokens: &[String], vocab: &Vocabulary) {
+        for token in tokens {
+            self.total_tokens += 1;
+            if vocab.lookup(token).is_none() {
+                self.unk_tokens += 1;
+                *self.forms.entry(vocab.normalized(token)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: OovStats) {
+        self.total_tokens += other.total_tokens;
+        self.unk_tokens += other.unk_tokens;
+        for (form, count) in other.forms {
+            *self.forms.entry(form).or_insert(0) += count;
+        }
+    }
+
+    fn oov_rate(&self) -> f64 {
+        if self.total_tokens > 0 {
+            self.unk_tokens as f64 / self.total_tokens as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Most frequent OOV forms, ties broken alphabetically.
+    fn top_forms(&self, n: usize) -> Vec<OovForm> {
+        let mut forms: Vec<(&String, &u64)> = self.forms.iter().collect();
+        forms.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        forms
+            .into_iter()
+            .take(n)
+            .map(|(token, &count)| OovForm {
+                token: token.clone(),
+                count,
+            })
+            .collect()
+    }
+
+    fn report(&self, n: usize) -> OovReport {
+        OovReport {
+            total_tokens: self.total_tokens,
+            unk_tokens: self.unk_tokens,
+            oov_rate: self.oov_rate(),
+            top_oov: self.top_forms(n),
+        }
+    }
+}
+
+fn encode_tokens(tokens: &[String], vocab: &Vocabulary, unk_mode: UnkMode, separator: u32) -> Result<Vec<u32>, String> {
+    let unk_id = match unk_mode {
+        UnkMode::Skip => separator,
+        _ => vocab.special_tokens.get("<UNK>").copied().unwrap_or(0),
+    };
 
     tokens
         .iter()
-        .map(|token| {
-            let normalized = token.to_lowercase();
-            vocab.tokens.get(&normalized).copied().unwrap_or(unk_id)
+        .enumerate()
+        .map(|(i, token)| match vocab.lookup(token) {
+            Some(id) => Ok(id),
+            None if unk_mode == UnkMode::Error => Err(format!("token {} ({:?}) is not in the vocabulary", i, token)),
+            None => Ok(unk_id),
         })
         .collect()
 }
 
+/// Loaded artifacts shared read-only by every tagging worker.
+struct TagContext<'a> {
+    config: &'a TagConfig,
+    vocab: Vocabulary,
+    automaton: DoubleArrayAhoCorasick<u32>,
+    payloads: Vec<Payload>,
+    /// Negative rules from the manifest, by the negative phrase_id.
+    negative_rules: HashMap<u64, NegativeRule>,
+    /// Category names by category_id; empty without `categories_path`.
+    categories: HashMap<u16, String>,
+    separator: u32,
+    encoding: TokenEncoding,
+    policy: MatchPolicy,
+    output_format: OutputFormat,
+    unk_mode: UnkMode,
+    track_oov: bool,
+}
+
+/// One tagged document, serialized and ready to be written in input order.
+struct TaggedDocument {
+    /// `None` when the document is omitted from the output.
+    json: Option<String>,
+    spans: usize,
+    oov: Option<OovStats>,
+    /// Exceeded `max_doc_tokens` and was not matched.
+    oversized: bool,
+}
+
+impl TagContext<'_> {
+    fn label(&self, m: &Match) -> &str {
+        self.categories.get(&m.payload.category_id).unwrap_or(&self.config.label)
+    }
+
+    /// BIO tags and B- token phrase_ids for resolved matches, or `None`s
+    /// outside `output_format: "bio"`. `parse_output_format` keeps the `all`
+    /// policy out, so the matches never overlap.
+    fn bio_tags(&self, token_count: usize, matches: &[Match]) -> (Option<Vec<String>>, Option<Vec<Option<u64>>>) {
+        if self.output_format != OutputFormat::Bio {
+            return (None, None);
+        }
+        let mut tags = vec!["O".to_string(); token_count];
+        let mut tag_ids = vec![None; token_count];
+        for m in matches {
+            let label = self.label(m);
+            tags[m.start] = format!("B-{}", label);
+            tag_ids[m.start] = Some(m.payload.phrase_id);
+            for tag in &mut tags[m.start + 1..m.end] {
+                *tag = format!("I-{}", label);
+            }
+        }
+        (Some(tags), Some(tag_ids))
+    }
+
+    fn tag_line(&self, line: &str) -> Result<TaggedDocument, String> {
+        let config = self.config;
+        let doc: InputDocument = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        if let Some(offsets) = &doc.offsets {
+            if offsets.len() != doc.tokens.len() {
+                return Err(format!(
+                    "Document {}: {} offsets for {} tokens",
+                    doc.doc_id,
+                    offsets.len(),
+                    doc.tokens.len()
+                ));
+            }
+        }
+
+        if config.max_doc_tokens.is_some_and(|max| doc.tokens.len() > max) {
+            let json = if config.omit_oversized {
+                None
+            } else {
+                let (tags, tag_ids) = self.bio_tags(doc.tokens.len(), &[]);
+                let output_doc = OutputDocument {
+                    doc_id: doc.doc_id,
+                    text: doc.text,
+                    tokens: doc.tokens,
+                    spans: Vec::new(),
+                    tags,
+                    tag_ids,
+                };
+                Some(serde_json::to_string(&output_doc).map_err(|e| e.to_string())?)
+            };
+            return Ok(TaggedDocument {
+                json,
+                spans: 0,
+                oov: None,
+                oversized: true,
+            });
+        }
+
+        let token_ids = encode_tokens(&doc.tokens, &self.vocab, self.unk_mode, self.separator)
+            .map_err(|e| format!("Document {}: {}", doc.doc_id, e))?;
+        let oov = self.track_oov.then(|| {
+            let mut oov = OovStats::default();
+            oov.record(&doc.tokens, &self.vocab);
+            oov
+        });
+
+        let bytes = self.encoding.encode(&token_ids, self.separator);
+
+        let matches: Vec<Match> = self
+            .automaton
+            .find_overlapping_iter(&bytes)
+            .filter_map(|m| {
+                let pattern_id = m.value() as usize;
+                let span = self.encoding.token_span(m.start(), m.end());
+                debug_assert!(span.is_some(), "match {}..{} is not on token boundaries", m.start(), m.end());
+                let (start, end) = span?;
+
+                self.payloads
+                    .get(pattern_id)
+                    .map(|payload| Match::new(start, end, pattern_id, payload.clone()))
+            })
+            .collect();
+
+        // A malformed artifact could report a span outside the document
+        if let Some(m) = matches.iter().find(|m| m.start >= m.end || m.end > doc.tokens.len()) {
+            return Err(format!(
+                "Document {}: phrase {} matched tokens {}..{} of {}; the automaton and payloads do not agree",
+                doc.doc_id,
+                m.payload.phrase_id,
+                m.start,
+                m.end,
+                doc.tokens.len()
+            ));
+        }
+
+        // Same suppression and resolution as the runtime Matcher, so both produce identical spans
+        let matches = apply_negative_rules(matches, &self.negative_rules);
+        let mut matches = resolve_overlaps_bounded(matches, self.policy, DEFAULT_MAX_CLUSTER).matches;
+
+        if matches.len() > config.max_spans {
+            matches.truncate(config.max_spans);
+        }
+
+        let (tags, tag_ids) = self.bio_tags(doc.tokens.len(), &matches);
+        let spans: Vec<Span> = matches
+            .into_iter()
+            .map(|m| Span {
+                start: m.start,
+                end: if config.inclusive_end { m.end - 1 } else { m.end },
+                char_start: doc.offsets.as_ref().map(|offsets| offsets[m.start].0),
+                char_end: doc.offsets.as_ref().map(|offsets| offsets[m.end - 1].1),
+                phrase_id: m.payload.phrase_id,
+                label: self.label(&m).to_string(),
+                salience: m.payload.salience,
+                count: m.payload.count,
+                text: config.emit_text.then(|| doc.tokens[m.start..m.end].join(" ")),
+            })
+            .collect();
+
+        let span_count = spans.len();
+        let output_doc = OutputDocument {
+            doc_id: doc.doc_id,
+            text: doc.text,
+            tokens: doc.tokens,
+            spans,
+            tags,
+            tag_ids,
+        };
+
+        Ok(TaggedDocument {
+            json: Some(serde_json::to_string(&output_doc).map_err(|e| e.to_string())?),
+            spans: span_count,
+            oov,
+            oversized: false,
+        })
+    }
+}
+
+/// Tags documents on `threads` workers while keeping memory bounded.
+///
+/// At most `window` documents are in flight at once (queued, being tagged, or
+/// waiting in the reorder buffer): the reader takes a credit before dispatching a
+/// line and the writer returns it once that document has been written. Peak memory
+/// is therefore proportional to `window` times the document size, not the corpus
+/// size, and output is written in input order.
+fn tag_parallel<I>(
+    context: &TagContext,
+    lines: I,
+    threads: usize,
+    window: usize,
+    write_document: &mut dyn FnMut(TaggedDocument) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    I: Iterator<Item = std::io::Result<String>> + Send,
+{
+    let (work_tx, work_rx) = mpsc::channel::<(usize, String)>();
+    let work_rx = Mutex::new(work_rx);
+
+    std::thread::scope(|scope| {
+        let (credit_tx, credit_rx) = mpsc::sync_channel::<()>(window);
+        for _ in 0..window {
+            credit_tx.send(()).expect("credit channel has capacity for the full window");
+        }
+
+        let (result_tx, result_rx) = mpsc::channel::<(usize, Result<TaggedDocument, String>)>();
+
+        let reader_results = result_tx.clone();
+        scope.spawn(move || {
+            let mut seq = 0;
+            for line in lines {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        let _ = reader_results.send((seq, Err(e.to_string())));
+                        return;
+                    }
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if credit_rx.recv().is_err() || work_tx.send((seq, line)).is_err() {
+                    return;
+                }
+                seq += 1;
+            }
+        });
+
+        for _ in 0..threads {
+            let result_tx = result_tx.clone();
+            let work_rx = &work_rx;
+            scope.spawn(move || loop {
+                let job = work_rx.lock().unwrap().recv();
+                let Ok((seq, line)) = job else { return };
+                if result_tx.send((seq, context.tag_line(&line))).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(result_tx);
+
+        let mut pending: BTreeMap<usize, Result<TaggedDocument, String>> = BTreeMap::new();
+        let mut next_seq = 0;
+        for (seq, result) in result_rx {
+            pending.insert(seq, result);
+            while let Some(result) = pending.remove(&next_seq) {
+                write_document(result?)?;
+                next_seq += 1;
+                let _ = credit_tx.send(());
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn parse_policy(name: &str) -> Result<MatchPolicy, String> {
+    MatchPolicy::from_str(name).ok_or_else(|| format!("Invalid policy: {}", name))
+}
+
+fn parse_unk_mode(name: &str) -> Result<UnkMode, String> {
+    UnkMode::from_str(name).ok_or_else(|| format!("Invalid unk_mode: {}", name))
+}
+
+/// BIO tagging needs each token in at most one span, which every policy but
+/// `all` guarantees.
+fn parse_output_format(name: &str, policy: MatchPolicy) -> Result<OutputFormat, String> {
+    let format = OutputFormat::from_str(name).ok_or_else(|| format!("Invalid output_format: {}", name))?;
+    if format == OutputFormat::Bio && policy == MatchPolicy::All {
+        return Err("output_format \"bio\" needs non-overlapping spans; use a policy other than all".to_string());
+    }
+    Ok(format)
+}
+
 fn tag_corpus(
     corpus_path: &str,
     config: &TagConfig,
     output_path: &str,
+    options: &TagOptions,
 ) -> Result<TaggingStats, Box<dyn std::error::Error>> {
     println!("🏷️  PhraseKit Corpus Tagging");
     println!("════════════════════════════════════════");
@@ -95,151 +660,146 @@ fn tag_corpus(
     println!("Output:     {}", output_path);
     println!();
 
+    let policy = parse_policy(&config.policy)?;
+    let output_format = parse_output_format(&config.output_format, policy)?;
+    let unk_mode = parse_unk_mode(&config.unk_mode)?;
+
     println!("📚 Loading matcher artifacts...");
 
     let vocab_data = std::fs::read_to_string(&config.vocab_path)?;
-    let vocab: Vocabulary = serde_json::from_str(&vocab_data)?;
+    let mut vocab: Vocabulary = serde_json::from_str(&vocab_data)?;
     println!("  ✓ Loaded vocabulary ({} tokens)", vocab.tokens.len());
 
-    use daachorse::DoubleArrayAhoCorasick;
     let automaton_bytes = std::fs::read(&config.automaton_path)?;
     let (automaton, _): (DoubleArrayAhoCorasick<u32>, _) = unsafe {
         DoubleArrayAhoCorasick::deserialize_unchecked(&automaton_bytes)
     };
     println!("  ✓ Loaded automaton");
 
-    let payloads_file = File::open(&config.payloads_path)?;
-    let payloads_reader = BufReader::new(payloads_file);
-    let payloads = payload::load_payloads(payloads_reader)?;
-    println!("  ✓ Loaded {} phrase payloads", payloads.len());
-
     #[derive(Debug, Deserialize)]
     struct Manifest {
+        version: String,
         separator_id: u32,
+        #[serde(default = "legacy_payload_format_version")]
+        payload_format_version: u32,
+        #[serde(default)]
+        normalization: Normalization,
+        #[serde(default)]
+        unicode_normalization: UnicodeNormalization,
+        #[serde(default)]
+        encoding: TokenEncoding,
+        #[serde(default)]
+        negative_rules: Vec<NegativeRule>,
+    }
+
+    fn legacy_payload_format_version() -> u32 {
+        1
     }
 
     let manifest_data = std::fs::read_to_string(&config.manifest_path)?;
     let manifest: Manifest = serde_json::from_str(&manifest_data)?;
     println!("  ✓ Loaded manifest");
+
+    let payload_format = PayloadFormat::from_version(manifest.payload_format_version)
+        .ok_or_else(|| format!("Unsupported payload_format_version {}", manifest.payload_format_version))?;
+    let payloads_file = File::open(&config.payloads_path)?;
+    let payloads_reader = BufReader::new(payloads_file);
+    let payloads = payload::load_payloads(payloads_reader, payload_format)?;
+    println!("  ✓ Loaded {} phrase payloads", payloads.len());
+
+    let categories = match &config.categories_path {
+        Some(path) => {
+            let names: HashMap<String, u16> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            println!("  ✓ Loaded {} categories", names.len());
+            names.into_iter().map(|(name, id)| (id, name)).collect()
+        }
+        None => HashMap::new(),
+    };
+
+    if let Some(warning) = check_versions(vocab.version.as_deref(), &manifest.version, options.allow_version_mismatch)? {
+        eprintln!("  ⚠️  {}", warning);
+    }
+    vocab.check_separator(manifest.separator_id)?;
+    check_normalization(config.case_sensitive, manifest.normalization)?;
+    vocab.normalization = manifest.normalization;
+    vocab.unicode_normalization = manifest.unicode_normalization;
     println!();
 
     println!("🔍 Tagging documents...");
 
-    let corpus_file = File::open(corpus_path)?;
-    let corpus_reader = BufReader::new(corpus_file);
-
-    let output_file = File::create(output_path)?;
-    let mut output_writer = BufWriter::new(output_file);
+    let corpus_reader = compression::open_input(corpus_path)?;
+    let mut output_writer = compression::create_output(output_path)?;
 
     let mut stats = TaggingStats {
         documents: 0,
         total_spans: 0,
         docs_with_spans: 0,
+        skipped_oversized: 0,
+        oov: options.oov_report.then(OovStats::default),
     };
 
-    for line in corpus_reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() {
-            continue;
-        }
-
-        let doc: InputDocument = serde_json::from_str(&line)?;
-
-        let token_ids = encode_tokens(&doc.tokens, &vocab);
+    let context = TagContext {
+        config,
+        vocab,
+        automaton,
+        payloads,
+        negative_rules: manifest.negative_rules.into_iter().map(|rule| (rule.phrase_id, rule)).collect(),
+        categories,
+        separator: manifest.separator_id,
+        encoding: manifest.encoding,
+        policy,
+        output_format,
+        unk_mode,
+        track_oov: options.oov_report,
+    };
 
-        let separator = manifest.separator_id;
-        let mut bytes = Vec::with_capacity(token_ids.len() * 5);
-        for &token_id in &token_ids {
-            bytes.extend_from_slice(&token_id.to_le_bytes());
-            bytes.extend_from_slice(&separator.to_le_bytes());
+    let mut write_document = |tagged: TaggedDocument| -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(json) = &tagged.json {
+            output_writer.write_all(json.as_bytes())?;
+            writeln!(&mut output_writer)?;
         }
-
-        #[derive(Debug, Clone, Copy)]
-        struct Match {
-            start: usize,
-            end: usize,
-            phrase_id: u32,
+        if tagged.oversized {
+            stats.skipped_oversized += 1;
         }
 
-        let mut matches: Vec<Match> = automaton
-            .find_overlapping_iter(&bytes)
-            .filter_map(|m| {
-                let pattern_id = m.value() as usize;
-                let start_token = m.start() / 8;
-                let end_token = (m.end() + 7) / 8;
-
-                payloads.get(pattern_id).map(|payload| Match {
-                    start: start_token,
-                    end: end_token,
-                    phrase_id: payload.phrase_id,
-                })
-            })
-            .collect();
-
-        if config.policy == "leftmost_longest" {
-            matches.sort_by_key(|m| (m.start, std::cmp::Reverse(m.end)));
-
-            let mut resolved = Vec::new();
-            let mut covered_end = 0;
-
-            for m in matches {
-                if m.start >= covered_end {
-                    resolved.push(m);
-                    covered_end = m.end;
-                }
-            }
-
-            matches = resolved;
-        } else if config.policy == "leftmost_first" {
-            matches.sort_by_key(|m| m.start);
-
-            let mut resolved = Vec::new();
-            let mut covered_end = 0;
-
-            for m in matches {
-                if m.start >= covered_end {
-                    resolved.push(m);
-                    covered_end = m.end;
-                }
-            }
-
-            matches = resolved;
+        stats.total_spans += tagged.spans;
+        if tagged.spans > 0 {
+            stats.docs_with_spans += 1;
         }
-
-        if matches.len() > config.max_spans {
-            matches.truncate(config.max_spans);
+        if let (Some(total), Some(doc_oov)) = (stats.oov.as_mut(), tagged.oov) {
+            total.merge(doc_oov);
         }
 
-        let spans: Vec<Span> = matches
-            .into_iter()
-            .map(|m| Span {
-                start: m.start,
-                end: m.end,
-                phrase_id: m.phrase_id,
-                label: config.label.clone(),
-            })
-            .collect();
+        stats.documents += 1;
 
-        stats.total_spans += spans.len();
-        if !spans.is_empty() {
-            stats.docs_with_spans += 1;
+        if options.flush_every.is_some_and(|n| stats.documents.is_multiple_of(n)) {
+            output_writer.flush()?;
         }
 
-        let output_doc = OutputDocument {
-            doc_id: doc.doc_id,
-            tokens: doc.tokens,
-            spans,
-        };
-
-        serde_json::to_writer(&mut output_writer, &output_doc)?;
-        writeln!(&mut output_writer)?;
-
-        stats.documents += 1;
-
         if stats.documents % 1000 == 0 {
             print!("\r  Processed {} documents...", stats.documents);
             std::io::stdout().flush()?;
         }
+
+        Ok(())
+    };
+
+    let threads = match config.threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    if threads > 1 {
+        tag_parallel(&context, corpus_reader.lines(), threads, config.read_ahead.max(1), &mut write_document)?;
+    } else {
+        for line in corpus_reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            write_document(context.tag_line(&line)?)?;
+        }
     }
 
     if stats.documents % 1000 != 0 {
@@ -249,7 +809,7 @@ fn tag_corpus(
         println!("  ✓ Processed {} documents", stats.documents);
     }
 
-    output_writer.flush()?;
+    output_writer.finish()?;
 
     println!();
     println!("✅ Tagging complete!");
@@ -258,6 +818,9 @@ fn tag_corpus(
     println!("  Documents:              {}", stats.documents);
     println!("  Total spans:            {}", stats.total_spans);
     println!("  Documents with spans:   {}", stats.docs_with_spans);
+    if let Some(max) = config.max_doc_tokens {
+        println!("  Skipped oversized:      {} (> {} tokens)", stats.skipped_oversized, max);
+    }
     println!(
         "  Avg spans per document: {:.2}",
         if stats.documents > 0 {
@@ -267,25 +830,108 @@ fn tag_corpus(
         }
     );
 
+    if let Some(oov) = &stats.oov {
+        println!();
+        println!("🔤 OOV Report:");
+        println!("  Total tokens:           {}", oov.total_tokens);
+        println!("  OOV tokens:             {}", oov.unk_tokens);
+        println!("  OOV rate:               {:.2}%", oov.oov_rate() * 100.0);
+
+        let top = oov.top_forms(options.oov_top);
+        if !top.is_empty() {
+            println!("  Top OOV forms:");
+            for form in &top {
+                println!("    {:<24} {}", form.token, form.count);
+            }
+        }
+
+        if let Some(path) = &options.oov_output {
+            let report_json = serde_json::to_string_pretty(&oov.report(options.oov_top))?;
+            std::fs::write(path, report_json)?;
+            println!("  ✓ Wrote OOV report to {}", path);
+        }
+    }
+
     Ok(stats)
 }
 
+fn parse_options(args: &[String]) -> Result<(Vec<String>, TagOptions), String> {
+    let mut positional = Vec::new();
+    let mut options = TagOptions {
+        oov_report: false,
+        oov_output: None,
+        oov_top: 20,
+        flush_every: None,
+        allow_version_mismatch: false,
+    };
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--oov-report" => options.oov_report = true,
+            "--oov-output" => {
+                let path = iter.next().ok_or("Missing value for --oov-output")?;
+                options.oov_report = true;
+                options.oov_output = Some(path.clone());
+            }
+            "--oov-top" => {
+                let value = iter.next().ok_or("Missing value for --oov-top")?;
+                options.oov_top = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for --oov-top: {:?}", value))?;
+            }
+            "--flush-every" => {
+                let value = iter.next().ok_or("Missing value for --flush-every")?;
+                let n: usize = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for --flush-every: {:?}", value))?;
+                if n == 0 {
+                    return Err("--flush-every must be at least 1".to_string());
+                }
+                options.flush_every = Some(n);
+            }
+            "--line-buffered" => options.flush_every = Some(1),
+            "--allow-version-mismatch" => options.allow_version_mismatch = true,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    Ok((positional, options))
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let parsed = parse_options(&args[1..]);
 
-    if args.len() != 4 {
-        eprintln!("Usage: {} <corpus.jsonl> <config.json> <output.jsonl>", args[0]);
-        eprintln!();
-        eprintln!("Arguments:");
-        eprintln!("  corpus.jsonl  - Input corpus with pre-tokenized documents");
-        eprintln!("  config.json   - Tagging configuration");
-        eprintln!("  output.jsonl  - Output path for tagged corpus");
-        process::exit(1);
-    }
+    let (positional, options) = match parsed {
+        Ok((positional, options)) if positional.len() == 3 => (positional, options),
+        other => {
+            if let Err(e) = other {
+                eprintln!("Error: {}", e);
+                eprintln!();
+            }
+            eprintln!("Usage: {} <corpus.jsonl> <config.json> <output.jsonl> [options]", args[0]);
+            eprintln!();
+            eprintln!("Arguments:");
+            eprintln!("  corpus.jsonl  - Input corpus with pre-tokenized documents");
+            eprintln!("  config.json   - Tagging configuration");
+            eprintln!("  output.jsonl  - Output path for tagged corpus");
+            eprintln!();
+            eprintln!("Options:");
+            eprintln!("  --oov-report         - Track tokens that map to <UNK> and print a summary");
+            eprintln!("  --oov-output <path>  - Also write the OOV report as JSON (implies --oov-report)");
+            eprintln!("  --oov-top <n>        - Number of most frequent OOV forms to report (default 20)");
+            eprintln!("  --flush-every <n>    - Flush output every n documents (default: fully buffered)");
+            eprintln!("  --line-buffered      - Flush output after every document");
+            eprintln!("  --allow-version-mismatch - Warn instead of failing when vocab and manifest versions differ");
+            process::exit(1);
+        }
+    };
 
-    let corpus_path = &args[1];
-    let config_path = &args[2];
-    let output_path = &args[3];
+    let corpus_path = &positional[0];
+    let config_path = &positional[1];
+    let output_path = &positional[2];
 
     if !Path::new(corpus_path).exists() {
         eprintln!("Error: Corpus file not found: {}", corpus_path);
@@ -297,24 +943,410 @@ fn main() {
         process::exit(1);
     }
 
-    let config_data = match std::fs::read_to_string(config_path) {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!("Error: Failed to read config file: {}", e);
-            process::exit(1);
-        }
-    };
-
-    let config: TagConfig = match serde_json::from_str(&config_data) {
+    let config: TagConfig = match config::load(config_path) {
         Ok(cfg) => cfg,
         Err(e) => {
-            eprintln!("Error: Failed to parse config: {}", e);
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
     };
+    if let Err(e) = config::validate(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
 
-    if let Err(e) = tag_corpus(corpus_path, &config, output_path) {
+    if let Err(e) = tag_corpus(corpus_path, &config, output_path, &options) {
         eprintln!("Error: Tagging failed: {}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_flush_options() {
+        let (positional, options) = parse_options(&args(&["in.jsonl", "c.json", "out.jsonl"])).unwrap();
+        assert_eq!(positional.len(), 3);
+        assert_eq!(options.flush_every, None);
+
+        let (_, options) = parse_options(&args(&["--flush-every", "50"])).unwrap();
+        assert_eq!(options.flush_every, Some(50));
+
+        let (_, options) = parse_options(&args(&["--line-buffered"])).unwrap();
+        assert_eq!(options.flush_every, Some(1));
+
+        assert!(parse_options(&args(&["--flush-every", "0"])).is_err());
+        assert!(parse_options(&args(&["--flush-every"])).is_err());
+    }
+
+    #[test]
+    fn test_check_versions() {
+        assert_eq!(check_versions(Some("pk-01"), "pk-01", false), Ok(None));
+
+        let err = check_versions(Some("pk-01"), "pk-02", false).unwrap_err();
+        assert!(err.contains("\"pk-01\"") && err.contains("\"pk-02\""));
+
+        let warning = check_versions(Some("pk-01"), "pk-02", true).unwrap();
+        assert!(warning.unwrap().contains("does not match"));
+
+        assert!(check_versions(None, "pk-02", false).unwrap().is_some());
+
+        let (_, options) = parse_options(&args(&["--allow-version-mismatch"])).unwrap();
+        assert!(options.allow_version_mismatch);
+    }
+
+    #[test]
+    fn test_vocab_entry_equal_to_separator_is_rejected() {
+        let vocab: Vocabulary = serde_json::from_str(
+            r#"{"tokens": {"machine": 1, "learning": 4294967294}, "special_tokens": {"<UNK>": 0}}"#,
+        )
+        .unwrap();
+        let err = vocab.check_separator(4294967294).unwrap_err();
+        assert!(err.contains(r#"token "learning" has ID 4294967294"#), "{}", err);
+        assert!(vocab.check_separator(4294967295).is_ok());
+    }
+
+    fn config(json: &str) -> TagConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// A context whose only phrase is "machine learning" (phrase_id 100).
+    fn context(config: &TagConfig) -> TagContext<'_> {
+        context_with(config, &[("machine", 1), ("learning", 2)], &[(&[1, 2], Payload::new(100, 1.0, 10, 2))])
+    }
+
+    fn context_with<'a>(config: &'a TagConfig, vocab: &[(&str, u32)], phrases: &[(&[u32], Payload)]) -> TagContext<'a> {
+        encoded_context(config, vocab, phrases, TokenEncoding::U32Sep)
+    }
+
+    fn encoded_context<'a>(
+        config: &'a TagConfig,
+        vocab: &[(&str, u32)],
+        phrases: &[(&[u32], Payload)],
+        encoding: TokenEncoding,
+    ) -> TagContext<'a> {
+        let separator = u32::MAX - 1;
+        let patterns = phrases.iter().map(|(tokens, _)| encoding.encode(tokens, separator));
+        let policy = parse_policy(&config.policy).unwrap();
+
+        TagContext {
+            config,
+            vocab: Vocabulary {
+                version: None,
+                tokens: vocab.iter().map(|&(token, id)| (token.to_string(), id)).collect(),
+                special_tokens: HashMap::from([("<UNK>".to_string(), 0)]),
+                case_sensitive_tokens: HashMap::new(),
+                normalization: Normalization::Lowercase,
+                unicode_normalization: UnicodeNormalization::None,
+            },
+            automaton: DoubleArrayAhoCorasick::new(patterns).unwrap(),
+            payloads: phrases.iter().map(|(_, payload)| payload.clone()).collect(),
+            negative_rules: HashMap::new(),
+            categories: HashMap::new(),
+            separator,
+            encoding,
+            policy,
+            output_format: parse_output_format(&config.output_format, policy).unwrap(),
+            unk_mode: parse_unk_mode(&config.unk_mode).unwrap(),
+            track_oov: false,
+        }
+    }
+
+    #[test]
+    fn test_policies_resolve_overlapping_phrases() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let vocab = [("a", 1), ("b", 2), ("c", 3), ("d", 4)];
+        // Over "a b c d": 100 = a b, 300 = a b c, 200 = b c d (most salient), 400 = c d
+        let phrases: [(&[u32], Payload); 4] = [
+            (&[1, 2], Payload::new(100, 1.0, 10, 2)),
+            (&[1, 2, 3], Payload::new(300, 1.5, 10, 3)),
+            (&[2, 3, 4], Payload::new(200, 3.0, 10, 3)),
+            (&[3, 4], Payload::new(400, 0.5, 10, 2)),
+        ];
+        let line = r#"{"doc_id": "d1", "tokens": ["a", "b", "c", "d"]}"#;
+        let spans = |policy: &str| {
+            let config = config(&format!(r#"{{{}, "policy": "{}"}}"#, paths, policy));
+            let tagged = context_with(&config, &vocab, &phrases).tag_line(line).unwrap();
+            let written: serde_json::Value = serde_json::from_str(tagged.json.as_deref().unwrap()).unwrap();
+            written["spans"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|span| span["phrase_id"].as_u64().unwrap())
+                .collect::<Vec<u64>>()
+        };
+
+        assert_eq!(spans("leftmost_longest"), vec![300]);
+        assert_eq!(spans("leftmost_first"), vec![100, 400]);
+        assert_eq!(spans("salience_max"), vec![200]);
+        assert_eq!(spans("all"), vec![100, 300, 200, 400]);
+        assert!(parse_policy("longest").is_err());
+    }
+
+    #[test]
+    fn test_max_doc_tokens_skips_oversized_documents() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let line = r#"{"doc_id": "d1", "tokens": ["machine", "learning", "is", "fun"]}"#;
+
+        let unlimited = config(&format!("{{{}}}", paths));
+        let tagged = context(&unlimited).tag_line(line).unwrap();
+        assert!(!tagged.oversized);
+        assert_eq!(tagged.spans, 1);
+
+        let limited = config(&format!(r#"{{{}, "max_doc_tokens": 3}}"#, paths));
+        let tagged = context(&limited).tag_line(line).unwrap();
+        assert!(tagged.oversized);
+        assert_eq!(tagged.spans, 0);
+        let written: serde_json::Value = serde_json::from_str(tagged.json.as_deref().unwrap()).unwrap();
+        assert_eq!(written["doc_id"], "d1");
+        assert_eq!(written["spans"], serde_json::json!([]));
+
+        let omitting = config(&format!(r#"{{{}, "max_doc_tokens": 3, "omit_oversized": true}}"#, paths));
+        let tagged = context(&omitting).tag_line(line).unwrap();
+        assert!(tagged.oversized);
+        assert!(tagged.json.is_none());
+    }
+
+    #[test]
+    fn test_emit_text_adds_span_text_and_payload_fields() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let line = r#"{"doc_id": "d1", "tokens": ["Machine", "Learning", "is", "fun"]}"#;
+        let first_span = |config: &TagConfig| {
+            let tagged = context(config).tag_line(line).unwrap();
+            let written: serde_json::Value = serde_json::from_str(tagged.json.as_deref().unwrap()).unwrap();
+            written["spans"][0].clone()
+        };
+
+        let plain = first_span(&config(&format!("{{{}}}", paths)));
+        assert_eq!(plain["salience"], 1.0);
+        assert_eq!(plain["count"], 10);
+        assert!(plain.get("text").is_none());
+
+        let with_text = first_span(&config(&format!(r#"{{{}, "emit_text": true}}"#, paths)));
+        assert_eq!(with_text["text"], "Machine Learning");
+    }
+
+    #[test]
+    fn test_token_offsets_add_char_offsets_to_spans() {
+        let config = config(r#"{"automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": ""}"#);
+        let span = |line: &str| {
+            let tagged = context(&config).tag_line(line).unwrap();
+            let written: serde_json::Value = serde_json::from_str(tagged.json.as_deref().unwrap()).unwrap();
+            written["spans"][0].clone()
+        };
+
+        let plain = span(r#"{"doc_id": "d1", "tokens": ["so", "machine", "learning"]}"#);
+        assert!(plain.get("char_start").is_none() && plain.get("char_end").is_none());
+
+        for field in ["offsets", "token_offsets"] {
+            let line = format!(
+                r#"{{"doc_id": "d1", "tokens": ["so", "machine", "learning"], "{}": [[0, 2], [3, 10], [11, 19]]}}"#,
+                field
+            );
+            let offset = span(&line);
+            assert_eq!((&offset["char_start"], &offset["char_end"]), (&serde_json::json!(3), &serde_json::json!(19)), "{}", field);
+        }
+    }
+
+    #[test]
+    fn test_categories_label_spans() {
+        let config = config(r#"{"automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": "", "label": "PHRASE"}"#);
+        let mut context = context_with(
+            &config,
+            &[("machine", 1), ("learning", 2), ("rust", 3)],
+            &[(&[1, 2], Payload::new(100, 1.0, 10, 2).with_category(2)), (&[3], Payload::new(200, 1.0, 10, 1))],
+        );
+        context.categories = HashMap::from([(1, "company".to_string()), (2, "skill".to_string())]);
+
+        let tagged = context.tag_line(r#"{"doc_id": "d1", "tokens": ["machine", "learning", "rust"]}"#).unwrap();
+        let written: serde_json::Value = serde_json::from_str(tagged.json.as_deref().unwrap()).unwrap();
+        let labels: Vec<&str> = written["spans"].as_array().unwrap().iter().map(|s| s["label"].as_str().unwrap()).collect();
+        // Uncategorized phrases keep the configured label
+        assert_eq!(labels, vec!["skill", "PHRASE"]);
+    }
+
+    #[test]
+    fn test_tag_parallel_preserves_input_order() {
+        let config = config(r#"{"automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": ""}"#);
+        let context = context(&config);
+        let lines: Vec<String> = (0..200)
+            .map(|i| {
+                // Longer documents at the front so later ones tend to finish first
+                let filler = vec!["\"x\""; (200 - i) * 5].join(", ");
+                format!(r#"{{"doc_id": "d{}", "tokens": [{}, "machine", "learning"]}}"#, i, filler)
+            })
+            .collect();
+
+        let sequential: Vec<String> = lines.iter().map(|line| context.tag_line(line).unwrap().json.unwrap()).collect();
+        let mut written = Vec::new();
+        tag_parallel(&context, lines.into_iter().map(Ok), 4, 8, &mut |tagged| {
+            written.push(tagged.json.unwrap());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(written, sequential);
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_fields_and_bad_values() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let err = config::parse::<TagConfig>("tag.json", &format!(r#"{{{}, "max_span": 10}}"#, paths)).unwrap_err();
+        assert!(err.to_string().contains("unknown field `max_span`"), "{}", err);
+
+        let bad = config(&format!(r#"{{{}, "policy": "longest", "output_format": "iob"}}"#, paths));
+        assert_eq!(bad.problems(), vec!["Invalid policy: longest", "Invalid output_format: iob"]);
+        assert_eq!(config(&format!(r#"{{{}, "policy": "all", "output_format": "bio"}}"#, paths)).problems().len(), 1);
+        assert!(config(&format!("{{{}}}", paths)).problems().is_empty());
+    }
+
+    #[test]
+    fn test_unk_mode_skip_prevents_matches_across_oov_tokens() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        // A phrase with an <UNK> slot, e.g. built from "machine <UNK> learning"
+        let vocab = [("machine", 1), ("learning", 2)];
+        let phrases: [(&[u32], Payload); 1] = [(&[1, 0, 2], Payload::new(100, 1.0, 10, 3))];
+        let line = r#"{"doc_id": "d1", "tokens": ["machine", "quantum", "learning"]}"#;
+        let tag = |unk_mode: &str| {
+            let config = config(&format!(r#"{{{}, "unk_mode": "{}"}}"#, paths, unk_mode));
+            context_with(&config, &vocab, &phrases).tag_line(line)
+        };
+
+        // "quantum" encodes as <UNK> and completes a false match
+        assert_eq!(tag("unk").unwrap().spans, 1);
+        assert_eq!(tag("skip").unwrap().spans, 0);
+        let err = tag("error").err().unwrap();
+        assert!(err.contains("Document d1") && err.contains("\"quantum\""), "{}", err);
+
+        let known = r#"{"doc_id": "d2", "tokens": ["machine", "learning"]}"#;
+        let config = config(&format!(r#"{{{}, "unk_mode": "error"}}"#, paths));
+        assert!(context(&config).tag_line(known).is_ok());
+        assert!(parse_unk_mode("drop").is_err());
+    }
+
+    #[test]
+    fn test_negative_rules_suppress_nearby_targets() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let config = config(&format!("{{{}}}", paths));
+        let vocab = [("apple", 1), ("inc", 2), ("pie", 3)];
+        let phrases: [(&[u32], Payload); 2] = [(&[1], Payload::new(100, 1.0, 10, 1)), (&[2], Payload::new(900, 1.0, 10, 1))];
+        let mut context = context_with(&config, &vocab, &phrases);
+        context.negative_rules = HashMap::from([(
+            900,
+            NegativeRule {
+                phrase_id: 900,
+                suppresses: vec![100],
+                window: 1,
+            },
+        )]);
+        let phrase_ids = |line: &str| {
+            let json = context.tag_line(line).unwrap().json.unwrap();
+            let written: serde_json::Value = serde_json::from_str(&json).unwrap();
+            written["spans"].as_array().unwrap().iter().map(|s| s["phrase_id"].as_u64().unwrap()).collect::<Vec<u64>>()
+        };
+
+        // "inc" is never written as a span, and suppresses "apple" up to one token away
+        assert_eq!(phrase_ids(r#"{"doc_id": "d1", "tokens": ["apple", "inc"]}"#), Vec::<u64>::new());
+        assert_eq!(phrase_ids(r#"{"doc_id": "d2", "tokens": ["apple", "pie", "inc"]}"#), Vec::<u64>::new());
+        assert_eq!(phrase_ids(r#"{"doc_id": "d3", "tokens": ["apple", "pie", "pie", "inc"]}"#), vec![100]);
+        assert_eq!(phrase_ids(r#"{"doc_id": "d4", "tokens": ["apple", "pie"]}"#), vec![100]);
+    }
+
+    #[test]
+    fn test_compact_encoding_tags_the_same_spans() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let vocab = [("a", 1), ("b", 255), ("c", 65_025), ("d", 4)];
+        let phrases: [(&[u32], Payload); 3] = [
+            (&[1, 255], Payload::new(100, 1.0, 10, 2)),
+            (&[255, 65_025, 4], Payload::new(200, 2.0, 10, 3)),
+            (&[0, 4], Payload::new(300, 1.0, 10, 2)),
+        ];
+        let line = r#"{"doc_id": "d1", "tokens": ["a", "b", "c", "d", "zzz", "d"]}"#;
+        let tag = |encoding: TokenEncoding, unk_mode: &str| {
+            let config = config(&format!(r#"{{{}, "policy": "all", "unk_mode": "{}"}}"#, paths, unk_mode));
+            let json = encoded_context(&config, &vocab, &phrases, encoding).tag_line(line).unwrap().json.unwrap();
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()["spans"].clone()
+        };
+
+        for unk_mode in ["unk", "skip"] {
+            let spans = tag(TokenEncoding::U24Sep1, unk_mode);
+            assert_eq!(spans, tag(TokenEncoding::U32Sep, unk_mode), "unk_mode {}", unk_mode);
+            let ids: Vec<u64> = spans.as_array().unwrap().iter().map(|s| s["phrase_id"].as_u64().unwrap()).collect();
+            assert_eq!(ids, if unk_mode == "unk" { vec![100, 200, 300] } else { vec![100, 200] });
+        }
+    }
+
+    #[test]
+    fn test_encoding_follows_manifest_normalization() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let config = config(&format!("{{{}}}", paths));
+        let vocab = [("BRCA1", 1), ("brca1", 2), ("gene", 3)];
+        let phrases: [(&[u32], Payload); 1] = [(&[1, 3], Payload::new(100, 1.0, 10, 2))];
+        let spans = |context: &TagContext, tokens: &str| {
+            context.tag_line(&format!(r#"{{"doc_id": "d1", "tokens": {}}}"#, tokens)).unwrap().spans
+        };
+
+        let mut context = context_with(&config, &vocab, &phrases);
+        context.vocab.normalization = Normalization::None;
+        assert_eq!(spans(&context, r#"["BRCA1", "gene"]"#), 1);
+        assert_eq!(spans(&context, r#"["brca1", "gene"]"#), 0);
+        assert_eq!(spans(&context, r#"["BRCA1", "Gene"]"#), 0);
+
+        // Lowercased, "BRCA1" reads as "brca1" (ID 2)
+        context.vocab.normalization = Normalization::Lowercase;
+        assert_eq!(spans(&context, r#"["BRCA1", "gene"]"#), 0);
+
+        assert!(check_normalization(None, Normalization::None).is_ok());
+        assert!(check_normalization(Some(true), Normalization::None).is_ok());
+        let err = check_normalization(Some(true), Normalization::Lowercase).unwrap_err();
+        assert!(err.contains("normalization \"lowercase\""), "{}", err);
+    }
+
+    #[test]
+    fn test_encoding_follows_manifest_unicode_normalization() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let config = config(&format!("{{{}}}", paths));
+        let vocab = [("caf\u{e9}", 1), ("menu", 2)];
+        let phrases: [(&[u32], Payload); 1] = [(&[1, 2], Payload::new(100, 1.0, 10, 2))];
+        let decomposed = r#"{"doc_id": "d1", "tokens": ["cafe\u0301", "menu"]}"#;
+
+        let mut context = context_with(&config, &vocab, &phrases);
+        assert_eq!(context.tag_line(decomposed).unwrap().spans, 0);
+        context.vocab.unicode_normalization = UnicodeNormalization::Nfc;
+        assert_eq!(context.tag_line(decomposed).unwrap().spans, 1);
+        assert_eq!(context.vocab.normalized("Cafe\u{301}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_bio_output_tags_tokens() {
+        let paths = r#""automaton_path": "", "payloads_path": "", "manifest_path": "", "vocab_path": """#;
+        let line = r#"{"doc_id": "d1", "tokens": ["so", "machine", "learning", "rust"]}"#;
+        let vocab = [("machine", 1), ("learning", 2), ("rust", 3)];
+        let phrases: [(&[u32], Payload); 2] = [(&[1, 2], Payload::new(100, 1.0, 10, 2)), (&[3], Payload::new(200, 1.0, 10, 1))];
+        let written = |config: &TagConfig| {
+            let tagged = context_with(config, &vocab, &phrases).tag_line(line).unwrap();
+            serde_json::from_str::<serde_json::Value>(tagged.json.as_deref().unwrap()).unwrap()
+        };
+
+        let spans = written(&config(&format!("{{{}}}", paths)));
+        assert!(spans.get("tags").is_none() && spans.get("tag_ids").is_none());
+
+        let bio = written(&config(&format!(r#"{{{}, "output_format": "bio", "label": "SKILL"}}"#, paths)));
+        assert_eq!(bio["tags"], serde_json::json!(["O", "B-SKILL", "I-SKILL", "B-SKILL"]));
+        assert_eq!(bio["tag_ids"], serde_json::json!([null, 100, null, 200]));
+        assert_eq!(bio["spans"].as_array().unwrap().len(), 2);
+
+        let oversized = written(&config(&format!(r#"{{{}, "output_format": "bio", "max_doc_tokens": 2}}"#, paths)));
+        assert_eq!(oversized["tags"], serde_json::json!(["O", "O", "O", "O"]));
+
+        assert!(parse_output_format("bio", MatchPolicy::All).is_err());
+        assert!(parse_output_format("iob", MatchPolicy::LeftmostLongest).is_err());
+        assert_eq!(parse_output_format("spans", MatchPolicy::All), Ok(OutputFormat::Spans));
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use phrasekit::verify::{verify_artifacts, CheckStatus};
+
+fn print_usage() {
+    eprintln!("Usage: phrasekit_verify <artifacts_dir> [--require-checksums]");
+    eprintln!("\nChecks that manifest.json, payloads.bin, phrases.daac, phrases.bin, and vocab.json");
+    eprintln!("belong to one build. Exits 0 when every check passes, 1 when any fails.");
+    eprintln!("\nOptions:");
+    eprintln!("  --require-checksums  Fail, rather than skip, when the manifest records no checksums");
+    eprintln!("\nExample:");
+    eprintln!("  phrasekit_verify ./artifacts/");
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let require_checksums = args.iter().any(|arg| arg == "--require-checksums");
+    let dirs: Vec<&String> = args.iter().filter(|arg| arg.as_str() != "--require-checksums").collect();
+    if dirs.len() != 1 || dirs[0].starts_with("--") {
+        print_usage();
+        std::process::exit(2);
+    }
+
+    let dir = PathBuf::from(dirs[0]);
+    let report = verify_artifacts(&dir, require_checksums);
+    let width = report.checks.iter().map(|check| check.name.len()).max().unwrap_or(0);
+    for check in &report.checks {
+        println!("  {}  {:<width$}  {}", check.status.label(), check.name, check.detail, width = width);
+    }
+
+    if report.passed() {
+        println!("\nPASS: {} ({} checks passed, {} skipped)", dir.display(), report.count(CheckStatus::Pass), report.count(CheckStatus::Skip));
+    } else {
+        let failed = report.count(CheckStatus::Fail);
+        println!("\nFAIL: {} ({} of {} checks failed)", dir.display(), failed, report.checks.len());
+        std::process::exit(1);
+    }
+}
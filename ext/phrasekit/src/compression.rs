@@ -0,0 +1,115 @@
+//! Transparent gzip and zstd for the CLI tools' line-oriented files, chosen by
+//! path extension.
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Plain,
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    /// `.gz` is gzip and `.zst` is zstd; anything else is read and written as-is.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Codec::Gzip,
+            Some("zst") => Codec::Zstd,
+            _ => Codec::Plain,
+        }
+    }
+}
+
+/// Opens `path` for buffered reading, decompressing by extension.
+/// Concatenated gzip members are read as one stream, as `zcat` does.
+pub fn open_input(path: impl AsRef<Path>) -> io::Result<Box<dyn BufRead + Send>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    Ok(match Codec::from_path(path) {
+        Codec::Plain => Box::new(BufReader::new(file)),
+        Codec::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(BufReader::new(file)))),
+        Codec::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+    })
+}
+
+/// A buffered output file, compressed by extension. Call `finish` when done:
+/// dropping it instead may leave a compressed stream without its trailer.
+pub enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+/// Creates `path` for buffered writing, compressing by extension.
+pub fn create_output(path: impl AsRef<Path>) -> io::Result<Output> {
+    let path = path.as_ref();
+    let writer = BufWriter::new(File::create(path)?);
+    Ok(match Codec::from_path(path) {
+        Codec::Plain => Output::Plain(writer),
+        Codec::Gzip => Output::Gzip(GzEncoder::new(writer, Compression::default())),
+        Codec::Zstd => Output::Zstd(zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+    })
+}
+
+impl Output {
+    /// Writes any compression trailer and flushes the file.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+            Output::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    /// Flushes through the compressor, so the bytes written so far can be
+    /// decompressed before `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["lines.jsonl", "lines.jsonl.gz", "lines.jsonl.zst"] {
+            let path = dir.path().join(name);
+            let mut output = create_output(&path).unwrap();
+            for i in 0..1000 {
+                writeln!(output, "{{\"line\": {}}}", i).unwrap();
+            }
+            output.finish().unwrap();
+
+            let lines: Vec<String> = open_input(&path).unwrap().lines().collect::<io::Result<_>>().unwrap();
+            assert_eq!(lines.len(), 1000, "{}", name);
+            assert_eq!(lines[999], "{\"line\": 999}");
+        }
+
+        let raw = std::fs::read(dir.path().join("lines.jsonl.gz")).unwrap();
+        assert_eq!(&raw[..2], &[0x1f, 0x8b]);
+        assert_eq!(Codec::from_path(Path::new("corpus.JSONL")), Codec::Plain);
+    }
+}
@@ -0,0 +1,120 @@
+//! Loading the CLI tools' JSON config files, with errors that point at the
+//! offending field and range checks reported together.
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error)]
+pub enum ConfigError {
+    #[error("Failed to read config {path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Malformed JSON, a wrongly typed value, or a field the config does not
+    /// have (every config struct denies unknown fields).
+    #[error("{path}:{line}:{column}: {message}")]
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Invalid config:{}", .0.iter().map(|problem| format!("\n  - {}", problem)).collect::<String>())]
+    Invalid(Vec<String>),
+}
+
+/// The CLIs return errors from `main`, which prints them with `Debug`.
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Range and consistency checks serde cannot express.
+pub trait Validate {
+    /// Every problem with the config, so all of them can be fixed in one pass.
+    fn problems(&self) -> Vec<String>;
+}
+
+/// Reads and parses the config at `path`; see `parse`.
+pub fn load<T: DeserializeOwned>(path: &str) -> Result<T, ConfigError> {
+    let data = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_string(),
+        source,
+    })?;
+    parse(path, &data)
+}
+
+/// Parses config JSON, reporting errors as `path:line:column: message`.
+pub fn parse<T: DeserializeOwned>(path: &str, data: &str) -> Result<T, ConfigError> {
+    serde_json::from_str(data).map_err(|e| {
+        // serde_json appends the position to its message; it is reported up front instead
+        let message = e.to_string();
+        let suffix = format!(" at line {} column {}", e.line(), e.column());
+        ConfigError::Parse {
+            path: path.to_string(),
+            line: e.line(),
+            column: e.column(),
+            message: message.strip_suffix(&suffix).unwrap_or(&message).to_string(),
+        }
+    })
+}
+
+pub fn validate<T: Validate>(config: &T) -> Result<(), ConfigError> {
+    let problems = config.problems();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Sample {
+        separator_id: u32,
+        #[serde(default)]
+        min_n: usize,
+    }
+
+    impl Validate for Sample {
+        fn problems(&self) -> Vec<String> {
+            let mut problems = Vec::new();
+            if self.separator_id == 0 {
+                problems.push("separator_id must be non-zero".to_string());
+            }
+            if self.min_n == 0 {
+                problems.push("min_n must be >= 1".to_string());
+            }
+            problems
+        }
+    }
+
+    #[test]
+    fn test_parse_reports_unknown_field_position() {
+        let err = parse::<Sample>("config.json", "{\n  \"seperator_id\": 4\n}").unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("config.json:2:16: unknown field `seperator_id`"), "{}", message);
+        assert!(message.contains("`separator_id`") && !message.contains(" at line "), "{}", message);
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let sample: Sample = parse("config.json", r#"{"separator_id": 0}"#).unwrap();
+        let message = validate(&sample).unwrap_err().to_string();
+        assert_eq!(message, "Invalid config:\n  - separator_id must be non-zero\n  - min_n must be >= 1");
+
+        let sample: Sample = parse("config.json", r#"{"separator_id": 4, "min_n": 2}"#).unwrap();
+        assert!(validate(&sample).is_ok());
+    }
+}
@@ -1,8 +1,298 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use thiserror::Error;
+use unicode_normalization::{is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization as _};
+
+/// Artifact format written by this version of the builder. Manifests without a
+/// `format_version` field predate versioning and are treated as version 0.
+/// Version 2 introduced 64-bit phrase_ids.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Newest payload record layout this version reads and writes; see
+/// `payload::PayloadFormat`.
+pub const PAYLOAD_FORMAT_VERSION: u32 = 3;
+
+/// Serialization format of `phrases.daac` produced by the linked daachorse.
+/// daachorse keeps its serialized layout stable within a major version, so
+/// this changes only with a daachorse major upgrade.
+pub const AUTOMATON_FORMAT: &str = "daachorse-1";
+
+/// Number of patterns in a serialized `AUTOMATON_FORMAT` automaton, or
+/// `None` if `bytes` is not exactly the size that layout implies. daachorse
+/// does not expose the count, but serializes one output record per pattern:
+/// a u32-counted vector of 12-byte states, a u32-counted vector of 12-byte
+/// outputs, a match-kind byte, and a u32 state count.
+pub fn automaton_pattern_count(bytes: &[u8]) -> Option<usize> {
+    const RECORD: usize = 12;
+    let read_len = |offset: usize| -> Option<usize> {
+        let len = bytes.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes(len.try_into().unwrap()) as usize)
+    };
+    let num_states = read_len(0)?;
+    let outputs_at = num_states.checked_mul(RECORD)?.checked_add(4)?;
+    let num_outputs = read_len(outputs_at)?;
+    let expected = num_outputs.checked_mul(RECORD)?.checked_add(outputs_at + 4 + 1 + 4)?;
+    (bytes.len() == expected).then_some(num_outputs)
+}
+
+/// Bytes per token in the `u32_sep` encoding: the little-endian token ID, then
+/// the separator.
+pub const TOKEN_STRIDE: usize = 8;
+
+/// Bytes per token in the `u24_sep1` encoding: three digits, then
+/// `U24_SEPARATOR`.
+pub const COMPACT_TOKEN_STRIDE: usize = 4;
+
+/// Ends every token in the `u24_sep1` encoding. Digits are base 255, so the
+/// byte never occurs inside a token and a match can only start and end on
+/// token boundaries.
+const U24_SEPARATOR: u8 = 0xFF;
+
+/// Token IDs below this fit the `u24_sep1` encoding: three base-255 digits.
+pub const U24_TOKEN_LIMIT: u32 = 255 * 255 * 255;
+
+/// Byte layout of token sequences as automaton input. Patterns and queries
+/// must use the same one, so it is recorded in the manifest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenEncoding {
+    /// 8 bytes a token: the u32 ID and the u32 separator_id, little-endian.
+    /// What every build used before `encoding` was recorded.
+    #[default]
+    U32Sep,
+    /// 4 bytes a token, half the automaton input: the ID as three base-255
+    /// digits, least significant first, then `U24_SEPARATOR`. Builds need
+    /// every token ID below `U24_TOKEN_LIMIT`; at query time an ID outside
+    /// that range (the separator_id, for one) is encoded as four
+    /// `U24_SEPARATOR` bytes, which no pattern can match.
+    U24Sep1,
+}
+
+impl TokenEncoding {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "u32_sep" => Some(Self::U32Sep),
+            "u24_sep1" => Some(Self::U24Sep1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::U32Sep => "u32_sep",
+            Self::U24Sep1 => "u24_sep1",
+        }
+    }
+
+    pub fn stride(self) -> usize {
+        match self {
+            Self::U32Sep => TOKEN_STRIDE,
+            Self::U24Sep1 => COMPACT_TOKEN_STRIDE,
+        }
+    }
+
+    /// First pattern token ID, if any, the encoding cannot represent.
+    pub fn unencodable<'a>(self, token_ids: impl IntoIterator<Item = &'a u32>) -> Option<u32> {
+        match self {
+            Self::U32Sep => None,
+            Self::U24Sep1 => token_ids.into_iter().copied().find(|&id| id >= U24_TOKEN_LIMIT),
+        }
+    }
+
+    /// Encodes token IDs as automaton input, the same way patterns are built.
+    pub fn encode(self, token_ids: &[u32], separator: u32) -> Vec<u8> {
+        match self {
+            Self::U32Sep => encode_token_ids(token_ids, separator),
+            Self::U24Sep1 => {
+                let mut bytes = Vec::with_capacity(token_ids.len() * COMPACT_TOKEN_STRIDE);
+                for &token_id in token_ids {
+                    bytes.extend_from_slice(&u24_token_bytes(token_id));
+                }
+                bytes
+            }
+        }
+    }
+
+    /// `encode` as a byte iterator, so long inputs can be scanned without
+    /// holding their encoding in memory.
+    pub fn encode_iter<I>(self, token_ids: I, separator: u32) -> impl Iterator<Item = u8>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        token_ids.into_iter().flat_map(move |token_id| {
+            let mut bytes = [0; TOKEN_STRIDE];
+            match self {
+                Self::U32Sep => {
+                    bytes[..4].copy_from_slice(&token_id.to_le_bytes());
+                    bytes[4..].copy_from_slice(&separator.to_le_bytes());
+                }
+                Self::U24Sep1 => bytes[..COMPACT_TOKEN_STRIDE].copy_from_slice(&u24_token_bytes(token_id)),
+            }
+            bytes.into_iter().take(self.stride())
+        })
+    }
+
+    /// Token range `[start, end)` of an automaton match over `encode` input,
+    /// or `None` if either byte offset falls inside a token's encoding, which
+    /// a consistent automaton never reports.
+    pub fn token_span(self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let stride = self.stride();
+        (start.is_multiple_of(stride) && end.is_multiple_of(stride)).then_some((start / stride, end / stride))
+    }
+}
+
+/// One token in `u24_sep1`: three base-255 digits, least significant first,
+/// then the separator byte. IDs past the limit become a block of separator
+/// bytes, which no pattern contains.
+fn u24_token_bytes(token_id: u32) -> [u8; COMPACT_TOKEN_STRIDE] {
+    if token_id < U24_TOKEN_LIMIT {
+        let id = token_id as usize;
+        [(id % 255) as u8, (id / 255 % 255) as u8, (id / (255 * 255)) as u8, U24_SEPARATOR]
+    } else {
+        [U24_SEPARATOR; COMPACT_TOKEN_STRIDE]
+    }
+}
+
+/// Encodes token IDs as `u32_sep` automaton input.
+pub fn encode_token_ids(token_ids: &[u32], separator: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(token_ids.len() * TOKEN_STRIDE);
+    for &token_id in token_ids {
+        bytes.extend_from_slice(&token_id.to_le_bytes());
+        bytes.extend_from_slice(&separator.to_le_bytes());
+    }
+    bytes
+}
+
+/// `TokenEncoding::token_span` for `u32_sep` input from `encode_token_ids`.
+pub fn token_span(start: usize, end: usize) -> Option<(usize, usize)> {
+    TokenEncoding::U32Sep.token_span(start, end)
+}
+
+/// Serializes every pattern's token IDs for phrases.bin, in pattern-id order:
+/// a u8 token count followed by that many little-endian u32 IDs.
+pub fn encode_pattern_tokens<'a>(patterns: impl IntoIterator<Item = &'a [u32]>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for token_ids in patterns {
+        bytes.push(token_ids.len() as u8);
+        for id in token_ids {
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn decode_pattern_tokens(mut bytes: &[u8]) -> Result<Vec<Vec<u32>>, String> {
+    let mut patterns = Vec::new();
+    while let Some((&len, rest)) = bytes.split_first() {
+        let size = len as usize * 4;
+        if rest.len() < size {
+            return Err(format!("phrases.bin is truncated in pattern {}", patterns.len()));
+        }
+        let (ids, rest) = rest.split_at(size);
+        patterns.push(ids.chunks_exact(4).map(|id| u32::from_le_bytes(id.try_into().unwrap())).collect());
+        bytes = rest;
+    }
+    Ok(patterns)
+}
+
+/// `vocab` of artifacts built from caller-supplied token IDs.
+pub const EXTERNAL_VOCAB: &str = "external";
+
+/// How the build normalized token strings before assigning IDs; encoders must
+/// normalize query tokens the same way before looking them up in `tokens`.
+/// Exact-case forms from case-sensitive slots are looked up unnormalized
+/// either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Lowercased; what every build did before `normalization` was recorded.
+    #[default]
+    Lowercase,
+    /// Kept as given, for builds with `case_sensitive: true`.
+    None,
+}
+
+impl Normalization {
+    pub fn from_case_sensitive(case_sensitive: bool) -> Self {
+        if case_sensitive {
+            Self::None
+        } else {
+            Self::Lowercase
+        }
+    }
+
+    pub fn is_case_sensitive(self) -> bool {
+        self == Self::None
+    }
+
+    pub fn apply(self, token: &str) -> Cow<'_, str> {
+        match self {
+            Self::Lowercase => Cow::Owned(token.to_lowercase()),
+            Self::None => Cow::Borrowed(token),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase",
+            Self::None => "none",
+        }
+    }
+}
+
+/// Unicode normalization form applied to token strings before `Normalization`,
+/// so composed and decomposed spellings ("café" as one code point or as "e"
+/// plus a combining accent) get one ID. Applies to exact-case forms too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeNormalization {
+    /// Code points kept as given; what every build did before
+    /// `unicode_normalization` was recorded.
+    #[default]
+    None,
+    /// Canonical composition.
+    Nfc,
+    /// Compatibility composition, which also folds ligatures, full-width
+    /// forms, and the like ("ﬁ" to "fi").
+    Nfkc,
+}
+
+impl UnicodeNormalization {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(Self::None),
+            "nfc" => Some(Self::Nfc),
+            "nfkc" => Some(Self::Nfkc),
+            _ => None,
+        }
+    }
+
+    /// Borrows `token` when it is already in normal form, which the quick
+    /// check confirms for almost all text without allocating.
+    pub fn apply(self, token: &str) -> Cow<'_, str> {
+        match self {
+            Self::None => Cow::Borrowed(token),
+            Self::Nfc if is_nfc_quick(token.chars()) == IsNormalized::Yes => Cow::Borrowed(token),
+            Self::Nfc => Cow::Owned(token.nfc().collect()),
+            Self::Nfkc if is_nfkc_quick(token.chars()) == IsNormalized::Yes => Cow::Borrowed(token),
+            Self::Nfkc => Cow::Owned(token.nfkc().collect()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Nfc => "nfc",
+            Self::Nfkc => "nfkc",
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
@@ -13,6 +303,74 @@ pub struct Manifest {
     pub salience_threshold: Option<f32>,
     pub built_at: String,
     pub separator_id: u32,
+    /// Largest token ID in any pattern, so a vocabulary can be checked
+    /// against the automaton; absent in manifests written before it was
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_token_id: Option<u32>,
+    /// Tokens in the shortest pattern, so loading can refuse zero-length
+    /// patterns without a pass over the payloads; absent in manifests written
+    /// before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_pattern_len: Option<usize>,
+    /// Tokens in the longest pattern, which bounds streaming and wildcard
+    /// re-matching; manifests written before it was recorded get it from a pass
+    /// over the payloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pattern_len: Option<usize>,
+    #[serde(default)]
+    pub format_version: u32,
+    /// Layout of the records in payloads.bin. Manifests without it use the
+    /// original 17-byte layout, version 1.
+    #[serde(default = "default_payload_format_version")]
+    pub payload_format_version: u32,
+    /// `EXTERNAL_VOCAB` when patterns were built from pre-tokenized IDs and
+    /// no vocab.json exists; absent when the builder wrote the vocabulary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vocab: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub negative_rules: Vec<NegativeRule>,
+    /// Absent in manifests written before it was recorded, which were all
+    /// lowercased.
+    #[serde(default)]
+    pub normalization: Normalization,
+    /// Absent in manifests written before it was recorded, which kept code
+    /// points as given.
+    #[serde(default)]
+    pub unicode_normalization: UnicodeNormalization,
+    /// Absent in manifests written before it was recorded, which were all
+    /// `u32_sep`.
+    #[serde(default)]
+    pub encoding: TokenEncoding,
+    /// `AUTOMATON_FORMAT` of the builder that serialized the automaton; absent
+    /// in manifests written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub automaton_format: Option<String>,
+    /// Lowercase hex SHA-256 of `phrases.daac`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub automaton_sha256: Option<String>,
+    /// Lowercase hex SHA-256 of the payload table; for sharded payloads, of
+    /// the shards concatenated in load order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payloads_sha256: Option<String>,
+}
+
+/// Lowercase hex SHA-256 of `bytes`, as recorded in manifests.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn default_payload_format_version() -> u32 {
+    1
+}
+
+/// A negative phrase that suppresses target phrases matched within `window` tokens of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NegativeRule {
+    pub phrase_id: u64,
+    pub suppresses: Vec<u64>,
+    #[serde(default)]
+    pub window: usize,
 }
 
 #[derive(Error, Debug)]
@@ -24,7 +382,6 @@ pub enum ManifestError {
     Json(#[from] serde_json::Error),
 
     #[error("Invalid manifest: {0}")]
-    #[allow(dead_code)]
     Invalid(String),
 }
 
@@ -33,17 +390,62 @@ impl Manifest {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
         let manifest: Manifest = serde_json::from_reader(reader)?;
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    pub fn validate(&self) -> Result<(), ManifestError> {
+        for (field, value) in [("version", &self.version), ("tokenizer", &self.tokenizer)] {
+            if value.trim().is_empty() {
+                return Err(ManifestError::Invalid(format!("{} must not be empty", field)));
+            }
+        }
 
-        if manifest.separator_id == 0 {
+        if self.num_patterns == 0 {
+            return Err(ManifestError::Invalid(
+                "num_patterns must be greater than zero".to_string(),
+            ));
+        }
+
+        if self.separator_id == 0 {
             return Err(ManifestError::Invalid(
                 "separator_id must be non-zero".to_string(),
             ));
         }
 
-        Ok(manifest)
+        if self.min_pattern_len == Some(0) {
+            return Err(ManifestError::Invalid(
+                "min_pattern_len must be greater than zero; the build contains a zero-length pattern".to_string(),
+            ));
+        }
+
+        if let (Some(min), Some(max)) = (self.min_pattern_len, self.max_pattern_len) {
+            if min > max {
+                return Err(ManifestError::Invalid(format!(
+                    "min_pattern_len {} is greater than max_pattern_len {}",
+                    min, max
+                )));
+            }
+        }
+
+        if self.format_version > FORMAT_VERSION {
+            return Err(ManifestError::Invalid(format!(
+                "format_version {} is newer than the supported version {}",
+                self.format_version, FORMAT_VERSION
+            )));
+        }
+
+        if !(1..=PAYLOAD_FORMAT_VERSION).contains(&self.payload_format_version) {
+            return Err(ManifestError::Invalid(format!(
+                "payload_format_version {} is not supported (this build reads 1 to {})",
+                self.payload_format_version, PAYLOAD_FORMAT_VERSION
+            )));
+        }
+
+        Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn validate_compatible(&self, other: &Manifest) -> Result<(), ManifestError> {
         if self.tokenizer != other.tokenizer {
             return Err(ManifestError::Invalid(format!(
@@ -59,6 +461,30 @@ impl Manifest {
             )));
         }
 
+        if self.normalization != other.normalization {
+            return Err(ManifestError::Invalid(format!(
+                "Normalization mismatch: expected {}, got {}",
+                self.normalization.as_str(),
+                other.normalization.as_str()
+            )));
+        }
+
+        if self.encoding != other.encoding {
+            return Err(ManifestError::Invalid(format!(
+                "Encoding mismatch: expected {}, got {}",
+                self.encoding.as_str(),
+                other.encoding.as_str()
+            )));
+        }
+
+        if self.unicode_normalization != other.unicode_normalization {
+            return Err(ManifestError::Invalid(format!(
+                "Unicode normalization mismatch: expected {}, got {}",
+                self.unicode_normalization.as_str(),
+                other.unicode_normalization.as_str()
+            )));
+        }
+
         Ok(())
     }
 }
@@ -67,6 +493,90 @@ impl Manifest {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pattern_tokens_roundtrip() {
+        let bytes = encode_pattern_tokens([&[3, 70000][..], &[1]]);
+        assert_eq!(bytes.len(), 1 + 8 + 1 + 4);
+        assert_eq!(decode_pattern_tokens(&bytes).unwrap(), vec![vec![3, 70000], vec![1]]);
+        assert!(decode_pattern_tokens(&bytes[..bytes.len() - 1]).unwrap_err().contains("truncated in pattern 1"));
+    }
+
+    #[test]
+    fn test_automaton_pattern_count_reads_the_serialized_layout() {
+        let patterns = [&[1, 2][..], &[2], &[1, 2, 3]];
+        let automaton = daachorse::DoubleArrayAhoCorasick::<u32>::with_values(
+            patterns.iter().enumerate().map(|(i, ids)| (encode_token_ids(ids, 4294967294), i as u32)),
+        )
+        .unwrap();
+        let bytes = automaton.serialize();
+        assert_eq!(automaton_pattern_count(&bytes), Some(3));
+
+        assert_eq!(automaton_pattern_count(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(automaton_pattern_count(&[bytes.as_slice(), &[0]].concat()), None);
+        assert_eq!(automaton_pattern_count(&[]), None);
+    }
+
+    #[test]
+    fn test_token_span_rejects_unaligned_offsets() {
+        let bytes = encode_token_ids(&[7, 8, 9], 4294967294);
+        assert_eq!(bytes.len(), 3 * TOKEN_STRIDE);
+        assert_eq!(&bytes[8..12], &8u32.to_le_bytes());
+
+        assert_eq!(token_span(8, 24), Some((1, 3)));
+        // A match starting or ending inside a token's encoding has no token
+        // span; rounding would have mapped these to tokens 0..2 and 1..3
+        assert_eq!(token_span(4, 16), None);
+        assert_eq!(token_span(8, 20), None);
+    }
+
+    #[test]
+    fn test_encode_iter_matches_encode() {
+        let token_ids = [7, 255, U24_TOKEN_LIMIT - 1, U24_TOKEN_LIMIT, 4294967294];
+        for encoding in [TokenEncoding::U32Sep, TokenEncoding::U24Sep1] {
+            let streamed: Vec<u8> = encoding.encode_iter(token_ids, 4294967294).collect();
+            assert_eq!(streamed, encoding.encode(&token_ids, 4294967294), "{}", encoding.as_str());
+        }
+    }
+
+    #[test]
+    fn test_compact_encoding_keeps_matches_on_token_boundaries() {
+        let encoding = TokenEncoding::U24Sep1;
+        let bytes = encoding.encode(&[7, 255, U24_TOKEN_LIMIT - 1, 4294967294], 4294967294);
+        assert_eq!(bytes.len(), 4 * COMPACT_TOKEN_STRIDE);
+        assert_eq!(&bytes[..8], &[7, 0, 0, 0xFF, 0, 1, 0, 0xFF]);
+        assert_eq!(&bytes[8..12], &[254, 254, 254, 0xFF]);
+        assert_eq!(&bytes[12..], &[0xFF; 4]);
+        assert_eq!(encoding.token_span(4, 12), Some((1, 3)));
+        assert_eq!(encoding.token_span(2, 8), None);
+
+        // Digits never equal the separator byte, so overlapping search finds
+        // only aligned hits, and none spanning the separator's barrier block
+        let patterns = [&[U24_TOKEN_LIMIT - 1, 1][..], &[1]];
+        let automaton = daachorse::DoubleArrayAhoCorasick::<u32>::with_values(
+            patterns.iter().enumerate().map(|(i, ids)| (encoding.encode(ids, 4294967294), i as u32)),
+        )
+        .unwrap();
+        let input = encoding.encode(&[254 * 255, 9, 1, 4294967294, 1], 4294967294);
+        let spans: Vec<(usize, usize, u32)> = automaton
+            .find_overlapping_iter(&input)
+            .map(|m| {
+                let (start, end) = encoding.token_span(m.start(), m.end()).expect("match off token boundaries");
+                (start, end, m.value())
+            })
+            .collect();
+        assert_eq!(spans, vec![(2, 3, 1), (4, 5, 1)]);
+
+        assert_eq!(encoding.unencodable(&[1, U24_TOKEN_LIMIT, 2]), Some(U24_TOKEN_LIMIT));
+        assert_eq!(TokenEncoding::U32Sep.unencodable(&[u32::MAX]), None);
+        assert_eq!(TokenEncoding::from_str("u24_sep1"), Some(encoding));
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"version": "v1", "tokenizer": "t", "num_patterns": 1, "built_at": "", "separator_id": 9, "encoding": "u24_sep1"}"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.encoding, encoding);
+        assert_eq!(manifest_with("v1", "t", 1).encoding, TokenEncoding::U32Sep);
+    }
+
     #[test]
     fn test_manifest_deserialize() {
         let json = r#"{
@@ -84,5 +594,145 @@ mod tests {
         assert_eq!(manifest.tokenizer, "scientist-v1");
         assert_eq!(manifest.num_patterns, 1287345);
         assert_eq!(manifest.separator_id, 4294967294);
+        assert!(manifest.negative_rules.is_empty());
+        assert_eq!(manifest.format_version, 0);
+        assert_eq!(manifest.payload_format_version, 1);
+    }
+
+    #[test]
+    fn test_manifest_rejects_unknown_payload_format_version() {
+        let mut manifest = manifest_with("v1", "t", 1);
+        manifest.payload_format_version = PAYLOAD_FORMAT_VERSION;
+        assert!(manifest.validate().is_ok());
+
+        manifest.payload_format_version = PAYLOAD_FORMAT_VERSION + 1;
+        assert!(invalid_message(&manifest).contains("payload_format_version"));
+        manifest.payload_format_version = 0;
+        assert!(invalid_message(&manifest).contains("payload_format_version"));
+    }
+
+    #[test]
+    fn test_manifest_rejects_newer_format_version() {
+        let json = format!(
+            r#"{{
+            "version": "v1",
+            "tokenizer": "t",
+            "num_patterns": 1,
+            "built_at": "2025-09-25T18:44:00Z",
+            "separator_id": 4294967294,
+            "format_version": {}
+        }}"#,
+            FORMAT_VERSION + 1
+        );
+
+        let manifest: Manifest = serde_json::from_str(&json).unwrap();
+        assert!(matches!(manifest.validate(), Err(ManifestError::Invalid(_))));
+    }
+
+    fn manifest_with(version: &str, tokenizer: &str, num_patterns: usize) -> Manifest {
+        let json = format!(
+            r#"{{
+            "version": {:?},
+            "tokenizer": {:?},
+            "num_patterns": {},
+            "built_at": "2025-09-25T18:44:00Z",
+            "separator_id": 4294967294
+        }}"#,
+            version, tokenizer, num_patterns
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn invalid_message(manifest: &Manifest) -> String {
+        match manifest.validate() {
+            Err(ManifestError::Invalid(message)) => message,
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_manifest_rejects_empty_fields() {
+        assert!(manifest_with("v1", "t", 1).validate().is_ok());
+
+        assert!(invalid_message(&manifest_with("", "t", 1)).contains("version"));
+        assert!(invalid_message(&manifest_with("  ", "t", 1)).contains("version"));
+        assert!(invalid_message(&manifest_with("v1", "", 1)).contains("tokenizer"));
+        assert!(invalid_message(&manifest_with("v1", "\t", 1)).contains("tokenizer"));
+        assert!(invalid_message(&manifest_with("v1", "t", 0)).contains("num_patterns"));
+
+        let mut degenerate = manifest_with("v1", "t", 1);
+        degenerate.min_pattern_len = Some(0);
+        assert!(invalid_message(&degenerate).contains("min_pattern_len"));
+
+        let mut inverted = manifest_with("v1", "t", 1);
+        inverted.min_pattern_len = Some(3);
+        inverted.max_pattern_len = Some(2);
+        assert!(invalid_message(&inverted).contains("max_pattern_len 2"));
+    }
+
+    #[test]
+    fn test_manifest_normalization() {
+        let legacy = manifest_with("v1", "t", 1);
+        assert_eq!(legacy.normalization, Normalization::Lowercase);
+        assert_eq!(legacy.normalization.apply("BRCA1"), "brca1");
+
+        let mut exact = legacy.clone();
+        exact.normalization = Normalization::from_case_sensitive(true);
+        let json = serde_json::to_string(&exact).unwrap();
+        assert!(json.contains(r#""normalization":"none""#), "{}", json);
+        let exact: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(exact.normalization.apply("BRCA1"), "BRCA1");
+
+        let err = legacy.validate_compatible(&exact).unwrap_err();
+        assert!(err.to_string().contains("Normalization mismatch: expected lowercase, got none"), "{}", err);
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let decomposed = "cafe\u{301}";
+        assert_eq!(UnicodeNormalization::None.apply(decomposed), decomposed);
+        assert_eq!(UnicodeNormalization::Nfc.apply(decomposed), "caf\u{e9}");
+        assert!(matches!(UnicodeNormalization::Nfc.apply("caf\u{e9}"), Cow::Borrowed(_)));
+        assert_eq!(UnicodeNormalization::Nfc.apply("\u{fb01}le"), "\u{fb01}le");
+        assert_eq!(UnicodeNormalization::Nfkc.apply("\u{fb01}le"), "file");
+        assert_eq!(UnicodeNormalization::from_str("nfkc"), Some(UnicodeNormalization::Nfkc));
+        assert_eq!(UnicodeNormalization::from_str("NFD"), None);
+
+        let legacy = manifest_with("v1", "t", 1);
+        assert_eq!(legacy.unicode_normalization, UnicodeNormalization::None);
+        let mut nfc = legacy.clone();
+        nfc.unicode_normalization = UnicodeNormalization::Nfc;
+        let json = serde_json::to_string(&nfc).unwrap();
+        assert!(json.contains(r#""unicode_normalization":"nfc""#), "{}", json);
+
+        let err = legacy.validate_compatible(&nfc).unwrap_err();
+        assert!(err.to_string().contains("Unicode normalization mismatch: expected none, got nfc"), "{}", err);
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(manifest_with("v1", "t", 1).automaton_sha256.is_none());
+    }
+
+    #[test]
+    fn test_manifest_negative_rules() {
+        let json = r#"{
+            "version": "v1",
+            "tokenizer": "t",
+            "num_patterns": 3,
+            "built_at": "2025-09-25T18:44:00Z",
+            "separator_id": 4294967294,
+            "negative_rules": [{"phrase_id": 9, "suppresses": [1, 2], "window": 3}]
+        }"#;
+
+        let manifest: Manifest = serde_json::from_str(json).unwrap();
+        assert_eq!(manifest.negative_rules.len(), 1);
+        assert_eq!(manifest.negative_rules[0].phrase_id, 9);
+        assert_eq!(manifest.negative_rules[0].suppresses, vec![1, 2]);
+        assert_eq!(manifest.negative_rules[0].window, 3);
     }
 }
\ No newline at end of file
@@ -1,11 +1,20 @@
-use crate::manifest::Manifest;
-use crate::payload::{load_payloads, Payload};
-use crate::policy::{resolve_overlaps, Match, MatchPolicy};
+use crate::manifest::{automaton_pattern_count, Manifest, ManifestError, NegativeRule, Normalization, UnicodeNormalization, AUTOMATON_FORMAT};
+use crate::payload::{Payload, PayloadFormat};
+use crate::payload_store::{payloads_sha256, shard_paths, PayloadStore};
+use crate::policy::{apply_negative_rules, resolve_overlaps_pinned, Match, MatchFilters, MatchPolicy, Resolution,
+    ScoreAggregation, StreamSummary, WindowedResolver, DEFAULT_MAX_CLUSTER};
+use crate::result_cache::ResultCache;
 use daachorse::DoubleArrayAhoCorasick;
-use std::fs::File;
-use std::io::BufReader;
+use memmap2::Mmap;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,16 +28,303 @@ pub enum MatcherError {
     #[error("Automaton error: {0}")]
     Automaton(String),
 
+    #[error("Checksum mismatch for {artifact}: manifest records {expected}, file hashes to {actual}")]
+    ChecksumMismatch {
+        artifact: &'static str,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Invalid payload {index}: {reason}")]
+    InvalidPayload { index: usize, reason: String },
+
+    #[error("Vocabulary incompatible with artifacts: {0}")]
+    Incompatible(String),
+
+    #[error("Invalid matcher set: {0}")]
+    InvalidSet(String),
+
     #[error("Matcher not loaded")]
-    #[allow(dead_code)]
     NotLoaded,
 }
 
+/// Options controlling how artifacts are loaded.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    /// Memory-map the payload table and decode payloads on demand instead of preloading them.
+    pub lazy_payloads: bool,
+    /// Maximum number of decoded payloads cached in lazy mode.
+    pub payload_cache_capacity: usize,
+    /// Largest overlap cluster `SalienceMax` resolves exactly; bigger clusters
+    /// fall back to top-k selection and mark the result degraded.
+    pub max_cluster: usize,
+    /// Fail on inconsistent artifacts. When false, consistency problems are
+    /// recorded in `Matcher::diagnostics` and loading continues.
+    pub strict: bool,
+    /// Check artifact bytes against SHA-256 checksums recorded in the
+    /// manifest before deserializing them. A mismatch fails even when not
+    /// strict; a checksum the manifest does not record is skipped with a
+    /// diagnostic.
+    pub verify_checksums: bool,
+    /// Scan every payload for values the builder never writes (non-finite
+    /// salience) and check negative rules against the loaded phrase_ids.
+    pub validate_patterns: bool,
+    /// Maximum number of match results cached by token stream; 0 disables the
+    /// cache.
+    pub result_cache_capacity: usize,
+    /// Deserialize the automaton from a read-only memory map of its file
+    /// instead of reading the file onto the heap. See `Matcher::load_mmap`.
+    pub mmap_automaton: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            lazy_payloads: false,
+            payload_cache_capacity: 65_536,
+            max_cluster: DEFAULT_MAX_CLUSTER,
+            strict: true,
+            verify_checksums: false,
+            validate_patterns: false,
+            result_cache_capacity: 0,
+            mmap_automaton: false,
+        }
+    }
+}
+
+/// How `match_tokens_unknown` treats tokens the caller's vocabulary could not
+/// encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnkPolicy {
+    /// Match the `<UNK>` id like any other token (the default behavior).
+    Map,
+    /// Unknown tokens are barriers: no match may span one.
+    Break,
+    /// An unknown token matches any candidate token id in its position. Each
+    /// candidate is substituted and the automaton re-run over the surrounding
+    /// window, so cost grows with the number of candidates.
+    Wildcard,
+}
+
+impl UnkPolicy {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "map" => Some(Self::Map),
+            "break" => Some(Self::Break),
+            "wildcard" => Some(Self::Wildcard),
+            _ => None,
+        }
+    }
+}
+
+/// Positions of unknown tokens in a document and how to treat them.
+#[derive(Debug, Clone, Copy)]
+pub struct UnknownTokens<'a> {
+    pub positions: &'a [usize],
+    pub policy: UnkPolicy,
+    /// Token ids tried at each unknown position under `UnkPolicy::Wildcard`,
+    /// normally every id in the vocabulary.
+    pub candidates: &'a [u32],
+}
+
+impl UnknownTokens<'_> {
+    pub const NONE: UnknownTokens<'static> = UnknownTokens {
+        positions: &[],
+        policy: UnkPolicy::Map,
+        candidates: &[],
+    };
+}
+
+/// Match calls whose latency feeds the `Stats` percentiles.
+const LATENCY_WINDOW: usize = 1024;
+
+/// Ring buffer of the most recent match latencies, in nanoseconds.
+#[derive(Default)]
+struct LatencyWindow {
+    samples: Vec<u64>,
+    next: usize,
+}
+
+impl LatencyWindow {
+    fn record(&mut self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        if self.samples.len() < LATENCY_WINDOW {
+            self.samples.push(nanos);
+        } else {
+            self.samples[self.next] = nanos;
+        }
+        self.next = (self.next + 1) % LATENCY_WINDOW;
+    }
+
+    /// Nearest-rank percentiles in microseconds; zeros before any call.
+    fn percentiles_us(&self, quantiles: [f64; 3]) -> [u64; 3] {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        quantiles.map(|q| {
+            if sorted.is_empty() {
+                return 0;
+            }
+            let rank = ((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+            sorted[rank - 1] / 1_000
+        })
+    }
+}
+
 pub struct Matcher {
     automaton: DoubleArrayAhoCorasick<u32>,
-    payloads: Vec<Payload>,
+    /// The mapped automaton file with `mmap_automaton`, held for as long as
+    /// the matcher so the file stays mapped read-only the whole time. Never
+    /// read; dropping it with the matcher is what unmaps the file.
+    #[allow(dead_code)]
+    automaton_map: Option<Mmap>,
+    /// Patterns the automaton holds; equal to `payloads.len()` once loaded.
+    automaton_patterns: usize,
+    payloads: PayloadStore,
+    /// Payload index of each phrase_id's first pattern, for `payload_for`.
+    /// Built on the first lookup: it takes a pass over every payload, which
+    /// lazy loading exists to avoid.
+    phrase_index: OnceLock<HashMap<u64, usize>>,
+    negative_rules: HashMap<u64, NegativeRule>,
     manifest: Manifest,
+    /// Set by `load_with_vocab`, for `encode_strings`.
+    vocab: Option<VocabSummary>,
+    /// Kept so `reload` can load replacement artifacts the same way.
+    options: LoadOptions,
+    max_cluster: usize,
+    /// Longest pattern in tokens; bounds the window re-matched per wildcard.
+    max_pattern_len: usize,
+    diagnostics: Vec<String>,
     loaded_at: SystemTime,
+    /// `match_tokens*` calls since load.
+    match_calls: AtomicU64,
+    /// Matches returned by those calls.
+    hits_total: AtomicU64,
+    /// Candidates entering overlap resolution and those it kept, indexed by
+    /// `MatchPolicy as usize`. Counted where resolution actually runs, so
+    /// results served from the result cache are not counted again.
+    raw_hits: [AtomicU64; MatchPolicy::ALL.len()],
+    resolved_hits: [AtomicU64; MatchPolicy::ALL.len()],
+    latencies: Mutex<LatencyWindow>,
+    result_cache: Option<ResultCache>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+/// Compares artifact bytes with the checksums in the manifest. Runs before
+/// the unchecked deserialization, so a truncated or half-written file is
+/// refused rather than loaded.
+fn verify_checksums(
+    manifest: &Manifest,
+    automaton_bytes: &[u8],
+    payloads_path: &Path,
+    diagnostics: &mut Vec<String>,
+) -> Result<(), MatcherError> {
+    if manifest.automaton_sha256.is_none() && manifest.payloads_sha256.is_none() {
+        diagnostics.push("verify_checksums: manifest records no checksums; skipped".to_string());
+        return Ok(());
+    }
+
+    let mut check = |artifact: &'static str, expected: &Option<String>, actual: &dyn Fn() -> std::io::Result<String>| {
+        let Some(expected) = expected else {
+            diagnostics.push(format!("verify_checksums: manifest records no {}_sha256; skipped", artifact));
+            return Ok(());
+        };
+        let actual = actual()?;
+        if actual.eq_ignore_ascii_case(expected) {
+            return Ok(());
+        }
+        Err(MatcherError::ChecksumMismatch {
+            artifact,
+            expected: expected.clone(),
+            actual,
+        })
+    };
+
+    check("automaton", &manifest.automaton_sha256, &|| Ok(format!("{:x}", Sha256::digest(automaton_bytes))))?;
+    check("payloads", &manifest.payloads_sha256, &|| payloads_sha256(payloads_path))
+}
+
+/// The fields of vocab.json needed to check it against a manifest and to
+/// encode string tokens.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VocabSummary {
+    pub separator_id: u32,
+    #[serde(default)]
+    tokens: HashMap<String, u32>,
+    #[serde(default)]
+    special_tokens: HashMap<String, u32>,
+    #[serde(default)]
+    case_sensitive_tokens: HashMap<String, u32>,
+}
+
+impl VocabSummary {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MatcherError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file)).map_err(std::io::Error::from)?)
+    }
+
+    /// Largest ID any entry maps to. IDs need not be contiguous, so this
+    /// rather than `vocab_size` bounds the IDs an encoder can produce.
+    pub fn max_token_id(&self) -> Option<u32> {
+        self.tokens
+            .values()
+            .chain(self.special_tokens.values())
+            .chain(self.case_sensitive_tokens.values())
+            .copied()
+            .max()
+    }
+
+    /// Same lookup as `phrasekit_tag`: after `unicode`, exact-case forms
+    /// first, then normalized as the build was, then `<UNK>`.
+    pub fn encode(&self, tokens: &[String], normalization: Normalization, unicode: UnicodeNormalization) -> Vec<u32> {
+        let unk_id = self.special_tokens.get("<UNK>").copied().unwrap_or(0);
+        tokens
+            .iter()
+            .map(|token| {
+                let token = unicode.apply(token);
+                self.case_sensitive_tokens
+                    .get(token.as_ref())
+                    .or_else(|| self.tokens.get(normalization.apply(&token).as_ref()))
+                    .copied()
+                    .unwrap_or(unk_id)
+            })
+            .collect()
+    }
+}
+
+/// Refuses a vocabulary whose encodings cannot line up with the automaton: a
+/// different separator, or patterns using IDs the vocabulary never assigns
+/// (typically a stale vocab.json). Fails regardless of `strict`.
+fn check_vocab(manifest: &Manifest, vocab: &VocabSummary, diagnostics: &mut Vec<String>) -> Result<(), MatcherError> {
+    if vocab.separator_id != manifest.separator_id {
+        return Err(MatcherError::Incompatible(format!(
+            "vocabulary separator_id {} differs from manifest separator_id {}",
+            vocab.separator_id, manifest.separator_id
+        )));
+    }
+
+    let Some(pattern_max) = manifest.max_token_id else {
+        diagnostics.push("vocab check: manifest records no max_token_id; token range skipped".to_string());
+        return Ok(());
+    };
+    match vocab.max_token_id() {
+        Some(vocab_max) if vocab_max >= pattern_max => Ok(()),
+        vocab_max => Err(MatcherError::Incompatible(format!(
+            "patterns use token IDs up to {} but the vocabulary's largest ID is {}",
+            pattern_max,
+            vocab_max.map_or("none".to_string(), |id| id.to_string())
+        ))),
+    }
+}
+
+/// Fails with `error` in strict mode; otherwise records it as a diagnostic.
+fn report(options: &LoadOptions, diagnostics: &mut Vec<String>, error: MatcherError) -> Result<(), MatcherError> {
+    if options.strict {
+        return Err(error);
+    }
+    diagnostics.push(error.to_string());
+    Ok(())
 }
 
 impl Matcher {
@@ -36,75 +332,590 @@ impl Matcher {
         automaton_path: P,
         payloads_path: P,
         manifest_path: P,
+    ) -> Result<Self, MatcherError> {
+        Self::load_with_options(automaton_path, payloads_path, manifest_path, LoadOptions::default())
+    }
+
+    /// `load` for services that must never run on a damaged artifact: the
+    /// manifest has to record both `automaton_sha256` and `payloads_sha256`,
+    /// and the files must match them before anything is deserialized.
+    ///
+    /// daachorse 1.x only offers unchecked deserialization, so the checksums
+    /// are what make the automaton bytes safe to hand to it.
+    pub fn load_checked<P: AsRef<Path>>(
+        automaton_path: P,
+        payloads_path: P,
+        manifest_path: P,
+    ) -> Result<Self, MatcherError> {
+        let manifest = Manifest::load(manifest_path.as_ref())?;
+        for (field, value) in [("automaton_sha256", &manifest.automaton_sha256), ("payloads_sha256", &manifest.payloads_sha256)] {
+            if value.is_none() {
+                return Err(MatcherError::Manifest(ManifestError::Invalid(format!(
+                    "{} is required for a checked load; rebuild or convert the artifacts",
+                    field
+                ))));
+            }
+        }
+
+        let options = LoadOptions {
+            verify_checksums: true,
+            ..LoadOptions::default()
+        };
+        Self::load_with_options(automaton_path, payloads_path, manifest_path, options)
+    }
+
+    /// `load_with_options` with `mmap_automaton` set: the automaton is
+    /// deserialized from a read-only mapping of `automaton_path`, kept alive
+    /// inside the matcher, rather than from a heap copy of the file. Loading
+    /// never holds a second, transient copy of a large automaton, and the
+    /// file's pages are the page cache ones every process mapping it shares.
+    ///
+    /// daachorse 1.x copies the double array out of the bytes it is given, so
+    /// the deserialized automaton itself is still heap memory per process.
+    ///
+    /// The file must not be modified, truncated, or rewritten in place while
+    /// the matcher exists: the mapping would change underneath it, which is
+    /// undefined behavior. Publish new artifacts to a new path (or rename over
+    /// the old one) and `reload`.
+    pub fn load_mmap<P: AsRef<Path>>(
+        automaton_path: P,
+        payloads_path: P,
+        manifest_path: P,
+        options: LoadOptions,
+    ) -> Result<Self, MatcherError> {
+        let options = LoadOptions {
+            mmap_automaton: true,
+            ..options
+        };
+        Self::load_with_options(automaton_path, payloads_path, manifest_path, options)
+    }
+
+    pub fn load_with_options<P: AsRef<Path>>(
+        automaton_path: P,
+        payloads_path: P,
+        manifest_path: P,
+        options: LoadOptions,
     ) -> Result<Self, MatcherError> {
         let manifest = Manifest::load(manifest_path)?;
+        let mut diagnostics = Vec::new();
+
+        // Deserialization below is unchecked, so an automaton from another
+        // daachorse format must be refused outright, even in lenient mode.
+        match manifest.automaton_format.as_deref() {
+            Some(AUTOMATON_FORMAT) => {}
+            Some(other) => {
+                return Err(MatcherError::Automaton(format!(
+                    "automaton_format {} is not supported (this build reads {}); rebuild the artifacts",
+                    other, AUTOMATON_FORMAT
+                )));
+            }
+            None => diagnostics.push(format!("automaton_format not recorded in manifest; assuming {}", AUTOMATON_FORMAT)),
+        }
 
-        let automaton_bytes = std::fs::read(automaton_path)?;
+        let (automaton_map, automaton_read) = if options.mmap_automaton {
+            let file = std::fs::File::open(automaton_path)?;
+            // Safety: the file must not change while mapped, as documented on
+            // `load_mmap`; the mapping is read-only and outlives every use.
+            (Some(unsafe { Mmap::map(&file)? }), Vec::new())
+        } else {
+            (None, std::fs::read(automaton_path)?)
+        };
+        let automaton_bytes: &[u8] = automaton_map.as_deref().unwrap_or(&automaton_read);
+        if options.verify_checksums {
+            verify_checksums(&manifest, automaton_bytes, payloads_path.as_ref(), &mut diagnostics)?;
+        }
+        let automaton_patterns = automaton_pattern_count(automaton_bytes).ok_or_else(|| {
+            MatcherError::Automaton(format!(
+                "automaton is {} bytes, which is not a serialized {} automaton; the file is truncated or corrupt",
+                automaton_bytes.len(),
+                AUTOMATON_FORMAT
+            ))
+        })?;
         let (automaton, _): (DoubleArrayAhoCorasick<u32>, _) = unsafe {
-            DoubleArrayAhoCorasick::deserialize_unchecked(&automaton_bytes)
+            DoubleArrayAhoCorasick::deserialize_unchecked(automaton_bytes)
         };
 
-        let payloads_file = File::open(payloads_path)?;
-        let payloads_reader = BufReader::new(payloads_file);
-        let payloads = load_payloads(payloads_reader)?;
+        // Manifest::validate only admits versions PayloadFormat knows
+        let format = PayloadFormat::from_version(manifest.payload_format_version)
+            .expect("payload_format_version validated with the manifest");
+        let payloads = if options.lazy_payloads {
+            PayloadStore::load_lazy(payloads_path, options.payload_cache_capacity, format)?
+        } else {
+            PayloadStore::load_eager(payloads_path, format)?
+        };
 
         if payloads.len() != manifest.num_patterns {
-            return Err(MatcherError::Automaton(format!(
+            let error = MatcherError::Automaton(format!(
                 "Payload count mismatch: manifest says {}, got {}",
                 manifest.num_patterns,
                 payloads.len()
+            ));
+            report(&options, &mut diagnostics, error)?;
+        }
+
+        // Refused even in lenient mode: pattern indices past the payload table
+        // would silently drop matches, and payloads past the automaton would
+        // never match.
+        if automaton_patterns != payloads.len() {
+            return Err(MatcherError::Automaton(format!(
+                "Automaton holds {} patterns but there are {} payloads; the files are from different builds",
+                automaton_patterns,
+                payloads.len()
             )));
         }
 
+        if let Some(index) = payloads.find_degenerate() {
+            let error = MatcherError::InvalidPayload {
+                index,
+                reason: "zero-length pattern (n == 0)".to_string(),
+            };
+            report(&options, &mut diagnostics, error)?;
+        }
+
+        if options.validate_patterns {
+            validate_patterns(&payloads, &manifest, &options, &mut diagnostics)?;
+        }
+
+        let negative_rules = manifest
+            .negative_rules
+            .iter()
+            .map(|rule| (rule.phrase_id, rule.clone()))
+            .collect();
+        let max_pattern_len = manifest
+            .max_pattern_len
+            .unwrap_or_else(|| payloads.scan().map(|p| p.n as usize).max().unwrap_or(0));
+
         Ok(Self {
             automaton,
+            automaton_map,
+            automaton_patterns,
             payloads,
+            phrase_index: OnceLock::new(),
+            negative_rules,
             manifest,
+            vocab: None,
+            options,
+            max_cluster: options.max_cluster,
+            max_pattern_len,
+            diagnostics,
             loaded_at: SystemTime::now(),
+            match_calls: AtomicU64::new(0),
+            hits_total: AtomicU64::new(0),
+            raw_hits: Default::default(),
+            resolved_hits: Default::default(),
+            latencies: Mutex::new(LatencyWindow::default()),
+            result_cache: (options.result_cache_capacity > 0).then(|| ResultCache::new(options.result_cache_capacity)),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
         })
     }
 
+    /// `load_with_options`, after checking the artifacts against the
+    /// vocabulary callers will encode tokens with.
+    pub fn load_with_vocab<P: AsRef<Path>>(
+        automaton_path: P,
+        payloads_path: P,
+        manifest_path: P,
+        vocab_path: P,
+        options: LoadOptions,
+    ) -> Result<Self, MatcherError> {
+        let manifest = Manifest::load(manifest_path.as_ref())?;
+        let vocab = VocabSummary::load(vocab_path)?;
+        let mut diagnostics = Vec::new();
+        check_vocab(&manifest, &vocab, &mut diagnostics)?;
+
+        let mut matcher = Self::load_with_options(automaton_path, payloads_path, manifest_path, options)?;
+        matcher.diagnostics.extend(diagnostics);
+        matcher.vocab = Some(vocab);
+        Ok(matcher)
+    }
+
+    /// Loads replacement artifacts with the options this matcher was loaded
+    /// with, checked against `vocab_path` if given. Without one, the current
+    /// vocabulary (if any) is kept and checked against the new artifacts.
+    /// `self` is untouched, so a failed reload leaves it usable.
+    pub fn reload<P: AsRef<Path>>(
+        &self,
+        automaton_path: P,
+        payloads_path: P,
+        manifest_path: P,
+        vocab_path: Option<P>,
+    ) -> Result<Self, MatcherError> {
+        match vocab_path {
+            Some(vocab_path) => Self::load_with_vocab(automaton_path, payloads_path, manifest_path, vocab_path, self.options),
+            None => {
+                let mut matcher = Self::load_with_options(automaton_path, payloads_path, manifest_path, self.options)?;
+                if let Some(vocab) = &self.vocab {
+                    // The kept vocabulary's tokens are normalized the old way
+                    if matcher.manifest.normalization != self.manifest.normalization {
+                        return Err(MatcherError::Incompatible(format!(
+                            "normalization changed from {} to {}; reload with the new build's vocab_path",
+                            self.manifest.normalization.as_str(),
+                            matcher.manifest.normalization.as_str()
+                        )));
+                    }
+                    if matcher.manifest.unicode_normalization != self.manifest.unicode_normalization {
+                        return Err(MatcherError::Incompatible(format!(
+                            "unicode_normalization changed from {} to {}; reload with the new build's vocab_path",
+                            self.manifest.unicode_normalization.as_str(),
+                            matcher.manifest.unicode_normalization.as_str()
+                        )));
+                    }
+                    check_vocab(&matcher.manifest, vocab, &mut matcher.diagnostics)?;
+                    matcher.vocab = Some(vocab.clone());
+                }
+                Ok(matcher)
+            }
+        }
+    }
+
+    /// Token IDs for `tokens` under the vocabulary given at load, or `None`
+    /// when the matcher was loaded without one.
+    pub fn encode_strings(&self, tokens: &[String]) -> Option<Vec<u32>> {
+        self.vocab.as_ref().map(|vocab| vocab.encode(tokens, self.manifest.normalization, self.manifest.unicode_normalization))
+    }
+
+    /// Resolved matches over `token_ids`, at most `max` of them. `max` caps the
+    /// returned spans, not the work: overlap resolution sees every candidate,
+    /// so truncation never changes which spans win.
     pub fn match_tokens(
         &self,
         token_ids: &[u32],
         policy: MatchPolicy,
         max: usize,
     ) -> Vec<Match> {
+        self.match_tokens_detailed(token_ids, policy, max, &HashSet::new()).matches
+    }
+
+    /// `match_tokens` plus whether overlap resolution had to degrade. Matches of
+    /// `pinned` phrase_ids win every overlap with unpinned ones.
+    pub fn match_tokens_detailed(
+        &self,
+        token_ids: &[u32],
+        policy: MatchPolicy,
+        max: usize,
+        pinned: &HashSet<u64>,
+    ) -> Resolution {
+        self.match_tokens_unknown(token_ids, policy, max, pinned, MatchFilters::NONE, UnknownTokens::NONE)
+    }
+
+    /// `match_tokens_detailed` with per-call handling of unknown tokens; see
+    /// `UnkPolicy`. Negative rules and overlap resolution apply to wildcard
+    /// matches like any other.
+    ///
+    /// Matches `filters` rejects are dropped once negative rules have run,
+    /// before overlap resolution, so they can neither win nor block an
+    /// overlap. `MatchFilters::NONE` keeps every match.
+    ///
+    /// With a result cache, calls whose unknown tokens cannot change the result
+    /// (no positions, or `UnkPolicy::Map`) are served from it.
+    ///
+    /// `max == 0` and empty `token_ids` return no matches without running the
+    /// automaton, and are not counted in `Stats`.
+    pub fn match_tokens_unknown(
+        &self,
+        token_ids: &[u32],
+        policy: MatchPolicy,
+        max: usize,
+        pinned: &HashSet<u64>,
+        filters: MatchFilters,
+        unknown: UnknownTokens,
+    ) -> Resolution {
+        if max == 0 || token_ids.is_empty() {
+            return Resolution { matches: Vec::new(), degraded: false };
+        }
+
+        let started = Instant::now();
+        let cacheable = unknown.positions.is_empty() || unknown.policy == UnkPolicy::Map;
+        let resolved = match &self.result_cache {
+            Some(cache) if cacheable => {
+                let (resolved, hit) = cache.get_or_insert_with(token_ids, policy, max, pinned, filters, || {
+                    self.resolve(token_ids, policy, max, pinned, filters, UnknownTokens::NONE)
+                });
+                let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+                counter.fetch_add(1, Ordering::Relaxed);
+                resolved
+            }
+            _ => self.resolve(token_ids, policy, max, pinned, filters, unknown),
+        };
+
+        self.match_calls.fetch_add(1, Ordering::Relaxed);
+        self.hits_total.fetch_add(resolved.matches.len() as u64, Ordering::Relaxed);
+        self.latencies.lock().record(started.elapsed());
+
+        resolved
+    }
+
+    /// Zeroes the call, hit, and cache counters and forgets recorded latencies,
+    /// so the next `Stats` covers only calls made after this one.
+    pub fn reset_stats(&self) {
+        let counters = [&self.match_calls, &self.hits_total, &self.cache_hits, &self.cache_misses];
+        for counter in counters.into_iter().chain(&self.raw_hits).chain(&self.resolved_hits) {
+            counter.store(0, Ordering::Relaxed);
+        }
+        *self.latencies.lock() = LatencyWindow::default();
+    }
+
+    /// `match_tokens` for inputs too long to hold every hit: resolved matches
+    /// are passed to `emit`, in start order, as the automaton scan settles
+    /// them, and `emit` can return `ControlFlow::Break` to stop the scan.
+    /// Token IDs are encoded as they are read, so neither the encoded input nor
+    /// the full hit list is materialized.
+    ///
+    /// Overlaps are resolved within a sliding window of `window` tokens (see
+    /// `WindowedResolver`). Until an overlap cluster outgrows it the output is
+    /// exactly `match_tokens`'s. Past that, `LeftmostLongest`, `LeftmostFirst`,
+    /// and `All` stay exact, while `SalienceMax`, `CountMax`, and `MinSpans`
+    /// are approximate at the cut; `StreamSummary::window_cuts` counts cuts.
+    /// Memory is bounded by the hits in the last `window` +
+    /// `max_pattern_len` tokens plus the widest negative rule window.
+    ///
+    /// There is no `max`; break out of `emit` instead. Calls bypass the result
+    /// cache and are not counted in `Stats`.
+    pub fn match_tokens_streaming<I, F>(
+        &self,
+        token_ids: I,
+        policy: MatchPolicy,
+        window: usize,
+        filters: MatchFilters,
+        mut emit: F,
+    ) -> StreamSummary
+    where
+        I: IntoIterator<Item = u32>,
+        F: FnMut(Match) -> ControlFlow<()>,
+    {
+        let encoding = self.manifest.encoding;
+        let bytes = encoding.encode_iter(token_ids, self.manifest.separator_id);
+        let mut resolver =
+            WindowedResolver::new(policy, self.max_cluster, &self.negative_rules, filters, self.max_pattern_len, window);
+
+        for m in self.automaton.find_overlapping_iter_from_iter(bytes) {
+            let span = encoding.token_span(m.start(), m.end());
+            debug_assert!(span.is_some(), "match {}..{} is not on token boundaries", m.start(), m.end());
+            let (Some((start, end)), Some(payload)) = (span, self.payloads.get(m.value() as usize)) else {
+                continue;
+            };
+            if resolver.push(Match::new(start, end, m.value() as usize, payload), &mut emit).is_break() {
+                return resolver.summary();
+            }
+        }
+
+        resolver.finish(&mut emit)
+    }
+
+    /// `match_tokens` split into candidate generation (automaton pass plus
+    /// negative rules) and overlap resolution, each timed. Bypasses the result
+    /// cache and the call counters, so profiling never skews `Stats`.
+    pub fn match_tokens_profiled(&self, token_ids: &[u32], policy: MatchPolicy, max: usize) -> ProfiledMatch {
+        let started = Instant::now();
+        let candidates = self.find_matches(token_ids);
+        let candidate_time = started.elapsed();
+
+        let input = candidates.clone();
+        let started = Instant::now();
+        let mut resolution = resolve_overlaps_pinned(input, policy, self.max_cluster, &HashSet::new());
+        resolution.matches.truncate(max);
+        let resolution_time = started.elapsed();
+
+        ProfiledMatch {
+            candidates,
+            resolution,
+            candidate_time,
+            resolution_time,
+        }
+    }
+
+    /// Drops every cached match result. Does nothing without a result cache.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.result_cache {
+            cache.clear();
+        }
+    }
+
+    fn resolve(
+        &self,
+        token_ids: &[u32],
+        policy: MatchPolicy,
+        max: usize,
+        pinned: &HashSet<u64>,
+        filters: MatchFilters,
+        unknown: UnknownTokens,
+    ) -> Resolution {
+        let mut matches = self.automaton_hits(token_ids, 0);
+        match unknown.policy {
+            UnkPolicy::Map => {}
+            UnkPolicy::Break => {
+                matches.retain(|m| !unknown.positions.iter().any(|&p| m.start <= p && p < m.end));
+            }
+            UnkPolicy::Wildcard => {
+                matches.extend(self.wildcard_hits(token_ids, unknown.positions, unknown.candidates, &matches));
+            }
+        }
+        let mut matches = apply_negative_rules(matches, &self.negative_rules);
+        matches.retain(|m| filters.keeps(m));
+        let raw_hits = matches.len() as u64;
+        let mut resolved = resolve_overlaps_pinned(matches, policy, self.max_cluster, pinned);
+        self.raw_hits[policy as usize].fetch_add(raw_hits, Ordering::Relaxed);
+        self.resolved_hits[policy as usize].fetch_add(resolved.matches.len() as u64, Ordering::Relaxed);
+
+        if resolved.matches.len() > max {
+            resolved.matches.truncate(max);
+        }
+
+        resolved
+    }
+
+    /// Returns the single match with the highest `salience_score()` anywhere in the
+    /// input, without overlap resolution. Ties go to the smaller phrase_id, then the
+    /// earlier span.
+    pub fn best_match(&self, token_ids: &[u32]) -> Option<Match> {
+        self.find_matches(token_ids).into_iter().max_by(|a, b| {
+            a.payload
+                .salience_score()
+                .partial_cmp(&b.payload.salience_score())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.payload.phrase_id.cmp(&a.payload.phrase_id))
+                .then(b.start.cmp(&a.start))
+        })
+    }
+
+    /// The payload of `phrase_id`, without matching. A phrase_id shared by
+    /// several patterns (slot alternatives, or duplicates in hand-built
+    /// artifacts) resolves to its lowest pattern index.
+    ///
+    /// The first call indexes every payload (decoding each one in lazy mode)
+    /// and keeps an entry per pattern for the matcher's lifetime.
+    pub fn payload_for(&self, phrase_id: u64) -> Option<Payload> {
+        let index = self.phrase_index.get_or_init(|| {
+            let mut index = HashMap::with_capacity(self.payloads.len());
+            for (pattern, payload) in self.payloads.scan().enumerate() {
+                index.entry(payload.phrase_id).or_insert(pattern);
+            }
+            index
+        });
+        self.payloads.get(*index.get(&phrase_id)?)
+    }
+
+    /// Counts every occurrence of each phrase_id in one automaton pass, with no
+    /// overlap resolution: "new york" inside "new york city" counts for both.
+    /// Negative phrases are not counted, and their suppression is not applied.
+    pub fn phrase_frequencies(&self, token_ids: &[u32]) -> HashMap<u64, u32> {
+        let mut frequencies = HashMap::new();
         if token_ids.is_empty() {
-            return Vec::new();
+            return frequencies;
         }
 
-        let separator = self.manifest.separator_id;
-        let mut bytes = Vec::with_capacity(token_ids.len() * 5);
-        for &token_id in token_ids {
-            bytes.extend_from_slice(&token_id.to_le_bytes());
-            bytes.extend_from_slice(&separator.to_le_bytes());
+        let bytes = self.manifest.encoding.encode(token_ids, self.manifest.separator_id);
+        for m in self.automaton.find_overlapping_iter(&bytes) {
+            if let Some(payload) = self.payloads.get(m.value() as usize) {
+                if !self.negative_rules.contains_key(&payload.phrase_id) {
+                    *frequencies.entry(payload.phrase_id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        frequencies
+    }
+
+    /// Sorted, distinct phrase_ids occurring anywhere in the input, for cheap
+    /// "does this document mention any of these phrases" gating. Same counting
+    /// rules as `phrase_frequencies`.
+    pub fn contains_phrase_ids(&self, token_ids: &[u32]) -> Vec<u64> {
+        let mut phrase_ids: Vec<u64> = self.phrase_frequencies(token_ids).into_keys().collect();
+        phrase_ids.sort_unstable();
+        phrase_ids
+    }
+
+    /// One score per phrase_id: the `salience_score()` of every occurrence,
+    /// combined by `aggregation`. Like `phrase_frequencies`, every occurrence
+    /// counts (no overlap resolution), but negative rules are applied.
+    pub fn phrase_scores(&self, token_ids: &[u32], aggregation: ScoreAggregation) -> HashMap<u64, f32> {
+        let mut occurrences: HashMap<u64, Vec<f32>> = HashMap::new();
+        for m in self.find_matches(token_ids) {
+            occurrences.entry(m.payload.phrase_id).or_default().push(m.payload.salience_score());
         }
 
-        let matches: Vec<Match> = self
-            .automaton
+        occurrences
+            .into_iter()
+            .map(|(phrase_id, scores)| (phrase_id, aggregation.aggregate(&scores)))
+            .collect()
+    }
+
+    /// `find_matches` without the matches `filters` rejects: what a
+    /// `MatcherSet` pools across namespaces before resolving overlaps.
+    pub(crate) fn candidates(&self, token_ids: &[u32], filters: MatchFilters) -> Vec<Match> {
+        let mut matches = self.find_matches(token_ids);
+        matches.retain(|m| filters.keeps(m));
+        matches
+    }
+
+    pub(crate) fn max_cluster(&self) -> usize {
+        self.max_cluster
+    }
+
+    /// All automaton hits with negative rules applied, before overlap resolution.
+    fn find_matches(&self, token_ids: &[u32]) -> Vec<Match> {
+        apply_negative_rules(self.automaton_hits(token_ids, 0), &self.negative_rules)
+    }
+
+    /// Raw automaton hits over `token_ids`, with positions shifted by `offset`.
+    fn automaton_hits(&self, token_ids: &[u32], offset: usize) -> Vec<Match> {
+        if token_ids.is_empty() {
+            return Vec::new();
+        }
+
+        let bytes = self.manifest.encoding.encode(token_ids, self.manifest.separator_id);
+
+        self.automaton
             .find_overlapping_iter(&bytes)
             .filter_map(|m| {
                 let pattern_id = m.value() as usize;
-                let start_token = m.start() / 8;
-                let end_token = (m.end() + 7) / 8;
+                let span = self.manifest.encoding.token_span(m.start(), m.end());
+                debug_assert!(span.is_some(), "match {}..{} is not on token boundaries", m.start(), m.end());
+                let (start, end) = span?;
 
                 self.payloads
                     .get(pattern_id)
-                    .map(|payload| Match::new(start_token, end_token, pattern_id, payload.clone()))
+                    .map(|payload| Match::new(start + offset, end + offset, pattern_id, payload))
             })
-            .collect();
+            .collect()
+    }
+
+    /// Matches that exist only when an unknown position holds one of
+    /// `candidates`. Every candidate is substituted into the window of
+    /// `max_pattern_len` tokens either side of the position, and the re-run is
+    /// verified: only hits covering the substituted position are kept, once
+    /// each, and never one already in `existing`.
+    fn wildcard_hits(&self, token_ids: &[u32], positions: &[usize], candidates: &[u32], existing: &[Match]) -> Vec<Match> {
+        let mut seen: HashSet<(usize, usize, usize)> = existing.iter().map(|m| (m.start, m.end, m.pattern_id)).collect();
+        let mut hits = Vec::new();
+        let reach = self.max_pattern_len.saturating_sub(1);
 
-        let mut resolved = resolve_overlaps(matches, policy);
+        for &position in positions.iter().filter(|&&p| p < token_ids.len()) {
+            let window_start = position.saturating_sub(reach);
+            let window_end = (position + reach + 1).min(token_ids.len());
+            let mut window = token_ids[window_start..window_end].to_vec();
 
-        if resolved.len() > max {
-            resolved.truncate(max);
+            for &candidate in candidates {
+                window[position - window_start] = candidate;
+                for m in self.automaton_hits(&window, window_start) {
+                    if m.start <= position && position < m.end && seen.insert((m.start, m.end, m.pattern_id)) {
+                        hits.push(m);
+                    }
+                }
+            }
         }
 
-        resolved
+        hits
+    }
+
+    /// Problems tolerated by a lenient load (and skipped checks), in the order
+    /// they were found. Always empty after a strict load, except for notes about
+    /// skipped checks.
+    pub fn diagnostics(&self) -> &[String] {
+        &self.diagnostics
     }
 
-    #[allow(dead_code)]
     pub fn manifest(&self) -> &Manifest {
         &self.manifest
     }
@@ -113,55 +924,220 @@ impl Matcher {
         self.payloads.len()
     }
 
-    #[allow(dead_code)]
+    /// Patterns in the loaded automaton itself, which load has checked equals
+    /// `num_patterns`.
+    pub fn automaton_pattern_count(&self) -> usize {
+        self.automaton_patterns
+    }
+
     pub fn loaded_at(&self) -> SystemTime {
         self.loaded_at
     }
 
+    pub fn lazy_payloads(&self) -> bool {
+        self.payloads.is_lazy()
+    }
+
+    /// Heap held by the automaton, payload table, and phrase_id index (once
+    /// `payload_for` has built it). Lazy payloads count only their cache;
+    /// mapped pages belong to the page cache.
+    pub fn memory_usage_bytes(&self) -> usize {
+        self.automaton.heap_bytes()
+            + self.payloads.heap_bytes()
+            + self.phrase_index.get().map_or(0, |index| index.capacity() * std::mem::size_of::<(u64, usize)>())
+    }
+
     pub fn memory_usage_mb(&self) -> f64 {
-        let automaton_size = std::mem::size_of_val(&self.automaton);
-        let payloads_size = self.payloads.len() * std::mem::size_of::<Payload>();
-        ((automaton_size + payloads_size) as f64) / 1_048_576.0
+        self.memory_usage_bytes() as f64 / 1_048_576.0
+    }
+}
+
+fn validate_patterns(
+    payloads: &PayloadStore,
+    manifest: &Manifest,
+    options: &LoadOptions,
+    diagnostics: &mut Vec<String>,
+) -> Result<(), MatcherError> {
+    let mut phrase_ids = HashSet::new();
+    for (index, payload) in payloads.scan().enumerate() {
+        if !payload.salience.is_finite() {
+            let error = MatcherError::InvalidPayload {
+                index,
+                reason: format!("non-finite salience {}", payload.salience),
+            };
+            report(options, diagnostics, error)?;
+        }
+        phrase_ids.insert(payload.phrase_id);
+    }
+
+    for rule in &manifest.negative_rules {
+        if !phrase_ids.contains(&rule.phrase_id) {
+            let error = MatcherError::Automaton(format!(
+                "Negative rule references phrase_id {} with no payload",
+                rule.phrase_id
+            ));
+            report(options, diagnostics, error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Projected heap use of a matcher, computed from file sizes alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryEstimate {
+    pub automaton_bytes: u64,
+    pub payload_count: u64,
+    /// Eager payload table; lazy loading needs only its cache instead.
+    pub payloads_bytes: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.automaton_bytes + self.payloads_bytes
     }
 }
 
+/// Estimates what loading these artifacts would cost without reading them: the
+/// automaton is deserialized into a buffer the size of its file, and each
+/// payload record becomes one in-memory `Payload`. `payloads_path` accepts the
+/// same shard directories and globs as `Matcher::load`. Records are counted at
+/// the size of the manifest's `payload_format_version`, so tables from older
+/// builds are sized correctly.
+pub fn estimate_memory<P: AsRef<Path>>(
+    automaton_path: P,
+    payloads_path: P,
+    manifest_path: P,
+) -> Result<MemoryEstimate, MatcherError> {
+    let manifest = Manifest::load(manifest_path)?;
+    // Manifest::validate only admits versions PayloadFormat knows
+    let format = PayloadFormat::from_version(manifest.payload_format_version)
+        .expect("payload_format_version validated with the manifest");
+    let automaton_bytes = std::fs::metadata(automaton_path)?.len();
+
+    let mut payload_count = 0;
+    for shard in shard_paths(payloads_path.as_ref())? {
+        payload_count += std::fs::metadata(shard)?.len() / format.record_size() as u64;
+    }
+
+    Ok(MemoryEstimate {
+        automaton_bytes,
+        payload_count,
+        payloads_bytes: payload_count * std::mem::size_of::<Payload>() as u64,
+    })
+}
+
+/// Result of `Matcher::match_tokens_profiled`.
+pub struct ProfiledMatch {
+    /// Every hit considered by overlap resolution, in automaton order.
+    pub candidates: Vec<Match>,
+    pub resolution: Resolution,
+    pub candidate_time: Duration,
+    pub resolution_time: Duration,
+}
+
 pub struct Stats {
     pub version: String,
     pub loaded_at: SystemTime,
     pub num_patterns: usize,
+    pub automaton_patterns: usize,
     pub heap_mb: f64,
+    pub lazy_payloads: bool,
+    pub match_calls: u64,
     pub hits_total: u64,
+    /// Candidates that reached overlap resolution, and those it kept before
+    /// `max` truncation, summed over all policies.
+    pub raw_hits_total: u64,
+    pub resolved_hits_total: u64,
+    /// The same counts for each policy that has seen candidates.
+    pub policy_hits: Vec<PolicyHits>,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Results currently held by the result cache.
+    pub cache_entries: usize,
     pub p50_us: u64,
     pub p95_us: u64,
     pub p99_us: u64,
 }
 
+/// Overlap resolution counts for one policy; see `Stats::policy_hits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyHits {
+    pub policy: MatchPolicy,
+    pub raw_hits: u64,
+    pub resolved_hits: u64,
+}
+
 impl Stats {
     pub fn from_matcher(matcher: &Matcher) -> Self {
+        let [p50_us, p95_us, p99_us] = matcher.latencies.lock().percentiles_us([0.50, 0.95, 0.99]);
+        let policy_hits: Vec<PolicyHits> = MatchPolicy::ALL
+            .iter()
+            .map(|&policy| PolicyHits {
+                policy,
+                raw_hits: matcher.raw_hits[policy as usize].load(Ordering::Relaxed),
+                resolved_hits: matcher.resolved_hits[policy as usize].load(Ordering::Relaxed),
+            })
+            .filter(|hits| hits.raw_hits > 0)
+            .collect();
         Self {
             version: matcher.manifest.version.clone(),
             loaded_at: matcher.loaded_at,
             num_patterns: matcher.num_patterns(),
+            automaton_patterns: matcher.automaton_pattern_count(),
             heap_mb: matcher.memory_usage_mb(),
-            hits_total: 0,
-            p50_us: 0,
-            p95_us: 0,
-            p99_us: 0,
+            lazy_payloads: matcher.lazy_payloads(),
+            match_calls: matcher.match_calls.load(Ordering::Relaxed),
+            hits_total: matcher.hits_total.load(Ordering::Relaxed),
+            raw_hits_total: policy_hits.iter().map(|hits| hits.raw_hits).sum(),
+            resolved_hits_total: policy_hits.iter().map(|hits| hits.resolved_hits).sum(),
+            policy_hits,
+            cache_hits: matcher.cache_hits.load(Ordering::Relaxed),
+            cache_misses: matcher.cache_misses.load(Ordering::Relaxed),
+            cache_entries: matcher.result_cache.as_ref().map_or(0, ResultCache::len),
+            p50_us,
+            p95_us,
+            p99_us,
         }
     }
+
+    /// Fraction of overlap-resolution candidates that survived, or `None`
+    /// before any call had a candidate. Values far below 1 mean large overlap
+    /// clusters are being resolved away.
+    pub fn overlap_resolution_ratio(&self) -> Option<f64> {
+        (self.raw_hits_total > 0).then(|| self.resolved_hits_total as f64 / self.raw_hits_total as f64)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::payload::Payload;
+    use crate::manifest::TokenEncoding;
+    use crate::payload::PAYLOAD_SIZE;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
-    fn create_test_artifacts() -> (NamedTempFile, NamedTempFile, NamedTempFile) {
-        let patterns = vec![vec![1u32, 2u32], vec![2u32, 3u32]];
+    fn encode_pattern(tokens: &[u32], separator: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &token in tokens {
+            bytes.extend_from_slice(&token.to_le_bytes());
+            bytes.extend_from_slice(&separator.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn create_artifacts(
+        patterns: &[&[u32]],
+        payloads: &[Payload],
+        extra_manifest: &str,
+    ) -> (NamedTempFile, NamedTempFile, NamedTempFile) {
+        let separator = 4294967294u32;
+        let encoded: Vec<Vec<u8>> = patterns
+            .iter()
+            .map(|p| encode_pattern(p, separator))
+            .collect();
 
-        let automaton = DoubleArrayAhoCorasick::new(patterns).unwrap();
+        let automaton: DoubleArrayAhoCorasick<u32> = DoubleArrayAhoCorasick::new(encoded).unwrap();
         let automaton_bytes = automaton.serialize();
 
         let mut automaton_file = NamedTempFile::new().unwrap();
@@ -169,26 +1145,40 @@ mod tests {
         automaton_file.flush().unwrap();
 
         let mut payloads_file = NamedTempFile::new().unwrap();
-        let payload1 = Payload::new(100, 1.5, 50, 2);
-        let payload2 = Payload::new(200, 2.0, 100, 2);
-        payload1.write_to(&mut payloads_file).unwrap();
-        payload2.write_to(&mut payloads_file).unwrap();
+        for payload in payloads {
+            payload.write_to(&mut payloads_file).unwrap();
+        }
         payloads_file.flush().unwrap();
 
         let mut manifest_file = NamedTempFile::new().unwrap();
-        let manifest_json = r#"{
+        let manifest_json = format!(
+            r#"{{
             "version": "test-v1",
             "tokenizer": "test-tokenizer",
-            "num_patterns": 2,
+            "num_patterns": {},
             "built_at": "2025-01-01T00:00:00Z",
-            "separator_id": 4294967294
-        }"#;
+            "separator_id": {},
+            "automaton_format": "daachorse-1",
+            "payload_format_version": 3{}
+        }}"#,
+            payloads.len(),
+            separator,
+            extra_manifest
+        );
         manifest_file.write_all(manifest_json.as_bytes()).unwrap();
         manifest_file.flush().unwrap();
 
         (automaton_file, payloads_file, manifest_file)
     }
 
+    fn create_test_artifacts() -> (NamedTempFile, NamedTempFile, NamedTempFile) {
+        create_artifacts(
+            &[&[1, 2], &[2, 3]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 100, 2)],
+            "",
+        )
+    }
+
     #[test]
     fn test_matcher_load() {
         let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
@@ -201,27 +1191,1031 @@ mod tests {
         .unwrap();
 
         assert_eq!(matcher.num_patterns(), 2);
+        assert_eq!(matcher.automaton_pattern_count(), 2);
         assert_eq!(matcher.manifest().version, "test-v1");
     }
 
     #[test]
-    fn test_matcher_match_tokens() {
+    fn test_load_rejects_automaton_payload_desync() {
+        // Manifest and payloads agree on three patterns; the automaton has two
+        let payloads = [Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 100, 2), Payload::new(300, 1.0, 10, 2)];
+        let (automaton_file, _, _) = create_test_artifacts();
+        let (_, payloads_file, manifest_file) = create_artifacts(&[&[1, 2], &[2, 3], &[3, 4]], &payloads, "");
+
+        for strict in [true, false] {
+            let err = Matcher::load_with_options(
+                automaton_file.path(),
+                payloads_file.path(),
+                manifest_file.path(),
+                LoadOptions { strict, ..LoadOptions::default() },
+            )
+            .err()
+            .unwrap();
+            assert!(err.to_string().contains("Automaton holds 2 patterns but there are 3 payloads"), "{}", err);
+        }
+
+        let mut truncated = NamedTempFile::new().unwrap();
+        truncated.write_all(&std::fs::read(automaton_file.path()).unwrap()[..40]).unwrap();
+        let err = Matcher::load(truncated.path(), payloads_file.path(), manifest_file.path()).err().unwrap();
+        assert!(err.to_string().contains("truncated or corrupt"), "{}", err);
+    }
+
+    #[test]
+    fn test_reload_keeps_load_options() {
         let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let options = LoadOptions { lazy_payloads: true, ..LoadOptions::default() };
+        let matcher = Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options).unwrap();
 
-        let matcher = Matcher::load(
-            automaton_file.path(),
-            payloads_file.path(),
-            manifest_file.path(),
-        )
-        .unwrap();
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&[&[7, 8]], &[Payload::new(300, 1.0, 10, 2)], "");
+        let reloaded = matcher.reload(automaton_file.path(), payloads_file.path(), manifest_file.path(), None).unwrap();
+        assert!(reloaded.lazy_payloads());
+        assert_eq!(reloaded.match_tokens(&[7, 8], MatchPolicy::LeftmostLongest, 10)[0].payload.phrase_id, 300);
 
-        let token_ids = vec![1, 2, 3, 4];
-        let matches = matcher.match_tokens(&token_ids, MatchPolicy::LeftmostLongest, 10);
+        let missing = Path::new("/nonexistent/phrases.daac");
+        assert!(matcher.reload(missing, payloads_file.path(), manifest_file.path(), None).is_err());
+        assert_eq!(matcher.match_tokens(&[1, 2], MatchPolicy::LeftmostLongest, 10)[0].payload.phrase_id, 100);
+    }
 
-        assert_eq!(matches.len(), 2);
+    #[test]
+    fn test_load_mmap_matches_like_load() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3], &[1, 2, 3], &[5]],
+            &[
+                Payload::new(100, 1.5, 50, 2),
+                Payload::new(200, 2.0, 100, 2),
+                Payload::new(300, 1.0, 10, 3),
+                Payload::new(400, 0.5, 5, 1),
+            ],
+            "",
+        );
+        let read = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        let mapped =
+            Matcher::load_mmap(automaton_file.path(), payloads_file.path(), manifest_file.path(), LoadOptions::default()).unwrap();
+        assert!(read.automaton_map.is_none());
+        assert!(mapped.automaton_map.is_some());
+
+        let ids = |matches: Vec<Match>| -> Vec<(usize, usize, u64)> {
+            matches.iter().map(|m| (m.start, m.end, m.payload.phrase_id)).collect()
+        };
+        for tokens in [&[1, 2, 3][..], &[5, 1, 2, 5, 2, 3], &[9, 9], &[]] {
+            for policy in [MatchPolicy::LeftmostLongest, MatchPolicy::SalienceMax, MatchPolicy::All] {
+                assert_eq!(ids(mapped.match_tokens(tokens, policy, 10)), ids(read.match_tokens(tokens, policy, 10)));
+            }
+        }
+
+        let reloaded = mapped.reload(automaton_file.path(), payloads_file.path(), manifest_file.path(), None).unwrap();
+        assert!(reloaded.automaton_map.is_some());
+    }
+
+    #[test]
+    fn test_load_with_vocab_rejects_incompatible_vocab() {
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&[&[1, 2], &[2, 3]], &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 100, 2)], r#", "max_token_id": 3"#);
+        let vocab = |json: &str| {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(json.as_bytes()).unwrap();
+            file
+        };
+        let load = |vocab_file: &NamedTempFile| {
+            Matcher::load_with_vocab(automaton_file.path(), payloads_file.path(), manifest_file.path(), vocab_file.path(), LoadOptions::default())
+        };
+
+        let good = vocab(r#"{"tokens": {"a": 1, "b": 2, "c": 3}, "special_tokens": {"<UNK>": 0}, "separator_id": 4294967294}"#);
+        assert!(load(&good).unwrap().diagnostics().is_empty());
+
+        let other_separator = vocab(r#"{"tokens": {"a": 1, "b": 2, "c": 3}, "separator_id": 4}"#);
+        assert!(matches!(load(&other_separator), Err(MatcherError::Incompatible(message)) if message.contains("separator_id")));
+
+        let stale = vocab(r#"{"tokens": {"a": 1, "b": 2}, "special_tokens": {"<UNK>": 0}, "separator_id": 4294967294}"#);
+        assert!(matches!(load(&stale), Err(MatcherError::Incompatible(message)) if message.contains("up to 3")));
+
+        // Older manifests carry no max_token_id; only the separator is checked
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load_with_vocab(automaton_file.path(), payloads_file.path(), manifest_file.path(), stale.path(), LoadOptions::default())
+            .unwrap();
+        assert_eq!(matcher.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn test_encode_uses_loaded_vocab() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let mut vocab_file = NamedTempFile::new().unwrap();
+        vocab_file
+            .write_all(br#"{"tokens": {"a": 1, "b": 2, "c": 3}, "special_tokens": {"<UNK>": 0}, "case_sensitive_tokens": {"US": 3}, "separator_id": 4294967294}"#)
+            .unwrap();
+
+        let plain = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        assert_eq!(plain.encode_strings(&["a".to_string()]), None);
+
+        let matcher =
+            Matcher::load_with_vocab(automaton_file.path(), payloads_file.path(), manifest_file.path(), vocab_file.path(), LoadOptions::default())
+                .unwrap();
+        let tokens: Vec<String> = ["A", "b", "US", "us", "zebra"].iter().map(|t| t.to_string()).collect();
+        assert_eq!(matcher.encode_strings(&tokens), Some(vec![1, 2, 3, 0, 0]));
+
+        // A reload without vocab_path keeps the vocabulary
+        let reloaded = matcher.reload(automaton_file.path(), payloads_file.path(), manifest_file.path(), None).unwrap();
+        assert_eq!(reloaded.encode_strings(&tokens[..2]), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_encode_honors_manifest_normalization() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 100, 2)],
+            r#", "normalization": "none""#,
+        );
+        let mut vocab_file = NamedTempFile::new().unwrap();
+        vocab_file
+            .write_all(br#"{"tokens": {"BRCA1": 1, "brca1": 2, "gene": 3}, "special_tokens": {"<UNK>": 0}, "separator_id": 4294967294}"#)
+            .unwrap();
+
+        let matcher =
+            Matcher::load_with_vocab(automaton_file.path(), payloads_file.path(), manifest_file.path(), vocab_file.path(), LoadOptions::default())
+                .unwrap();
+        let tokens: Vec<String> = ["BRCA1", "brca1", "Brca1", "gene"].iter().map(|t| t.to_string()).collect();
+        assert_eq!(matcher.encode_strings(&tokens), Some(vec![1, 2, 0, 3]));
+
+        // Keeping this vocabulary for a lowercased build would mis-encode
+        let (automaton_file, payloads_file, lowercase_manifest) = create_test_artifacts();
+        let err = matcher.reload(automaton_file.path(), payloads_file.path(), lowercase_manifest.path(), None).err().unwrap();
+        assert!(err.to_string().contains("normalization changed from none to lowercase"), "{}", err);
+    }
+
+    #[test]
+    fn test_decomposed_query_matches_composed_phrase_under_nfc() {
+        // vocab.json of a build whose phrase spelled "café" precomposed
+        let mut vocab_file = NamedTempFile::new().unwrap();
+        vocab_file
+            .write_all("{\"tokens\": {\"caf\u{e9}\": 1, \"au\": 2, \"lait\": 3}, \"special_tokens\": {\"<UNK>\": 0}, \"separator_id\": 4294967294}".as_bytes())
+            .unwrap();
+        let query: Vec<String> = ["Cafe\u{301}", "au", "lait"].iter().map(|t| t.to_string()).collect();
+
+        for (extra_manifest, expected) in [("", vec![]), (r#", "unicode_normalization": "nfc""#, vec![100])] {
+            let (automaton_file, payloads_file, manifest_file) =
+                create_artifacts(&[&[1, 2, 3]], &[Payload::new(100, 2.0, 40, 3)], extra_manifest);
+            let matcher =
+                Matcher::load_with_vocab(automaton_file.path(), payloads_file.path(), manifest_file.path(), vocab_file.path(), LoadOptions::default())
+                    .unwrap();
+            let token_ids = matcher.encode_strings(&query).unwrap();
+            let phrase_ids: Vec<u64> = matcher.match_tokens(&token_ids, MatchPolicy::LeftmostLongest, 10).iter().map(|m| m.payload.phrase_id).collect();
+            assert_eq!(phrase_ids, expected, "{:?}", extra_manifest);
+        }
+    }
+
+    #[test]
+    fn test_memory_usage_counts_automaton_heap() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        // A double array allocates whole blocks of states, so even two
+        // patterns take kilobytes; the automaton handle alone is a few words
+        let bytes = matcher.memory_usage_bytes();
+        assert!(bytes > matcher.automaton.heap_bytes());
+        assert!((1_024..1_048_576).contains(&bytes), "{} bytes", bytes);
+        assert!((matcher.memory_usage_mb() - bytes as f64 / 1_048_576.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_payload_for_first_pattern_wins() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[3], &[4, 5]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 30, 1), Payload::new(100, 9.0, 1, 2)],
+            "",
+        );
+        for lazy_payloads in [false, true] {
+            let options = LoadOptions { lazy_payloads, ..LoadOptions::default() };
+            let matcher =
+                Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options).unwrap();
+
+            assert!(matcher.phrase_index.get().is_none());
+            assert_eq!(matcher.payload_for(200).unwrap().count, 30);
+            assert_eq!(matcher.phrase_index.get().map(HashMap::len), Some(2));
+            assert_eq!(matcher.payload_for(100).unwrap().salience, 1.5);
+            assert!(matcher.payload_for(999).is_none());
+        }
+    }
+
+    #[test]
+    fn test_matcher_match_tokens() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+
+        let matcher = Matcher::load(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+        )
+        .unwrap();
+
+        // [1, 2] and [2, 3] overlap here, so only a policy that keeps
+        // overlaps reports both
+        let token_ids = vec![1, 2, 3, 4];
+        let matches = matcher.match_tokens(&token_ids, MatchPolicy::All, 10);
+
+        assert_eq!(matches.len(), 2);
         assert_eq!(matches[0].start, 0);
         assert_eq!(matches[0].end, 2);
         assert_eq!(matches[1].start, 1);
         assert_eq!(matches[1].end, 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_matcher_match_tokens_leftmost_longest_drops_overlaps() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        let spans = |token_ids: &[u32]| {
+            matcher.match_tokens(token_ids, MatchPolicy::LeftmostLongest, 10)
+                .iter()
+                .map(|m| (m.start, m.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(spans(&[1, 2, 3, 4]), vec![(0, 2)]);
+        assert_eq!(spans(&[1, 2, 2, 3]), vec![(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn test_compact_encoding_never_reports_a_match_straddling_tokens() {
+        let encoding = TokenEncoding::U24Sep1;
+        // Digits of 1 are [1, 0, 0], of 256 [1, 1, 0], of 65026 [1, 0, 1], so the
+        // input below repeats pattern digit runs at offsets inside other tokens
+        let patterns: [&[u32]; 4] = [&[1], &[1, 2], &[256], &[65026, 2]];
+        let payloads: Vec<Payload> = (0..patterns.len() as u64).map(|i| Payload::new(i, 1.0, 10, 1)).collect();
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&patterns, &payloads, r#", "encoding": "u24_sep1""#);
+        let automaton = DoubleArrayAhoCorasick::<u32>::with_values(
+            patterns.iter().enumerate().map(|(i, ids)| (encoding.encode(ids, 4294967294), i as u32)),
+        )
+        .unwrap();
+        std::fs::write(automaton_file.path(), automaton.serialize()).unwrap();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        assert_eq!(matcher.manifest().encoding, encoding);
+
+        let input = [257, 1, 65026, 2, 256, 1, 2, 65280, 256, 0, 1];
+        let mut expected = Vec::new();
+        for (pattern_id, pattern) in patterns.iter().enumerate() {
+            for start in 0..=input.len() - pattern.len() {
+                if input[start..start + pattern.len()] == **pattern {
+                    expected.push((start, start + pattern.len(), pattern_id));
+                }
+            }
+        }
+        expected.sort();
+
+        let mut spans: Vec<(usize, usize, usize)> = matcher
+            .match_tokens(&input, MatchPolicy::All, usize::MAX)
+            .iter()
+            .map(|m| (m.start, m.end, m.pattern_id))
+            .collect();
+        spans.sort();
+        assert_eq!(spans, expected);
+    }
+
+    #[test]
+    fn test_min_salience_filters_before_resolution() {
+        // [1, 2, 3] (salience 0.5) overlaps both [1, 2] and [2, 3]
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2, 3], &[1, 2], &[2, 3]],
+            &[Payload::new(100, 0.5, 10, 3), Payload::new(200, 2.0, 10, 2), Payload::new(300, 1.0, 10, 2)],
+            "",
+        );
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        let run = |min_salience: f32| {
+            let resolution = matcher.match_tokens_unknown(
+                &[1, 2, 3],
+                MatchPolicy::LeftmostLongest,
+                10,
+                &HashSet::new(),
+                MatchFilters { min_salience, ..MatchFilters::NONE },
+                UnknownTokens::NONE,
+            );
+            resolution.matches.iter().map(|m| m.payload.phrase_id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(f32::NEG_INFINITY), vec![100]);
+        // Without the long span, [1, 2] wins leftmost and [2, 3] overlaps it
+        assert_eq!(run(1.0), vec![200]);
+        assert!(run(5.0).is_empty());
+    }
+
+    #[test]
+    fn test_min_n_filters_before_resolution() {
+        // The 2-token [1, 2] starts first and blocks the 3-token [2, 3, 4] under leftmost_longest
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3, 4]],
+            &[Payload::new(100, 1.0, 10, 2), Payload::new(200, 1.0, 10, 3)],
+            "",
+        );
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        let run = |min_n| {
+            let filters = MatchFilters { min_n, ..MatchFilters::NONE };
+            let resolution = matcher.match_tokens_unknown(
+                &[1, 2, 3, 4],
+                MatchPolicy::LeftmostLongest,
+                10,
+                &HashSet::new(),
+                filters,
+                UnknownTokens::NONE,
+            );
+            resolution.matches.iter().map(|m| m.payload.phrase_id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(0), vec![100]);
+        assert_eq!(run(3), vec![200]);
+        assert!(run(4).is_empty());
+    }
+
+    #[test]
+    fn test_unk_policies() {
+        // [0, 3] stands for a phrase containing the <UNK> id itself
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2, 3], &[0, 3]],
+            &[Payload::new(100, 1.0, 10, 3), Payload::new(300, 1.0, 10, 2)],
+            "",
+        );
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        // "1 ? 3" with an unknown middle token encoded as <UNK> (0)
+        let tokens = [1, 0, 3];
+        let run = |policy: UnkPolicy| {
+            let unknown = UnknownTokens {
+                positions: &[1],
+                policy,
+                candidates: &[1, 2, 3],
+            };
+            let resolution =
+                matcher.match_tokens_unknown(&tokens, MatchPolicy::LeftmostLongest, 10, &HashSet::new(), MatchFilters::NONE, unknown);
+            resolution.matches.iter().map(|m| (m.payload.phrase_id, m.start, m.end)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(UnkPolicy::Map), vec![(300, 1, 3)]);
+        assert!(run(UnkPolicy::Break).is_empty());
+        assert_eq!(run(UnkPolicy::Wildcard), vec![(100, 0, 3)]);
+
+        assert_eq!(UnkPolicy::from_str("wildcard"), Some(UnkPolicy::Wildcard));
+        assert_eq!(UnkPolicy::from_str("ignore"), None);
+    }
+
+    #[test]
+    fn test_max_pattern_len_comes_from_the_manifest() {
+        let patterns: [&[u32]; 2] = [&[1, 2, 3], &[0, 3]];
+        let payloads = [Payload::new(100, 1.0, 10, 3), Payload::new(300, 1.0, 10, 2)];
+
+        // Legacy manifests are scanned, in either payload mode
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(&patterns, &payloads, "");
+        for lazy_payloads in [false, true] {
+            let options = LoadOptions { lazy_payloads, ..LoadOptions::default() };
+            let matcher =
+                Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options)
+                    .unwrap();
+            assert_eq!(matcher.max_pattern_len, 3);
+        }
+
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&patterns, &payloads, r#", "min_pattern_len": 2, "max_pattern_len": 3"#);
+        let options = LoadOptions { lazy_payloads: true, ..LoadOptions::default() };
+        let matcher =
+            Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options).unwrap();
+        assert_eq!(matcher.max_pattern_len, 3);
+
+        let unknown = UnknownTokens {
+            positions: &[1],
+            policy: UnkPolicy::Wildcard,
+            candidates: &[1, 2, 3],
+        };
+        let resolution =
+            matcher.match_tokens_unknown(&[1, 0, 3], MatchPolicy::LeftmostLongest, 10, &HashSet::new(), MatchFilters::NONE, unknown);
+        assert_eq!(resolution.matches.iter().map(|m| m.payload.phrase_id).collect::<Vec<_>>(), vec![100]);
+    }
+
+    #[test]
+    fn test_stats_count_match_calls() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        matcher.match_tokens(&[9], MatchPolicy::LeftmostLongest, 10);
+
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!(stats.match_calls, 2);
+        assert_eq!(stats.hits_total, 2);
+        assert_eq!(stats.cache_hits + stats.cache_misses, 0);
+    }
+
+    #[test]
+    fn test_stats_count_hits_before_and_after_overlap_resolution() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        assert_eq!(Stats::from_matcher(&matcher).overlap_resolution_ratio(), None);
+
+        // [1, 2] and [2, 3] overlap: leftmost_longest keeps one, all keeps both.
+        // `max` truncation after resolution is not counted as resolved away.
+        matcher.match_tokens(&[1, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 1);
+        matcher.match_tokens(&[1, 2, 3], MatchPolicy::All, 10);
+
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!(stats.hits_total, 4);
+        assert_eq!((stats.raw_hits_total, stats.resolved_hits_total), (6, 5));
+        assert_eq!(
+            stats.policy_hits,
+            vec![
+                PolicyHits { policy: MatchPolicy::LeftmostLongest, raw_hits: 4, resolved_hits: 3 },
+                PolicyHits { policy: MatchPolicy::All, raw_hits: 2, resolved_hits: 2 },
+            ]
+        );
+        assert!((stats.overlap_resolution_ratio().unwrap() - 5.0 / 6.0).abs() < 1e-9);
+
+        matcher.reset_stats();
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!((stats.raw_hits_total, stats.resolved_hits_total), (0, 0));
+        assert!(stats.policy_hits.is_empty());
+    }
+
+    #[test]
+    fn test_latency_window_percentiles() {
+        let mut window = LatencyWindow::default();
+        assert_eq!(window.percentiles_us([0.5, 0.95, 0.99]), [0, 0, 0]);
+
+        for micros in 1..=100 {
+            window.record(Duration::from_micros(micros));
+        }
+        assert_eq!(window.percentiles_us([0.5, 0.95, 0.99]), [50, 95, 99]);
+
+        // Only the last LATENCY_WINDOW samples count
+        for _ in 0..LATENCY_WINDOW {
+            window.record(Duration::from_micros(7));
+        }
+        assert_eq!(window.percentiles_us([0.5, 0.95, 0.99]), [7, 7, 7]);
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(matcher.latencies.lock().samples.len(), 1);
+
+        matcher.reset_stats();
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!((stats.match_calls, stats.hits_total), (0, 0));
+        assert_eq!(stats.p99_us, 0);
+        assert!(matcher.latencies.lock().samples.is_empty());
+    }
+
+    #[test]
+    fn test_max_zero_and_empty_input_skip_matching() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[7]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(700, 1.0, 10, 1)],
+            "",
+        );
+        let options = LoadOptions {
+            result_cache_capacity: 4,
+            ..LoadOptions::default()
+        };
+        let matcher =
+            Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options)
+                .unwrap();
+
+        assert!(matcher.match_tokens(&[1, 2, 7], MatchPolicy::LeftmostLongest, 0).is_empty());
+        assert!(matcher.match_tokens(&[], MatchPolicy::All, 10).is_empty());
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!((stats.match_calls, stats.raw_hits_total, stats.cache_misses, stats.cache_entries), (0, 0, 0, 0));
+        assert_eq!(stats.p99_us, 0);
+
+        let single = matcher.match_tokens(&[7], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(single.iter().map(|m| (m.start, m.end, m.payload.phrase_id)).collect::<Vec<_>>(), vec![(0, 1, 700)]);
+        assert!(matcher.match_tokens(&[1], MatchPolicy::LeftmostLongest, 10).is_empty());
+        assert_eq!(Stats::from_matcher(&matcher).match_calls, 2);
+    }
+
+    #[test]
+    fn test_streaming_matches_equal_match_tokens() {
+        // Every pair over tokens 1..=3 plus some triples, so hits chain into
+        // long overlap clusters; [4, 4] is a negative phrase in one variant
+        let patterns: Vec<Vec<u32>> = vec![
+            vec![1, 2], vec![2, 3], vec![3, 1], vec![1, 1], vec![2, 1], vec![3, 2], vec![1, 3],
+            vec![1, 2, 3], vec![2, 2, 2], vec![3, 1, 2], vec![3], vec![4, 4],
+        ];
+        let payloads: Vec<Payload> = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Payload::new(100 * (i as u64 + 1), 1.0 + (i * 7 % 5) as f32, 10 + (i as u32 * 13 % 7), p.len() as u8))
+            .collect();
+        let pattern_refs: Vec<&[u32]> = patterns.iter().map(Vec::as_slice).collect();
+
+        let mut state = 7u64;
+        let tokens: Vec<u32> = (0..2000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as u32 % 4 + 1
+            })
+            .collect();
+
+        let negative = r#", "negative_rules": [{"phrase_id": 1200, "suppresses": [100, 800], "window": 2}]"#;
+        let mut totals = Vec::new();
+        for extra_manifest in ["", negative] {
+            let (automaton_file, payloads_file, manifest_file) = create_artifacts(&pattern_refs, &payloads, extra_manifest);
+            let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+            let key = |m: &Match| (m.start, m.end, m.payload.phrase_id);
+            let stream = |policy, window| {
+                let mut spans = Vec::new();
+                let summary = matcher.match_tokens_streaming(tokens.iter().copied(), policy, window, MatchFilters::NONE, |m| {
+                    spans.push(key(&m));
+                    ControlFlow::Continue(())
+                });
+                assert_eq!(summary.emitted, spans.len());
+                (spans, summary.window_cuts)
+            };
+
+            let all = matcher.match_tokens(&tokens, MatchPolicy::All, usize::MAX);
+            totals.push(all.iter().filter(|m| [100, 800].contains(&m.payload.phrase_id)).count());
+            for policy in MatchPolicy::ALL {
+                let expected: Vec<_> = matcher.match_tokens(&tokens, policy, usize::MAX).iter().map(key).collect();
+                assert!(!expected.is_empty());
+                assert_eq!(stream(policy, tokens.len()), (expected.clone(), 0), "{}", policy.as_str());
+
+                let (spans, cuts) = stream(policy, 4);
+                assert!(cuts > 0);
+                match policy {
+                    MatchPolicy::LeftmostLongest | MatchPolicy::LeftmostFirst | MatchPolicy::All => {
+                        assert_eq!(spans, expected, "{}", policy.as_str())
+                    }
+                    _ => assert!(spans.windows(2).all(|pair| pair[0].1 <= pair[1].0), "{}", policy.as_str()),
+                }
+            }
+        }
+        assert!(totals[1] < totals[0], "the negative phrase suppressed nothing");
+
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(&pattern_refs, &payloads, "");
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        let mut first = Vec::new();
+        matcher.match_tokens_streaming(tokens.iter().copied(), MatchPolicy::LeftmostLongest, 16, MatchFilters::NONE, |m| {
+            first.push(m.start);
+            if first.len() == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        });
+        assert_eq!(first.len(), 3);
+        let summary = matcher.match_tokens_streaming(std::iter::empty(), MatchPolicy::All, 16, MatchFilters::NONE, |_| ControlFlow::Continue(()));
+        assert_eq!(summary, StreamSummary::default());
+        assert_eq!(Stats::from_matcher(&matcher).match_calls, 0);
+    }
+
+    #[test]
+    fn test_result_cache_hits_and_misses() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let options = LoadOptions {
+            result_cache_capacity: 4,
+            ..LoadOptions::default()
+        };
+        let matcher =
+            Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options)
+                .unwrap();
+
+        let first = matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        let second = matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 1);
+
+        let spans = |matches: &[Match]| matches.iter().map(|m| (m.start, m.end)).collect::<Vec<_>>();
+        assert_eq!(spans(&first), spans(&second));
+
+        let stats = Stats::from_matcher(&matcher);
+        assert_eq!((stats.cache_hits, stats.cache_misses), (1, 2));
+        assert_eq!(stats.match_calls, 3);
+        assert_eq!(stats.hits_total, 5);
+        assert_eq!(stats.cache_entries, 2);
+
+        matcher.clear_cache();
+        matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(Stats::from_matcher(&matcher).cache_misses, 3);
+    }
+
+    #[test]
+    fn test_match_tokens_profiled() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        // [1, 2] and [2, 3] overlap; both are candidates, one survives
+        let profiled = matcher.match_tokens_profiled(&[1, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(profiled.candidates.len(), 2);
+        assert_eq!(profiled.resolution.matches.len(), 1);
+
+        let expected = matcher.match_tokens(&[1, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(profiled.resolution.matches[0].start, expected[0].start);
+        assert_eq!(Stats::from_matcher(&matcher).match_calls, 1);
+    }
+
+    #[test]
+    fn test_matcher_best_match() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+
+        let matcher = Matcher::load(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+        )
+        .unwrap();
+
+        // [1, 2] and [2, 3] overlap; the second has the higher salience score
+        let best = matcher.best_match(&[1, 2, 3]).unwrap();
+        assert_eq!(best.payload.phrase_id, 200);
+        assert_eq!(best.start, 1);
+        assert_eq!(best.end, 3);
+
+        assert!(matcher.best_match(&[9, 9]).is_none());
+        assert!(matcher.best_match(&[]).is_none());
+    }
+
+    #[test]
+    fn test_matcher_lazy_payloads() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let options = LoadOptions {
+            lazy_payloads: true,
+            payload_cache_capacity: 1,
+            ..LoadOptions::default()
+        };
+
+        let matcher = Matcher::load_with_options(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+            options,
+        )
+        .unwrap();
+
+        assert!(matcher.lazy_payloads());
+        assert_eq!(matcher.num_patterns(), 2);
+
+        let matches = matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].payload.phrase_id, 100);
+        assert_eq!(matches[1].payload.phrase_id, 200);
+    }
+
+    #[test]
+    fn test_matcher_negative_rules() {
+        // [1] ("apple") is suppressed when the negative [8] ("inc") is within 1 token
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1], &[8]],
+            &[Payload::new(100, 1.5, 50, 1), Payload::new(900, 0.0, 0, 1)],
+            r#", "negative_rules": [{"phrase_id": 900, "suppresses": [100], "window": 1}]"#,
+        );
+
+        let matcher = Matcher::load(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+        )
+        .unwrap();
+
+        let suppressed = matcher.match_tokens(&[1, 5, 8], MatchPolicy::LeftmostLongest, 10);
+        assert!(suppressed.is_empty());
+
+        let kept = matcher.match_tokens(&[1, 5, 5, 8], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].payload.phrase_id, 100);
+    }
+
+    #[test]
+    fn test_matcher_rejects_zero_length_payload() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 30, 0)],
+            "",
+        );
+
+        for lazy_payloads in [false, true] {
+            let result = Matcher::load_with_options(
+                automaton_file.path(),
+                payloads_file.path(),
+                manifest_file.path(),
+                LoadOptions { lazy_payloads, ..LoadOptions::default() },
+            );
+
+            match result {
+                Err(MatcherError::InvalidPayload { index, .. }) => assert_eq!(index, 1),
+                Err(other) => panic!("unexpected error: {}", other),
+                Ok(_) => panic!("zero-length payload should be rejected"),
+            }
+        }
+
+        // A manifest recording a zero-length pattern is refused without reading payloads
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&[&[1, 2]], &[Payload::new(100, 1.5, 50, 2)], r#", "min_pattern_len": 0"#);
+        let result = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path());
+        assert!(matches!(result, Err(MatcherError::Manifest(ManifestError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_zero_length_payload_is_rejected_despite_manifest_min_pattern_len() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3]],
+            &[Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 30, 0)],
+            r#", "min_pattern_len": 2"#,
+        );
+
+        let result = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path());
+        assert!(matches!(result, Err(MatcherError::InvalidPayload { index: 1, .. })));
+    }
+
+    #[test]
+    fn test_phrase_frequencies() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+
+        let matcher = Matcher::load(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+        )
+        .unwrap();
+
+        // [1,2] twice and the overlapping [2,3] once
+        let frequencies = matcher.phrase_frequencies(&[1, 2, 3, 9, 1, 2]);
+        assert_eq!(frequencies.len(), 2);
+        assert_eq!(frequencies[&100], 2);
+        assert_eq!(frequencies[&200], 1);
+
+        assert!(matcher.phrase_frequencies(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_contains_phrase_ids() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        assert_eq!(matcher.contains_phrase_ids(&[2, 3, 9, 1, 2, 1, 2]), vec![100, 200]);
+        assert_eq!(matcher.contains_phrase_ids(&[2, 3]), vec![200]);
+        assert!(matcher.contains_phrase_ids(&[9, 9]).is_empty());
+    }
+
+    #[test]
+    fn test_phrase_scores_aggregations() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        // Phrase 100 ([1,2]) matches twice, phrase 200 ([2,3]) once
+        let tokens = [1, 2, 3, 9, 1, 2];
+        let score = Payload::new(100, 1.5, 50, 2).salience_score();
+
+        let sum = matcher.phrase_scores(&tokens, ScoreAggregation::Sum);
+        assert!((sum[&100] - 2.0 * score).abs() < 1e-5);
+        assert!((sum[&200] - Payload::new(200, 2.0, 100, 2).salience_score()).abs() < 1e-5);
+
+        let max = matcher.phrase_scores(&tokens, ScoreAggregation::Max);
+        assert!((max[&100] - score).abs() < 1e-5);
+
+        let mean = matcher.phrase_scores(&tokens, ScoreAggregation::Mean);
+        assert!((mean[&100] - score).abs() < 1e-5);
+
+        let weighted = matcher.phrase_scores(&tokens, ScoreAggregation::CountWeighted);
+        assert!((weighted[&100] - score * 3f32.ln()).abs() < 1e-5);
+
+        assert!(matcher.phrase_scores(&[], ScoreAggregation::Sum).is_empty());
+    }
+
+    #[test]
+    fn test_score_aggregation_from_str() {
+        assert_eq!(ScoreAggregation::from_str("count_weighted"), Some(ScoreAggregation::CountWeighted));
+        assert_eq!(ScoreAggregation::from_str("median"), None);
+        assert_eq!(ScoreAggregation::Max.aggregate(&[1.0, 3.0, 2.0]), 3.0);
+    }
+
+    #[test]
+    fn test_matcher_loads_payload_shards() {
+        let (automaton_file, payloads_file, manifest_file) = create_test_artifacts();
+
+        // Split the two payload records into one shard each
+        let bytes = std::fs::read(payloads_file.path()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("payloads-000.bin"), &bytes[..PAYLOAD_SIZE]).unwrap();
+        std::fs::write(dir.path().join("payloads-001.bin"), &bytes[PAYLOAD_SIZE..]).unwrap();
+
+        let matcher = Matcher::load(automaton_file.path(), dir.path(), manifest_file.path()).unwrap();
+        let matches = matcher.match_tokens(&[1, 2, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(matches[0].payload.phrase_id, 100);
+        assert_eq!(matches[1].payload.phrase_id, 200);
+
+        // A missing shard no longer adds up to the manifest's pattern count
+        std::fs::remove_file(dir.path().join("payloads-001.bin")).unwrap();
+        assert!(Matcher::load(automaton_file.path(), dir.path(), manifest_file.path()).is_err());
+    }
+
+    #[test]
+    fn test_match_reports_build_order_pattern_id() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[5], &[1, 2], &[2, 3]],
+            &[
+                Payload::new(900, 1.0, 1, 1),
+                Payload::new(100, 1.5, 50, 2),
+                Payload::new(200, 2.0, 30, 2),
+            ],
+            "",
+        );
+
+        let matcher = Matcher::load(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+        )
+        .unwrap();
+
+        let matches = matcher.match_tokens(&[2, 3, 5, 1, 2], MatchPolicy::LeftmostLongest, 10);
+        let ids: Vec<(u64, usize)> = matches.iter().map(|m| (m.payload.phrase_id, m.pattern_id)).collect();
+        assert_eq!(ids, vec![(200, 2), (900, 0), (100, 1)]);
+    }
+
+    #[test]
+    fn test_estimate_memory() {
+        let payloads: Vec<Payload> = (0..3).map(|i| Payload::new(i, 1.0, 10, 2)).collect();
+        let (automaton_file, payloads_file, manifest_file) =
+            create_artifacts(&[&[1, 2], &[3, 4], &[5, 6]], &payloads, "");
+
+        let estimate = estimate_memory(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        assert_eq!(estimate.automaton_bytes, std::fs::metadata(automaton_file.path()).unwrap().len());
+        assert_eq!(estimate.payload_count, 3);
+        assert_eq!(estimate.payloads_bytes, 3 * std::mem::size_of::<Payload>() as u64);
+        assert_eq!(estimate.total_bytes(), estimate.automaton_bytes + estimate.payloads_bytes);
+
+        assert!(estimate_memory(automaton_file.path(), Path::new("/nonexistent/payloads.bin"), manifest_file.path()).is_err());
+        assert!(estimate_memory(automaton_file.path(), payloads_file.path(), Path::new("/nonexistent/manifest.json")).is_err());
+    }
+
+    #[test]
+    fn test_estimate_memory_counts_legacy_records_at_their_own_size() {
+        let payloads: Vec<Payload> = (0..7).map(|i| Payload::new(i, 1.0, 10, 2)).collect();
+        let (automaton_file, _, manifest_file) = create_artifacts(&[&[1, 2]], &payloads, "");
+
+        let mut payloads_file = NamedTempFile::new().unwrap();
+        for payload in &payloads {
+            payload.write_as(&mut payloads_file, PayloadFormat::V1).unwrap();
+        }
+        payloads_file.flush().unwrap();
+        let manifest = std::fs::read_to_string(manifest_file.path()).unwrap()
+            .replace(r#""payload_format_version": 3"#, r#""payload_format_version": 1"#);
+        std::fs::write(manifest_file.path(), manifest).unwrap();
+
+        let estimate = estimate_memory(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+        assert_eq!(std::fs::metadata(payloads_file.path()).unwrap().len(), 7 * 17);
+        assert_eq!(estimate.payload_count, 7);
+        assert_eq!(estimate.payloads_bytes, 7 * std::mem::size_of::<Payload>() as u64);
+    }
+
+    #[test]
+    fn test_lenient_load_records_diagnostics() {
+        // Manifest claims one more pattern than payloads.bin holds
+        let payloads = [Payload::new(100, 1.5, 50, 2), Payload::new(200, 2.0, 30, 0)];
+        let (automaton_file, payloads_file, _) = create_artifacts(&[&[1, 2], &[2, 3]], &payloads, "");
+        let (_, _, manifest_file) = create_artifacts(&[&[1, 2], &[2, 3], &[3, 4]], &[payloads[0].clone(), payloads[1].clone(), payloads[1].clone()], "");
+
+        let strict = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path());
+        assert!(matches!(strict, Err(MatcherError::Automaton(_))));
+
+        let matcher = Matcher::load_with_options(
+            automaton_file.path(),
+            payloads_file.path(),
+            manifest_file.path(),
+            LoadOptions { strict: false, ..LoadOptions::default() },
+        )
+        .unwrap();
+
+        let diagnostics = matcher.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].contains("Payload count mismatch"));
+        assert!(diagnostics[1].contains("zero-length"));
+
+        let matches = matcher.match_tokens(&[1, 2], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(matches[0].payload.phrase_id, 100);
+    }
+
+    #[test]
+    fn test_validate_patterns() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[2, 3]],
+            &[Payload::new(100, f32::NAN, 50, 2), Payload::new(200, 2.0, 30, 2)],
+            r#", "negative_rules": [{"phrase_id": 900, "suppresses": [100], "window": 1}]"#,
+        );
+        let load = |options: LoadOptions| {
+            Matcher::load_with_options(automaton_file.path(), payloads_file.path(), manifest_file.path(), options)
+        };
+
+        // Not checked unless asked for
+        assert!(load(LoadOptions::default()).unwrap().diagnostics().is_empty());
+
+        let strict = load(LoadOptions { validate_patterns: true, ..LoadOptions::default() });
+        assert!(matches!(strict, Err(MatcherError::InvalidPayload { index: 0, .. })));
+
+        let lenient = load(LoadOptions {
+            validate_patterns: true,
+            strict: false,
+            verify_checksums: true,
+            ..LoadOptions::default()
+        })
+        .unwrap();
+        let diagnostics = lenient.diagnostics();
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics[0].contains("no checksums"));
+        assert!(diagnostics[1].contains("non-finite salience"));
+        assert!(diagnostics[2].contains("phrase_id 900"));
+    }
+
+    #[test]
+    fn test_load_checked() {
+        let (automaton_file, payloads_file, _) = create_test_artifacts();
+        let automaton_sha = crate::manifest::sha256_hex(&std::fs::read(automaton_file.path()).unwrap());
+        let payloads_sha = crate::manifest::sha256_hex(&std::fs::read(payloads_file.path()).unwrap());
+        let manifest = |checksums: &str| {
+            let mut manifest_file = NamedTempFile::new().unwrap();
+            write!(
+                manifest_file,
+                r#"{{"version": "v1", "tokenizer": "t", "num_patterns": 2, "built_at": "2025-01-01T00:00:00Z", "separator_id": 4294967294, "automaton_format": "daachorse-1", "payload_format_version": 3{}}}"#,
+                checksums
+            )
+            .unwrap();
+            manifest_file
+        };
+        let load = |manifest_file: &NamedTempFile| {
+            Matcher::load_checked(automaton_file.path(), payloads_file.path(), manifest_file.path())
+        };
+
+        let good = manifest(&format!(r#", "automaton_sha256": "{}", "payloads_sha256": "{}""#, automaton_sha, payloads_sha));
+        let matcher = load(&good).unwrap();
+        assert!(matcher.diagnostics().is_empty());
+        assert_eq!(matcher.match_tokens(&[1, 2], MatchPolicy::LeftmostLongest, 10).len(), 1);
+
+        let zeros = "0".repeat(64);
+        let bad = manifest(&format!(r#", "automaton_sha256": "{}", "payloads_sha256": "{}""#, automaton_sha, zeros));
+        assert!(matches!(load(&bad), Err(MatcherError::ChecksumMismatch { artifact: "payloads", .. })));
+
+        // Lenient loads refuse a mismatch too
+        let lenient = Matcher::load_with_options(
+            automaton_file.path(),
+            payloads_file.path(),
+            bad.path(),
+            LoadOptions { verify_checksums: true, strict: false, ..LoadOptions::default() },
+        );
+        assert!(matches!(lenient, Err(MatcherError::ChecksumMismatch { .. })));
+
+        let partial = manifest(&format!(r#", "automaton_sha256": "{}""#, automaton_sha));
+        assert!(matches!(load(&partial), Err(MatcherError::Manifest(_))));
+        let matcher = Matcher::load_with_options(
+            automaton_file.path(),
+            payloads_file.path(),
+            partial.path(),
+            LoadOptions { verify_checksums: true, ..LoadOptions::default() },
+        )
+        .unwrap();
+        assert!(matcher.diagnostics()[0].contains("no payloads_sha256"));
+    }
+
+    #[test]
+    fn test_automaton_format_check() {
+        let (automaton_file, payloads_file, _) = create_test_artifacts();
+        let manifest = |format: Option<&str>| {
+            let mut manifest_file = NamedTempFile::new().unwrap();
+            let format = format.map_or(String::new(), |f| format!(r#", "automaton_format": "{}""#, f));
+            write!(
+                manifest_file,
+                r#"{{"version": "v1", "tokenizer": "t", "num_patterns": 2, "built_at": "2025-01-01T00:00:00Z", "separator_id": 4294967294, "payload_format_version": 3{}}}"#,
+                format
+            )
+            .unwrap();
+            manifest_file
+        };
+        let load = |manifest_file: &NamedTempFile, strict: bool| {
+            Matcher::load_with_options(
+                automaton_file.path(),
+                payloads_file.path(),
+                manifest_file.path(),
+                LoadOptions { strict, ..LoadOptions::default() },
+            )
+        };
+
+        assert!(load(&manifest(Some(AUTOMATON_FORMAT)), true).unwrap().diagnostics().is_empty());
+
+        // Legacy manifests load, with a note
+        let legacy = load(&manifest(None), true).unwrap();
+        assert!(legacy.diagnostics()[0].contains("automaton_format not recorded"));
+
+        // A different format is refused even when lenient
+        let mismatch = manifest(Some("daachorse-2"));
+        assert!(matches!(load(&mismatch, true), Err(MatcherError::Automaton(_))));
+        assert!(matches!(load(&mismatch, false), Err(MatcherError::Automaton(_))));
+    }
+
+    #[test]
+    fn test_matches_carry_payload_idf() {
+        let (automaton_file, payloads_file, manifest_file) = create_artifacts(
+            &[&[1, 2], &[3]],
+            &[Payload::new(100, 1.5, 50, 2).with_idf(2.5), Payload::new(200, 1.0, 5, 1)],
+            "",
+        );
+        let matcher = Matcher::load(automaton_file.path(), payloads_file.path(), manifest_file.path()).unwrap();
+
+        let matches = matcher.match_tokens(&[1, 2, 3], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(matches[0].payload.idf, 2.5);
+        assert_eq!(matches[1].payload.idf, 0.0);
+    }
+}
@@ -0,0 +1,181 @@
+//! Several artifact builds matched over the same token IDs in one call, with
+//! each match tagged by the namespace that produced it.
+
+use crate::matcher::{Matcher, MatcherError};
+use crate::policy::{resolve_overlaps_bounded, Match, MatchFilters, MatchPolicy, Resolution};
+
+/// Matchers built separately (one phrase set per domain, say) over one token
+/// ID space. Each keeps its own vocabulary, manifest, and negative rules; all
+/// must share a `separator_id`.
+///
+/// Candidates from every namespace are pooled and resolved together, exactly
+/// as if they came from one artifact: a policy never prefers a namespace. Of
+/// identical spans, the usual tie-break keeps the higher salience, then the
+/// smaller phrase_id, and after that the namespace added first.
+pub struct MatcherSet {
+    /// In the order given to `new`; names are unique.
+    members: Vec<(String, Matcher)>,
+    /// The smallest `max_cluster` any member was loaded with.
+    max_cluster: usize,
+}
+
+impl MatcherSet {
+    pub fn new(members: Vec<(String, Matcher)>) -> Result<Self, MatcherError> {
+        let Some((first_name, first)) = members.first() else {
+            return Err(MatcherError::InvalidSet("no matchers given".to_string()));
+        };
+
+        let separator_id = first.manifest().separator_id;
+        for (index, (name, matcher)) in members.iter().enumerate() {
+            if members[..index].iter().any(|(other, _)| other == name) {
+                return Err(MatcherError::InvalidSet(format!("duplicate namespace {:?}", name)));
+            }
+            if matcher.manifest().separator_id != separator_id {
+                return Err(MatcherError::InvalidSet(format!(
+                    "namespace {:?} uses separator_id {}, but {:?} uses {}",
+                    name,
+                    matcher.manifest().separator_id,
+                    first_name,
+                    separator_id
+                )));
+            }
+        }
+
+        let max_cluster = members.iter().map(|(_, matcher)| matcher.max_cluster()).min().unwrap_or_default();
+        Ok(Self { members, max_cluster })
+    }
+
+    pub fn namespaces(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn get(&self, namespace: &str) -> Option<&Matcher> {
+        self.members.iter().find(|(name, _)| name == namespace).map(|(_, matcher)| matcher)
+    }
+
+    pub fn match_tokens(&self, token_ids: &[u32], policy: MatchPolicy, max: usize) -> Vec<Match> {
+        self.match_tokens_detailed(token_ids, policy, max, MatchFilters::NONE).matches
+    }
+
+    /// `match_tokens` plus whether overlap resolution had to degrade. Each
+    /// namespace applies its own negative rules, which only suppress its own
+    /// phrases; `filters` then apply to every namespace alike.
+    ///
+    /// Calls do not count toward the members' `Stats` or use their result caches.
+    /// Like `Matcher::match_tokens_unknown`, `max == 0` and empty `token_ids`
+    /// return no matches without matching.
+    pub fn match_tokens_detailed(
+        &self,
+        token_ids: &[u32],
+        policy: MatchPolicy,
+        max: usize,
+        filters: MatchFilters,
+    ) -> Resolution {
+        if max == 0 || token_ids.is_empty() {
+            return Resolution { matches: Vec::new(), degraded: false };
+        }
+
+        // Pooled in namespace order; resolution sorts stably, so that order is
+        // the last tie-break between identical spans
+        let mut candidates = Vec::new();
+        for (name, matcher) in &self.members {
+            candidates.extend(matcher.candidates(token_ids, filters).into_iter().map(|mut m| {
+                m.namespace = Some(name.clone());
+                m
+            }));
+        }
+
+        let mut resolved = resolve_overlaps_bounded(candidates, policy, self.max_cluster);
+        resolved.matches.truncate(max);
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifacts::{build_artifacts, load_and_validate_phrases, BuildConfig, BuildLimits, IdfSource};
+    use crate::progress::silent;
+    use std::path::Path;
+
+    fn build(dir: &Path, separator_id: u32, lines: &[&str]) -> Matcher {
+        let input = dir.join("phrases.jsonl");
+        std::fs::write(&input, lines.join("\n")).unwrap();
+        let config: BuildConfig = serde_json::from_str(&format!(
+            r#"{{"version": "v1", "tokenizer": "external", "separator_id": {}}}"#,
+            separator_id
+        ))
+        .unwrap();
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(input.to_str().unwrap(), &config, &silent).unwrap();
+        let artifacts =
+            build_artifacts(&phrases, unique_tokens, &config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+        let paths = artifacts.write(dir, "phrases.jsonl", false, &silent).unwrap();
+        Matcher::load(paths.automaton, paths.payloads, paths.manifest).unwrap()
+    }
+
+    fn set(namespaces: &[(&str, &[&str])]) -> (MatcherSet, Vec<tempfile::TempDir>) {
+        let dirs: Vec<tempfile::TempDir> = namespaces.iter().map(|_| tempfile::tempdir().unwrap()).collect();
+        let members = namespaces
+            .iter()
+            .zip(&dirs)
+            .map(|((name, lines), dir)| (name.to_string(), build(dir.path(), 4294967294, lines)))
+            .collect();
+        (MatcherSet::new(members).unwrap(), dirs)
+    }
+
+    fn spans(matches: &[Match]) -> Vec<(usize, usize, &str)> {
+        matches.iter().map(|m| (m.start, m.end, m.namespace.as_deref().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_matcher_set_resolves_overlaps_across_namespaces() {
+        let (set, _dirs) = set(&[
+            ("medical", &[r#"{"token_ids": [1, 2], "phrase_id": 1, "salience": 1.0, "count": 10}"#]),
+            ("legal", &[r#"{"token_ids": [2, 3, 4], "phrase_id": 1, "salience": 5.0, "count": 10}"#]),
+        ]);
+        assert_eq!(set.namespaces().collect::<Vec<_>>(), vec!["medical", "legal"]);
+
+        let tokens = [1, 2, 3, 4];
+        assert_eq!(spans(&set.match_tokens(&tokens, MatchPolicy::LeftmostLongest, 10)), vec![(0, 2, "medical")]);
+        assert_eq!(spans(&set.match_tokens(&tokens, MatchPolicy::SalienceMax, 10)), vec![(1, 4, "legal")]);
+        assert_eq!(
+            spans(&set.match_tokens(&tokens, MatchPolicy::All, 10)),
+            vec![(0, 2, "medical"), (1, 4, "legal")]
+        );
+
+        assert!(set.match_tokens(&tokens, MatchPolicy::All, 0).is_empty());
+        assert!(set.match_tokens(&[], MatchPolicy::All, 10).is_empty());
+
+        let filters = MatchFilters { min_salience: 2.0, ..MatchFilters::NONE };
+        let resolved = set.match_tokens_detailed(&tokens, MatchPolicy::LeftmostLongest, 10, filters);
+        assert_eq!(spans(&resolved.matches), vec![(1, 4, "legal")]);
+    }
+
+    #[test]
+    fn test_matcher_set_identical_spans_prefer_the_earlier_namespace() {
+        let phrase = r#"{"token_ids": [5, 6], "phrase_id": 3, "salience": 2.0, "count": 10}"#;
+        let (set, _dirs) = set(&[("legal", &[phrase]), ("finance", &[phrase])]);
+        let matches = set.match_tokens(&[5, 6], MatchPolicy::LeftmostLongest, 10);
+        assert_eq!(spans(&matches), vec![(0, 2, "legal")]);
+
+        let json = serde_json::to_string(&matches[0]).unwrap();
+        assert!(json.contains(r#""namespace":"legal""#), "{}", json);
+    }
+
+    #[test]
+    fn test_matcher_set_rejects_inconsistent_members() {
+        let phrase = r#"{"token_ids": [5, 6], "phrase_id": 3, "salience": 2.0, "count": 10}"#;
+        let dirs: Vec<tempfile::TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let matcher = |index: usize, separator_id| build(dirs[index].path(), separator_id, &[phrase]);
+
+        assert!(MatcherSet::new(Vec::new()).is_err());
+        let err = MatcherSet::new(vec![("a".to_string(), matcher(0, 4294967294)), ("a".to_string(), matcher(1, 4294967294))])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Invalid matcher set: duplicate namespace \"a\"");
+        let err = MatcherSet::new(vec![("a".to_string(), matcher(0, 4294967294)), ("b".to_string(), matcher(2, 4294967293))])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("\"b\" uses separator_id 4294967293"), "{}", err);
+    }
+}
@@ -0,0 +1,948 @@
+//! N-gram mining over a tokenized corpus: `phrasekit_mine` without the CLI.
+
+use crate::compression;
+use crate::config::Validate;
+use crate::manifest::UnicodeNormalization;
+use crate::progress::Progress;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Tokens per shingle for near-duplicate detection.
+const SHINGLE_SIZE: usize = 5;
+/// MinHash signature length; split into `BANDS` bands of `ROWS` for LSH.
+const NUM_HASHES: usize = 64;
+const BANDS: usize = 16;
+const ROWS: usize = NUM_HASHES / BANDS;
+pub const DEFAULT_NEAR_DUP_THRESHOLD: f64 = 0.8;
+/// Corpus lines parsed and counted per parallel batch.
+const CHUNK_LINES: usize = 10_000;
+/// Stands in for a skipped interior token in skip-gram `tokens`.
+pub const GAP_TOKEN: &str = "*";
+/// Shortest n-gram that may contain a gap: skips are interior, so a 3-gram is
+/// the first with a position to skip.
+const MIN_SKIP_N: usize = 3;
+/// Sketch counters per row: 16 MB a row, 64 MB at the default depth of 4.
+const DEFAULT_SKETCH_WIDTH: usize = 1 << 22;
+const DEFAULT_SKETCH_DEPTH: usize = 4;
+
+/// Lowercased n-gram tokens to corpus frequency.
+pub type NgramCounts = HashMap<Vec<String>, u32>;
+
+#[derive(Debug, Deserialize)]
+pub struct Document {
+    pub tokens: Vec<String>,
+    #[serde(default)]
+    pub doc_id: Option<String>,
+    /// Exclusive token indices where a sentence ends; no n-gram crosses one.
+    #[serde(default)]
+    pub sentence_breaks: Vec<usize>,
+}
+
+impl Document {
+    fn normalize_unicode(&mut self, unicode: UnicodeNormalization) {
+        for token in &mut self.tokens {
+            if let Cow::Owned(normalized) = unicode.apply(token) {
+                *token = normalized;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MineConfig {
+    #[serde(default = "default_min_n")]
+    pub min_n: usize,
+    #[serde(default = "default_max_n")]
+    pub max_n: usize,
+    #[serde(default = "default_min_count")]
+    pub min_count: u32,
+    /// N-grams seen more often than this are dropped as boilerplate.
+    #[serde(default)]
+    pub max_count: Option<u32>,
+    /// Keeps only the K most frequent n-grams left after `min_count` and
+    /// `max_count`; equal counts are cut in token order.
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// Most interior positions of an n-gram (`n >= 3`) that may be replaced by
+    /// `GAP_TOKEN`; 0 mines only contiguous n-grams.
+    #[serde(default)]
+    pub max_skip: usize,
+    /// Newline-delimited stopwords; n-grams that start or end with one are not
+    /// counted.
+    #[serde(default)]
+    pub stopwords_path: Option<String>,
+    /// Worker threads for parsing and counting; 0 uses one per core.
+    #[serde(default)]
+    pub threads: usize,
+    /// Count in a `CountMinSketch` first, then read the corpus again and count
+    /// exactly only the n-grams it estimates at `min_count` or more.
+    #[serde(default)]
+    pub sketch: bool,
+    #[serde(default = "default_sketch_width")]
+    pub sketch_width: usize,
+    #[serde(default = "default_sketch_depth")]
+    pub sketch_depth: usize,
+    /// Count n-grams in their original case, so "BRCA1" and "brca1" stay
+    /// apart. Stopwords and dedup still compare lowercased.
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Unicode form tokens are brought to before anything else, matching the
+    /// `unicode_normalization` the phrases will be built with.
+    #[serde(default)]
+    pub unicode_normalization: UnicodeNormalization,
+}
+
+impl Default for MineConfig {
+    fn default() -> Self {
+        Self {
+            min_n: default_min_n(),
+            max_n: default_max_n(),
+            min_count: default_min_count(),
+            max_count: None,
+            top_k: None,
+            max_skip: 0,
+            stopwords_path: None,
+            threads: 0,
+            sketch: false,
+            sketch_width: default_sketch_width(),
+            sketch_depth: default_sketch_depth(),
+            case_sensitive: false,
+            unicode_normalization: UnicodeNormalization::None,
+        }
+    }
+}
+
+impl Validate for MineConfig {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.min_n < 1 {
+            problems.push(format!("min_n must be >= 1, got {}", self.min_n));
+        }
+        if self.max_n > 10 {
+            problems.push(format!("max_n must be <= 10, got {}", self.max_n));
+        }
+        if self.min_n > self.max_n {
+            problems.push(format!("min_n ({}) must be <= max_n ({})", self.min_n, self.max_n));
+        }
+        if let Some(max_count) = self.max_count.filter(|&max_count| max_count < self.min_count) {
+            problems.push(format!("max_count ({}) must be >= min_count ({})", max_count, self.min_count));
+        }
+        if self.top_k == Some(0) {
+            problems.push("top_k must be >= 1".to_string());
+        }
+        if self.sketch {
+            if self.sketch_width == 0 || self.sketch_depth == 0 {
+                problems.push(format!(
+                    "sketch_width and sketch_depth must be >= 1, got {} and {}",
+                    self.sketch_width, self.sketch_depth
+                ));
+            } else if self.sketch_width.checked_mul(self.sketch_depth).is_none() {
+                problems.push("sketch_width * sketch_depth overflows".to_string());
+            }
+        }
+        problems
+    }
+}
+
+fn default_min_n() -> usize {
+    2
+}
+
+fn default_max_n() -> usize {
+    5
+}
+
+fn default_min_count() -> u32 {
+    10
+}
+
+fn default_sketch_width() -> usize {
+    DEFAULT_SKETCH_WIDTH
+}
+
+fn default_sketch_depth() -> usize {
+    DEFAULT_SKETCH_DEPTH
+}
+
+#[derive(Debug, Serialize)]
+struct Ngram {
+    tokens: Vec<String>,
+    count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupMode {
+    /// Drop documents whose lowercased token sequence was already seen.
+    Exact,
+    /// Also drop documents whose estimated shingle Jaccard similarity to an
+    /// earlier document is at least `threshold`.
+    Near { threshold: f64 },
+}
+
+/// Tracks documents seen so far and flags duplicates as they stream past.
+struct Deduper {
+    mode: DedupMode,
+    exact: HashSet<u64>,
+    signatures: Vec<[u64; NUM_HASHES]>,
+    buckets: HashMap<(usize, u64), Vec<usize>>,
+}
+
+impl Deduper {
+    fn new(mode: DedupMode) -> Self {
+        Self {
+            mode,
+            exact: HashSet::new(),
+            signatures: Vec::new(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns true if `tokens` duplicates an earlier document; otherwise
+    /// records it and returns false.
+    fn is_duplicate(&mut self, tokens: &[String]) -> bool {
+        let normalized: Vec<String> = tokens.iter().map(|t| t.to_lowercase()).collect();
+
+        if !self.exact.insert(hash_of(&normalized)) {
+            return true;
+        }
+
+        let DedupMode::Near { threshold } = self.mode else {
+            return false;
+        };
+
+        let signature = minhash(&normalized);
+        let band_keys: Vec<(usize, u64)> = (0..BANDS)
+            .map(|band| (band, hash_of(&signature[band * ROWS..(band + 1) * ROWS])))
+            .collect();
+
+        // LSH: only documents sharing a band are compared
+        let mut checked = HashSet::new();
+        for key in &band_keys {
+            for &candidate in self.buckets.get(key).into_iter().flatten() {
+                if checked.insert(candidate) && similarity(&signature, &self.signatures[candidate]) >= threshold {
+                    return true;
+                }
+            }
+        }
+
+        let id = self.signatures.len();
+        self.signatures.push(signature);
+        for key in band_keys {
+            self.buckets.entry(key).or_default().push(id);
+        }
+        false
+    }
+}
+
+fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// splitmix64 finalizer, used to derive the MinHash family from one base hash.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn minhash(tokens: &[String]) -> [u64; NUM_HASHES] {
+    let mut signature = [u64::MAX; NUM_HASHES];
+    // Documents shorter than a shingle count as a single shingle
+    let size = SHINGLE_SIZE.min(tokens.len()).max(1);
+    for shingle in tokens.windows(size) {
+        let base = hash_of(shingle);
+        for (i, slot) in signature.iter_mut().enumerate() {
+            *slot = (*slot).min(mix(base ^ (i as u64).wrapping_mul(0x9e3779b97f4a7c15)));
+        }
+    }
+    signature
+}
+
+/// Fraction of agreeing MinHash slots, an estimate of shingle Jaccard similarity.
+fn similarity(a: &[u64; NUM_HASHES], b: &[u64; NUM_HASHES]) -> f64 {
+    a.iter().zip(b).filter(|(x, y)| x == y).count() as f64 / NUM_HASHES as f64
+}
+
+/// Approximate n-gram counts in `depth` rows of `width` counters, shared by the
+/// counting threads. An estimate never undercounts; it overcounts by at most
+/// `e * N / width`, where N is the number of n-grams added, with probability
+/// `1 - e^-depth`.
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<AtomicU32>,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        Self {
+            width,
+            depth,
+            counters: (0..width * depth).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    /// One counter per row, each row hashed with its own member of the `mix` family.
+    fn cells<'a>(&'a self, ngram: &[String]) -> impl Iterator<Item = usize> + 'a {
+        let base = hash_of(ngram);
+        (0..self.depth).map(move |row| {
+            let hash = mix(base ^ (row as u64).wrapping_mul(0x9e3779b97f4a7c15));
+            row * self.width + (hash % self.width as u64) as usize
+        })
+    }
+
+    pub fn add(&self, ngram: &[String]) {
+        for cell in self.cells(ngram) {
+            // Saturates rather than wrapping, so an estimate can never drop
+            let _ = self.counters[cell].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_add(1));
+        }
+    }
+
+    pub fn estimate(&self, ngram: &[String]) -> u32 {
+        self.cells(ngram).map(|cell| self.counters[cell].load(Ordering::Relaxed)).min().unwrap_or(0)
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.counters.len() * std::mem::size_of::<AtomicU32>()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MiningStats {
+    pub total_docs: usize,
+    pub duplicate_docs: usize,
+    pub total_tokens: usize,
+    pub total_ngrams_extracted: usize,
+    /// Gapped variants emitted on top of `total_ngrams_extracted`.
+    pub skip_grams_extracted: usize,
+    /// N-grams dropped for a stopword as their first or last token.
+    pub filtered_boundary_stopword: usize,
+    /// With `sketch`, the candidates counted exactly in the second pass.
+    pub unique_ngrams: usize,
+    /// N-grams at `min_count` or more dropped for exceeding `max_count`.
+    pub filtered_max_count: usize,
+    /// N-grams within the count bounds dropped for falling outside `top_k`.
+    pub filtered_top_k: usize,
+    pub ngrams_after_filter: usize,
+}
+
+/// Counts every n-gram in the JSONL corpus at `corpus_path`, skipping lines
+/// that fail to parse and, with `dedup`, documents seen before. With
+/// `config.sketch` the corpus is read twice and the counts hold only n-grams
+/// the sketch put at `min_count` or more, each counted exactly.
+pub fn mine_ngrams(
+    corpus_path: &str,
+    config: &MineConfig,
+    dedup: Option<DedupMode>,
+    progress: &dyn Fn(Progress),
+) -> Result<(NgramCounts, MiningStats), Box<dyn std::error::Error>> {
+    let stopwords = match &config.stopwords_path {
+        Some(path) => {
+            let stopwords = load_stopwords(path, config.unicode_normalization)?;
+            progress(Progress::Done(format!("Loaded {} stopwords", stopwords.len())));
+            stopwords
+        }
+        None => HashSet::new(),
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(config.threads).build()?;
+    progress(Progress::Done(format!("Using {} threads", pool.current_num_threads())));
+
+    let sketch = config.sketch.then(|| CountMinSketch::new(config.sketch_width, config.sketch_depth));
+    if let Some(sketch) = &sketch {
+        progress(Progress::Done(format!(
+            "Counting in a {} x {} sketch ({} MB)",
+            config.sketch_depth,
+            config.sketch_width,
+            sketch.size_bytes() / 1_048_576
+        )));
+    }
+
+    let pass = Pass {
+        corpus_path,
+        config,
+        dedup,
+        stopwords: &stopwords,
+        pool: &pool,
+        progress,
+    };
+    let mut stats = MiningStats::default();
+    let tally = match &sketch {
+        Some(sketch) => Tally::Sketch(sketch),
+        None => Tally::Exact,
+    };
+    let mut total = pass.run(tally, Some(&mut stats))?;
+
+    stats.total_ngrams_extracted = total.ngrams;
+    stats.skip_grams_extracted = total.skip_grams;
+    stats.filtered_boundary_stopword = total.filtered_boundary_stopword;
+    progress(Progress::Done(format!("Processed {} documents", stats.total_docs)));
+    if dedup.is_some() {
+        progress(Progress::Done(format!("Skipped {} duplicate documents", stats.duplicate_docs)));
+    }
+
+    match &sketch {
+        Some(sketch) => {
+            progress(Progress::Note("Counting sketch candidates exactly...".into()));
+            total = pass.run(Tally::Candidates(sketch, config.min_count), None)?;
+            stats.unique_ngrams = total.counts.len();
+            progress(Progress::Done(format!("Counted {} candidate n-grams", stats.unique_ngrams)));
+        }
+        None => {
+            stats.unique_ngrams = total.counts.len();
+            progress(Progress::Done(format!("Extracted {} unique n-grams", stats.unique_ngrams)));
+        }
+    }
+
+    Ok((total.counts, stats))
+}
+
+/// One read of the corpus, shared by both passes of sketch mining.
+struct Pass<'a> {
+    corpus_path: &'a str,
+    config: &'a MineConfig,
+    dedup: Option<DedupMode>,
+    stopwords: &'a HashSet<String>,
+    pool: &'a rayon::ThreadPool,
+    progress: &'a dyn Fn(Progress),
+}
+
+impl Pass<'_> {
+    /// Streams the corpus in `CHUNK_LINES` batches. Document stats are kept,
+    /// and parse errors reported, only when `stats` is given, so a second
+    /// pass stays quiet. Dedup starts over each pass and so drops the same
+    /// documents.
+    fn run(&self, tally: Tally, mut stats: Option<&mut MiningStats>) -> Result<Extraction, Box<dyn std::error::Error>> {
+        let reader = compression::open_input(self.corpus_path)?;
+        let mut total = Extraction::default();
+        let mut deduper = self.dedup.map(Deduper::new);
+        let mut docs_seen = 0;
+
+        let mut lines = reader.lines().enumerate();
+        loop {
+            let mut chunk = Vec::with_capacity(CHUNK_LINES);
+            for (line_num, line) in lines.by_ref() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    chunk.push((line_num, line));
+                }
+                if chunk.len() == CHUNK_LINES {
+                    break;
+                }
+            }
+            if chunk.is_empty() {
+                break;
+            }
+
+            let parsed: Vec<(usize, Result<Document, serde_json::Error>)> = self.pool.install(|| {
+                chunk
+                    .into_par_iter()
+                    .map(|(line_num, line)| {
+                        let doc = serde_json::from_str::<Document>(&line).map(|mut doc| {
+                            doc.normalize_unicode(self.config.unicode_normalization);
+                            doc
+                        });
+                        (line_num, doc)
+                    })
+                    .collect()
+            });
+
+            // Dedup keeps the first occurrence, so it runs in input order
+            let mut docs = Vec::with_capacity(parsed.len());
+            for (line_num, doc) in parsed {
+                let doc = match doc {
+                    Ok(d) => d,
+                    Err(e) => {
+                        if stats.is_some() {
+                            (self.progress)(Progress::Warning(format!("Line {}: Failed to parse: {}", line_num + 1, e)));
+                        }
+                        continue;
+                    }
+                };
+
+                docs_seen += 1;
+                if let Some(deduper) = deduper.as_mut() {
+                    if deduper.is_duplicate(&doc.tokens) {
+                        if let Some(stats) = stats.as_mut() {
+                            stats.duplicate_docs += 1;
+                        }
+                        continue;
+                    }
+                }
+                if let Some(stats) = stats.as_mut() {
+                    stats.total_tokens += doc.tokens.len();
+                }
+                docs.push(doc);
+            }
+
+            let extraction = self.pool.install(|| {
+                docs.par_iter()
+                    .fold(Extraction::default, |mut extraction, doc| {
+                        extraction.add_document(doc, self.config, self.stopwords, tally);
+                        extraction
+                    })
+                    .reduce(Extraction::default, Extraction::merge)
+            });
+            total = total.merge(extraction);
+
+            (self.progress)(Progress::Processed { count: docs_seen, unit: "documents" });
+        }
+
+        if let Some(stats) = stats {
+            stats.total_docs = docs_seen;
+        }
+        Ok(total)
+    }
+}
+
+/// Where `Extraction` counts the n-grams it visits.
+#[derive(Clone, Copy)]
+enum Tally<'a> {
+    /// Exactly, in `counts`.
+    Exact,
+    /// Approximately, in the sketch; `counts` stays empty.
+    Sketch(&'a CountMinSketch),
+    /// Exactly, but only n-grams the sketch estimates at the given count or more.
+    Candidates(&'a CountMinSketch, u32),
+}
+
+/// N-gram counts from a batch of documents. Counts only ever add, so merging
+/// batches in any order gives the same totals.
+#[derive(Debug, Default)]
+struct Extraction {
+    counts: NgramCounts,
+    ngrams: usize,
+    skip_grams: usize,
+    filtered_boundary_stopword: usize,
+}
+
+impl Extraction {
+    fn add_document(&mut self, doc: &Document, config: &MineConfig, stopwords: &HashSet<String>, tally: Tally) {
+        let is_stopword = |token: &String| {
+            if config.case_sensitive {
+                stopwords.contains(&token.to_lowercase())
+            } else {
+                stopwords.contains(token)
+            }
+        };
+
+        // Extract n-grams from each sentence of the document
+        for sentence in sentences(&doc.tokens, &doc.sentence_breaks) {
+            for n in config.min_n..=config.max_n {
+                for window in sentence.windows(n) {
+                    let ngram: Vec<String> = if config.case_sensitive {
+                        window.to_vec()
+                    } else {
+                        window.iter().map(|t| t.to_lowercase()).collect()
+                    };
+
+                    // Interior stopwords are fine ("bill of rights"); boundary ones make junk
+                    if is_stopword(&ngram[0]) || is_stopword(&ngram[n - 1]) {
+                        self.filtered_boundary_stopword += 1;
+                        continue;
+                    }
+
+                    for skip_gram in skip_grams(&ngram, config.max_skip) {
+                        self.count(skip_gram, tally);
+                        self.skip_grams += 1;
+                    }
+                    self.count(ngram, tally);
+                    self.ngrams += 1;
+                }
+            }
+        }
+    }
+
+    fn count(&mut self, ngram: Vec<String>, tally: Tally) {
+        match tally {
+            Tally::Exact => *self.counts.entry(ngram).or_insert(0) += 1,
+            Tally::Sketch(sketch) => sketch.add(&ngram),
+            Tally::Candidates(sketch, min_count) => {
+                if sketch.estimate(&ngram) >= min_count {
+                    *self.counts.entry(ngram).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        // Fold the smaller map into the larger one
+        let (mut into, from) = if self.counts.len() >= other.counts.len() { (self, other) } else { (other, self) };
+        for (ngram, count) in from.counts {
+            *into.counts.entry(ngram).or_insert(0) += count;
+        }
+        into.ngrams += from.ngrams;
+        into.skip_grams += from.skip_grams;
+        into.filtered_boundary_stopword += from.filtered_boundary_stopword;
+        into
+    }
+}
+
+/// Splits `tokens` at `breaks`. Breaks may be unsorted or repeated; ones at 0,
+/// at the end, or past it split nothing.
+fn sentences<'a>(tokens: &'a [String], breaks: &[usize]) -> Vec<&'a [String]> {
+    let mut breaks: Vec<usize> = breaks.iter().copied().filter(|&b| b > 0 && b < tokens.len()).collect();
+    breaks.sort_unstable();
+    breaks.dedup();
+
+    let mut sentences = Vec::with_capacity(breaks.len() + 1);
+    let mut start = 0;
+    for end in breaks.into_iter().chain([tokens.len()]) {
+        sentences.push(&tokens[start..end]);
+        start = end;
+    }
+    sentences
+}
+
+/// One stopword per line, lowercased, as n-gram boundaries are compared even
+/// when mining is `case_sensitive`, and in the corpus's Unicode form; blank
+/// lines are ignored.
+pub fn load_stopwords(path: &str, unicode: UnicodeNormalization) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let reader = compression::open_input(path)?;
+    let mut stopwords = HashSet::new();
+    for line in reader.lines() {
+        let word = unicode.apply(line?.trim()).to_lowercase();
+        if !word.is_empty() {
+            stopwords.insert(word);
+        }
+    }
+    Ok(stopwords)
+}
+
+/// Every copy of `ngram` with between 1 and `max_skip` interior tokens replaced
+/// by `GAP_TOKEN`. An n-gram has `C(n-2, 1) + ... + C(n-2, max_skip)` of them,
+/// and none below `MIN_SKIP_N`.
+fn skip_grams(ngram: &[String], max_skip: usize) -> Vec<Vec<String>> {
+    if max_skip == 0 || ngram.len() < MIN_SKIP_N {
+        return Vec::new();
+    }
+
+    let interior = ngram.len() - 2;
+    (1u32..1 << interior)
+        .filter(|mask| mask.count_ones() as usize <= max_skip)
+        .map(|mask| {
+            ngram
+                .iter()
+                .enumerate()
+                .map(|(i, token)| {
+                    let skipped = i > 0 && i <= interior && mask & (1 << (i - 1)) != 0;
+                    if skipped { GAP_TOKEN.to_string() } else { token.clone() }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The n-grams within `config.min_count..=config.max_count`, cut to
+/// `config.top_k`, most frequent first. Records what each filter dropped in
+/// `stats`.
+pub fn select_ngrams(ngram_counts: NgramCounts, config: &MineConfig, stats: &mut MiningStats) -> Vec<(Vec<String>, u32)> {
+    let max_count = config.max_count.unwrap_or(u32::MAX);
+    let mut ngrams = Vec::new();
+    for (tokens, count) in ngram_counts {
+        if count > max_count {
+            stats.filtered_max_count += 1;
+        } else if count >= config.min_count {
+            ngrams.push((tokens, count));
+        }
+    }
+
+    // Ties break on the tokens so neither the order nor the top_k cut depends
+    // on HashMap order
+    ngrams.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if let Some(top_k) = config.top_k {
+        stats.filtered_top_k += ngrams.len().saturating_sub(top_k);
+        ngrams.truncate(top_k);
+    }
+
+    stats.ngrams_after_filter = ngrams.len();
+    ngrams
+}
+
+/// Writes `ngrams` (from `select_ngrams`) as `candidate_phrases` JSONL.
+/// Returns how many were written.
+pub fn write_ngrams(output_path: &str, ngrams: Vec<(Vec<String>, u32)>) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut writer = compression::create_output(output_path)?;
+
+    let count = ngrams.len();
+    for (tokens, count) in ngrams {
+        let ngram = Ngram { tokens, count };
+        let json = serde_json::to_string(&ngram)?;
+        writeln!(writer, "{}", json)?;
+    }
+
+    writer.finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::silent;
+
+    fn doc(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_exact_dedup_ignores_case() {
+        let mut deduper = Deduper::new(DedupMode::Exact);
+        assert!(!deduper.is_duplicate(&doc("lysis buffer for cell culture")));
+        assert!(deduper.is_duplicate(&doc("Lysis Buffer for cell culture")));
+        assert!(!deduper.is_duplicate(&doc("lysis buffer for cell culture applications")));
+    }
+
+    #[test]
+    fn test_near_dedup_catches_boilerplate_variants() {
+        let page = "home products about contact privacy policy terms of service cookie settings \
+                    subscribe to our newsletter for the latest lab supply deals and product news";
+        let mut deduper = Deduper::new(DedupMode::Near { threshold: DEFAULT_NEAR_DUP_THRESHOLD });
+
+        assert!(!deduper.is_duplicate(&doc(page)));
+        assert!(deduper.is_duplicate(&doc(&format!("{} today", page))));
+        assert!(!deduper.is_duplicate(&doc("rat cdk10 oligo design kit for molecular biology research")));
+    }
+
+    #[test]
+    fn test_skip_grams() {
+        assert!(skip_grams(&doc("machine learning"), 2).is_empty());
+        assert!(skip_grams(&doc("machine the learning"), 0).is_empty());
+        assert_eq!(skip_grams(&doc("machine the learning"), 1), vec![doc("machine * learning")]);
+
+        // 4-gram, up to 2 skips: C(2,1) + C(2,2) = 3 variants
+        let mut grams = skip_grams(&doc("a b c d"), 2);
+        grams.sort();
+        assert_eq!(grams, vec![doc("a * * d"), doc("a * c d"), doc("a b * d")]);
+
+        // 5-gram, 1 skip: C(3,1) = 3 variants
+        assert_eq!(skip_grams(&doc("a b c d e"), 1).len(), 3);
+    }
+
+    #[test]
+    fn test_select_ngrams_applies_count_bounds_and_top_k() {
+        let counts: NgramCounts = [("the results", 900), ("cell lysis", 40), ("lysis buffer", 40), ("rat liver", 25), ("a b", 3)]
+            .into_iter()
+            .map(|(ngram, count)| (doc(ngram), count))
+            .collect();
+        let config = MineConfig {
+            min_count: 5,
+            max_count: Some(500),
+            top_k: Some(2),
+            ..MineConfig::default()
+        };
+
+        let mut stats = MiningStats::default();
+        let ngrams = select_ngrams(counts.clone(), &config, &mut stats);
+        assert_eq!(ngrams, vec![(doc("cell lysis"), 40), (doc("lysis buffer"), 40)]);
+        assert_eq!((stats.filtered_max_count, stats.filtered_top_k, stats.ngrams_after_filter), (1, 1, 2));
+
+        let mut stats = MiningStats::default();
+        let ngrams = select_ngrams(counts, &MineConfig { min_count: 5, ..MineConfig::default() }, &mut stats);
+        assert_eq!(ngrams.len(), 4);
+        assert_eq!((stats.filtered_max_count, stats.filtered_top_k), (0, 0));
+
+        let problems = MineConfig { min_count: 5, max_count: Some(4), top_k: Some(0), ..MineConfig::default() }.problems();
+        assert_eq!(problems, vec!["max_count (4) must be >= min_count (5)", "top_k must be >= 1"]);
+    }
+
+    #[test]
+    fn test_sentences_split_at_breaks() {
+        let tokens = doc("cells were lysed . buffer was added");
+        assert_eq!(sentences(&tokens, &[]), vec![&tokens[..]]);
+        assert_eq!(sentences(&tokens, &[4, 0, 4, 99]), vec![&tokens[..4], &tokens[4..]]);
+    }
+
+    #[test]
+    fn test_ngrams_do_not_cross_sentence_breaks() {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        writeln!(corpus, r#"{{"tokens": ["cells", "lysed", "buffer", "added"], "sentence_breaks": [2]}}"#).unwrap();
+        writeln!(corpus, r#"{{"tokens": ["cells", "lysed", "buffer", "added"]}}"#).unwrap();
+
+        let config = MineConfig {
+            min_n: 2,
+            max_n: 3,
+            min_count: 1,
+            max_skip: 0,
+            stopwords_path: None,
+            threads: 1,
+            ..MineConfig::default()
+        };
+        let (counts, stats) = mine_ngrams(corpus.path().to_str().unwrap(), &config, None, &silent).unwrap();
+
+        assert_eq!(counts[&doc("cells lysed")], 2);
+        assert_eq!(counts[&doc("buffer added")], 2);
+        // Only the document without breaks contributes spanning n-grams
+        assert_eq!(counts[&doc("lysed buffer")], 1);
+        assert_eq!(counts[&doc("cells lysed buffer")], 1);
+        assert_eq!(stats.total_ngrams_extracted, 2 + 5);
+    }
+
+    #[test]
+    fn test_parallel_mining_matches_single_threaded() {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..(CHUNK_LINES + 500) {
+            writeln!(corpus, r#"{{"tokens": ["rat", "cdk{}", "oligo", "kit", "t{}"]}}"#, i % 7, i % 3).unwrap();
+        }
+        let path = corpus.path().to_str().unwrap();
+        let mine = |threads: usize| {
+            let config = MineConfig {
+                min_n: 2,
+                max_n: 4,
+                min_count: 1,
+                max_skip: 1,
+                stopwords_path: None,
+                threads,
+                ..MineConfig::default()
+            };
+            mine_ngrams(path, &config, Some(DedupMode::Exact), &silent).unwrap()
+        };
+
+        let (single, single_stats) = mine(1);
+        let (parallel, parallel_stats) = mine(4);
+        assert_eq!(single, parallel);
+        assert_eq!(single_stats.total_docs, CHUNK_LINES + 500);
+        // 21 distinct documents survive exact dedup: i % 7 and i % 3 repeat every 21
+        assert_eq!(single_stats.total_docs - single_stats.duplicate_docs, 21);
+        assert_eq!(single_stats.skip_grams_extracted, parallel_stats.skip_grams_extracted);
+        assert_eq!(single[&doc("rat * oligo")], 21);
+    }
+
+    #[test]
+    fn test_sketch_never_undercounts() {
+        let sketch = CountMinSketch::new(16, 3);
+        let grams: Vec<Vec<String>> = (0..100).map(|i| doc(&format!("token{} next", i % 40))).collect();
+        grams.iter().for_each(|gram| sketch.add(gram));
+        for i in 0..40 {
+            let exact = grams.iter().filter(|gram| **gram == doc(&format!("token{} next", i))).count() as u32;
+            assert!(sketch.estimate(&doc(&format!("token{} next", i))) >= exact);
+        }
+        assert_eq!(sketch.size_bytes(), 16 * 3 * 4);
+    }
+
+    #[test]
+    fn test_sketch_mining_matches_exact_above_min_count() {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..600 {
+            writeln!(corpus, r#"{{"tokens": ["rat", "cdk{}", "oligo", "kit", "n{}"]}}"#, i % 9, i % 250).unwrap();
+        }
+        let path = corpus.path().to_str().unwrap();
+        let mine = |sketch: bool| {
+            let config = MineConfig {
+                min_n: 2,
+                max_n: 4,
+                min_count: 5,
+                max_skip: 1,
+                threads: 2,
+                sketch,
+                // Small enough for collisions, big enough to rule most rare n-grams out
+                sketch_width: 4096,
+                sketch_depth: 3,
+                ..MineConfig::default()
+            };
+            mine_ngrams(path, &config, Some(DedupMode::Exact), &silent).unwrap()
+        };
+
+        let (exact, exact_stats) = mine(false);
+        let (sketched, sketch_stats) = mine(true);
+        let frequent = |counts: &NgramCounts| {
+            let mut frequent: Vec<(Vec<String>, u32)> = counts.iter().filter(|(_, &c)| c >= 5).map(|(g, &c)| (g.clone(), c)).collect();
+            frequent.sort();
+            frequent
+        };
+        assert_eq!(frequent(&sketched), frequent(&exact));
+        assert!(sketched.len() < exact.len());
+        assert_eq!(sketch_stats.total_docs, exact_stats.total_docs);
+        assert_eq!(sketch_stats.duplicate_docs, exact_stats.duplicate_docs);
+        assert_eq!(sketch_stats.total_ngrams_extracted, exact_stats.total_ngrams_extracted);
+    }
+
+    #[test]
+    fn test_sketch_dimensions_are_validated() {
+        let config = MineConfig { sketch: true, sketch_width: 0, ..MineConfig::default() };
+        assert_eq!(config.problems().len(), 1);
+        assert!(MineConfig { sketch_width: 0, ..MineConfig::default() }.problems().is_empty());
+        assert!(MineConfig { sketch: true, ..MineConfig::default() }.problems().is_empty());
+    }
+
+    #[test]
+    fn test_boundary_stopwords_are_filtered() {
+        let mut stopwords = tempfile::NamedTempFile::new().unwrap();
+        writeln!(stopwords, "OF\n\nthe").unwrap();
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        writeln!(corpus, r#"{{"tokens": ["bill", "of", "rights", "of", "the", "people"]}}"#).unwrap();
+
+        let config = MineConfig {
+            min_n: 2,
+            max_n: 3,
+            min_count: 1,
+            max_skip: 0,
+            stopwords_path: Some(stopwords.path().to_str().unwrap().to_string()),
+            threads: 1,
+            ..MineConfig::default()
+        };
+        let (counts, stats) = mine_ngrams(corpus.path().to_str().unwrap(), &config, None, &silent).unwrap();
+
+        // Of 9 n-grams only "bill of rights" has no stopword at either end
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec![&doc("bill of rights")]);
+        assert_eq!(stats.filtered_boundary_stopword, 8);
+        assert_eq!(stats.total_ngrams_extracted, 1);
+    }
+
+    #[test]
+    fn test_case_sensitive_mining_keeps_case() {
+        let mut stopwords = tempfile::NamedTempFile::new().unwrap();
+        writeln!(stopwords, "the").unwrap();
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        writeln!(corpus, r#"{{"tokens": ["BRCA1", "gene"]}}"#).unwrap();
+        writeln!(corpus, r#"{{"tokens": ["brca1", "gene", "The"]}}"#).unwrap();
+
+        let mine = |case_sensitive: bool| {
+            let config = MineConfig {
+                min_n: 2,
+                max_n: 2,
+                min_count: 1,
+                stopwords_path: Some(stopwords.path().to_str().unwrap().to_string()),
+                threads: 1,
+                case_sensitive,
+                ..MineConfig::default()
+            };
+            mine_ngrams(corpus.path().to_str().unwrap(), &config, None, &silent).unwrap()
+        };
+
+        let (counts, stats) = mine(true);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&doc("BRCA1 gene")], 1);
+        assert_eq!(counts[&doc("brca1 gene")], 1);
+        assert_eq!(stats.filtered_boundary_stopword, 1);
+
+        let (counts, _) = mine(false);
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[&doc("brca1 gene")], 2);
+    }
+
+    #[test]
+    fn test_unicode_normalization_merges_decomposed_forms() {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        writeln!(corpus, r#"{{"tokens": ["caf\u00e9", "menu"]}}"#).unwrap();
+        writeln!(corpus, r#"{{"tokens": ["cafe\u0301", "menu"]}}"#).unwrap();
+
+        let mine = |unicode_normalization: UnicodeNormalization| {
+            let config = MineConfig { min_n: 2, max_n: 2, min_count: 1, threads: 1, unicode_normalization, ..MineConfig::default() };
+            mine_ngrams(corpus.path().to_str().unwrap(), &config, None, &silent).unwrap().0
+        };
+
+        assert_eq!(mine(UnicodeNormalization::None).len(), 2);
+        let counts = mine(UnicodeNormalization::Nfc);
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[&vec!["caf\u{e9}".to_string(), "menu".to_string()]], 2);
+    }
+}
@@ -1,66 +1,202 @@
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 
+/// Payload record layouts, selected by the manifest's `payload_format_version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    /// 17-byte records with a u32 phrase_id (manifests without the field).
+    V1,
+    /// 21-byte records with a u64 phrase_id.
+    V2,
+    /// 23-byte `V2` records followed by a u16 category_id.
+    V3,
+}
+
+impl PayloadFormat {
+    /// Layout written by this version of the builder.
+    pub const CURRENT: Self = Self::V3;
+
+    pub fn from_version(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            3 => Some(Self::V3),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> u32 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+            Self::V3 => 3,
+        }
+    }
+
+    pub fn record_size(self) -> usize {
+        match self {
+            Self::V1 => 17,
+            Self::V2 => 21,
+            Self::V3 => PAYLOAD_SIZE,
+        }
+    }
+
+    fn id_size(self) -> usize {
+        match self {
+            Self::V1 => 4,
+            Self::V2 | Self::V3 => 8,
+        }
+    }
+}
+
+/// Size of one serialized payload record in the current layout, in bytes.
+pub const PAYLOAD_SIZE: usize = 23;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Payload {
-    pub phrase_id: u32,
+    pub phrase_id: u64,
     pub salience: f32,
     pub count: u32,
+    /// Smoothed inverse document frequency from the build corpus; 0.0 when the
+    /// build had no document frequencies (stored in the formerly padded bytes).
+    pub idf: f32,
     pub n: u8,
+    /// ID of the phrase's category in the build's categories.json; 0 for
+    /// uncategorized phrases and for layouts before `V3`.
+    #[serde(default)]
+    pub category_id: u16,
 }
 
 impl Payload {
-    #[allow(dead_code)]
-    pub fn new(phrase_id: u32, salience: f32, count: u32, n: u8) -> Self {
+    pub fn new(phrase_id: u64, salience: f32, count: u32, n: u8) -> Self {
         Self {
             phrase_id,
             salience,
             count,
+            idf: 0.0,
             n,
+            category_id: 0,
         }
     }
 
+    pub fn with_idf(mut self, idf: f32) -> Self {
+        self.idf = idf;
+        self
+    }
+
+    pub fn with_category(mut self, category_id: u16) -> Self {
+        self.category_id = category_id;
+        self
+    }
+
     pub fn salience_score(&self) -> f32 {
         self.salience * ((self.count + 1) as f32).ln()
     }
 
-    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
-        let mut buf = [0u8; 17];
-        reader.read_exact(&mut buf)?;
+    pub fn read_from<R: Read>(reader: &mut R, format: PayloadFormat) -> std::io::Result<Self> {
+        let mut buf = [0u8; PAYLOAD_SIZE];
+        let buf = &mut buf[..format.record_size()];
+        reader.read_exact(buf)?;
 
-        let phrase_id = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-        let salience = f32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let count = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
-        let n = buf[16];
+        let (id, rest) = buf.split_at(format.id_size());
+        let phrase_id = match format {
+            PayloadFormat::V1 => u32::from_le_bytes(id.try_into().unwrap()) as u64,
+            PayloadFormat::V2 | PayloadFormat::V3 => u64::from_le_bytes(id.try_into().unwrap()),
+        };
+        let salience = f32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let count = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]);
+        let idf = f32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]);
+        let n = rest[12];
+        let category_id = match format {
+            PayloadFormat::V3 => u16::from_le_bytes([rest[13], rest[14]]),
+            PayloadFormat::V1 | PayloadFormat::V2 => 0,
+        };
 
         Ok(Self {
             phrase_id,
             salience,
             count,
+            idf,
             n,
+            category_id,
         })
     }
 
-    #[allow(dead_code)]
+    /// Decodes the payload at `index` from a buffer of fixed-size records.
+    pub fn read_at(bytes: &[u8], index: usize, format: PayloadFormat) -> Option<Self> {
+        let size = format.record_size();
+        let offset = index.checked_mul(size)?;
+        let mut record = bytes.get(offset..offset.checked_add(size)?)?;
+        Self::read_from(&mut record, format).ok()
+    }
+
+    /// Writes the record in the current layout.
     pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        writer.write_all(&self.phrase_id.to_le_bytes())?;
+        self.write_as(writer, PayloadFormat::CURRENT)
+    }
+
+    /// Writes the record in `format`; fails for a phrase_id that `V1` cannot
+    /// hold and for a category_id that layouts before `V3` cannot hold.
+    pub fn write_as<W: Write>(&self, writer: &mut W, format: PayloadFormat) -> std::io::Result<()> {
+        if self.category_id != 0 && format != PayloadFormat::V3 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("category_id {} does not fit payload_format_version {}", self.category_id, format.version()),
+            ));
+        }
+        match format {
+            PayloadFormat::V1 => {
+                let phrase_id = u32::try_from(self.phrase_id).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("phrase_id {} does not fit payload_format_version 1", self.phrase_id),
+                    )
+                })?;
+                writer.write_all(&phrase_id.to_le_bytes())?;
+            }
+            PayloadFormat::V2 | PayloadFormat::V3 => writer.write_all(&self.phrase_id.to_le_bytes())?,
+        }
         writer.write_all(&self.salience.to_le_bytes())?;
         writer.write_all(&self.count.to_le_bytes())?;
-        writer.write_all(&[0u8; 4])?;
+        writer.write_all(&self.idf.to_le_bytes())?;
         writer.write_all(&[self.n])?;
+        if format == PayloadFormat::V3 {
+            writer.write_all(&self.category_id.to_le_bytes())?;
+        }
         Ok(())
     }
 }
 
-pub fn load_payloads<R: Read>(mut reader: R) -> std::io::Result<Vec<Payload>> {
-    let mut payloads = Vec::new();
+/// Fails unless `len` bytes hold a whole number of `format` records; a partial
+/// trailing record would otherwise be dropped silently.
+pub fn check_record_alignment(name: &str, len: u64, format: PayloadFormat) -> std::io::Result<()> {
+    let size = format.record_size() as u64;
+    if !len.is_multiple_of(size) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} is {} bytes, not a multiple of the {}-byte record size of payload_format_version {}",
+                name,
+                len,
+                size,
+                format.version()
+            ),
+        ));
+    }
+    Ok(())
+}
 
-    loop {
-        match Payload::read_from(&mut reader) {
-            Ok(payload) => payloads.push(payload),
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e),
-        }
+/// Reads every record to the end of input; a partial trailing record is an
+/// error rather than being dropped.
+pub fn load_payloads<R: Read>(mut reader: R, format: PayloadFormat) -> std::io::Result<Vec<Payload>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    check_record_alignment("payload table", bytes.len() as u64, format)?;
+
+    let mut records = bytes.as_slice();
+    let mut payloads = Vec::with_capacity(bytes.len() / format.record_size());
+    while !records.is_empty() {
+        payloads.push(Payload::read_from(&mut records, format)?);
     }
 
     Ok(payloads)
@@ -78,7 +214,7 @@ mod tests {
         payload.write_to(&mut buf).unwrap();
 
         let mut cursor = std::io::Cursor::new(buf);
-        let loaded = Payload::read_from(&mut cursor).unwrap();
+        let loaded = Payload::read_from(&mut cursor, PayloadFormat::CURRENT).unwrap();
 
         assert_eq!(loaded.phrase_id, 12345);
         assert_eq!(loaded.count, 314);
@@ -86,6 +222,86 @@ mod tests {
         assert!((loaded.salience - 2.13).abs() < 0.001);
     }
 
+    #[test]
+    fn test_payload_idf_roundtrip() {
+        let mut buf = Vec::new();
+        Payload::new(1, 1.0, 10, 2).with_idf(3.25).write_to(&mut buf).unwrap();
+        Payload::new(2, 1.0, 10, 2).write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), 2 * PAYLOAD_SIZE);
+
+        assert_eq!(Payload::read_at(&buf, 0, PayloadFormat::CURRENT).unwrap().idf, 3.25);
+        // Artifacts without idf keep zeroed bytes here
+        assert_eq!(Payload::read_at(&buf, 1, PayloadFormat::CURRENT).unwrap().idf, 0.0);
+    }
+
+    #[test]
+    fn test_payload_read_at() {
+        let mut buf = Vec::new();
+        Payload::new(1, 1.0, 10, 2).write_to(&mut buf).unwrap();
+        Payload::new(2, 2.0, 20, 3).write_to(&mut buf).unwrap();
+
+        let second = Payload::read_at(&buf, 1, PayloadFormat::CURRENT).unwrap();
+        assert_eq!(second.phrase_id, 2);
+        assert_eq!(second.n, 3);
+        assert!(Payload::read_at(&buf, 2, PayloadFormat::CURRENT).is_none());
+    }
+
+    #[test]
+    fn test_payload_format_roundtrip() {
+        let wide = 5_000_000_000u64;
+        let mut v2 = Vec::new();
+        Payload::new(wide, 1.5, 7, 3).write_as(&mut v2, PayloadFormat::V2).unwrap();
+        Payload::new(9, 2.5, 8, 2).write_as(&mut v2, PayloadFormat::V2).unwrap();
+        assert_eq!(v2.len(), 2 * 21);
+
+        let loaded = load_payloads(v2.as_slice(), PayloadFormat::V2).unwrap();
+        assert_eq!(loaded[0].phrase_id, wide);
+        assert_eq!((loaded[1].phrase_id, loaded[1].count, loaded[1].n), (9, 8, 2));
+
+        let mut v1 = Vec::new();
+        Payload::new(12345, 1.5, 7, 3).write_as(&mut v1, PayloadFormat::V1).unwrap();
+        assert_eq!(v1.len(), 17);
+        let loaded = load_payloads(v1.as_slice(), PayloadFormat::V1).unwrap();
+        assert_eq!((loaded[0].phrase_id, loaded[0].count, loaded[0].n), (12345, 7, 3));
+        assert_eq!(loaded[0].salience, 1.5);
+
+        assert!(Payload::new(wide, 1.0, 1, 1).write_as(&mut Vec::new(), PayloadFormat::V1).is_err());
+    }
+
+    #[test]
+    fn test_payload_category_roundtrip() {
+        let mut v3 = Vec::new();
+        Payload::new(1, 1.0, 10, 2).with_category(7).write_as(&mut v3, PayloadFormat::V3).unwrap();
+        Payload::new(2, 1.0, 10, 2).write_as(&mut v3, PayloadFormat::V3).unwrap();
+        assert_eq!(v3.len(), 2 * 23);
+
+        let loaded = load_payloads(v3.as_slice(), PayloadFormat::V3).unwrap();
+        assert_eq!((loaded[0].phrase_id, loaded[0].category_id), (1, 7));
+        assert_eq!((loaded[1].phrase_id, loaded[1].category_id), (2, 0));
+
+        // Older layouts have no room for a category
+        let err = Payload::new(1, 1.0, 10, 2).with_category(7).write_as(&mut Vec::new(), PayloadFormat::V2).unwrap_err();
+        assert!(err.to_string().contains("category_id 7"));
+        let mut v2 = Vec::new();
+        Payload::new(3, 1.0, 10, 2).write_as(&mut v2, PayloadFormat::V2).unwrap();
+        assert_eq!(load_payloads(v2.as_slice(), PayloadFormat::V2).unwrap()[0].category_id, 0);
+    }
+
+    #[test]
+    fn test_load_payloads_rejects_partial_records() {
+        let mut buf = Vec::new();
+        Payload::new(1, 1.0, 10, 2).write_as(&mut buf, PayloadFormat::V1).unwrap();
+
+        // 17 bytes is not a whole number of 21-byte records
+        let err = load_payloads(buf.as_slice(), PayloadFormat::V2).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("21-byte"));
+
+        assert_eq!(PayloadFormat::from_version(1), Some(PayloadFormat::V1));
+        assert_eq!(PayloadFormat::from_version(3), Some(PayloadFormat::V3));
+        assert_eq!(PayloadFormat::from_version(4), None);
+    }
+
     #[test]
     fn test_salience_score() {
         let payload = Payload::new(1, 2.0, 99, 2);
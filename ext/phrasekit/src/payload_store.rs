@@ -0,0 +1,427 @@
+use crate::payload::{check_record_alignment, load_payloads, Payload, PayloadFormat};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Lowercase hex SHA-256 of the payload table, shards concatenated in load order.
+pub fn payloads_sha256(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    for shard in shard_paths(path)? {
+        std::io::copy(&mut std::fs::File::open(shard)?, &mut hasher)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Payload table backing a matcher, either fully loaded or read on demand.
+pub enum PayloadStore {
+    Eager(Vec<Payload>),
+    Lazy(LazyPayloads),
+}
+
+/// Memory-mapped payload table that decodes records as they are matched.
+///
+/// Decoded payloads are kept in a bounded cache; once it holds `cache_capacity`
+/// entries the least recently used one makes room for the next miss.
+pub struct LazyPayloads {
+    shards: Vec<Mmap>,
+    /// Index of the first payload in each shard.
+    shard_starts: Vec<usize>,
+    len: usize,
+    format: PayloadFormat,
+    cache: Mutex<PayloadCache>,
+    cache_capacity: usize,
+}
+
+/// Least-recently-used cache of decoded payloads, keyed by pattern index.
+struct PayloadCache {
+    /// Payloads and their `last_used` tick by pattern index.
+    entries: HashMap<usize, (Payload, u64)>,
+    /// Pattern indices by `last_used`, oldest first.
+    recency: BTreeMap<u64, usize>,
+    clock: u64,
+}
+
+impl PayloadCache {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, index: usize) -> Option<Payload> {
+        self.clock += 1;
+        let (payload, last_used) = self.entries.get_mut(&index)?;
+        self.recency.remove(last_used);
+        *last_used = self.clock;
+        self.recency.insert(self.clock, index);
+        Some(payload.clone())
+    }
+
+    fn insert(&mut self, index: usize, payload: Payload, capacity: usize) {
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else { break };
+            self.entries.remove(&oldest);
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, index);
+        self.entries.insert(index, (payload, self.clock));
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Resolves a payloads path to the files it names, in pattern-id order.
+///
+/// A directory yields every file directly inside it, and a path whose file name
+/// contains `*` yields the matching files in its parent; both are sorted
+/// lexically. Any other path is a single payloads file.
+pub fn shard_paths(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let (dir, pattern) = if path.is_dir() {
+        (path, "*")
+    } else {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains('*') => (path.parent().unwrap_or(Path::new(".")), name),
+            _ => return Ok(vec![path.to_path_buf()]),
+        }
+    };
+
+    let mut shards = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if entry.file_type()?.is_file() && !name.starts_with('.') && wildcard_match(pattern, name) {
+            shards.push(entry.path());
+        }
+    }
+    shards.sort();
+
+    if shards.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No payload shards found at {}", path.display()),
+        ));
+    }
+
+    Ok(shards)
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || name.len() < first.len() + last.len() || !name.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// A partial trailing record means the file is truncated or in another layout;
+/// in a shard it would also shift every later pattern id.
+fn check_alignment(path: &Path, bytes: u64, format: PayloadFormat) -> std::io::Result<()> {
+    check_record_alignment(&format!("Payload file {}", path.display()), bytes, format)
+}
+
+impl PayloadStore {
+    pub fn load_eager<P: AsRef<Path>>(path: P, format: PayloadFormat) -> std::io::Result<Self> {
+        let shards = shard_paths(path.as_ref())?;
+
+        let mut payloads = Vec::new();
+        for shard in &shards {
+            let file = File::open(shard)?;
+            check_alignment(shard, file.metadata()?.len(), format)?;
+            payloads.extend(load_payloads(BufReader::new(file), format)?);
+        }
+
+        Ok(Self::Eager(payloads))
+    }
+
+    pub fn load_lazy<P: AsRef<Path>>(path: P, cache_capacity: usize, format: PayloadFormat) -> std::io::Result<Self> {
+        let paths = shard_paths(path.as_ref())?;
+
+        let mut shards = Vec::with_capacity(paths.len());
+        let mut shard_starts = Vec::with_capacity(paths.len());
+        let mut len = 0;
+        for shard in &paths {
+            let file = File::open(shard)?;
+            // Safety: artifacts are treated as immutable once published; replacing them
+            // goes through a fresh load rather than an in-place rewrite.
+            let mmap = unsafe { Mmap::map(&file)? };
+            // Checked on the mapping itself, since that is what records are decoded from
+            check_alignment(shard, mmap.len() as u64, format)?;
+            shard_starts.push(len);
+            len += mmap.len() / format.record_size();
+            shards.push(mmap);
+        }
+
+        Ok(Self::Lazy(LazyPayloads {
+            shards,
+            shard_starts,
+            len,
+            format,
+            cache: Mutex::new(PayloadCache::with_capacity(cache_capacity)),
+            cache_capacity,
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Eager(payloads) => payloads.len(),
+            Self::Lazy(lazy) => lazy.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Payload> {
+        match self {
+            Self::Eager(payloads) => payloads.get(index).cloned(),
+            Self::Lazy(lazy) => lazy.get(index),
+        }
+    }
+
+    /// Every payload in pattern order. In lazy mode this decodes straight from the
+    /// mapped table without filling the cache.
+    pub fn scan(&self) -> Box<dyn Iterator<Item = Payload> + '_> {
+        match self {
+            Self::Eager(payloads) => Box::new(payloads.iter().cloned()),
+            Self::Lazy(lazy) => Box::new((0..lazy.len).filter_map(|index| lazy.read(index))),
+        }
+    }
+
+    /// Index of the first payload describing a zero-length pattern, if any.
+    pub fn find_degenerate(&self) -> Option<usize> {
+        self.scan().position(|p| p.n == 0)
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        matches!(self, Self::Lazy(_))
+    }
+
+    /// Heap bytes held by decoded payloads (mapped pages are not counted).
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            Self::Eager(payloads) => payloads.capacity() * std::mem::size_of::<Payload>(),
+            // The cache table is allocated at full capacity up front; the recency
+            // index grows with it, one node entry per cached payload
+            Self::Lazy(lazy) => {
+                let cache = lazy.cache.lock();
+                cache.entries.capacity() * std::mem::size_of::<(usize, (Payload, u64))>()
+                    + cache.len() * std::mem::size_of::<(u64, usize)>()
+                    + lazy.shard_starts.capacity() * std::mem::size_of::<usize>()
+            }
+        }
+    }
+}
+
+impl LazyPayloads {
+    /// Decodes a payload straight from its shard, bypassing the cache.
+    fn read(&self, index: usize) -> Option<Payload> {
+        if index >= self.len {
+            return None;
+        }
+        let shard = self.shard_starts.partition_point(|&start| start <= index) - 1;
+        Payload::read_at(&self.shards[shard], index - self.shard_starts[shard], self.format)
+    }
+
+    fn get(&self, index: usize) -> Option<Payload> {
+        if index >= self.len {
+            return None;
+        }
+
+        if self.cache_capacity == 0 {
+            return self.read(index);
+        }
+
+        let mut cache = self.cache.lock();
+        if let Some(payload) = cache.get(index) {
+            return Some(payload);
+        }
+
+        let payload = self.read(index)?;
+        cache.insert(index, payload.clone(), self.cache_capacity);
+
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_payloads(count: u32) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        for i in 0..count {
+            Payload::new(i as u64 + 100, i as f32, i * 10, 2).write_to(&mut file).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_lazy_matches_eager() {
+        let file = write_payloads(5);
+
+        let eager = PayloadStore::load_eager(file.path(), PayloadFormat::CURRENT).unwrap();
+        let lazy = PayloadStore::load_lazy(file.path(), 2, PayloadFormat::CURRENT).unwrap();
+
+        assert_eq!(eager.len(), 5);
+        assert_eq!(lazy.len(), 5);
+        assert!(lazy.is_lazy());
+
+        for i in 0..5 {
+            let a = eager.get(i).unwrap();
+            let b = lazy.get(i).unwrap();
+            assert_eq!(a.phrase_id, b.phrase_id);
+            assert_eq!(a.count, b.count);
+        }
+
+        assert!(lazy.get(5).is_none());
+    }
+
+    #[test]
+    fn test_find_degenerate() {
+        let mut file = write_payloads(3);
+        Payload::new(999, 1.0, 1, 0).write_to(&mut file).unwrap();
+        file.flush().unwrap();
+
+        let eager = PayloadStore::load_eager(file.path(), PayloadFormat::CURRENT).unwrap();
+        let lazy = PayloadStore::load_lazy(file.path(), 0, PayloadFormat::CURRENT).unwrap();
+        assert_eq!(eager.find_degenerate(), Some(3));
+        assert_eq!(lazy.find_degenerate(), Some(3));
+
+        let clean = write_payloads(3);
+        assert_eq!(PayloadStore::load_eager(clean.path(), PayloadFormat::CURRENT).unwrap().find_degenerate(), None);
+    }
+
+    #[test]
+    fn test_lazy_cache_is_bounded() {
+        let file = write_payloads(10);
+        let lazy = PayloadStore::load_lazy(file.path(), 3, PayloadFormat::CURRENT).unwrap();
+
+        for i in 0..10 {
+            lazy.get(i).unwrap();
+        }
+
+        match &lazy {
+            PayloadStore::Lazy(inner) => assert!(inner.cache.lock().len() <= 3),
+            PayloadStore::Eager(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_lazy_cache_keeps_hot_payloads() {
+        let file = write_payloads(10);
+        let PayloadStore::Lazy(lazy) = PayloadStore::load_lazy(file.path(), 3, PayloadFormat::CURRENT).unwrap() else {
+            unreachable!()
+        };
+
+        // Payload 0 is read between every cold one, so it is never the least recently used
+        for i in 1..10 {
+            assert_eq!(lazy.get(0).unwrap().phrase_id, 100);
+            lazy.get(i).unwrap();
+        }
+
+        let cache = lazy.cache.lock();
+        assert_eq!(cache.len(), 3);
+        assert!(cache.entries.contains_key(&0));
+        assert!(cache.entries.contains_key(&9));
+        assert!(!cache.entries.contains_key(&1));
+    }
+
+    #[test]
+    fn test_shards_concatenate_in_lexical_order() {
+        let dir = tempfile::tempdir().unwrap();
+        // Written out of order; lexical order is shard-0, shard-1, shard-2
+        for (name, ids) in [("shard-2.bin", 4..6), ("shard-0.bin", 0..2), ("shard-1.bin", 2..4)] {
+            let mut file = File::create(dir.path().join(name)).unwrap();
+            for id in ids {
+                Payload::new(id, 1.0, 1, 1).write_to(&mut file).unwrap();
+            }
+        }
+
+        let glob = dir.path().join("shard-*.bin");
+        for store in [
+            PayloadStore::load_eager(dir.path(), PayloadFormat::CURRENT).unwrap(),
+            PayloadStore::load_eager(&glob, PayloadFormat::CURRENT).unwrap(),
+            PayloadStore::load_lazy(dir.path(), 0, PayloadFormat::CURRENT).unwrap(),
+        ] {
+            assert_eq!(store.len(), 6);
+            for i in 0..6 {
+                assert_eq!(store.get(i).unwrap().phrase_id, i as u64);
+            }
+            assert!(store.get(6).is_none());
+        }
+    }
+
+    #[test]
+    fn test_misaligned_shard_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut first = File::create(dir.path().join("a.bin")).unwrap();
+        Payload::new(1, 1.0, 1, 1).write_to(&mut first).unwrap();
+        first.write_all(&[0u8; 3]).unwrap();
+        let mut second = File::create(dir.path().join("b.bin")).unwrap();
+        Payload::new(2, 1.0, 1, 1).write_to(&mut second).unwrap();
+
+        let err = PayloadStore::load_eager(dir.path(), PayloadFormat::CURRENT).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let lazy_err = PayloadStore::load_lazy(dir.path(), 0, PayloadFormat::CURRENT).err().unwrap();
+        assert_eq!(lazy_err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(lazy_err.to_string(), err.to_string());
+    }
+
+    #[test]
+    fn test_legacy_payload_format() {
+        let mut file = NamedTempFile::new().unwrap();
+        for id in 0..3 {
+            Payload::new(id + 100, 1.0, 5, 2).write_as(&mut file, PayloadFormat::V1).unwrap();
+        }
+        file.flush().unwrap();
+
+        for store in [
+            PayloadStore::load_eager(file.path(), PayloadFormat::V1).unwrap(),
+            PayloadStore::load_lazy(file.path(), 0, PayloadFormat::V1).unwrap(),
+        ] {
+            assert_eq!(store.len(), 3);
+            assert_eq!(store.get(2).unwrap().phrase_id, 102);
+        }
+
+        // 51 bytes of V1 records do not divide into 21-byte V2 records
+        let err = PayloadStore::load_eager(file.path(), PayloadFormat::V2).err().unwrap();
+        assert!(err.to_string().contains("not a multiple"));
+        let lazy_err = PayloadStore::load_lazy(file.path(), 0, PayloadFormat::V2).err().unwrap();
+        assert_eq!(lazy_err.to_string(), err.to_string());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("payloads-*.bin", "payloads-00001.bin"));
+        assert!(wildcard_match("*", "anything"));
+        assert!(!wildcard_match("payloads-*.bin", "payloads-00001.tmp"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+}
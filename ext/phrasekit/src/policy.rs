@@ -1,30 +1,122 @@
+use crate::manifest::NegativeRule;
 use crate::payload::Payload;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::ControlFlow;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Default cap on one overlap cluster under `SalienceMax` and `CountMax`; far above what real
+/// documents produce, so only pathological inputs take the degraded path.
+pub const DEFAULT_MAX_CLUSTER: usize = 10_000;
+
+/// Default `window` for `Matcher::match_tokens_streaming`, in tokens: far
+/// longer than any overlap cluster in ordinary text.
+pub const DEFAULT_STREAM_WINDOW: usize = 1024;
+
+/// Default pseudo-count for `Match::confidence`: a phrase seen 10 times keeps
+/// half its salience.
+pub const DEFAULT_CONFIDENCE_PRIOR: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MatchPolicy {
     LeftmostLongest,
     LeftmostFirst,
     SalienceMax,
+    /// Like `SalienceMax`, but each cluster keeps its highest `payload.count`;
+    /// equal counts prefer the longer span, then the earlier start.
+    CountMax,
+    /// Fewest, longest spans: matches are taken longest first (earlier start
+    /// breaks ties) and kept unless they overlap one already kept.
+    MinSpans,
+    /// No resolution: every raw match, overlapping and nested ones included,
+    /// ordered by `(start, end)`.
+    All,
 }
 
 impl MatchPolicy {
+    /// Every policy, in declaration order, so `policy as usize` indexes it.
+    pub const ALL: [MatchPolicy; 6] = [
+        Self::LeftmostLongest,
+        Self::LeftmostFirst,
+        Self::SalienceMax,
+        Self::CountMax,
+        Self::MinSpans,
+        Self::All,
+    ];
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "leftmost_longest" => Some(Self::LeftmostLongest),
             "leftmost_first" => Some(Self::LeftmostFirst),
             "salience_max" => Some(Self::SalienceMax),
+            "count_max" => Some(Self::CountMax),
+            "min_spans" => Some(Self::MinSpans),
+            "all" => Some(Self::All),
             _ => None,
         }
     }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LeftmostLongest => "leftmost_longest",
+            Self::LeftmostFirst => "leftmost_first",
+            Self::SalienceMax => "salience_max",
+            Self::CountMax => "count_max",
+            Self::MinSpans => "min_spans",
+            Self::All => "all",
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// How `Matcher::phrase_scores` combines the `salience_score()` of a phrase's
+/// repeated occurrences into one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreAggregation {
+    Sum,
+    Max,
+    Mean,
+    /// Mean score times `ln(1 + occurrences)`: repetition raises the score with
+    /// diminishing returns, like `count` does inside `salience_score()`.
+    CountWeighted,
+}
+
+impl ScoreAggregation {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "sum" => Some(Self::Sum),
+            "max" => Some(Self::Max),
+            "mean" => Some(Self::Mean),
+            "count_weighted" => Some(Self::CountWeighted),
+            _ => None,
+        }
+    }
+
+    /// Combines the scores of one phrase's occurrences; `scores` is never empty.
+    pub fn aggregate(self, scores: &[f32]) -> f32 {
+        let sum: f32 = scores.iter().sum();
+        let mean = sum / scores.len() as f32;
+        match self {
+            Self::Sum => sum,
+            Self::Max => scores.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            Self::Mean => mean,
+            Self::CountWeighted => mean * ((scores.len() + 1) as f32).ln(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Match {
     pub start: usize,
     pub end: usize,
-    #[allow(dead_code)]
+    /// Build-order index of the matched pattern (its position in payloads.bin).
     pub pattern_id: usize,
+    #[serde(flatten)]
     pub payload: Payload,
+    /// Which artifacts of a `MatcherSet` produced the match; `None` from a
+    /// single `Matcher`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
 }
 
 impl Match {
@@ -34,32 +126,497 @@ impl Match {
             end,
             pattern_id,
             payload,
+            namespace: None,
         }
     }
 
+    /// Span length in tokens; a match is never empty.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.end - self.start
     }
 
-    pub fn overlaps(&self, other: &Match) -> bool {
-        !(self.end <= other.start || other.end <= self.start)
+    /// `end` as reported to callers: the exclusive boundary by default, or the
+    /// index of the last token when `inclusive` is set.
+    pub fn reported_end(&self, inclusive: bool) -> usize {
+        if inclusive {
+            self.end.saturating_sub(1)
+        } else {
+            self.end
+        }
+    }
+
+    /// Salience shrunk toward zero by observed count:
+    /// `salience * count / (count + prior)`. Low-count phrases lose most of their
+    /// salience; well-attested ones keep nearly all of it. `prior` is the count
+    /// at which half the salience survives.
+    pub fn confidence(&self, prior: f32) -> f32 {
+        let count = self.payload.count as f32;
+        let weight = count + prior.max(0.0);
+        if weight == 0.0 {
+            return 0.0;
+        }
+        self.payload.salience * count / weight
+    }
+
+    /// Start position as a fraction of a `doc_len`-token document:
+    /// `start / doc_len`, clamped to `[0, 1]`. A match at the first token is 0.0,
+    /// so every match in a single-token document is 0.0; an empty document
+    /// (which has no matches) also yields 0.0.
+    pub fn position_frac(&self, doc_len: usize) -> f32 {
+        if doc_len == 0 {
+            return 0.0;
+        }
+        (self.start as f32 / doc_len as f32).clamp(0.0, 1.0)
+    }
+
+    /// Stable identifier for this phrase over these tokens, for deduplicating
+    /// matches across documents: 64-bit FNV-1a over the little-endian bytes of
+    /// `phrase_id` followed by each matched token id (`token_ids[start..end]`).
+    /// A phrase_id that fits in 32 bits contributes 4 bytes, a larger one 8, so
+    /// keys from before 64-bit phrase_ids are unchanged. Position is not
+    /// included, so the same surface keys the same everywhere, and the value
+    /// never depends on the Rust version or process.
+    pub fn match_key(&self, token_ids: &[u32]) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let phrase_id = self.payload.phrase_id.to_le_bytes();
+        let phrase_id_len = if self.payload.phrase_id <= u32::MAX as u64 { 4 } else { 8 };
+        let tokens = token_ids.get(self.start..self.end).unwrap_or_default();
+        phrase_id[..phrase_id_len]
+            .iter()
+            .copied()
+            .chain(tokens.iter().flat_map(|token| token.to_le_bytes()))
+            .fold(FNV_OFFSET, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+    }
+
+    /// Number of tokens between two spans; 0 when they overlap or are adjacent.
+    // Spelled out per side so the overlap case reads as its own branch
+    #[allow(clippy::implicit_saturating_sub)]
+    pub fn gap(&self, other: &Match) -> usize {
+        if self.end <= other.start {
+            other.start - self.end
+        } else if other.end <= self.start {
+            self.start - other.end
+        } else {
+            0
+        }
     }
 }
 
-pub fn resolve_overlaps(mut matches: Vec<Match>, policy: MatchPolicy) -> Vec<Match> {
-    if matches.is_empty() {
+/// Serializes matches as JSONL, one flat object per line (each line
+/// newline-terminated), for dumping to logs or files while debugging.
+pub fn matches_to_jsonl(matches: &[Match]) -> String {
+    let mut out = String::new();
+    for m in matches {
+        // Plain numeric fields; serialization cannot fail
+        out.push_str(&serde_json::to_string(m).expect("match serializes"));
+        out.push('\n');
+    }
+    out
+}
+
+/// Removes negative matches and every match they suppress.
+///
+/// A match is suppressed when its phrase_id is listed by a negative match whose
+/// token gap to it is at most the rule's `window` (overlapping and adjacent spans
+/// have a gap of 0). Negative matches are never returned themselves.
+pub fn apply_negative_rules(matches: Vec<Match>, rules: &HashMap<u64, NegativeRule>) -> Vec<Match> {
+    if rules.is_empty() {
+        return matches;
+    }
+
+    let negatives: Vec<(&Match, &NegativeRule)> = matches
+        .iter()
+        .filter_map(|m| rules.get(&m.payload.phrase_id).map(|rule| (m, rule)))
+        .collect();
+
+    if negatives.is_empty() {
         return matches;
     }
 
-    matches.sort_by_key(|m| m.start);
+    let suppressed: Vec<bool> = matches
+        .iter()
+        .map(|m| {
+            rules.contains_key(&m.payload.phrase_id)
+                || negatives.iter().any(|(neg, rule)| {
+                    rule.suppresses.contains(&m.payload.phrase_id) && neg.gap(m) <= rule.window
+                })
+        })
+        .collect();
+
+    matches
+        .into_iter()
+        .zip(suppressed)
+        .filter(|(_, suppressed)| !suppressed)
+        .map(|(m, _)| m)
+        .collect()
+}
+
+/// Per-call thresholds applied before overlap resolution, so a filtered-out
+/// match can neither win nor block an overlap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchFilters {
+    /// Drops matches whose `payload.salience` is below it.
+    pub min_salience: f32,
+    /// Drops matches shorter than this many tokens (`payload.n`).
+    pub min_n: u8,
+}
+
+impl MatchFilters {
+    pub const NONE: MatchFilters = MatchFilters {
+        min_salience: f32::NEG_INFINITY,
+        min_n: 0,
+    };
+
+    pub fn keeps(&self, m: &Match) -> bool {
+        m.payload.salience >= self.min_salience && m.payload.n >= self.min_n
+    }
+}
+
+/// Result of overlap resolution.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub matches: Vec<Match>,
+    /// Set when an overlap cluster exceeded `max_cluster` and was resolved with
+    /// the cheaper top-k selection instead of the exact scan.
+    pub degraded: bool,
+}
+
+/// Resolves overlapping matches under `policy`, bounding the work spent on any
+/// one overlap cluster. Only `SalienceMax` and `CountMax` scan clusters; the
+/// leftmost policies are linear already.
+pub fn resolve_overlaps_bounded(mut matches: Vec<Match>, policy: MatchPolicy, max_cluster: usize) -> Resolution {
+    if matches.is_empty() {
+        return Resolution { matches, degraded: false };
+    }
+
+    if policy == MatchPolicy::All {
+        // phrase_id breaks ties so identical spans keep a fixed order
+        matches.sort_by_key(|m| (m.start, m.end, m.payload.phrase_id));
+        return Resolution { matches, degraded: false };
+    }
+
+    dedup_identical_spans(&mut matches);
 
     match policy {
-        MatchPolicy::LeftmostLongest => resolve_leftmost_longest(matches),
-        MatchPolicy::LeftmostFirst => resolve_leftmost_first(matches),
-        MatchPolicy::SalienceMax => resolve_salience_max(matches),
+        MatchPolicy::All => unreachable!("returned above"),
+        MatchPolicy::LeftmostLongest => Resolution { matches: resolve_leftmost_longest(matches), degraded: false },
+        MatchPolicy::LeftmostFirst => Resolution { matches: resolve_leftmost_first(matches), degraded: false },
+        MatchPolicy::SalienceMax => resolve_cluster_max(matches, max_cluster, by_salience_score),
+        MatchPolicy::CountMax => resolve_cluster_max(matches, max_cluster, by_count),
+        MatchPolicy::MinSpans => Resolution { matches: resolve_min_spans(matches), degraded: false },
     }
 }
 
+/// Like `resolve_overlaps_bounded`, but matches whose phrase_id is in `pinned`
+/// beat any overlapping unpinned match regardless of length or salience. Pinned
+/// matches are resolved among themselves under `policy` first; unpinned matches
+/// then fill the gaps they leave. `All` keeps every match, so pinning has no
+/// effect on it.
+pub fn resolve_overlaps_pinned(
+    matches: Vec<Match>,
+    policy: MatchPolicy,
+    max_cluster: usize,
+    pinned: &HashSet<u64>,
+) -> Resolution {
+    if pinned.is_empty() || policy == MatchPolicy::All {
+        return resolve_overlaps_bounded(matches, policy, max_cluster);
+    }
+
+    let (pinned_matches, rest): (Vec<Match>, Vec<Match>) =
+        matches.into_iter().partition(|m| pinned.contains(&m.payload.phrase_id));
+    let winners = resolve_overlaps_bounded(pinned_matches, policy, max_cluster);
+
+    // Winners are disjoint and ordered by start
+    let rest = rest
+        .into_iter()
+        .filter(|m| {
+            let next = winners.matches.partition_point(|w| w.end <= m.start);
+            winners.matches.get(next).is_none_or(|w| w.start >= m.end)
+        })
+        .collect();
+    let others = resolve_overlaps_bounded(rest, policy, max_cluster);
+
+    let mut combined = winners.matches;
+    combined.extend(others.matches);
+    combined.sort_by_key(|m| m.start);
+
+    Resolution { matches: combined, degraded: winners.degraded || others.degraded }
+}
+
+/// Outcome of `Matcher::match_tokens_streaming`, besides the matches it
+/// emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// Matches passed to the callback.
+    pub emitted: usize,
+    /// Overlap clusters cut at the window boundary. While this is 0 the
+    /// matches equal `match_tokens` under every policy.
+    pub window_cuts: usize,
+    /// Set when an overlap cluster exceeded `max_cluster`, as in `Resolution`.
+    pub degraded: bool,
+}
+
+/// Overlap resolution over automaton hits as they arrive, so a long input
+/// never holds more than a window of them.
+///
+/// The automaton reports hits in order of end position, so once a hit ending
+/// at token `e` is seen, every hit starting before `e - max_pattern_len` is
+/// known. A run of transitively overlapping hits that ends before that point,
+/// and beyond the window of any negative phrase still unseen, no later hit can
+/// touch: it is resolved and emitted exactly as global resolution would.
+///
+/// A run still open after spanning `window` tokens is cut. Its known hits are
+/// resolved on their own, and later hits overlapping the matches emitted are
+/// dropped. The leftmost policies decide every hit from earlier ones only, and
+/// `All` resolves nothing, so cuts never change their output. `SalienceMax`,
+/// `CountMax`, and `MinSpans` weigh a whole cluster at once, so at a cut they
+/// may keep a different match than `match_tokens`.
+pub struct WindowedResolver<'a> {
+    policy: MatchPolicy,
+    max_cluster: usize,
+    rules: &'a HashMap<u64, NegativeRule>,
+    filters: MatchFilters,
+    max_pattern_len: usize,
+    window: usize,
+    /// Tokens past its end a run must be known for before it is settled:
+    /// the widest negative rule window plus one, or 0 without rules.
+    negative_lag: usize,
+    /// Hits not yet emitted.
+    pending: Vec<Match>,
+    /// Negative hits of emitted runs that may still suppress later hits.
+    negatives: Vec<Match>,
+    /// Every hit starting before this token is known.
+    horizon: usize,
+    /// End of the last match emitted at a cut; later hits starting before it
+    /// are dropped.
+    barrier: usize,
+    summary: StreamSummary,
+}
+
+impl<'a> WindowedResolver<'a> {
+    pub fn new(
+        policy: MatchPolicy,
+        max_cluster: usize,
+        rules: &'a HashMap<u64, NegativeRule>,
+        filters: MatchFilters,
+        max_pattern_len: usize,
+        window: usize,
+    ) -> Self {
+        let negative_lag = rules.values().map(|rule| rule.window + 1).max().unwrap_or(0);
+        Self {
+            policy,
+            max_cluster,
+            rules,
+            filters,
+            max_pattern_len,
+            window,
+            negative_lag,
+            pending: Vec::new(),
+            negatives: Vec::new(),
+            horizon: 0,
+            barrier: 0,
+            summary: StreamSummary::default(),
+        }
+    }
+
+    /// Takes the next automaton hit, emitting whatever it settles.
+    pub fn push(&mut self, hit: Match, emit: &mut impl FnMut(Match) -> ControlFlow<()>) -> ControlFlow<()> {
+        self.max_pattern_len = self.max_pattern_len.max(hit.len());
+        let horizon = hit.end.saturating_sub(self.max_pattern_len);
+        if hit.start >= self.barrier || self.is_negative(&hit) {
+            self.pending.push(hit);
+        }
+
+        if horizon > self.horizon {
+            self.horizon = horizon;
+            self.drain(emit)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Emits everything still pending, once the input has ended.
+    pub fn finish(mut self, emit: &mut impl FnMut(Match) -> ControlFlow<()>) -> StreamSummary {
+        self.horizon = usize::MAX;
+        let _ = self.drain(emit);
+        self.summary
+    }
+
+    pub fn summary(&self) -> StreamSummary {
+        self.summary
+    }
+
+    fn is_negative(&self, m: &Match) -> bool {
+        self.rules.contains_key(&m.payload.phrase_id)
+    }
+
+    fn drain(&mut self, emit: &mut impl FnMut(Match) -> ControlFlow<()>) -> ControlFlow<()> {
+        // Hits arrive by end, so this is close to sorted already
+        self.pending.sort_by_key(|m| m.start);
+
+        let mut settled = 0;
+        while settled < self.pending.len() {
+            let mut run_end = self.pending[settled].end;
+            let mut next = settled + 1;
+            while next < self.pending.len() && self.pending[next].start < run_end {
+                run_end = run_end.max(self.pending[next].end);
+                next += 1;
+            }
+            if run_end.saturating_add(self.negative_lag) > self.horizon {
+                break;
+            }
+            settled = next;
+        }
+        if settled > 0 {
+            let runs: Vec<Match> = self.pending.drain(..settled).collect();
+            self.resolve(runs, emit)?;
+        }
+
+        let open_since = self.pending.first().map(|m| m.start);
+        if open_since.is_some_and(|start| self.horizon.saturating_sub(start) > self.window) {
+            // Known hits whose negative phrases are all known too
+            let cut_at = if self.negative_lag == 0 {
+                self.horizon
+            } else {
+                (self.horizon + 1).saturating_sub(self.max_pattern_len + self.negative_lag)
+            };
+            let cut = self.pending.partition_point(|m| m.start < cut_at);
+            if cut > 0 {
+                self.summary.window_cuts += 1;
+                let prefix: Vec<Match> = self.pending.drain(..cut).collect();
+                let emitted_end = self.resolve(prefix, emit)?;
+                if self.policy != MatchPolicy::All {
+                    self.barrier = self.barrier.max(emitted_end);
+                    let (barrier, rules) = (self.barrier, self.rules);
+                    self.pending.retain(|m| m.start >= barrier || rules.contains_key(&m.payload.phrase_id));
+                }
+            }
+        }
+
+        if !self.negatives.is_empty() {
+            let earliest = self.pending.first().map_or(self.horizon, |m| m.start.min(self.horizon));
+            let lag = self.negative_lag;
+            self.negatives.retain(|n| n.end + lag > earliest);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Resolves `hits` as one input and emits the result, returning the
+    /// furthest end among the matches emitted (0 for none).
+    fn resolve(
+        &mut self,
+        mut hits: Vec<Match>,
+        emit: &mut impl FnMut(Match) -> ControlFlow<()>,
+    ) -> ControlFlow<(), usize> {
+        if !self.rules.is_empty() {
+            let rules = self.rules;
+            let is_negative = |m: &&Match| rules.contains_key(&m.payload.phrase_id);
+            let nearby: Vec<Match> = self.negatives.iter().chain(self.pending.iter().filter(is_negative)).cloned().collect();
+            self.negatives.extend(hits.iter().filter(is_negative).cloned());
+            hits.extend(nearby);
+            hits = apply_negative_rules(hits, rules);
+        }
+        hits.retain(|m| self.filters.keeps(m));
+
+        let resolution = resolve_overlaps_bounded(hits, self.policy, self.max_cluster);
+        self.summary.degraded |= resolution.degraded;
+        let mut emitted_end = 0;
+        for m in resolution.matches {
+            emitted_end = emitted_end.max(m.end);
+            self.summary.emitted += 1;
+            if emit(m).is_break() {
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(emitted_end)
+    }
+}
+
+/// Position of a match within its result set, ordered by salience.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchRank {
+    /// 0-based rank by salience descending; ties keep document order.
+    pub rank: usize,
+    /// Salience min-max scaled over the result set to `[0, 1]`.
+    pub score_normalized: f32,
+}
+
+/// Ranks an already-resolved result set, returning one entry per match in the
+/// same order as `matches`. When every match has the same salience, all of
+/// them score 1.0.
+pub fn rank_matches(matches: &[Match]) -> Vec<MatchRank> {
+    let mut order: Vec<usize> = (0..matches.len()).collect();
+    order.sort_by(|&a, &b| {
+        matches[b]
+            .payload
+            .salience
+            .partial_cmp(&matches[a].payload.salience)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let (min, max) = matches.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), m| {
+        (min.min(m.payload.salience), max.max(m.payload.salience))
+    });
+    let range = max - min;
+
+    let mut ranks = vec![MatchRank { rank: 0, score_normalized: 1.0 }; matches.len()];
+    for (rank, &index) in order.iter().enumerate() {
+        ranks[index].rank = rank;
+        if range > 0.0 {
+            ranks[index].score_normalized = (matches[index].payload.salience - min) / range;
+        }
+    }
+
+    ranks
+}
+
+/// Indices of `matches` ordered by `salience_score()` descending, for callers
+/// that report matches by importance instead of position. The sort is stable,
+/// so equal scores keep document order.
+pub fn salience_order(matches: &[Match]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..matches.len()).collect();
+    order.sort_by(|&a, &b| {
+        matches[b]
+            .payload
+            .salience_score()
+            .partial_cmp(&matches[a].payload.salience_score())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    order
+}
+
+/// Keeps the first match of each distinct length `n` in an already-resolved
+/// result set, preserving order, then cuts it to `max`; later matches of a
+/// length already seen are dropped. `matches` must not be cut to `max`
+/// beforehand, or lengths past the cut are never considered.
+pub fn keep_one_per_length(mut matches: Vec<Match>, max: usize) -> Vec<Match> {
+    let mut seen = HashSet::new();
+    matches.retain(|m| seen.insert(m.payload.n));
+    matches.truncate(max);
+    matches
+}
+
+/// Sorts matches by span and keeps one match per identical `(start, end)`: the
+/// highest salience, then the smallest phrase_id. Applied before every policy so
+/// the choice among identical spans never depends on automaton output order.
+fn dedup_identical_spans(matches: &mut Vec<Match>) {
+    matches.sort_by(|a, b| {
+        a.start
+            .cmp(&b.start)
+            .then(a.end.cmp(&b.end))
+            .then(
+                b.payload
+                    .salience
+                    .partial_cmp(&a.payload.salience)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+            .then(a.payload.phrase_id.cmp(&b.payload.phrase_id))
+    });
+    matches.dedup_by(|later, kept| later.start == kept.start && later.end == kept.end);
+}
+
 fn resolve_leftmost_longest(matches: Vec<Match>) -> Vec<Match> {
     let mut result = Vec::new();
     let mut current_end = 0;
@@ -102,42 +659,115 @@ fn resolve_leftmost_first(matches: Vec<Match>) -> Vec<Match> {
     result
 }
 
-fn resolve_salience_max(matches: Vec<Match>) -> Vec<Match> {
+/// Longest-first greedy over the whole input. Overlap clusters never interact,
+/// so this is the same as running it per cluster: each cluster is covered by its
+/// longest span plus whatever longest spans fit in the gaps it leaves.
+fn resolve_min_spans(matches: Vec<Match>) -> Vec<Match> {
+    // Input is sorted by start, so a stable sort keeps earlier starts first on ties
+    let mut order: Vec<usize> = (0..matches.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(matches[i].len()));
+    accept_disjoint(&matches, order)
+}
+
+/// `SalienceMax` ordering: higher `salience_score()` is better.
+fn by_salience_score(a: &Match, b: &Match) -> std::cmp::Ordering {
+    a.payload
+        .salience_score()
+        .partial_cmp(&b.payload.salience_score())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// `CountMax` ordering: higher count, then longer span, then earlier start.
+fn by_count(a: &Match, b: &Match) -> std::cmp::Ordering {
+    a.payload
+        .count
+        .cmp(&b.payload.count)
+        .then(a.len().cmp(&b.len()))
+        .then(b.start.cmp(&a.start))
+}
+
+/// Keeps the best match of each overlap cluster under `better`, which orders
+/// matches worst to best, then fills the gaps it leaves on either side with the
+/// best matches that fit.
+fn resolve_cluster_max(
+    matches: Vec<Match>,
+    max_cluster: usize,
+    better: fn(&Match, &Match) -> std::cmp::Ordering,
+) -> Resolution {
     let mut result = Vec::new();
+    let mut degraded = false;
     let mut i = 0;
 
     while i < matches.len() {
+        // Overlap is not transitive (A-B and B-C overlap while A and C are
+        // disjoint), so the cluster runs until a match starts at or after
+        // every end seen so far
+        let mut cluster_end = matches[i].end;
         let mut j = i + 1;
-        while j < matches.len() && matches[j].overlaps(&matches[i]) {
+        while j < matches.len() && matches[j].start < cluster_end {
+            cluster_end = cluster_end.max(matches[j].end);
             j += 1;
         }
 
-        let best = matches[i..j]
-            .iter()
-            .max_by(|a, b| {
-                a.payload
-                    .salience_score()
-                    .partial_cmp(&b.payload.salience_score())
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            })
-            .unwrap()
-            .clone();
+        let cluster = &matches[i..j];
+        if cluster.len() > max_cluster {
+            degraded = true;
+            result.extend(select_top_k(cluster, max_cluster, better));
+        } else {
+            result.extend(select_top_k(cluster, cluster.len(), better));
+        }
+        i = j;
+    }
 
-        result.push(best.clone());
-        i = matches[i..]
-            .iter()
-            .position(|m| m.start >= best.end)
-            .map(|idx| i + idx)
-            .unwrap_or(matches.len());
+    Resolution { matches: result, degraded }
+}
+
+/// Keeps the `k` best matches of a cluster under `better` (all of them unless
+/// the cluster is oversized), then greedily accepts them best-first unless they
+/// overlap an already accepted match; equal matches go to the earlier start.
+/// Returns the picks ordered by start.
+fn select_top_k(cluster: &[Match], k: usize, better: fn(&Match, &Match) -> std::cmp::Ordering) -> Vec<Match> {
+    let by_score = |a: &usize, b: &usize| {
+        better(&cluster[*b], &cluster[*a]).then(cluster[*a].start.cmp(&cluster[*b].start))
+    };
+
+    let mut order: Vec<usize> = (0..cluster.len()).collect();
+    if k > 0 && order.len() > k {
+        order.select_nth_unstable_by(k - 1, by_score);
     }
+    order.truncate(k.max(1));
+    order.sort_by(by_score);
 
-    result
+    accept_disjoint(cluster, order)
+}
+
+/// Visits `matches` in `order`, keeping each one that does not overlap a match
+/// kept before it. Returns the kept matches ordered by start.
+fn accept_disjoint(matches: &[Match], order: Vec<usize>) -> Vec<Match> {
+    // Accepted spans are disjoint, keyed by start
+    let mut accepted: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    for index in order {
+        let m = &matches[index];
+        let blocked = accepted
+            .range(..m.end)
+            .next_back()
+            .is_some_and(|(_, &(end, _))| end > m.start);
+        if !blocked {
+            accepted.insert(m.start, (m.end, index));
+        }
+    }
+
+    accepted.into_values().map(|(_, index)| matches[index].clone()).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn resolve_overlaps(matches: Vec<Match>, policy: MatchPolicy) -> Vec<Match> {
+        resolve_overlaps_bounded(matches, policy, DEFAULT_MAX_CLUSTER).matches
+    }
+
     fn make_match(start: usize, end: usize, salience: f32, count: u32) -> Match {
         Match::new(
             start,
@@ -175,6 +805,95 @@ mod tests {
         assert_eq!(resolved[1].start, 3);
     }
 
+    fn make_phrase(start: usize, end: usize, phrase_id: u64) -> Match {
+        Match::new(
+            start,
+            end,
+            0,
+            Payload::new(phrase_id, 1.0, 100, (end - start) as u8),
+        )
+    }
+
+    fn negative_rules(phrase_id: u64, suppresses: Vec<u64>, window: usize) -> HashMap<u64, NegativeRule> {
+        let mut rules = HashMap::new();
+        rules.insert(
+            phrase_id,
+            NegativeRule {
+                phrase_id,
+                suppresses,
+                window,
+            },
+        );
+        rules
+    }
+
+    #[test]
+    fn test_match_gap() {
+        assert_eq!(make_phrase(0, 2, 1).gap(&make_phrase(2, 3, 2)), 0);
+        assert_eq!(make_phrase(0, 2, 1).gap(&make_phrase(1, 3, 2)), 0);
+        assert_eq!(make_phrase(0, 2, 1).gap(&make_phrase(5, 6, 2)), 3);
+        assert_eq!(make_phrase(5, 6, 1).gap(&make_phrase(0, 2, 2)), 3);
+    }
+
+    #[test]
+    fn test_negative_rule_suppresses_within_window() {
+        // "apple" at 0..1, "inc" (negative) at 2..3 → gap of 1
+        let matches = vec![make_phrase(0, 1, 10), make_phrase(2, 3, 99)];
+        let rules = negative_rules(99, vec![10], 1);
+
+        let filtered = apply_negative_rules(matches, &rules);
+        assert!(filtered.is_empty());
+    }
+
+    #[test]
+    fn test_negative_rule_outside_window() {
+        let matches = vec![make_phrase(0, 1, 10), make_phrase(3, 4, 99)];
+        let rules = negative_rules(99, vec![10], 1);
+
+        let filtered = apply_negative_rules(matches, &rules);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].payload.phrase_id, 10);
+    }
+
+    #[test]
+    fn test_negative_rule_ignores_unlisted_phrases() {
+        let matches = vec![make_phrase(0, 1, 10), make_phrase(1, 2, 11), make_phrase(2, 3, 99)];
+        let rules = negative_rules(99, vec![10], 5);
+
+        let filtered = apply_negative_rules(matches, &rules);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].payload.phrase_id, 11);
+    }
+
+    #[test]
+    fn test_identical_spans_prefer_salience_then_phrase_id() {
+        let policies = [
+            MatchPolicy::LeftmostLongest,
+            MatchPolicy::LeftmostFirst,
+            MatchPolicy::SalienceMax,
+            MatchPolicy::CountMax,
+            MatchPolicy::MinSpans,
+        ];
+
+        for policy in policies {
+            let matches = vec![
+                Match::new(0, 2, 0, Payload::new(7, 1.0, 100, 2)),
+                Match::new(0, 2, 1, Payload::new(5, 2.0, 100, 2)),
+            ];
+            let resolved = resolve_overlaps(matches, policy);
+            assert_eq!(resolved.len(), 1);
+            assert_eq!(resolved[0].payload.phrase_id, 5);
+
+            let tied = vec![
+                Match::new(0, 2, 0, Payload::new(7, 1.0, 100, 2)),
+                Match::new(0, 2, 1, Payload::new(5, 1.0, 100, 2)),
+            ];
+            let resolved = resolve_overlaps(tied, policy);
+            assert_eq!(resolved.len(), 1);
+            assert_eq!(resolved[0].payload.phrase_id, 5);
+        }
+    }
+
     #[test]
     fn test_salience_max() {
         let matches = vec![
@@ -187,4 +906,320 @@ mod tests {
         assert_eq!(resolved.len(), 2);
         assert_eq!(resolved[0].len(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_count_max() {
+        let matches = vec![
+            // Frequent beats salient
+            make_match(0, 2, 1.0, 500),
+            make_match(1, 4, 9.0, 50),
+            // Equal counts: the longer span
+            make_match(5, 7, 1.0, 10),
+            make_match(5, 8, 1.0, 10),
+            // Equal counts and lengths: the earlier start
+            make_match(10, 12, 1.0, 7),
+            make_match(11, 13, 2.0, 7),
+        ];
+
+        let spans = |policy| -> Vec<(usize, usize)> {
+            resolve_overlaps(matches.clone(), policy).iter().map(|m| (m.start, m.end)).collect()
+        };
+        assert_eq!(spans(MatchPolicy::CountMax), vec![(0, 2), (5, 8), (10, 12)]);
+        assert_eq!(spans(MatchPolicy::SalienceMax)[0], (1, 4));
+        assert_eq!(MatchPolicy::from_str("count_max"), Some(MatchPolicy::CountMax));
+    }
+
+    #[test]
+    fn test_policy_names_round_trip() {
+        for (index, policy) in MatchPolicy::ALL.into_iter().enumerate() {
+            assert_eq!(MatchPolicy::from_str(policy.as_str()), Some(policy));
+            assert_eq!(policy as usize, index);
+        }
+    }
+
+    #[test]
+    fn test_windowed_resolver_is_exact_for_leftmost_and_approximate_at_cuts() {
+        // A chain of overlapping hits, in the automaton's end order
+        let hits = vec![
+            make_match(0, 2, 5.0, 10),
+            make_match(1, 3, 1.0, 10),
+            make_match(2, 4, 1.0, 10),
+            make_match(3, 5, 2.0, 10),
+            make_match(4, 6, 9.0, 10),
+        ];
+        let rules = HashMap::new();
+        let stream = |policy, window| {
+            let mut resolver = WindowedResolver::new(policy, DEFAULT_MAX_CLUSTER, &rules, MatchFilters::NONE, 2, window);
+            let mut spans = Vec::new();
+            let mut emit = |m: Match| {
+                spans.push((m.start, m.end));
+                ControlFlow::Continue(())
+            };
+            for hit in hits.clone() {
+                let _ = resolver.push(hit, &mut emit);
+            }
+            let summary = resolver.finish(&mut emit);
+            (spans, summary.window_cuts)
+        };
+        let global = |policy| -> Vec<(usize, usize)> {
+            resolve_overlaps(hits.clone(), policy).iter().map(|m| (m.start, m.end)).collect()
+        };
+
+        for policy in MatchPolicy::ALL {
+            assert_eq!(stream(policy, 10), (global(policy), 0), "{}", policy.as_str());
+        }
+        for policy in [MatchPolicy::LeftmostLongest, MatchPolicy::LeftmostFirst, MatchPolicy::All] {
+            let (spans, cuts) = stream(policy, 1);
+            assert_eq!(spans, global(policy), "{}", policy.as_str());
+            assert!(cuts > 0);
+        }
+
+        // Cut before [4, 6) is seen, [3, 5) wins its window and blocks it
+        assert_eq!(global(MatchPolicy::SalienceMax), vec![(0, 2), (2, 4), (4, 6)]);
+        assert_eq!(stream(MatchPolicy::SalienceMax, 1), (vec![(0, 2), (3, 5)], 2));
+    }
+
+    #[test]
+    fn test_match_key_is_stable() {
+        let tokens = [7, 1, 2, 9, 1, 2];
+        let first = make_phrase(1, 3, 100);
+        let second = make_phrase(4, 6, 100);
+
+        // Same phrase over the same tokens keys the same wherever it occurs
+        assert_eq!(first.match_key(&tokens), second.match_key(&tokens));
+        // Pinned value: the scheme must not change between releases
+        assert_eq!(first.match_key(&tokens), 0x48c4_a76b_ad1a_c6f2);
+
+        assert_ne!(first.match_key(&tokens), make_phrase(1, 3, 200).match_key(&tokens));
+        assert_ne!(first.match_key(&tokens), make_phrase(0, 2, 100).match_key(&tokens));
+    }
+
+    #[test]
+    fn test_salience_order() {
+        let matches = vec![
+            make_match(0, 2, 1.0, 10),
+            make_match(3, 5, 3.0, 10),
+            make_match(6, 7, 1.0, 10),
+            make_match(8, 9, 2.0, 10),
+        ];
+
+        assert_eq!(salience_order(&matches), vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn test_keep_one_per_length() {
+        let matches = vec![make_phrase(0, 2, 1), make_phrase(3, 5, 2), make_phrase(6, 9, 3)];
+
+        let kept = keep_one_per_length(matches.clone(), 10);
+        let ids: Vec<u64> = kept.iter().map(|m| m.payload.phrase_id).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        // The cap applies to distinct lengths, not to the matches before dedup
+        let kept = keep_one_per_length(matches, 2);
+        assert_eq!(kept.iter().map(|m| m.payload.phrase_id).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_rank_matches() {
+        let matches = vec![
+            make_match(0, 2, 1.0, 10),
+            make_match(3, 5, 3.0, 10),
+            make_match(6, 7, 2.0, 10),
+        ];
+
+        let ranks = rank_matches(&matches);
+        assert_eq!(ranks.iter().map(|r| r.rank).collect::<Vec<_>>(), vec![2, 0, 1]);
+        assert_eq!(ranks[0].score_normalized, 0.0);
+        assert_eq!(ranks[1].score_normalized, 1.0);
+        assert!((ranks[2].score_normalized - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rank_matches_equal_salience() {
+        let matches = vec![make_match(0, 2, 1.5, 10), make_match(3, 5, 1.5, 10)];
+
+        let ranks = rank_matches(&matches);
+        assert_eq!(ranks[0], MatchRank { rank: 0, score_normalized: 1.0 });
+        assert_eq!(ranks[1], MatchRank { rank: 1, score_normalized: 1.0 });
+        assert!(rank_matches(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_reported_end() {
+        let single = make_match(4, 5, 1.0, 10);
+        assert_eq!(single.reported_end(false), 5);
+        assert_eq!(single.reported_end(true), 4);
+
+        let multi = make_match(1, 4, 1.0, 10);
+        assert_eq!(multi.reported_end(false), 4);
+        assert_eq!(multi.reported_end(true), 3);
+    }
+
+    #[test]
+    fn test_salience_max_bounded_cluster() {
+        // 20,000 spans that all overlap the first; the best is a short one at 500
+        let mut matches: Vec<Match> = (0..20_000).map(|s| make_match(s, s + 20_000, 1.0, 10)).collect();
+        matches.push(make_match(500, 501, 9.0, 10));
+
+        let exact = resolve_overlaps_bounded(matches.clone(), MatchPolicy::SalienceMax, usize::MAX);
+        assert!(!exact.degraded);
+
+        let bounded = resolve_overlaps_bounded(matches, MatchPolicy::SalienceMax, 100);
+        assert!(bounded.degraded);
+        assert_eq!(bounded.matches[0].start, 500);
+        assert_eq!(bounded.matches[0].payload.salience, 9.0);
+        for pair in bounded.matches.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_salience_max_chained_overlaps() {
+        // B overlaps both A and C, which are disjoint from each other, so all
+        // three form one cluster
+        let a = make_match(0, 2, 1.0, 10);
+        let b = make_match(1, 3, 2.0, 10);
+        let c = make_match(2, 4, 3.0, 10);
+        let spans = |matches: Vec<Match>| -> Vec<(usize, usize)> {
+            resolve_overlaps(matches, MatchPolicy::SalienceMax).iter().map(|m| (m.start, m.end)).collect()
+        };
+
+        // C is the best of the cluster and A fills the gap before it; comparing
+        // only against A used to stop the cluster at B and drop C
+        assert_eq!(spans(vec![a.clone(), b.clone(), c.clone()]), vec![(0, 2), (2, 4)]);
+
+        // With B best, neither neighbour fits beside it
+        let b = make_match(1, 3, 5.0, 10);
+        assert_eq!(spans(vec![a, b, c]), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn test_salience_max_small_cluster_not_degraded() {
+        let matches = vec![make_match(0, 2, 1.0, 100), make_match(1, 3, 2.0, 100)];
+
+        let resolved = resolve_overlaps_bounded(matches, MatchPolicy::SalienceMax, 2);
+        assert!(!resolved.degraded);
+        assert_eq!(resolved.matches.len(), 1);
+        assert_eq!(resolved.matches[0].start, 1);
+    }
+
+    #[test]
+    fn test_confidence_shrinks_low_counts() {
+        let rare = make_match(0, 2, 4.0, 10);
+        let common = make_match(0, 2, 4.0, 990);
+
+        assert!((rare.confidence(10.0) - 2.0).abs() < 1e-6);
+        assert!((common.confidence(10.0) - 3.96).abs() < 1e-4);
+        assert_eq!(rare.confidence(0.0), 4.0);
+        assert_eq!(make_match(0, 2, 4.0, 0).confidence(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_pinned_shorter_phrase_beats_longer() {
+        let mut promoted = make_match(1, 2, 0.5, 10);
+        promoted.payload.phrase_id = 42;
+        let matches = vec![make_match(0, 3, 3.0, 100), promoted, make_match(2, 4, 1.0, 10)];
+
+        for policy in [MatchPolicy::LeftmostLongest, MatchPolicy::LeftmostFirst, MatchPolicy::SalienceMax] {
+            let unpinned = resolve_overlaps_pinned(matches.clone(), policy, DEFAULT_MAX_CLUSTER, &HashSet::new());
+            assert_eq!(unpinned.matches[0].end, 3);
+
+            let pinned = resolve_overlaps_pinned(matches.clone(), policy, DEFAULT_MAX_CLUSTER, &HashSet::from([42]));
+            let spans: Vec<(usize, usize)> = pinned.matches.iter().map(|m| (m.start, m.end)).collect();
+            assert_eq!(spans, vec![(1, 2), (2, 4)], "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_pinned_ties_use_policy() {
+        let mut short = make_match(0, 1, 1.0, 10);
+        short.payload.phrase_id = 1;
+        let mut long = make_match(0, 2, 1.0, 10);
+        long.payload.phrase_id = 2;
+        let pinned = HashSet::from([1, 2]);
+
+        let resolved = resolve_overlaps_pinned(vec![short, long], MatchPolicy::LeftmostLongest, DEFAULT_MAX_CLUSTER, &pinned);
+        assert_eq!(resolved.matches.len(), 1);
+        assert_eq!(resolved.matches[0].payload.phrase_id, 2);
+    }
+
+    #[test]
+    fn test_matches_to_jsonl() {
+        let matches = vec![make_match(0, 2, 2.5, 100), make_match(3, 4, 1.0, 7)];
+        let jsonl = matches_to_jsonl(&matches);
+
+        let lines: Vec<serde_json::Value> = jsonl.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(jsonl.ends_with('\n'));
+        assert_eq!(lines[0]["start"], 0);
+        assert_eq!(lines[0]["end"], 2);
+        assert_eq!(lines[0]["salience"], 2.5);
+        assert_eq!(lines[1]["count"], 7);
+        assert!(lines[1].get("payload").is_none());
+
+        assert_eq!(matches_to_jsonl(&[]), "");
+    }
+
+    #[test]
+    fn test_position_frac() {
+        assert_eq!(make_match(0, 2, 1.0, 1).position_frac(10), 0.0);
+        assert_eq!(make_match(5, 7, 1.0, 1).position_frac(10), 0.5);
+        assert_eq!(make_match(9, 10, 1.0, 1).position_frac(10), 0.9);
+        assert_eq!(make_match(0, 1, 1.0, 1).position_frac(1), 0.0);
+        assert_eq!(make_match(3, 4, 1.0, 1).position_frac(2), 1.0);
+        assert_eq!(make_match(0, 1, 1.0, 1).position_frac(0), 0.0);
+    }
+
+    fn spans(matches: &[Match]) -> Vec<(usize, usize)> {
+        matches.iter().map(|m| (m.start, m.end)).collect()
+    }
+
+    #[test]
+    fn test_min_spans_prefers_later_covering_span() {
+        // [0,2) starts first, but [1,5) covers more of the cluster on its own
+        let matches = vec![make_match(0, 2, 1.0, 10), make_match(1, 5, 1.0, 10), make_match(4, 6, 1.0, 10)];
+
+        let leftmost = resolve_overlaps(matches.clone(), MatchPolicy::LeftmostLongest);
+        assert_eq!(spans(&leftmost), vec![(0, 2), (4, 6)]);
+
+        let min_spans = resolve_overlaps(matches, MatchPolicy::MinSpans);
+        assert_eq!(spans(&min_spans), vec![(1, 5)]);
+    }
+
+    #[test]
+    fn test_min_spans_fills_gaps_and_breaks_ties_leftmost() {
+        let matches = vec![
+            make_match(0, 2, 1.0, 10),
+            make_match(1, 3, 1.0, 10),
+            make_match(3, 8, 1.0, 10),
+            make_match(7, 9, 1.0, 10),
+            make_match(9, 10, 1.0, 10),
+        ];
+
+        let resolved = resolve_overlaps(matches, MatchPolicy::MinSpans);
+        assert_eq!(spans(&resolved), vec![(0, 2), (3, 8), (9, 10)]);
+    }
+
+    #[test]
+    fn test_min_spans_from_str() {
+        assert_eq!(MatchPolicy::from_str("min_spans"), Some(MatchPolicy::MinSpans));
+    }
+
+    #[test]
+    fn test_all_policy_keeps_every_match_by_span() {
+        let matches = vec![
+            make_match(1, 3, 1.0, 10),
+            make_match(0, 3, 2.0, 10),
+            make_match(0, 2, 3.0, 10),
+            make_match(1, 2, 0.5, 10),
+        ];
+        // Every match has phrase_id 0, so a pinning policy would keep only one
+        let pinned = HashSet::from([0]);
+
+        let resolved = resolve_overlaps_pinned(matches, MatchPolicy::All, DEFAULT_MAX_CLUSTER, &pinned);
+        let spans: Vec<(usize, usize)> = resolved.matches.iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(0, 2), (0, 3), (1, 2), (1, 3)]);
+        assert!(!resolved.degraded);
+        assert_eq!(MatchPolicy::from_str("all"), Some(MatchPolicy::All));
+    }
+}
@@ -0,0 +1,47 @@
+//! Progress events from mining, scoring, and building. The library never
+//! prints; each function takes a reporter, and the CLI tools print what it
+//! receives.
+
+use std::fmt;
+
+/// One progress event. `Display` gives it as the CLI tools print it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// A new stage of the run, such as "🔨 Building automaton...".
+    Stage(String),
+    /// A step within a stage that has started but not finished.
+    Note(String),
+    /// A step that finished.
+    Done(String),
+    /// Interim count while a long input is read.
+    Processed { count: usize, unit: &'static str },
+    /// Input that was skipped, or a setting that looks wrong; the run goes on.
+    Warning(String),
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::Stage(message) => write!(f, "{}", message),
+            Progress::Note(message) => write!(f, "  {}", message),
+            Progress::Done(message) => write!(f, "  ✓ {}", message),
+            Progress::Processed { count, unit } => write!(f, "  Processed {} {}...", count, unit),
+            Progress::Warning(message) => write!(f, "⚠️  {}", message),
+        }
+    }
+}
+
+/// A reporter that drops every event.
+pub fn silent(_: Progress) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_matches_cli_output() {
+        assert_eq!(Progress::Done("Loaded 3 phrases".into()).to_string(), "  ✓ Loaded 3 phrases");
+        assert_eq!(Progress::Processed { count: 10000, unit: "lines" }.to_string(), "  Processed 10000 lines...");
+        assert_eq!(Progress::Warning("Line 2: Empty token".into()).to_string(), "⚠️  Line 2: Empty token");
+    }
+}
@@ -0,0 +1,195 @@
+use crate::policy::{MatchFilters, MatchPolicy, Resolution};
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Everything a cached resolution depends on besides the loaded artifacts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    token_ids: Vec<u32>,
+    policy: MatchPolicy,
+    max: usize,
+    /// Sorted, so equal pinned sets produce equal keys.
+    pinned: Vec<u64>,
+    /// `min_salience` bits; f32 is not `Hash`.
+    min_salience: u32,
+    min_n: u8,
+}
+
+impl CacheKey {
+    fn new(token_ids: &[u32], policy: MatchPolicy, max: usize, pinned: &HashSet<u64>, filters: MatchFilters) -> Self {
+        let mut pinned: Vec<u64> = pinned.iter().copied().collect();
+        pinned.sort_unstable();
+        Self {
+            token_ids: token_ids.to_vec(),
+            policy,
+            max,
+            pinned,
+            min_salience: filters.min_salience.to_bits(),
+            min_n: filters.min_n,
+        }
+    }
+
+    fn digest(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+struct Entry {
+    key: CacheKey,
+    resolution: Resolution,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Entries by key digest. A digest collision overwrites the older entry.
+    entries: HashMap<u64, Entry>,
+    /// Digests by `last_used`, oldest first.
+    recency: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, digest: u64) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&digest) {
+            self.recency.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.recency.insert(self.clock, digest);
+        }
+    }
+}
+
+/// Least-recently-used cache of match results for repeated token streams.
+///
+/// Results are only valid for the artifacts they were computed from; the cache
+/// lives inside a `Matcher`, so a reload or swap starts with an empty one.
+pub struct ResultCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+        }
+    }
+
+    /// Returns the cached resolution for this call, or computes and stores it.
+    /// The second value is true on a cache hit.
+    pub fn get_or_insert_with(
+        &self,
+        token_ids: &[u32],
+        policy: MatchPolicy,
+        max: usize,
+        pinned: &HashSet<u64>,
+        filters: MatchFilters,
+        compute: impl FnOnce() -> Resolution,
+    ) -> (Resolution, bool) {
+        let key = CacheKey::new(token_ids, policy, max, pinned, filters);
+        let digest = key.digest();
+
+        {
+            let mut inner = self.inner.lock();
+            if inner.entries.get(&digest).is_some_and(|entry| entry.key == key) {
+                inner.touch(digest);
+                return (inner.entries[&digest].resolution.clone(), true);
+            }
+        }
+
+        // Computed outside the lock so concurrent misses do not serialize
+        let resolution = compute();
+
+        let mut inner = self.inner.lock();
+        if let Some(old) = inner.entries.remove(&digest) {
+            inner.recency.remove(&old.last_used);
+        }
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else { break };
+            inner.entries.remove(&oldest);
+        }
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.recency.insert(last_used, digest);
+        inner.entries.insert(
+            digest,
+            Entry {
+                key,
+                resolution: resolution.clone(),
+                last_used,
+            },
+        );
+
+        (resolution, false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        inner.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolution() -> Resolution {
+        Resolution {
+            matches: Vec::new(),
+            degraded: false,
+        }
+    }
+
+    #[test]
+    fn test_result_cache_evicts_least_recently_used() {
+        let cache = ResultCache::new(2);
+        let none = HashSet::new();
+        let lookup = |tokens: &[u32]| cache.get_or_insert_with(tokens, MatchPolicy::LeftmostLongest, 10, &none, MatchFilters::NONE, resolution).1;
+
+        assert!(!lookup(&[1]));
+        assert!(!lookup(&[2]));
+        assert!(lookup(&[1]));
+        // [2] is now the least recently used and makes room for [3]
+        assert!(!lookup(&[3]));
+        assert_eq!(cache.len(), 2);
+        assert!(lookup(&[1]));
+        assert!(!lookup(&[2]));
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(!lookup(&[1]));
+    }
+
+    #[test]
+    fn test_result_cache_key_includes_options() {
+        let cache = ResultCache::new(8);
+        let none = HashSet::new();
+        let tokens = [1, 2, 3];
+
+        let run = |policy, max, pinned: &HashSet<u64>| {
+            cache.get_or_insert_with(&tokens, policy, max, pinned, MatchFilters::NONE, resolution).1
+        };
+
+        assert!(!run(MatchPolicy::LeftmostLongest, 10, &none));
+        assert!(!run(MatchPolicy::SalienceMax, 10, &none));
+        assert!(!run(MatchPolicy::LeftmostLongest, 5, &none));
+        assert!(!run(MatchPolicy::LeftmostLongest, 10, &HashSet::from([7])));
+        assert!(run(MatchPolicy::LeftmostLongest, 10, &HashSet::from([7])));
+        assert!(run(MatchPolicy::LeftmostLongest, 10, &none));
+        let filtered = |filters| cache.get_or_insert_with(&tokens, MatchPolicy::LeftmostLongest, 10, &none, filters, resolution).1;
+        assert!(!filtered(MatchFilters { min_salience: 1.0, ..MatchFilters::NONE }));
+        assert!(!filtered(MatchFilters { min_n: 3, ..MatchFilters::NONE }));
+        assert!(filtered(MatchFilters { min_n: 3, ..MatchFilters::NONE }));
+    }
+}
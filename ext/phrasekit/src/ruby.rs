@@ -0,0 +1,654 @@
+//! The `PhraseKit::NativeMatcher` and `PhraseKit::NativeMatcherSet` Ruby classes.
+
+use crate::matcher::{estimate_memory, LoadOptions, Matcher as RustMatcher, Stats, UnkPolicy, UnknownTokens};
+use crate::matcher_set::MatcherSet as RustMatcherSet;
+use crate::payload::Payload;
+use crate::policy::{keep_one_per_length, matches_to_jsonl, rank_matches, salience_order, Match, MatchFilters, MatchPolicy,
+    ScoreAggregation, DEFAULT_CONFIDENCE_PRIOR, DEFAULT_STREAM_WINDOW};
+use magnus::{block, define_module, function, method, prelude::*, Error, RArray, RHash, Ruby, Value};
+use parking_lot::RwLock;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+
+type SharedMatcher = Arc<RwLock<Option<Arc<RustMatcher>>>>;
+
+/// Documents shorter than this are matched with the GVL held: releasing and
+/// reacquiring it costs more than the scan it would free other threads for.
+const GVL_RELEASE_MIN_TOKENS: usize = 512;
+
+/// Runs `f` with the GVL released when `tokens` is large enough to be worth
+/// it, so other Ruby threads run during the scan. `f` must not touch Ruby
+/// objects, and must not hold the matcher lock: a thread waiting on the lock
+/// with the GVL held would stop `f` from ever getting the GVL back.
+fn without_gvl<F: FnOnce() -> R, R>(tokens: usize, f: F) -> R {
+    if tokens < GVL_RELEASE_MIN_TOKENS {
+        return f();
+    }
+
+    struct Call<F, R> {
+        f: Option<F>,
+        result: Option<std::thread::Result<R>>,
+    }
+
+    unsafe extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut c_void) -> *mut c_void {
+        let call = &mut *(data as *mut Call<F, R>);
+        let f = call.f.take().expect("called once");
+        // A panic must not unwind through Ruby's frames; rethrown below
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+        ptr::null_mut()
+    }
+
+    let mut call = Call { f: Some(f), result: None };
+    unsafe {
+        rb_sys::rb_thread_call_without_gvl(
+            Some(trampoline::<F, R>),
+            &mut call as *mut Call<F, R> as *mut c_void,
+            None,
+            ptr::null_mut(),
+        );
+    }
+    match call.result.expect("callback ran") {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+#[magnus::wrap(class = "PhraseKit::NativeMatcher", free_immediately, size)]
+struct MatcherWrapper {
+    matcher: SharedMatcher,
+}
+
+impl MatcherWrapper {
+    fn new() -> Self {
+        Self {
+            matcher: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// A handle on the loaded matcher, taken out of the lock so the lock is not
+    /// held while the GVL is released.
+    fn loaded(&self) -> Result<Arc<RustMatcher>, Error> {
+        self.matcher
+            .read()
+            .clone()
+            .ok_or_else(|| Error::new(magnus::exception::runtime_error(), "Matcher not loaded"))
+    }
+
+    fn load(&self, automaton_path: String, payloads_path: String, manifest_path: String) -> Result<(), Error> {
+        let matcher = RustMatcher::load(&automaton_path, &payloads_path, &manifest_path)
+            .map_err(|e| Error::new(magnus::exception::runtime_error(), format!("Failed to load matcher: {}", e)))?;
+
+        let mut guard = self.matcher.write();
+        *guard = Some(Arc::new(matcher));
+
+        Ok(())
+    }
+
+    fn load_with_options(
+        &self,
+        automaton_path: String,
+        payloads_path: String,
+        manifest_path: String,
+        options: RHash,
+    ) -> Result<(), Error> {
+        let matcher = load_matcher(&automaton_path, &payloads_path, &manifest_path, options)?;
+
+        let mut guard = self.matcher.write();
+        *guard = Some(Arc::new(matcher));
+
+        Ok(())
+    }
+
+    /// Swaps in new artifacts loaded with the current load options. The new
+    /// matcher is built before the write lock is taken, so in-flight matches
+    /// finish on the old one; on failure the old one stays loaded.
+    fn reload(
+        &self,
+        automaton_path: String,
+        payloads_path: String,
+        manifest_path: String,
+        vocab_path: Option<String>,
+    ) -> Result<String, Error> {
+        let current = self.loaded()?;
+
+        let matcher = current
+            .reload(&automaton_path, &payloads_path, &manifest_path, vocab_path.as_ref())
+            .map_err(|e| Error::new(magnus::exception::runtime_error(), format!("Failed to reload matcher: {}", e)))?;
+        let version = matcher.manifest().version.clone();
+
+        *self.matcher.write() = Some(Arc::new(matcher));
+
+        Ok(version)
+    }
+
+    fn match_tokens(&self, token_ids: Vec<u32>, policy: String, max: usize) -> Result<RArray, Error> {
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+
+        let resolution = without_gvl(token_ids.len(), || {
+            matcher.match_tokens_detailed(&token_ids, match_policy, max, &HashSet::new())
+        });
+
+        let result = RArray::new();
+        for m in &resolution.matches {
+            let hash = match_to_hash(m)?;
+            if resolution.degraded {
+                hash.aset("degraded", true)?;
+            }
+            result.push(hash)?;
+        }
+
+        Ok(result)
+    }
+
+    /// `match_tokens` on string tokens, encoded with the vocabulary given as
+    /// `vocab_path` at load.
+    fn match_strings(&self, tokens: Vec<String>, policy: String, max: usize) -> Result<RArray, Error> {
+        let token_ids = self.loaded()?.encode_strings(&tokens).ok_or_else(|| {
+            Error::new(magnus::exception::runtime_error(), "Vocabulary not loaded; load with vocab_path")
+        })?;
+        self.match_tokens(token_ids, policy, max)
+    }
+
+    fn match_tokens_with_options(
+        &self,
+        token_ids: Vec<u32>,
+        policy: String,
+        max: usize,
+        options: RHash,
+    ) -> Result<RArray, Error> {
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+
+        let rank = options.lookup::<_, Option<bool>>("rank")?.unwrap_or(false);
+        let inclusive_end = options.lookup::<_, Option<bool>>("inclusive_end")?.unwrap_or(false);
+        let include_pattern_id = options.lookup::<_, Option<bool>>("include_pattern_id")?.unwrap_or(false);
+        let confidence = options.lookup::<_, Option<bool>>("confidence")?.unwrap_or(false);
+        let position_frac = options.lookup::<_, Option<bool>>("position_frac")?.unwrap_or(false);
+        let one_per_length = options.lookup::<_, Option<bool>>("one_per_length")?.unwrap_or(false);
+        let match_key = options.lookup::<_, Option<bool>>("match_key")?.unwrap_or(false);
+        let sort_by_salience = match options.lookup::<_, Option<String>>("sort_by")?.as_deref() {
+            None | Some("position") => false,
+            Some("salience") => true,
+            Some(other) => {
+                return Err(Error::new(magnus::exception::arg_error(), format!("Invalid sort_by: {}", other)));
+            }
+        };
+        let pinned: HashSet<u64> = options
+            .lookup::<_, Option<Vec<u64>>>("pinned")?
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let confidence_prior = options
+            .lookup::<_, Option<f32>>("confidence_prior")?
+            .unwrap_or(DEFAULT_CONFIDENCE_PRIOR);
+        let filters = MatchFilters {
+            min_salience: options.lookup::<_, Option<f32>>("min_salience")?.unwrap_or(f32::NEG_INFINITY),
+            min_n: options.lookup::<_, Option<u8>>("min_n")?.unwrap_or(0),
+        };
+        let unk_policy = match options.lookup::<_, Option<String>>("unk_policy")? {
+            None => UnkPolicy::Map,
+            Some(name) => UnkPolicy::from_str(&name)
+                .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid unk_policy: {}", name)))?,
+        };
+        let unknown_positions = options.lookup::<_, Option<Vec<usize>>>("unknown_positions")?.unwrap_or_default();
+        let wildcard_candidates = options.lookup::<_, Option<Vec<u32>>>("wildcard_candidates")?.unwrap_or_default();
+        let unknown = UnknownTokens {
+            positions: &unknown_positions,
+            policy: unk_policy,
+            candidates: &wildcard_candidates,
+        };
+
+        // One per length picks from every resolved match, so `max` is applied
+        // after it rather than by the matcher
+        let resolve_max = if one_per_length && max > 0 { usize::MAX } else { max };
+        let mut resolution = without_gvl(token_ids.len(), || {
+            matcher.match_tokens_unknown(&token_ids, match_policy, resolve_max, &pinned, filters, unknown)
+        });
+        if one_per_length {
+            resolution.matches = keep_one_per_length(resolution.matches, max);
+        }
+        let ranks = if rank { rank_matches(&resolution.matches) } else { Vec::new() };
+        let order = if sort_by_salience {
+            salience_order(&resolution.matches)
+        } else {
+            (0..resolution.matches.len()).collect()
+        };
+
+        let result = RArray::new();
+        for i in order {
+            let m = &resolution.matches[i];
+            let hash = match_to_hash(m)?;
+            if resolution.degraded {
+                hash.aset("degraded", true)?;
+            }
+            if inclusive_end {
+                hash.aset("end", m.reported_end(true))?;
+            }
+            if include_pattern_id {
+                hash.aset("pattern_id", m.pattern_id)?;
+            }
+            if confidence {
+                hash.aset("confidence", m.confidence(confidence_prior))?;
+            }
+            if position_frac {
+                hash.aset("position_frac", m.position_frac(token_ids.len()))?;
+            }
+            if match_key {
+                hash.aset("match_key", m.match_key(&token_ids))?;
+            }
+            if let Some(rank) = ranks.get(i) {
+                hash.aset("rank", rank.rank)?;
+                hash.aset("score_normalized", rank.score_normalized)?;
+            }
+            result.push(hash)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Same matches as `match_tokens`, serialized as a JSONL string for debugging.
+    fn match_tokens_jsonl(&self, token_ids: Vec<u32>, policy: String, max: usize) -> Result<String, Error> {
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+
+        Ok(without_gvl(token_ids.len(), || matches_to_jsonl(&matcher.match_tokens(&token_ids, match_policy, max))))
+    }
+
+    /// Matches each document in `batch` and yields `(doc_index, matches)` to the
+    /// block, in input order, before moving on, so only one document's results
+    /// are alive at a time.
+    fn match_tokens_batch_each(&self, batch: RArray, policy: String, max: usize) -> Result<(), Error> {
+        if !block::block_given() {
+            return Err(Error::new(magnus::exception::arg_error(), "match_tokens_batch_each requires a block"));
+        }
+
+        // Take our own handle so the block can reload the matcher without deadlocking.
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+
+        for doc_index in 0..batch.len() {
+            let token_ids: Vec<u32> = batch.entry(doc_index as isize)?;
+            let matches = without_gvl(token_ids.len(), || matcher.match_tokens(&token_ids, match_policy, max));
+
+            let result = RArray::with_capacity(matches.len());
+            for m in &matches {
+                result.push(match_to_hash(m)?)?;
+            }
+
+            block::yield_values::<(usize, RArray), Value>((doc_index, result))?;
+        }
+
+        Ok(())
+    }
+
+    /// Yields each match of `Matcher::match_tokens_streaming` to the block as
+    /// soon as it is resolved, returning the `StreamSummary` as a Hash. The
+    /// block runs mid-scan, so the GVL stays held throughout.
+    fn match_tokens_streaming(&self, token_ids: Vec<u32>, policy: String, window: Option<usize>) -> Result<RHash, Error> {
+        if !block::block_given() {
+            return Err(Error::new(magnus::exception::arg_error(), "match_tokens_streaming requires a block"));
+        }
+
+        // Take our own handle so the block can reload the matcher without deadlocking.
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+        let window = window.unwrap_or(DEFAULT_STREAM_WINDOW);
+
+        // An exception or `break` in the block stops the scan and is re-raised
+        let mut failure = None;
+        let summary = matcher.match_tokens_streaming(token_ids, match_policy, window, MatchFilters::NONE, |m| {
+            match match_to_hash(&m).and_then(|hash| block::yield_values::<(RHash,), Value>((hash,))) {
+                Ok(_) => ControlFlow::Continue(()),
+                Err(e) => {
+                    failure = Some(e);
+                    ControlFlow::Break(())
+                }
+            }
+        });
+        if let Some(e) = failure {
+            return Err(e);
+        }
+
+        let hash = RHash::new();
+        hash.aset("emitted", summary.emitted)?;
+        hash.aset("window_cuts", summary.window_cuts)?;
+        hash.aset("degraded", summary.degraded)?;
+        Ok(hash)
+    }
+
+    fn match_tokens_batch(&self, batch: RArray, policy: String, max: usize) -> Result<RArray, Error> {
+        // One lock acquisition for the whole batch; a concurrent reload does not
+        // affect documents already in flight.
+        let matcher = self.loaded()?;
+
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+
+        // Copied out first so the whole batch is matched in one GVL release
+        let documents: Vec<Vec<u32>> = (0..batch.len())
+            .map(|doc_index| batch.entry(doc_index as isize))
+            .collect::<Result<_, _>>()?;
+        let tokens = documents.iter().map(Vec::len).sum();
+        let all_matches: Vec<Vec<Match>> = without_gvl(tokens, || {
+            documents.iter().map(|token_ids| matcher.match_tokens(token_ids, match_policy, max)).collect()
+        });
+
+        let results = RArray::with_capacity(batch.len());
+        for matches in all_matches {
+            let result = RArray::with_capacity(matches.len());
+            for m in &matches {
+                result.push(match_to_hash(m)?)?;
+            }
+            results.push(result)?;
+        }
+
+        Ok(results)
+    }
+
+    fn best_match(&self, token_ids: Vec<u32>) -> Result<Option<RHash>, Error> {
+        let matcher = self.loaded()?;
+
+        matcher.best_match(&token_ids).as_ref().map(match_to_hash).transpose()
+    }
+
+    fn payload_for(&self, phrase_id: u64) -> Result<Option<RHash>, Error> {
+        let matcher = self.loaded()?;
+
+        matcher
+            .payload_for(phrase_id)
+            .map(|payload| {
+                let hash = RHash::new();
+                add_payload_fields(&hash, &payload)?;
+                Ok(hash)
+            })
+            .transpose()
+    }
+
+    fn phrase_frequencies(&self, token_ids: Vec<u32>) -> Result<RHash, Error> {
+        let matcher = self.loaded()?;
+
+        let hash = RHash::new();
+        for (phrase_id, count) in matcher.phrase_frequencies(&token_ids) {
+            hash.aset(phrase_id, count)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// `Matcher::contains_phrase_ids` for every document in `batch` under one
+    /// read lock, in input order.
+    fn contains_phrase_ids_batch(&self, batch: Vec<Vec<u32>>) -> Result<Vec<Vec<u64>>, Error> {
+        let matcher = self.loaded()?;
+
+        Ok(batch.iter().map(|token_ids| matcher.contains_phrase_ids(token_ids)).collect())
+    }
+
+    fn phrase_scores(&self, token_ids: Vec<u32>, aggregation: String) -> Result<RHash, Error> {
+        let matcher = self.loaded()?;
+
+        let aggregation = ScoreAggregation::from_str(&aggregation).ok_or_else(|| {
+            Error::new(magnus::exception::arg_error(), format!("Invalid aggregation: {}", aggregation))
+        })?;
+
+        let hash = RHash::new();
+        for (phrase_id, score) in matcher.phrase_scores(&token_ids, aggregation) {
+            hash.aset(phrase_id, score)?;
+        }
+
+        Ok(hash)
+    }
+
+    fn stats(&self) -> Result<RHash, Error> {
+        let matcher = self.loaded()?;
+
+        let stats = Stats::from_matcher(&matcher);
+        let hash = RHash::new();
+
+        hash.aset("version", stats.version.as_str())?;
+        hash.aset("loaded_at", stats.loaded_at.duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64)?;
+        hash.aset("num_patterns", stats.num_patterns)?;
+        hash.aset("automaton_patterns", stats.automaton_patterns)?;
+        hash.aset("heap_mb", stats.heap_mb)?;
+        hash.aset("lazy_payloads", stats.lazy_payloads)?;
+        hash.aset("match_calls", stats.match_calls)?;
+        hash.aset("hits_total", stats.hits_total)?;
+        hash.aset("raw_hits_total", stats.raw_hits_total)?;
+        hash.aset("resolved_hits_total", stats.resolved_hits_total)?;
+        hash.aset("overlap_resolution_ratio", stats.overlap_resolution_ratio())?;
+        let policy_hits = RHash::new();
+        for hits in &stats.policy_hits {
+            let counts = RHash::new();
+            counts.aset("raw_hits", hits.raw_hits)?;
+            counts.aset("resolved_hits", hits.resolved_hits)?;
+            policy_hits.aset(hits.policy.as_str(), counts)?;
+        }
+        hash.aset("policy_hits", policy_hits)?;
+        hash.aset("cache_hits", stats.cache_hits)?;
+        hash.aset("cache_misses", stats.cache_misses)?;
+        hash.aset("cache_entries", stats.cache_entries)?;
+        hash.aset("p50_us", stats.p50_us)?;
+        hash.aset("p95_us", stats.p95_us)?;
+        hash.aset("p99_us", stats.p99_us)?;
+
+        Ok(hash)
+    }
+
+    fn reset_stats(&self) -> Result<(), Error> {
+        let matcher = self.loaded()?;
+
+        matcher.reset_stats();
+        Ok(())
+    }
+
+    fn clear_cache(&self) -> Result<(), Error> {
+        let matcher = self.loaded()?;
+
+        matcher.clear_cache();
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Result<Vec<String>, Error> {
+        let matcher = self.loaded()?;
+
+        Ok(matcher.diagnostics().to_vec())
+    }
+
+    fn normalization(&self) -> Result<&'static str, Error> {
+        let matcher = self.loaded()?;
+
+        Ok(matcher.manifest().normalization.as_str())
+    }
+
+    fn unicode_normalization(&self) -> Result<&'static str, Error> {
+        let matcher = self.loaded()?;
+
+        Ok(matcher.manifest().unicode_normalization.as_str())
+    }
+
+    fn healthcheck(&self) -> Result<bool, Error> {
+        self.loaded()?;
+        Ok(true)
+    }
+}
+
+#[magnus::wrap(class = "PhraseKit::NativeMatcherSet", free_immediately, size)]
+struct MatcherSetWrapper {
+    set: RustMatcherSet,
+}
+
+impl MatcherSetWrapper {
+    /// `NativeMatcherSet.load`: one hash per namespace, in priority order for
+    /// identical spans, holding "namespace", "automaton_path", "payloads_path",
+    /// "manifest_path", and any `load_with_options` options for that matcher.
+    fn load(entries: RArray) -> Result<Self, Error> {
+        let mut members = Vec::new();
+        for entry in entries.to_vec::<RHash>()? {
+            let namespace: String = entry.fetch("namespace")?;
+            let automaton_path: String = entry.fetch("automaton_path")?;
+            let payloads_path: String = entry.fetch("payloads_path")?;
+            let manifest_path: String = entry.fetch("manifest_path")?;
+            let matcher = load_matcher(&automaton_path, &payloads_path, &manifest_path, entry)?;
+            members.push((namespace, matcher));
+        }
+
+        let set = RustMatcherSet::new(members)
+            .map_err(|e| Error::new(magnus::exception::runtime_error(), format!("Failed to load matcher set: {}", e)))?;
+        Ok(Self { set })
+    }
+
+    fn namespaces(&self) -> Vec<String> {
+        self.set.namespaces().map(String::from).collect()
+    }
+
+    /// Matches from every namespace, resolved together; each hash carries
+    /// "namespace". `options` takes "min_salience" and "min_n".
+    fn match_tokens(&self, token_ids: Vec<u32>, policy: String, max: usize, options: RHash) -> Result<RArray, Error> {
+        let match_policy = MatchPolicy::from_str(&policy)
+            .ok_or_else(|| Error::new(magnus::exception::arg_error(), format!("Invalid policy: {}", policy)))?;
+        let filters = MatchFilters {
+            min_salience: options.lookup::<_, Option<f32>>("min_salience")?.unwrap_or(f32::NEG_INFINITY),
+            min_n: options.lookup::<_, Option<u8>>("min_n")?.unwrap_or(0),
+        };
+
+        let resolution = without_gvl(token_ids.len(), || {
+            self.set.match_tokens_detailed(&token_ids, match_policy, max, filters)
+        });
+
+        let result = RArray::new();
+        for m in &resolution.matches {
+            let hash = match_to_hash(m)?;
+            if resolution.degraded {
+                hash.aset("degraded", true)?;
+            }
+            result.push(hash)?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Loads one matcher with the options `NativeMatcher#load_with_options` takes.
+fn load_matcher(automaton_path: &str, payloads_path: &str, manifest_path: &str, options: RHash) -> Result<RustMatcher, Error> {
+    let mut load_options = LoadOptions::default();
+    if let Some(lazy) = options.lookup::<_, Option<bool>>("lazy_payloads")? {
+        load_options.lazy_payloads = lazy;
+    }
+    if let Some(capacity) = options.lookup::<_, Option<usize>>("payload_cache_size")? {
+        load_options.payload_cache_capacity = capacity;
+    }
+    if let Some(max_cluster) = options.lookup::<_, Option<usize>>("max_cluster")? {
+        load_options.max_cluster = max_cluster;
+    }
+    if let Some(strict) = options.lookup::<_, Option<bool>>("strict")? {
+        load_options.strict = strict;
+    }
+    if let Some(verify) = options.lookup::<_, Option<bool>>("verify_checksums")? {
+        load_options.verify_checksums = verify;
+    }
+    if let Some(validate) = options.lookup::<_, Option<bool>>("validate_patterns")? {
+        load_options.validate_patterns = validate;
+    }
+    if let Some(capacity) = options.lookup::<_, Option<usize>>("result_cache_size")? {
+        load_options.result_cache_capacity = capacity;
+    }
+
+    let loaded = match options.lookup::<_, Option<String>>("vocab_path")? {
+        Some(vocab_path) => {
+            RustMatcher::load_with_vocab(automaton_path, payloads_path, manifest_path, &vocab_path, load_options)
+        }
+        None => RustMatcher::load_with_options(automaton_path, payloads_path, manifest_path, load_options),
+    };
+    loaded.map_err(|e| Error::new(magnus::exception::runtime_error(), format!("Failed to load matcher: {}", e)))
+}
+
+fn match_to_hash(m: &Match) -> Result<RHash, Error> {
+    let hash = RHash::new();
+    hash.aset("start", m.start)?;
+    hash.aset("end", m.end)?;
+    add_payload_fields(&hash, &m.payload)?;
+    if let Some(namespace) = &m.namespace {
+        hash.aset("namespace", namespace.as_str())?;
+    }
+    Ok(hash)
+}
+
+fn add_payload_fields(hash: &RHash, payload: &Payload) -> Result<(), Error> {
+    hash.aset("phrase_id", payload.phrase_id)?;
+    hash.aset("salience", payload.salience)?;
+    hash.aset("count", payload.count)?;
+    hash.aset("n", payload.n)?;
+    if payload.idf > 0.0 {
+        hash.aset("idf", payload.idf)?;
+    }
+    if payload.category_id > 0 {
+        hash.aset("category_id", payload.category_id)?;
+    }
+    Ok(())
+}
+
+/// `NativeMatcher.estimate_memory`: projected heap use in MB, from file sizes and the manifest only.
+fn estimate_matcher_memory(automaton_path: String, payloads_path: String, manifest_path: String) -> Result<RHash, Error> {
+    let estimate = estimate_memory(&automaton_path, &payloads_path, &manifest_path)
+        .map_err(|e| Error::new(magnus::exception::runtime_error(), format!("Failed to estimate memory: {}", e)))?;
+
+    let to_mb = |bytes: u64| bytes as f64 / 1_048_576.0;
+    let hash = RHash::new();
+    hash.aset("automaton_mb", to_mb(estimate.automaton_bytes))?;
+    hash.aset("payloads_mb", to_mb(estimate.payloads_bytes))?;
+    hash.aset("total_mb", to_mb(estimate.total_bytes()))?;
+    hash.aset("payload_count", estimate.payload_count)?;
+    Ok(hash)
+}
+
+#[magnus::init]
+fn init(ruby: &Ruby) -> Result<(), Error> {
+    let module = define_module("PhraseKit")?;
+    let class = module.define_class("NativeMatcher", ruby.class_object())?;
+
+    class.define_singleton_method("new", function!(MatcherWrapper::new, 0))?;
+    class.define_singleton_method("estimate_memory", function!(estimate_matcher_memory, 3))?;
+    class.define_method("load", method!(MatcherWrapper::load, 3))?;
+    class.define_method("load_with_options", method!(MatcherWrapper::load_with_options, 4))?;
+    class.define_method("reload", method!(MatcherWrapper::reload, 4))?;
+    class.define_method("match_tokens", method!(MatcherWrapper::match_tokens, 3))?;
+    class.define_method("match_strings", method!(MatcherWrapper::match_strings, 3))?;
+    class.define_method("match_tokens_with_options", method!(MatcherWrapper::match_tokens_with_options, 4))?;
+    class.define_method("match_tokens_jsonl", method!(MatcherWrapper::match_tokens_jsonl, 3))?;
+    class.define_method("match_tokens_batch_each", method!(MatcherWrapper::match_tokens_batch_each, 3))?;
+    class.define_method("match_tokens_streaming", method!(MatcherWrapper::match_tokens_streaming, 3))?;
+    class.define_method("match_tokens_batch", method!(MatcherWrapper::match_tokens_batch, 3))?;
+    class.define_method("best_match", method!(MatcherWrapper::best_match, 1))?;
+    class.define_method("payload_for", method!(MatcherWrapper::payload_for, 1))?;
+    class.define_method("phrase_frequencies", method!(MatcherWrapper::phrase_frequencies, 1))?;
+    class.define_method("contains_phrase_ids_batch", method!(MatcherWrapper::contains_phrase_ids_batch, 1))?;
+    class.define_method("phrase_scores", method!(MatcherWrapper::phrase_scores, 2))?;
+    class.define_method("stats", method!(MatcherWrapper::stats, 0))?;
+    class.define_method("reset_stats", method!(MatcherWrapper::reset_stats, 0))?;
+    class.define_method("clear_cache", method!(MatcherWrapper::clear_cache, 0))?;
+    class.define_method("diagnostics", method!(MatcherWrapper::diagnostics, 0))?;
+    class.define_method("normalization", method!(MatcherWrapper::normalization, 0))?;
+    class.define_method("unicode_normalization", method!(MatcherWrapper::unicode_normalization, 0))?;
+    class.define_method("healthcheck", method!(MatcherWrapper::healthcheck, 0))?;
+
+    let set_class = module.define_class("NativeMatcherSet", ruby.class_object())?;
+    set_class.define_singleton_method("load", function!(MatcherSetWrapper::load, 1))?;
+    set_class.define_method("namespaces", method!(MatcherSetWrapper::namespaces, 0))?;
+    set_class.define_method("match_tokens", method!(MatcherSetWrapper::match_tokens, 4))?;
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,567 @@
+//! Salience scoring of mined n-grams against a background corpus:
+//! `phrasekit_score` without the CLI.
+
+use crate::compression;
+use crate::config::Validate;
+use crate::manifest::UnicodeNormalization;
+use crate::progress::Progress;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Lines, Write};
+
+#[derive(Debug, Deserialize)]
+struct InputNgram {
+    tokens: Vec<String>,
+    count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScoreConfig {
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default = "default_min_salience")]
+    pub min_salience: f32,
+    #[serde(default = "default_min_domain_count")]
+    pub min_domain_count: u32,
+    #[serde(default = "default_assign_phrase_ids")]
+    pub assign_phrase_ids: bool,
+    #[serde(default = "default_starting_phrase_id")]
+    pub starting_phrase_id: u64,
+    /// Added to the background count in the `ratio` denominator.
+    #[serde(default = "default_smoothing")]
+    pub smoothing: f32,
+    /// Scale background counts to the domain total before taking the `ratio`,
+    /// so corpora of different sizes compare by rate.
+    #[serde(default)]
+    pub normalize_by_corpus_size: bool,
+    /// `pmi` salience of phrases absent from the background.
+    #[serde(default = "default_unseen_pmi")]
+    pub unseen_pmi: f32,
+    /// Compare phrases in their original case, for n-grams mined with
+    /// `case_sensitive: true`.
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Unicode form both sides' tokens are brought to before comparing, for
+    /// n-grams mined without it or from differently encoded corpora.
+    #[serde(default)]
+    pub unicode_normalization: UnicodeNormalization,
+}
+
+const METHODS: [&str; 6] = ["ratio", "pmi", "tfidf", "llr", "dice", "tscore"];
+
+impl Validate for ScoreConfig {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !METHODS.contains(&self.method.as_str()) {
+            problems.push(format!(
+                "Invalid method: {}. Must be 'ratio', 'pmi', 'tfidf', 'llr', 'dice', or 'tscore'",
+                self.method
+            ));
+        }
+        if self.smoothing.is_nan() || self.smoothing <= 0.0 {
+            problems.push(format!("Invalid smoothing: {}. Must be positive", self.smoothing));
+        }
+        problems
+    }
+}
+
+fn default_method() -> String {
+    "ratio".to_string()
+}
+
+fn default_min_salience() -> f32 {
+    2.0
+}
+
+fn default_min_domain_count() -> u32 {
+    10
+}
+
+fn default_assign_phrase_ids() -> bool {
+    true
+}
+
+fn default_starting_phrase_id() -> u64 {
+    1000
+}
+
+fn default_smoothing() -> f32 {
+    1.0
+}
+
+fn default_unseen_pmi() -> f32 {
+    10.0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputPhrase {
+    pub tokens: Vec<String>,
+    pub salience: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phrase_id: Option<u64>,
+    pub domain_count: u32,
+    pub background_count: u32,
+}
+
+/// Phrase count and summed occurrences of a corpus, from a first pass so the
+/// domain corpus can be streamed through scoring.
+#[derive(Debug, Default)]
+pub struct CorpusTotals {
+    pub phrases: usize,
+    pub occurrences: u64,
+    /// Counts of single-token phrases, for `dice`. Empty unless the corpus
+    /// was mined with `min_n: 1`.
+    pub unigram_counts: HashMap<String, u32>,
+}
+
+impl CorpusTotals {
+    pub fn add(&mut self, tokens: &[String], count: u32) {
+        self.phrases += 1;
+        self.occurrences += count as u64;
+        if let [token] = tokens {
+            self.unigram_counts.insert(token.clone(), count);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScoringStats {
+    pub domain_phrases: usize,
+    pub background_phrases: usize,
+    pub after_domain_filter: usize,
+    pub after_salience_filter: usize,
+}
+
+/// `(tokens, count)` pairs from a phrase JSONL file, in `unicode` form and
+/// lowercased unless `case_sensitive`. Unparseable lines are skipped, with a
+/// warning to `progress`.
+pub struct PhraseLines<'a> {
+    lines: Lines<Box<dyn BufRead + Send>>,
+    line_num: usize,
+    case_sensitive: bool,
+    unicode: UnicodeNormalization,
+    progress: &'a dyn Fn(Progress),
+}
+
+impl<'a> PhraseLines<'a> {
+    pub fn open(
+        path: &str,
+        case_sensitive: bool,
+        unicode: UnicodeNormalization,
+        progress: &'a dyn Fn(Progress),
+    ) -> io::Result<Self> {
+        Ok(Self {
+            lines: compression::open_input(path)?.lines(),
+            line_num: 0,
+            case_sensitive,
+            unicode,
+            progress,
+        })
+    }
+}
+
+impl Iterator for PhraseLines<'_> {
+    type Item = io::Result<(Vec<String>, u32)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line_num += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let ngram: InputNgram = match serde_json::from_str(&line) {
+                Ok(n) => n,
+                Err(e) => {
+                    (self.progress)(Progress::Warning(format!("Line {}: Failed to parse: {}", self.line_num, e)));
+                    continue;
+                }
+            };
+
+            let tokens: Vec<String> = if self.case_sensitive {
+                ngram.tokens.iter().map(|t| self.unicode.apply(t).into_owned()).collect()
+            } else {
+                ngram.tokens.iter().map(|t| self.unicode.apply(t).to_lowercase()).collect()
+            };
+            return Some(Ok((tokens, ngram.count)));
+        }
+    }
+}
+
+/// A whole phrase file as a map, for the background side of scoring.
+pub fn load_phrases(
+    path: &str,
+    case_sensitive: bool,
+    unicode: UnicodeNormalization,
+    progress: &dyn Fn(Progress),
+) -> Result<HashMap<Vec<String>, u32>, Box<dyn std::error::Error>> {
+    let mut phrases = HashMap::new();
+    for phrase in PhraseLines::open(path, case_sensitive, unicode, progress)? {
+        let (tokens, count) = phrase?;
+        phrases.insert(tokens, count);
+    }
+    Ok(phrases)
+}
+
+/// Scores domain phrases as they are read. `domain_totals` must describe the
+/// same phrases; every method but `ratio` needs the totals up front.
+pub fn score_phrases(
+    domain_phrases: impl IntoIterator<Item = io::Result<(Vec<String>, u32)>>,
+    domain_totals: &CorpusTotals,
+    background_phrases: &HashMap<Vec<String>, u32>,
+    config: &ScoreConfig,
+) -> Result<(Vec<OutputPhrase>, ScoringStats), Box<dyn std::error::Error>> {
+    let mut scored = Vec::new();
+    let mut stats = ScoringStats {
+        domain_phrases: domain_totals.phrases,
+        background_phrases: background_phrases.len(),
+        after_domain_filter: 0,
+        after_salience_filter: 0,
+    };
+
+    // Compute total counts for PMI and LLR
+    let total_domain = domain_totals.occurrences;
+    let total_background: u64 = background_phrases.values().map(|&c| c as u64).sum();
+    let background_scale = if config.normalize_by_corpus_size && total_background > 0 {
+        total_domain as f64 / total_background as f64
+    } else {
+        1.0
+    };
+
+    for phrase in domain_phrases {
+        let (tokens, domain_count) = phrase?;
+        // Filter by minimum domain count
+        if domain_count < config.min_domain_count {
+            continue;
+        }
+        stats.after_domain_filter += 1;
+
+        // Get background count (default to 0 if not found)
+        let background_count = background_phrases.get(&tokens).copied().unwrap_or(0);
+
+        // Compute salience based on method
+        let salience = match config.method.as_str() {
+            "ratio" => compute_ratio_salience(domain_count, background_count, background_scale, config.smoothing),
+            "pmi" => compute_pmi_salience(
+                domain_count,
+                background_count,
+                total_domain,
+                total_background,
+                config.unseen_pmi,
+            ),
+            "tfidf" => compute_tfidf_salience(domain_count, background_count, total_domain),
+            "llr" => compute_llr_salience(domain_count, background_count, total_domain, total_background),
+            "dice" => compute_dice_salience(&tokens, domain_count, background_count, &domain_totals.unigram_counts),
+            "tscore" => compute_tscore_salience(domain_count, background_count, total_domain, total_background),
+            _ => unreachable!(),
+        };
+
+        // Filter by minimum salience
+        if salience < config.min_salience {
+            continue;
+        }
+        stats.after_salience_filter += 1;
+
+        scored.push(OutputPhrase {
+            tokens,
+            salience,
+            phrase_id: None,  // Will be assigned later if needed
+            domain_count,
+            background_count,
+        });
+    }
+
+    // Sort by salience (descending)
+    scored.sort_by(|a, b| b.salience.partial_cmp(&a.salience).unwrap());
+
+    Ok((scored, stats))
+}
+
+/// `domain_count / (background_count * background_scale + smoothing)`. The
+/// denominator is summed in f64 and rounded once, so `smoothing = 1.0` with a
+/// scale of 1.0 gives exactly the historical `domain / (background + 1)`.
+fn compute_ratio_salience(domain_count: u32, background_count: u32, background_scale: f64, smoothing: f32) -> f32 {
+    let denominator = background_count as f64 * background_scale + smoothing as f64;
+    domain_count as f32 / denominator as f32
+}
+
+fn compute_pmi_salience(
+    domain_count: u32,
+    background_count: u32,
+    total_domain: u64,
+    total_background: u64,
+    unseen_pmi: f32,
+) -> f32 {
+    if background_count == 0 {
+        return unseen_pmi;
+    }
+
+    let p_domain = domain_count as f64 / total_domain as f64;
+    let p_background = background_count as f64 / total_background as f64;
+
+    let pmi = (p_domain / p_background).log2();
+    pmi as f32
+}
+
+fn compute_tfidf_salience(domain_count: u32, background_count: u32, total_domain: u64) -> f32 {
+    let tf = domain_count as f32 / total_domain as f32;
+    let idf = ((total_domain + 1) as f32 / (background_count + 1) as f32).ln();
+    tf * idf
+}
+
+/// Dunning's log-likelihood ratio G² over the 2x2 table of this phrase versus
+/// all other n-grams, in the domain versus the background corpus. Signed:
+/// negative when the phrase is relatively more frequent in the background, so
+/// background-heavy phrases never outscore domain-specific ones.
+fn compute_llr_salience(
+    domain_count: u32,
+    background_count: u32,
+    total_domain: u64,
+    total_background: u64,
+) -> f32 {
+    let a = domain_count as f64;
+    let b = background_count as f64;
+    let c = total_domain.saturating_sub(domain_count as u64) as f64;
+    let d = total_background.saturating_sub(background_count as u64) as f64;
+    let n = a + b + c + d;
+    if n == 0.0 {
+        return 0.0;
+    }
+
+    // observed * ln(observed / expected), with 0 * ln(0) = 0
+    let term = |observed: f64, row: f64, col: f64| {
+        if observed == 0.0 {
+            0.0
+        } else {
+            observed * (observed * n / (row * col)).ln()
+        }
+    };
+    let g2 = 2.0 * (term(a, a + b, a + c) + term(b, a + b, b + d) + term(c, c + d, a + c) + term(d, c + d, b + d));
+
+    // Compare rates as a * (b + d) vs b * (a + c) to avoid dividing by zero totals
+    let sign = if a * (b + d) >= b * (a + c) { 1.0 } else { -1.0 };
+    (sign * g2.max(0.0)) as f32
+}
+
+/// Token-level Dice, `n * domain_count / sum of the n tokens' domain counts`,
+/// which is the classic `2 * f(xy) / (f(x) + f(y))` for bigrams. A token
+/// missing from `unigram_counts` counts as `domain_count`, the least it can
+/// be. With no unigrams at all, falls back to the phrase-level
+/// `2 * domain_count / (2 * domain_count + background_count)`: the Dice overlap
+/// of the phrase's domain occurrences with all its occurrences.
+fn compute_dice_salience(
+    tokens: &[String],
+    domain_count: u32,
+    background_count: u32,
+    unigram_counts: &HashMap<String, u32>,
+) -> f32 {
+    let domain = domain_count as f64;
+    if unigram_counts.is_empty() {
+        let denominator = 2.0 * domain + background_count as f64;
+        return if denominator == 0.0 { 0.0 } else { (2.0 * domain / denominator) as f32 };
+    }
+
+    let token_sum: f64 = tokens
+        .iter()
+        .map(|token| unigram_counts.get(token).copied().unwrap_or(domain_count) as f64)
+        .sum();
+    if token_sum == 0.0 {
+        return 0.0;
+    }
+    (tokens.len() as f64 * domain / token_sum) as f32
+}
+
+/// `(observed - expected) / sqrt(observed)`, where `expected` is the domain
+/// count if the phrase occurred at its pooled rate across both corpora.
+fn compute_tscore_salience(
+    domain_count: u32,
+    background_count: u32,
+    total_domain: u64,
+    total_background: u64,
+) -> f32 {
+    let observed = domain_count as f64;
+    let total = (total_domain + total_background) as f64;
+    if observed == 0.0 || total == 0.0 {
+        return 0.0;
+    }
+    let expected = total_domain as f64 * (domain_count as f64 + background_count as f64) / total;
+    ((observed - expected) / observed.sqrt()) as f32
+}
+
+/// Writes `phrases` as `phrases` JSONL in the order given, numbering them
+/// from `starting_phrase_id` when `assign_phrase_ids` is set. Returns how
+/// many were written.
+pub fn write_phrases(
+    output_path: &str,
+    mut phrases: Vec<OutputPhrase>,
+    config: &ScoreConfig,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut writer = compression::create_output(output_path)?;
+
+    // Assign phrase IDs if requested
+    if config.assign_phrase_ids {
+        for (i, phrase) in phrases.iter_mut().enumerate() {
+            phrase.phrase_id = Some(config.starting_phrase_id + i as u64);
+        }
+    }
+
+    let count = phrases.len();
+    for phrase in phrases {
+        let json = serde_json::to_string(&phrase)?;
+        writeln!(writer, "{}", json)?;
+    }
+
+    writer.finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::silent;
+
+    fn config(json: &str) -> ScoreConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn score(
+        domain: &HashMap<Vec<String>, u32>,
+        background: &HashMap<Vec<String>, u32>,
+        config: &ScoreConfig,
+    ) -> Vec<OutputPhrase> {
+        let mut totals = CorpusTotals::default();
+        domain.iter().for_each(|(tokens, &count)| totals.add(tokens, count));
+        let phrases = domain.iter().map(|(tokens, &count)| Ok((tokens.clone(), count)));
+        score_phrases(phrases, &totals, background, config).unwrap().0
+    }
+
+    #[test]
+    fn test_default_ratio_matches_laplace_smoothing() {
+        let defaults = config("{}");
+        assert_eq!(defaults.smoothing, 1.0);
+        assert!(!defaults.normalize_by_corpus_size);
+
+        for (domain, background) in [(10, 0), (2450, 5), (8500, 125_000), (7, 16_777_216), (u32::MAX, 3_000_000_000)] {
+            let expected = domain as f32 / (background as u64 + 1) as f32;
+            let actual = compute_ratio_salience(domain, background, 1.0, defaults.smoothing);
+            assert_eq!(actual.to_bits(), expected.to_bits(), "{} / {}", domain, background);
+        }
+    }
+
+    #[test]
+    fn test_default_scoring_output_is_unchanged() {
+        let domain = HashMap::from([(vec!["lysis".to_string(), "buffer".to_string()], 30), (vec!["for".to_string()], 12)]);
+        let background = HashMap::from([(vec!["for".to_string()], 5000)]);
+
+        let scored = score(&domain, &background, &config(r#"{"min_salience": 0.0}"#));
+        let json: Vec<String> = scored.iter().map(|p| serde_json::to_string(p).unwrap()).collect();
+        assert_eq!(json, vec![
+            r#"{"tokens":["lysis","buffer"],"salience":30.0,"domain_count":30,"background_count":0}"#.to_string(),
+            format!(r#"{{"tokens":["for"],"salience":{},"domain_count":12,"background_count":5000}}"#, 12f32 / 5001f32),
+        ]);
+
+        let pmi = score(&domain, &background, &config(r#"{"method": "pmi", "min_salience": -100.0}"#));
+        assert_eq!(pmi[0].salience, 10.0);
+    }
+
+    #[test]
+    fn test_phrase_lines_lowercase_and_skip_bad_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, r#"{{"tokens":["Lysis","Buffer"],"count":3}}"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "not json").unwrap();
+        writeln!(file, r#"{{"tokens":["for"],"count":7}}"#).unwrap();
+        writeln!(file, r#"{{"tokens":["Cafe\u0301"],"count":2}}"#).unwrap();
+        file.flush().unwrap();
+
+        let read_as = |case_sensitive: bool, unicode: UnicodeNormalization| -> Vec<(Vec<String>, u32)> {
+            PhraseLines::open(file.path().to_str().unwrap(), case_sensitive, unicode, &silent)
+                .unwrap()
+                .collect::<io::Result<_>>()
+                .unwrap()
+        };
+        let read = |case_sensitive: bool| read_as(case_sensitive, UnicodeNormalization::None);
+        assert_eq!(read(false), vec![
+            (vec!["lysis".to_string(), "buffer".to_string()], 3),
+            (vec!["for".to_string()], 7),
+            (vec!["cafe\u{301}".to_string()], 2),
+        ]);
+        assert_eq!(read(true)[0].0, vec!["Lysis".to_string(), "Buffer".to_string()]);
+        assert_eq!(read_as(false, UnicodeNormalization::Nfc)[2].0, vec!["caf\u{e9}".to_string()]);
+        assert_eq!(read_as(true, UnicodeNormalization::Nfc)[2].0, vec!["Caf\u{e9}".to_string()]);
+    }
+
+    #[test]
+    fn test_ratio_smoothing_and_normalization() {
+        assert_eq!(compute_ratio_salience(10, 0, 1.0, 0.5), 20.0);
+        // A background 100x the domain: 200 background hits are 2 at domain scale
+        assert_eq!(compute_ratio_salience(12, 200, 0.01, 1.0), 4.0);
+        assert_eq!(compute_pmi_salience(5, 0, 100, 100, 3.5), 3.5);
+    }
+
+    #[test]
+    fn test_llr_salience_matches_hand_computed_g2() {
+        // a=10, b=0, c=90, d=100: every expected count is 5, 5, 95, 95, so
+        // G² = 2 * (10 ln 2 + 90 ln(90/95) + 100 ln(100/95)) = 14.3895
+        let g2 = compute_llr_salience(10, 0, 100, 100);
+        assert!((g2 - 14.3895).abs() < 1e-3, "got {}", g2);
+
+        // Mirrored table: same magnitude, background-heavy, so negative
+        let mirrored = compute_llr_salience(0, 10, 100, 100);
+        assert!((mirrored + 14.3895).abs() < 1e-3, "got {}", mirrored);
+
+        // Identical rates carry no evidence either way
+        assert!(compute_llr_salience(10, 20, 100, 200).abs() < 1e-6);
+        assert_eq!(compute_llr_salience(0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_dice_uses_unigram_counts_when_mined() {
+        let tokens = vec!["lysis".to_string(), "buffer".to_string()];
+        let unigrams = HashMap::from([("lysis".to_string(), 40), ("buffer".to_string(), 60)]);
+        assert_eq!(compute_dice_salience(&tokens, 30, 5, &unigrams), 0.6);
+        // Missing components count as the phrase itself
+        let partial = HashMap::from([("lysis".to_string(), 30)]);
+        assert_eq!(compute_dice_salience(&tokens, 30, 5, &partial), 1.0);
+
+        // Phrase-level fallback: 2 * 30 / (60 + 40)
+        assert_eq!(compute_dice_salience(&tokens, 30, 40, &HashMap::new()), 0.6);
+        assert_eq!(compute_dice_salience(&tokens, 30, 0, &HashMap::new()), 1.0);
+        assert_eq!(compute_dice_salience(&tokens, 0, 0, &HashMap::new()), 0.0);
+    }
+
+    #[test]
+    fn test_tscore_against_pooled_expectation() {
+        // Pooled rate 20 / 200 predicts 10 domain hits; 16 observed
+        assert!((compute_tscore_salience(16, 4, 100, 100) - 1.5).abs() < 1e-6);
+        // Background-heavy phrases fall below zero
+        assert!(compute_tscore_salience(4, 16, 100, 100) < 0.0);
+        assert_eq!(compute_tscore_salience(10, 10, 100, 100), 0.0);
+        assert_eq!(compute_tscore_salience(0, 5, 100, 100), 0.0);
+    }
+
+    #[test]
+    fn test_dice_and_tscore_methods_are_scored() {
+        let lysis_buffer = vec!["lysis".to_string(), "buffer".to_string()];
+        let domain = HashMap::from([
+            (lysis_buffer.clone(), 30),
+            (vec!["lysis".to_string()], 40),
+            (vec!["buffer".to_string()], 60),
+        ]);
+        let background = HashMap::from([(vec!["buffer".to_string()], 500)]);
+
+        let dice = score(&domain, &background, &config(r#"{"method": "dice", "min_salience": 0.0, "min_domain_count": 1}"#));
+        let phrase = dice.iter().find(|p| p.tokens == lysis_buffer).unwrap();
+        assert_eq!(phrase.salience, 0.6);
+
+        let tscore = score(&domain, &background, &config(r#"{"method": "tscore", "min_salience": -100.0, "min_domain_count": 1}"#));
+        assert!(tscore.iter().find(|p| p.tokens == lysis_buffer).unwrap().salience > 0.0);
+        assert_eq!(tscore.last().unwrap().tokens, vec!["buffer".to_string()]);
+        assert!(tscore.last().unwrap().salience < 0.0);
+    }
+}
@@ -0,0 +1,380 @@
+//! Cross-checks that the files of an artifact directory belong to one build,
+//! for `phrasekit_verify`.
+
+use crate::manifest::{automaton_pattern_count, decode_pattern_tokens, sha256_hex, Manifest, AUTOMATON_FORMAT, EXTERNAL_VOCAB};
+use crate::payload::{Payload, PayloadFormat};
+use crate::payload_store::{payloads_sha256, PayloadStore};
+use daachorse::DoubleArrayAhoCorasick;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// The check could not run: its input is absent, or an earlier failure
+    /// made it meaningless.
+    Skip,
+}
+
+impl CheckStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Pass => "PASS",
+            Self::Fail => "FAIL",
+            Self::Skip => "SKIP",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != CheckStatus::Fail)
+    }
+
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks.iter().filter(|check| check.status == status).count()
+    }
+
+    fn record(&mut self, name: &'static str, status: CheckStatus, detail: impl Into<String>) {
+        self.checks.push(Check { name, status, detail: detail.into() });
+    }
+}
+
+/// vocab.json with IDs read as u64, so an ID outside the u32 token range is
+/// reported rather than failing the parse.
+#[derive(Debug, Deserialize)]
+struct RawVocab {
+    separator_id: u64,
+    #[serde(default)]
+    tokens: HashMap<String, u64>,
+    #[serde(default)]
+    special_tokens: HashMap<String, u64>,
+    #[serde(default)]
+    case_sensitive_tokens: HashMap<String, u64>,
+}
+
+/// Runs every check on the build in `dir`: manifest.json, payloads.bin,
+/// phrases.daac, phrases.bin, and vocab.json. Missing checksums are skipped
+/// unless `require_checksums`.
+pub fn verify_artifacts(dir: &Path, require_checksums: bool) -> Report {
+    let mut report = Report::default();
+
+    let manifest = match Manifest::load(dir.join("manifest.json")) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            report.record("manifest", CheckStatus::Fail, e.to_string());
+            return report;
+        }
+    };
+    report.record(
+        "manifest",
+        CheckStatus::Pass,
+        format!("version {}, {} patterns, separator_id {}", manifest.version, manifest.num_patterns, manifest.separator_id),
+    );
+
+    let payloads = check_payloads(dir, &manifest, &mut report);
+    let automaton_bytes = std::fs::read(dir.join("phrases.daac"));
+    let automaton_intact = check_checksums(dir, &manifest, automaton_bytes.as_deref().ok(), require_checksums, &mut report);
+    match (&automaton_bytes, payloads) {
+        (Err(e), _) => report.record("automaton", CheckStatus::Fail, format!("phrases.daac: {}", e)),
+        (Ok(_), _) if !automaton_intact => {
+            report.record("automaton", CheckStatus::Skip, "not loaded: phrases.daac fails its checksum")
+        }
+        (Ok(_), None) => report.record("automaton", CheckStatus::Skip, "no payloads to check against"),
+        (Ok(bytes), Some(payloads)) => check_automaton(dir, &manifest, bytes, &payloads, &mut report),
+    }
+    check_vocab(dir, &manifest, &mut report);
+
+    report
+}
+
+/// Loads the payload table and checks its length against `num_patterns`.
+/// Returns the payloads when they could be read.
+fn check_payloads(dir: &Path, manifest: &Manifest, report: &mut Report) -> Option<Vec<Payload>> {
+    // Manifest::load only admits versions PayloadFormat knows
+    let format = PayloadFormat::from_version(manifest.payload_format_version)?;
+    let store = match PayloadStore::load_eager(dir.join("payloads.bin"), format) {
+        Ok(store) => store,
+        Err(e) => {
+            report.record("payloads", CheckStatus::Fail, format!("payloads.bin: {}", e));
+            return None;
+        }
+    };
+    let payloads: Vec<Payload> = store.scan().collect();
+
+    if payloads.len() != manifest.num_patterns {
+        report.record(
+            "payloads",
+            CheckStatus::Fail,
+            format!("{} records, but the manifest says num_patterns {}", payloads.len(), manifest.num_patterns),
+        );
+    } else if let Some(index) = store.find_degenerate() {
+        report.record("payloads", CheckStatus::Fail, format!("record {} is a zero-length pattern (n == 0)", index));
+    } else {
+        report.record("payloads", CheckStatus::Pass, format!("{} records, matching num_patterns", payloads.len()));
+    }
+    Some(payloads)
+}
+
+/// Recomputes the checksums the manifest records. Returns false when the
+/// automaton's does not match, so its unchecked deserialization is skipped.
+fn check_checksums(
+    dir: &Path,
+    manifest: &Manifest,
+    automaton_bytes: Option<&[u8]>,
+    require_checksums: bool,
+    report: &mut Report,
+) -> bool {
+    let mut automaton_intact = true;
+    let mut mismatches = Vec::new();
+    let mut missing = Vec::new();
+
+    match (&manifest.automaton_sha256, automaton_bytes) {
+        (None, _) => missing.push("automaton_sha256"),
+        (Some(_), None) => automaton_intact = false,
+        (Some(expected), Some(bytes)) => {
+            if !sha256_hex(bytes).eq_ignore_ascii_case(expected) {
+                mismatches.push("phrases.daac");
+                automaton_intact = false;
+            }
+        }
+    }
+    match &manifest.payloads_sha256 {
+        None => missing.push("payloads_sha256"),
+        Some(expected) => match payloads_sha256(&dir.join("payloads.bin")) {
+            Ok(actual) if actual.eq_ignore_ascii_case(expected) => {}
+            // An unreadable table already failed the payloads check
+            Ok(_) => mismatches.push("payloads.bin"),
+            Err(_) => {}
+        },
+    }
+
+    if !mismatches.is_empty() {
+        let verb = if mismatches.len() == 1 { "does" } else { "do" };
+        let detail = format!("{} {} not match the manifest; files from different builds?", mismatches.join(" and "), verb);
+        report.record("checksums", CheckStatus::Fail, detail);
+    } else if missing.len() == 2 {
+        let status = if require_checksums { CheckStatus::Fail } else { CheckStatus::Skip };
+        report.record("checksums", status, "manifest records none; run phrasekit_convert to add them");
+    } else if !missing.is_empty() && require_checksums {
+        report.record("checksums", CheckStatus::Fail, format!("manifest records no {}", missing.join(" or ")));
+    } else {
+        let checked = 2 - missing.len();
+        report.record("checksums", CheckStatus::Pass, format!("{} of 2 recorded checksums match", checked));
+    }
+    automaton_intact
+}
+
+/// Confirms the automaton holds exactly one pattern per payload and, from
+/// phrases.bin, that each pattern's token IDs match to its own index.
+fn check_automaton(dir: &Path, manifest: &Manifest, bytes: &[u8], payloads: &[Payload], report: &mut Report) {
+    if let Some(format) = manifest.automaton_format.as_deref().filter(|&format| format != AUTOMATON_FORMAT) {
+        report.record(
+            "automaton",
+            CheckStatus::Fail,
+            format!("automaton_format {} is not {}; rebuild the artifacts", format, AUTOMATON_FORMAT),
+        );
+        return;
+    }
+    let Some(count) = automaton_pattern_count(bytes) else {
+        let detail = format!("phrases.daac is not a serialized {} automaton; truncated or corrupt", AUTOMATON_FORMAT);
+        return report.record("automaton", CheckStatus::Fail, detail);
+    };
+    if count != payloads.len() {
+        let detail = format!("automaton holds {} patterns but payloads.bin has {}", count, payloads.len());
+        return report.record("automaton", CheckStatus::Fail, detail);
+    }
+
+    let patterns = match std::fs::read(dir.join("phrases.bin")) {
+        Ok(pattern_bytes) => match decode_pattern_tokens(&pattern_bytes) {
+            Ok(patterns) => patterns,
+            Err(e) => return report.record("automaton", CheckStatus::Fail, e),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let detail = format!("{} patterns, matching payloads.bin; phrases.bin not found, so patterns not traced", count);
+            return report.record("automaton", CheckStatus::Pass, detail);
+        }
+        Err(e) => return report.record("automaton", CheckStatus::Fail, format!("phrases.bin: {}", e)),
+    };
+    if patterns.len() != payloads.len() {
+        let detail = format!("phrases.bin has {} patterns but payloads.bin has {}", patterns.len(), payloads.len());
+        return report.record("automaton", CheckStatus::Fail, detail);
+    }
+
+    // Same unchecked deserialization the matcher performs on load
+    let (automaton, _): (DoubleArrayAhoCorasick<u32>, _) = unsafe { DoubleArrayAhoCorasick::deserialize_unchecked(bytes) };
+
+    let mut mismatched = Vec::new();
+    for (index, (token_ids, payload)) in patterns.iter().zip(payloads).enumerate() {
+        let encoded = manifest.encoding.encode(token_ids, manifest.separator_id);
+        let found = automaton
+            .find_overlapping_iter(&encoded)
+            .any(|m| m.start() == 0 && m.end() == encoded.len() && m.value() as usize == index);
+        if !found || payload.n as usize != token_ids.len() {
+            mismatched.push(index);
+        }
+    }
+
+    if mismatched.is_empty() {
+        report.record("automaton", CheckStatus::Pass, format!("all {} patterns map to their payloads", patterns.len()));
+    } else {
+        let first: Vec<String> = mismatched.iter().take(5).map(usize::to_string).collect();
+        report.record(
+            "automaton",
+            CheckStatus::Fail,
+            format!("{} patterns do not match their payloads (first: {})", mismatched.len(), first.join(", ")),
+        );
+    }
+}
+
+/// Checks every vocab.json ID fits a u32 token, no ID is the separator, and
+/// the vocabulary covers every token ID the patterns use.
+fn check_vocab(dir: &Path, manifest: &Manifest, report: &mut Report) {
+    if manifest.vocab.as_deref() == Some(EXTERNAL_VOCAB) {
+        return report.record("vocabulary", CheckStatus::Skip, "built from external token IDs; no vocab.json");
+    }
+    let vocab: RawVocab = match std::fs::read_to_string(dir.join("vocab.json")) {
+        Ok(json) => match serde_json::from_str(&json) {
+            Ok(vocab) => vocab,
+            Err(e) => return report.record("vocabulary", CheckStatus::Fail, format!("vocab.json: {}", e)),
+        },
+        Err(e) => return report.record("vocabulary", CheckStatus::Fail, format!("vocab.json: {}", e)),
+    };
+
+    if vocab.separator_id != manifest.separator_id as u64 {
+        let detail = format!("separator_id {} differs from the manifest's {}", vocab.separator_id, manifest.separator_id);
+        return report.record("vocabulary", CheckStatus::Fail, detail);
+    }
+
+    let mut entries: Vec<(&String, u64)> = vocab
+        .tokens
+        .iter()
+        .chain(&vocab.special_tokens)
+        .chain(&vocab.case_sensitive_tokens)
+        .map(|(token, &id)| (token, id))
+        .collect();
+    // Reported in ID order, so the first problem named is the same every run
+    entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+
+    let mut problems = Vec::new();
+    if let Some((token, id)) = entries.iter().find(|(_, id)| *id > u32::MAX as u64) {
+        problems.push(format!("token {:?} has ID {}, which does not fit in a u32", token, id));
+    }
+    if let Some((token, _)) = entries.iter().find(|(_, id)| *id == manifest.separator_id as u64) {
+        problems.push(format!("token {:?} has the separator_id {}", token, manifest.separator_id));
+    }
+    let vocab_max = entries.last().map(|&(_, id)| id);
+    if let Some(pattern_max) = manifest.max_token_id.filter(|&max| vocab_max.is_none_or(|vocab_max| vocab_max < max as u64)) {
+        problems.push(format!(
+            "patterns use token IDs up to {} but the largest vocabulary ID is {}",
+            pattern_max,
+            vocab_max.map_or("none".to_string(), |id| id.to_string())
+        ));
+    }
+
+    if problems.is_empty() {
+        report.record("vocabulary", CheckStatus::Pass, format!("{} token IDs, none colliding with the separator", entries.len()));
+    } else {
+        report.record("vocabulary", CheckStatus::Fail, problems.join("; "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifacts::{build_artifacts, load_and_validate_phrases, BuildConfig, BuildLimits, IdfSource};
+    use crate::progress::silent;
+
+    const PHRASES: [&str; 2] = [
+        r#"{"tokens": ["lysis", "buffer"], "phrase_id": 1, "salience": 2.0, "count": 10}"#,
+        r#"{"tokens": ["rat", "liver", "cells"], "phrase_id": 2, "salience": 3.0, "count": 12}"#,
+    ];
+
+    fn build(dir: &Path, lines: &[&str]) {
+        let input = dir.join("phrases.jsonl");
+        std::fs::write(&input, lines.join("\n")).unwrap();
+        let config: BuildConfig =
+            serde_json::from_str(r#"{"version": "v1", "tokenizer": "test", "separator_id": 4294967294}"#).unwrap();
+        let (phrases, _, unique_tokens) = load_and_validate_phrases(input.to_str().unwrap(), &config, &silent).unwrap();
+        let artifacts =
+            build_artifacts(&phrases, unique_tokens, &config, &BuildLimits::default(), &IdfSource::default(), &silent).unwrap();
+        artifacts.write(dir, "phrases.jsonl", false, &silent).unwrap();
+    }
+
+    fn statuses(report: &Report) -> Vec<(&'static str, CheckStatus)> {
+        report.checks.iter().map(|check| (check.name, check.status)).collect()
+    }
+
+    #[test]
+    fn test_verify_passes_a_clean_build() {
+        let dir = tempfile::tempdir().unwrap();
+        build(dir.path(), &PHRASES);
+
+        let report = verify_artifacts(dir.path(), true);
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(report.count(CheckStatus::Pass), 5);
+    }
+
+    #[test]
+    fn test_verify_catches_files_from_another_build() {
+        let dir = tempfile::tempdir().unwrap();
+        build(dir.path(), &PHRASES);
+        let other = tempfile::tempdir().unwrap();
+        build(other.path(), &[PHRASES[1], PHRASES[0]]);
+        std::fs::copy(other.path().join("phrases.daac"), dir.path().join("phrases.daac")).unwrap();
+
+        let report = verify_artifacts(dir.path(), false);
+        assert!(!report.passed());
+        assert!(statuses(&report).contains(&("checksums", CheckStatus::Fail)));
+        assert!(statuses(&report).contains(&("automaton", CheckStatus::Skip)));
+
+        // Without checksums the swapped automaton is caught pattern by pattern
+        let manifest_path = dir.path().join("manifest.json");
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        manifest.automaton_sha256 = None;
+        manifest.payloads_sha256 = None;
+        std::fs::write(&manifest_path, serde_json::to_string(&manifest).unwrap()).unwrap();
+
+        let report = verify_artifacts(dir.path(), false);
+        assert_eq!(
+            statuses(&report),
+            vec![
+                ("manifest", CheckStatus::Pass),
+                ("payloads", CheckStatus::Pass),
+                ("checksums", CheckStatus::Skip),
+                ("automaton", CheckStatus::Fail),
+                ("vocabulary", CheckStatus::Pass),
+            ]
+        );
+        assert!(!verify_artifacts(dir.path(), true).passed());
+    }
+
+    #[test]
+    fn test_verify_rejects_a_vocabulary_colliding_with_the_separator() {
+        let dir = tempfile::tempdir().unwrap();
+        build(dir.path(), &PHRASES);
+        let vocab_path = dir.path().join("vocab.json");
+        let mut vocab: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&vocab_path).unwrap()).unwrap();
+        vocab["tokens"]["stray"] = 4294967294u32.into();
+        std::fs::write(&vocab_path, vocab.to_string()).unwrap();
+
+        let report = verify_artifacts(dir.path(), false);
+        let check = report.checks.iter().find(|check| check.name == "vocabulary").unwrap();
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("\"stray\" has the separator_id"), "{}", check.detail);
+    }
+}
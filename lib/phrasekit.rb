@@ -3,59 +3,226 @@ require "phrasekit/phrasekit"
 require "phrasekit/miner"
 require "phrasekit/scorer"
 require "phrasekit/tagger"
+require "phrasekit/matcher_set"
 
 module PhraseKit
   class Error < StandardError; end
 
+  UNK_POLICIES = %w[map break wildcard].freeze
+
   class << self
     attr_reader :vocabulary
 
-    def load!(automaton_path:, payloads_path:, manifest_path:, vocab_path: nil)
+    # categories_path is the build's categories.json; with it, matches of
+    # categorized phrases carry their category name as :category next to
+    # :category_id.
+    def load!(automaton_path:, payloads_path:, manifest_path:, vocab_path: nil, categories_path: nil, lazy_payloads: false,
+      payload_cache_size: nil, max_cluster: nil, strict: true, verify_checksums: false, validate_patterns: false, result_cache_size: 0)
       @matcher = NativeMatcher.new
+      options = {"lazy_payloads" => lazy_payloads, "strict" => strict,
+                 "verify_checksums" => verify_checksums, "validate_patterns" => validate_patterns}
+      options["payload_cache_size"] = payload_cache_size if payload_cache_size
+      options["max_cluster"] = max_cluster if max_cluster
+      options["result_cache_size"] = result_cache_size
+      options["vocab_path"] = vocab_path.to_s if vocab_path
       begin
-        @matcher.load(automaton_path.to_s, payloads_path.to_s, manifest_path.to_s)
+        @matcher.load_with_options(automaton_path.to_s, payloads_path.to_s, manifest_path.to_s, options)
       rescue RuntimeError => e
         raise Error, e.message
       end
 
-      if vocab_path
-        begin
-          require "json"
-          vocab_data = JSON.parse(File.read(vocab_path))
-          @vocabulary = {
-            tokens: vocab_data["tokens"],
-            special_tokens: vocab_data["special_tokens"],
-            separator_id: vocab_data["separator_id"]
-          }
-        rescue => e
-          raise Error, "Failed to load vocabulary: #{e.message}"
-        end
+      @vocabulary_ids = nil
+      @vocabulary = vocab_path ? read_vocabulary(vocab_path) : nil
+      @case_sensitive = @matcher.normalization == "none"
+      @unicode_form = unicode_form(@matcher.unicode_normalization)
+      @categories = categories_path ? read_categories(categories_path) : nil
+    end
+
+    # Swaps in rebuilt artifacts with the options given to load!. Matches
+    # already running finish on the old artifacts, and if the new ones fail to
+    # load the old ones stay loaded. Without vocab_path or categories_path the
+    # current vocabulary or categories are kept. Returns the new manifest version.
+    def reload!(automaton_path:, payloads_path:, manifest_path:, vocab_path: nil, categories_path: nil)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      vocabulary = read_vocabulary(vocab_path) if vocab_path
+      categories = read_categories(categories_path) if categories_path
+      version = begin
+        @matcher.reload(automaton_path.to_s, payloads_path.to_s, manifest_path.to_s, vocab_path&.to_s)
+      rescue RuntimeError => e
+        raise Error, e.message
+      end
+
+      if vocabulary
+        @vocabulary = vocabulary
+        @vocabulary_ids = nil
+      end
+      @categories = categories if categories
+      @case_sensitive = @matcher.normalization == "none"
+      @unicode_form = unicode_form(@matcher.unicode_normalization)
+      version
+    end
+
+    # Projected memory for loading these artifacts, from file sizes only (no
+    # deserialization); usable before load!.
+    def estimate_memory(automaton_path:, payloads_path:, manifest_path:)
+      NativeMatcher.estimate_memory(automaton_path.to_s, payloads_path.to_s, manifest_path.to_s).symbolize_keys
+    rescue RuntimeError => e
+      raise Error, e.message
+    end
+
+    def match_tokens(token_ids:, policy: :leftmost_longest, max: 32, rank: false, inclusive_end: false, include_pattern_id: false,
+      confidence: false, confidence_prior: nil, pinned: nil, position_frac: false, one_per_length: false,
+      sort_by: :position, match_key: false, min_salience: -Float::INFINITY, min_n: 0)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      validate_max(max)
+      pinned = Array(pinned)
+      sort_by = sort_by.to_s
+      matches = if rank || inclusive_end || include_pattern_id || confidence || pinned.any? || position_frac || one_per_length ||
+          match_key || sort_by != "position" || min_salience > -Float::INFINITY || min_n > 0
+        options = {"rank" => rank, "inclusive_end" => inclusive_end, "include_pattern_id" => include_pattern_id,
+                   "confidence" => confidence, "position_frac" => position_frac, "one_per_length" => one_per_length,
+                   "sort_by" => sort_by, "match_key" => match_key}
+        options["confidence_prior"] = confidence_prior.to_f if confidence_prior
+        options["pinned"] = pinned.map(&:to_i) if pinned.any?
+        options["min_salience"] = min_salience.to_f if min_salience > -Float::INFINITY
+        options["min_n"] = min_n.to_i if min_n > 0
+        @matcher.match_tokens_with_options(token_ids, policy.to_s, max, options)
       else
-        @vocabulary = nil
+        @matcher.match_tokens(token_ids, policy.to_s, max)
       end
+      count_match_call
+      label_categories(matches.map(&:symbolize_keys))
     end
 
-    def match_tokens(token_ids:, policy: :leftmost_longest, max: 32)
+    # match_tokens on string tokens, encoded natively with the vocab_path given
+    # to load! (the same lookup phrasekit_tag uses). For vocab_override or
+    # unk_policy, use match_text_tokens.
+    def match_strings(tokens:, policy: :leftmost_longest, max: 32)
       raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
-      @matcher.match_tokens(token_ids, policy.to_s, max).map(&:symbolize_keys)
+      validate_max(max)
+      matches = begin
+        @matcher.match_strings(tokens.map(&:to_s), policy.to_s, max)
+      rescue RuntimeError => e
+        raise Error, e.message
+      end
+      count_match_call
+      label_categories(matches.map(&:symbolize_keys))
     end
 
-    def encode_tokens(tokens)
-      raise Error, "Vocabulary not loaded. Call PhraseKit.load! with vocab_path" unless @vocabulary
+    # Debugging aid: the matches match_tokens would return, as a JSONL string.
+    def match_tokens_jsonl(token_ids:, policy: :leftmost_longest, max: 32)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      validate_max(max)
+      jsonl = @matcher.match_tokens_jsonl(token_ids, policy.to_s, max)
+      count_match_call
+      jsonl
+    end
 
-      unk_id = @vocabulary[:special_tokens]["<UNK>"]
-      tokens.map do |token|
-        normalized = token.to_s.downcase
-        @vocabulary[:tokens][normalized] || unk_id
+    def match_tokens_batch_each(batch:, policy: :leftmost_longest, max: 32)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      raise ArgumentError, "match_tokens_batch_each requires a block" unless block_given?
+      validate_max(max)
+
+      @matcher.match_tokens_batch_each(batch, policy.to_s, max) do |doc_index, matches|
+        yield doc_index, label_categories(matches.map(&:symbolize_keys))
+        count_match_call
       end
+      nil
+    end
+
+    # match_tokens for every document of batch in one native call, returned
+    # as an array of per-document results in input order.
+    def match_tokens_batch(batch:, policy: :leftmost_longest, max: 32)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      validate_max(max)
+      results = @matcher.match_tokens_batch(batch, policy.to_s, max)
+      count_match_call(results.size)
+      results.map { |matches| label_categories(matches.map(&:symbolize_keys)) }
+    end
+
+    # Yields each match of one very long token sequence (a book, a log dump)
+    # as soon as the scan resolves it, instead of building the whole result
+    # array. Overlaps are resolved within a sliding window of `window` tokens
+    # (nil for the extension's default): exact for :leftmost_longest,
+    # :leftmost_first, and :all, approximate at window cuts for the others.
+    # `break` stops the scan. Returns {emitted:, window_cuts:, degraded:}.
+    def match_tokens_streaming(token_ids:, policy: :leftmost_longest, window: nil)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      raise ArgumentError, "match_tokens_streaming requires a block" unless block_given?
+      raise ArgumentError, "window must be a non-negative Integer, got #{window.inspect}" unless window.nil? || (window.is_a?(Integer) && window >= 0)
+
+      summary = @matcher.match_tokens_streaming(token_ids, policy.to_s, window) do |match|
+        yield label_categories([match.symbolize_keys]).first
+      end
+      count_match_call
+      summary.symbolize_keys
+    end
+
+    def best_match(token_ids:)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      match = @matcher.best_match(token_ids)&.symbolize_keys
+      match && label_categories([match]).first
+    end
+
+    # The payload of phrase_id (its first pattern's, if it has several), or nil.
+    def payload_for(phrase_id:)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      payload = @matcher.payload_for(phrase_id)&.symbolize_keys
+      payload && label_categories([payload]).first
     end
 
-    def match_text_tokens(tokens:, policy: :leftmost_longest, max: 32)
+    def phrase_frequencies(token_ids:)
       raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.phrase_frequencies(token_ids)
+    end
+
+    # Sorted distinct phrase_ids found in each document of batch, in input
+    # order, under a single lock; for gating a corpus on a target phrase set.
+    def contains_phrase_ids_batch(batch:)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.contains_phrase_ids_batch(batch)
+    end
+
+    # One score per phrase_id, combining the salience_score of every occurrence.
+    # agg is :sum, :max, :mean, or :count_weighted.
+    def phrase_scores(token_ids:, agg: :sum)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.phrase_scores(token_ids, agg.to_s)
+    end
+
+    # vocab_override is a token => id map consulted before the loaded vocabulary
+    # (exact form, then downcased unless the build is case-sensitive), so one
+    # matcher can serve tenants whose vocabularies differ slightly. Precedence:
+    # override, base vocabulary, <UNK>. Tokens are first brought to the build's
+    # Unicode normalization form, if any, for every lookup.
+    def encode_tokens(tokens, vocab_override: nil)
+      encode_with_unknowns(tokens, vocab_override).first
+    end
+
+    # unk_policy controls tokens the vocabulary cannot encode: :map (default)
+    # matches them as <UNK>, :break makes them barriers no match may span, and
+    # :wildcard lets them stand for any vocabulary token. :wildcard re-runs the
+    # automaton once per vocabulary entry around each unknown token, so it is
+    # far slower on documents with unknowns.
+    def match_text_tokens(tokens:, policy: :leftmost_longest, max: 32, rank: false, inclusive_end: false, vocab_override: nil,
+      unk_policy: :map)
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      validate_max(max)
       raise Error, "Vocabulary not loaded. Call PhraseKit.load! with vocab_path" unless @vocabulary
+      unk_policy = unk_policy.to_s
+      raise ArgumentError, "Invalid unk_policy: #{unk_policy}" unless UNK_POLICIES.include?(unk_policy)
+
+      token_ids, unknown_positions = encode_with_unknowns(tokens, vocab_override)
+      if unk_policy == "map" || unknown_positions.empty?
+        return match_tokens(token_ids: token_ids, policy: policy, max: max, rank: rank, inclusive_end: inclusive_end)
+      end
 
-      token_ids = encode_tokens(tokens)
-      match_tokens(token_ids: token_ids, policy: policy, max: max)
+      options = {"rank" => rank, "inclusive_end" => inclusive_end, "unk_policy" => unk_policy,
+                 "unknown_positions" => unknown_positions}
+      options["wildcard_candidates"] = vocabulary_ids if unk_policy == "wildcard"
+      matches = @matcher.match_tokens_with_options(token_ids, policy.to_s, max, options)
+      count_match_call
+      label_categories(matches.map(&:symbolize_keys))
     end
 
     def stats
@@ -63,12 +230,33 @@ module PhraseKit
       begin
         stats_hash = @matcher.stats.symbolize_keys
         stats_hash[:loaded_at] = Time.at(stats_hash[:loaded_at] / 1000.0)
+        stats_hash[:policy_hits] = stats_hash[:policy_hits].to_h { |policy, counts| [policy.to_sym, counts.symbolize_keys] }
         stats_hash
       rescue RuntimeError => e
         raise Error, e.message
       end
     end
 
+    # Zeroes the stats counters and latency percentiles, e.g. after each
+    # dashboard scrape.
+    def reset_stats
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.reset_stats
+    end
+
+    # Empties the match-result cache enabled by load!(result_cache_size:).
+    def clear_cache
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.clear_cache
+    end
+
+    # Problems a lenient (strict: false) load tolerated, plus notes about
+    # skipped checks, as strings.
+    def diagnostics
+      raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
+      @matcher.diagnostics
+    end
+
     def healthcheck
       raise Error, "PhraseKit not loaded. Call PhraseKit.load! first" unless @matcher
       begin
@@ -77,6 +265,105 @@ module PhraseKit
         raise Error, e.message
       end
     end
+
+    # Calls the block with the stats hash after every `every` match calls
+    # (match_tokens, match_text_tokens, match_tokens_jsonl, and each document of
+    # match_tokens_batch_each). Call without a block to unregister. The block runs
+    # synchronously on the thread whose call crossed the threshold, after that
+    # call's matches are computed and outside any lock, so it may call back into
+    # PhraseKit; its own match calls count toward the next snapshot.
+    def on_stats(every: nil, &block)
+      if block
+        raise ArgumentError, "every must be a positive integer" unless every.is_a?(Integer) && every.positive?
+        @stats_mutex ||= Mutex.new
+        @stats_mutex.synchronize do
+          @stats_calls = 0
+          @stats_callback = [every, block]
+        end
+      else
+        @stats_callback = nil
+      end
+      nil
+    end
+
+    # max caps the matches returned per call; 0 returns none without matching.
+    # Shared with MatcherSet#match_tokens.
+    def validate_max(max)
+      raise ArgumentError, "max must be a non-negative Integer, got #{max.inspect}" unless max.is_a?(Integer) && max >= 0
+    end
+
+    private
+
+    def read_vocabulary(vocab_path)
+      require "json"
+      vocab_data = JSON.parse(File.read(vocab_path))
+      {
+        tokens: vocab_data["tokens"],
+        special_tokens: vocab_data["special_tokens"],
+        case_sensitive_tokens: vocab_data["case_sensitive_tokens"] || {},
+        separator_id: vocab_data["separator_id"]
+      }
+    rescue => e
+      raise Error, "Failed to load vocabulary: #{e.message}"
+    end
+
+    # category_id => name from a categories.json of name => category_id.
+    def read_categories(categories_path)
+      require "json"
+      JSON.parse(File.read(categories_path)).to_h { |name, id| [id, name] }
+    rescue => e
+      raise Error, "Failed to load categories: #{e.message}"
+    end
+
+    # String#unicode_normalize form for the manifest's unicode_normalization;
+    # nil when the build kept code points as given.
+    def unicode_form(unicode_normalization)
+      unicode_normalization.to_sym unless unicode_normalization == "none"
+    end
+
+    def label_categories(matches)
+      return matches unless @categories
+      matches.each do |match|
+        category = @categories[match[:category_id]]
+        match[:category] = category if category
+      end
+    end
+
+    # Token ids plus the positions of tokens no vocabulary entry covered.
+    def encode_with_unknowns(tokens, vocab_override)
+      raise Error, "Vocabulary not loaded. Call PhraseKit.load! with vocab_path" unless @vocabulary
+
+      unk_id = @vocabulary[:special_tokens]["<UNK>"]
+      case_sensitive = @vocabulary[:case_sensitive_tokens]
+      override = vocab_override ? vocab_override.transform_keys(&:to_s) : {}
+      unknown_positions = []
+      token_ids = tokens.each_with_index.map do |token, i|
+        token = token.to_s
+        token = token.unicode_normalize(@unicode_form) if @unicode_form
+        key = @case_sensitive ? token : token.downcase
+        id = override[token] || override[key] ||
+          case_sensitive[token] || @vocabulary[:tokens][key]
+        unknown_positions << i unless id
+        id || unk_id
+      end
+      [token_ids, unknown_positions]
+    end
+
+    def vocabulary_ids
+      @vocabulary_ids ||= (@vocabulary[:tokens].values + @vocabulary[:case_sensitive_tokens].values).uniq
+    end
+
+    def count_match_call(calls = 1)
+      every, callback = @stats_callback
+      return unless callback
+
+      due = @stats_mutex.synchronize do
+        before = @stats_calls
+        @stats_calls += calls
+        @stats_calls / every > before / every
+      end
+      callback.call(stats) if due
+    end
   end
 end
 
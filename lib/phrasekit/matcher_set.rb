@@ -0,0 +1,67 @@
+module PhraseKit
+  # Several artifact builds matched over the same token IDs in one call. Each
+  # match carries the :namespace it came from; overlaps across namespaces are
+  # resolved under the policy as if all phrases were in one build.
+  class MatcherSet
+    attr_reader :namespaces
+
+    # dirs maps each namespace to a phrasekit_build output directory, in
+    # priority order for identical spans. vocab.json and categories.json are
+    # used when present. The remaining options are those of PhraseKit.load!
+    # and apply to every namespace.
+    def self.load(dirs, **options)
+      new(dirs, **options)
+    end
+
+    def initialize(dirs, lazy_payloads: false, payload_cache_size: nil, max_cluster: nil, strict: true,
+      verify_checksums: false, validate_patterns: false)
+      raise Error, "MatcherSet needs at least one artifacts directory" if dirs.empty?
+
+      @categories = {}
+      entries = dirs.map do |namespace, dir|
+        entry = {"namespace" => namespace.to_s,
+                 "automaton_path" => File.join(dir.to_s, "phrases.daac"),
+                 "payloads_path" => File.join(dir.to_s, "payloads.bin"),
+                 "manifest_path" => File.join(dir.to_s, "manifest.json"),
+                 "lazy_payloads" => lazy_payloads, "strict" => strict,
+                 "verify_checksums" => verify_checksums, "validate_patterns" => validate_patterns}
+        entry["payload_cache_size"] = payload_cache_size if payload_cache_size
+        entry["max_cluster"] = max_cluster if max_cluster
+        vocab_path = File.join(dir.to_s, "vocab.json")
+        entry["vocab_path"] = vocab_path if File.exist?(vocab_path)
+        categories_path = File.join(dir.to_s, "categories.json")
+        @categories[namespace.to_s] = read_categories(categories_path) if File.exist?(categories_path)
+        entry
+      end
+
+      @native = begin
+        NativeMatcherSet.load(entries)
+      rescue RuntimeError => e
+        raise Error, e.message
+      end
+      @namespaces = @native.namespaces
+    end
+
+    def match_tokens(token_ids:, policy: :leftmost_longest, max: 32, min_salience: -Float::INFINITY, min_n: 0)
+      PhraseKit.validate_max(max)
+
+      options = {}
+      options["min_salience"] = min_salience.to_f if min_salience > -Float::INFINITY
+      options["min_n"] = min_n.to_i if min_n > 0
+      matches = @native.match_tokens(token_ids, policy.to_s, max, options).map(&:symbolize_keys)
+      matches.each do |match|
+        category = @categories.dig(match[:namespace], match[:category_id])
+        match[:category] = category if category
+      end
+    end
+
+    private
+
+    def read_categories(categories_path)
+      require "json"
+      JSON.parse(File.read(categories_path)).to_h { |name, id| [id, name] }
+    rescue => e
+      raise Error, "Failed to load categories: #{e.message}"
+    end
+  end
+end
@@ -5,7 +5,9 @@ module PhraseKit
     class Error < StandardError; end
 
     class << self
-      def mine(input_path:, output_path:, min_n: 2, max_n: 5, min_count: 10, config_path: nil)
+      def mine(input_path:, output_path:, min_n: 2, max_n: 5, min_count: 10, max_count: nil, top_k: nil, max_skip: 0,
+        stopwords_path: nil, threads: 0, sketch: false, sketch_width: nil, sketch_depth: nil, config_path: nil, dedup: nil, dedup_threshold: nil, case_sensitive: false,
+        unicode_normalization: nil)
         binary_path = find_binary
 
         # Create temporary config if not provided
@@ -13,18 +15,32 @@ module PhraseKit
           require "tempfile"
           require "json"
 
-          config_file = Tempfile.new(["mine_config", ".json"])
-          config_file.write(JSON.generate({
+          config = {
             min_n: min_n,
             max_n: max_n,
-            min_count: min_count
-          }))
+            min_count: min_count,
+            max_skip: max_skip,
+            threads: threads
+          }
+          config[:max_count] = max_count if max_count
+          config[:top_k] = top_k if top_k
+          config[:stopwords_path] = stopwords_path.to_s if stopwords_path
+          config[:sketch] = true if sketch
+          config[:sketch_width] = sketch_width if sketch_width
+          config[:sketch_depth] = sketch_depth if sketch_depth
+          config[:case_sensitive] = true if case_sensitive
+          config[:unicode_normalization] = unicode_normalization.to_s if unicode_normalization
+
+          config_file = Tempfile.new(["mine_config", ".json"])
+          config_file.write(JSON.generate(config))
           config_file.flush
           config_path = config_file.path
         end
 
         # Run mining
         cmd = [binary_path, input_path.to_s, config_path.to_s, output_path.to_s]
+        cmd += ["--dedup", dedup.to_s] if dedup
+        cmd += ["--dedup-threshold", dedup_threshold.to_s] if dedup_threshold
         output = `#{cmd.shelljoin} 2>&1`
 
         unless $?.success?
@@ -63,8 +79,14 @@ module PhraseKit
         stats = {}
 
         output.scan(/Total documents:\s+(\d+)/) { stats[:total_docs] = $1.to_i }
+        output.scan(/Duplicates dropped:\s+(\d+)/) { stats[:duplicate_docs] = $1.to_i }
         output.scan(/Total tokens:\s+(\d+)/) { stats[:total_tokens] = $1.to_i }
+        output.scan(/Skip-grams extracted:\s+(\d+)/) { stats[:skip_grams_extracted] = $1.to_i }
+        output.scan(/Boundary stopwords:\s+(\d+)/) { stats[:filtered_boundary_stopword] = $1.to_i }
         output.scan(/Unique n-grams:\s+(\d+)/) { stats[:unique_ngrams] = $1.to_i }
+        output.scan(/Sketch candidates:\s+(\d+)/) { stats[:sketch_candidates] = $1.to_i }
+        output.scan(/Over max_count=\d+:\s+(\d+)/) { stats[:filtered_max_count] = $1.to_i }
+        output.scan(/Beyond top_k=\d+:\s+(\d+)/) { stats[:filtered_top_k] = $1.to_i }
         output.scan(/After min_count=\d+:\s+(\d+)/) { stats[:ngrams_after_filter] = $1.to_i }
 
         stats
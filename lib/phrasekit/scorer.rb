@@ -14,6 +14,11 @@ module PhraseKit
         min_domain_count: 10,
         assign_phrase_ids: true,
         starting_phrase_id: 1000,
+        smoothing: 1.0,
+        normalize_by_corpus_size: false,
+        unseen_pmi: 10.0,
+        case_sensitive: false,
+        unicode_normalization: :none,
         config_path: nil
       )
         binary_path = find_binary
@@ -29,7 +34,12 @@ module PhraseKit
             min_salience: min_salience,
             min_domain_count: min_domain_count,
             assign_phrase_ids: assign_phrase_ids,
-            starting_phrase_id: starting_phrase_id
+            starting_phrase_id: starting_phrase_id,
+            smoothing: smoothing,
+            normalize_by_corpus_size: normalize_by_corpus_size,
+            unseen_pmi: unseen_pmi,
+            case_sensitive: case_sensitive,
+            unicode_normalization: unicode_normalization.to_s
           }))
           config_file.flush
           config_path = config_file.path
@@ -13,10 +13,23 @@ module PhraseKit
         payloads_path: nil,
         manifest_path: nil,
         vocab_path: nil,
+        categories_path: nil,
         policy: :leftmost_longest,
         max_spans: 100,
         label: "PHRASE",
-        config_path: nil
+        threads: 1,
+        read_ahead: 1024,
+        inclusive_end: false,
+        max_doc_tokens: nil,
+        omit_oversized: false,
+        emit_text: false,
+        output_format: :spans,
+        unk_mode: :unk,
+        case_sensitive: nil,
+        config_path: nil,
+        oov_report: false,
+        oov_output_path: nil,
+        allow_version_mismatch: false
       )
         binary_path = find_binary
 
@@ -29,22 +42,36 @@ module PhraseKit
             payloads_path ||= File.join(artifacts_dir, "payloads.bin")
             manifest_path ||= File.join(artifacts_dir, "manifest.json")
             vocab_path ||= File.join(artifacts_dir, "vocab.json")
+            default_categories = File.join(artifacts_dir, "categories.json")
+            categories_path ||= default_categories if File.exist?(default_categories)
           end
 
           unless automaton_path && payloads_path && manifest_path && vocab_path
             raise Error, "Must provide either artifacts_dir or all artifact paths"
           end
 
-          config_file = Tempfile.new(["tag_config", ".json"])
-          config_file.write(JSON.generate({
+          config = {
             automaton_path: automaton_path.to_s,
             payloads_path: payloads_path.to_s,
             manifest_path: manifest_path.to_s,
             vocab_path: vocab_path.to_s,
             policy: policy.to_s,
             max_spans: max_spans,
-            label: label.to_s
-          }))
+            label: label.to_s,
+            threads: threads,
+            read_ahead: read_ahead,
+            inclusive_end: inclusive_end,
+            omit_oversized: omit_oversized,
+            emit_text: emit_text,
+            output_format: output_format.to_s,
+            unk_mode: unk_mode.to_s
+          }
+          config[:max_doc_tokens] = max_doc_tokens if max_doc_tokens
+          config[:categories_path] = categories_path.to_s if categories_path
+          config[:case_sensitive] = case_sensitive unless case_sensitive.nil?
+
+          config_file = Tempfile.new(["tag_config", ".json"])
+          config_file.write(JSON.generate(config))
           config_file.flush
           config_path = config_file.path
         end
@@ -55,6 +82,9 @@ module PhraseKit
           config_path.to_s,
           output_path.to_s
         ]
+        cmd << "--oov-report" if oov_report
+        cmd.push("--oov-output", oov_output_path.to_s) if oov_output_path
+        cmd << "--allow-version-mismatch" if allow_version_mismatch
         output = `#{cmd.shelljoin} 2>&1`
 
         unless $?.success?
@@ -91,7 +121,10 @@ module PhraseKit
         output.scan(/Documents:\s+(\d+)/) { stats[:documents] = $1.to_i }
         output.scan(/Total spans:\s+(\d+)/) { stats[:total_spans] = $1.to_i }
         output.scan(/Documents with spans:\s+(\d+)/) { stats[:docs_with_spans] = $1.to_i }
+        output.scan(/Skipped oversized:\s+(\d+)/) { stats[:skipped_oversized] = $1.to_i }
         output.scan(/Avg spans per document:\s+([\d.]+)/) { stats[:avg_spans_per_doc] = $1.to_f }
+        output.scan(/OOV tokens:\s+(\d+)/) { stats[:oov_tokens] = $1.to_i }
+        output.scan(/OOV rate:\s+([\d.]+)%/) { stats[:oov_rate] = $1.to_f / 100.0 }
 
         stats
       end
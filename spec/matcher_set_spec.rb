@@ -0,0 +1,37 @@
+require "spec_helper"
+require "phrasekit"
+
+RSpec.describe PhraseKit::MatcherSet do
+  let(:set) { PhraseKit::MatcherSet.load({medical: "spec/fixtures", legal: "spec/fixtures"}) }
+
+  it "lists namespaces in load order" do
+    expect(set.namespaces).to eq(["medical", "legal"])
+  end
+
+  it "tags matches with their namespace and keeps the first of identical spans" do
+    matches = set.match_tokens(token_ids: [100, 101, 102])
+    expect(matches.size).to eq(1)
+    expect(matches.first).to include(phrase_id: 300, n: 3, namespace: "medical", category: "method")
+  end
+
+  it "returns every namespace's match under :all" do
+    matches = set.match_tokens(token_ids: [100, 101, 102], policy: :all)
+    expect(matches.map { |m| m[:namespace] }.uniq).to eq(["medical", "legal"])
+  end
+
+  it "returns nothing for max: 0 or empty input" do
+    expect(set.match_tokens(token_ids: [100, 101, 102], max: 0)).to eq([])
+    expect(set.match_tokens(token_ids: [])).to eq([])
+    expect { set.match_tokens(token_ids: [100, 101], max: -1) }.to raise_error(ArgumentError, /max must be/)
+  end
+
+  it "rejects duplicate namespaces and missing artifacts" do
+    expect { PhraseKit::MatcherSet.load({}) }.to raise_error(PhraseKit::Error, /at least one/)
+    expect {
+      PhraseKit::MatcherSet.load({"a" => "spec/fixtures", :a => "spec/fixtures"})
+    }.to raise_error(PhraseKit::Error, /duplicate namespace/)
+    expect {
+      PhraseKit::MatcherSet.load({medical: "spec/missing"})
+    }.to raise_error(PhraseKit::Error, /Failed to load matcher/)
+  end
+end
@@ -52,6 +52,133 @@ RSpec.describe PhraseKit::Miner do
         expect(stats[:ngrams_after_filter]).to be > 0
       end
 
+      it "drops duplicate documents with dedup" do
+        temp_corpus.puts('{"tokens":["Rat","CDK10","oligo"],"doc_id":"5"}')
+        temp_corpus.flush
+
+        stats = PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 2,
+          min_count: 1,
+          dedup: :exact
+        )
+
+        expect(stats[:total_docs]).to eq(5)
+        expect(stats[:duplicate_docs]).to eq(1)
+
+        counts = File.readlines(temp_output.path).map { |l| JSON.parse(l) }.to_h { |ng| [ng["tokens"], ng["count"]] }
+        expect(counts[["rat", "cdk10"]]).to eq(3)
+      end
+
+      it "aggregates skip-grams with max_skip" do
+        temp_corpus.puts('{"tokens":["rat","p53","oligo"],"doc_id":"5"}')
+        temp_corpus.flush
+
+        stats = PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 3,
+          min_count: 1,
+          max_skip: 1
+        )
+
+        expect(stats[:skip_grams_extracted]).to eq(3)
+
+        counts = File.readlines(temp_output.path).map { |l| JSON.parse(l) }.to_h { |ng| [ng["tokens"], ng["count"]] }
+        expect(counts[["rat", "*", "oligo"]]).to eq(2)
+        expect(counts[["rat", "*", "protein"]]).to eq(1)
+      end
+
+      it "skips n-grams with a boundary stopword" do
+        stopwords = Tempfile.new(["stopwords", ".txt"])
+        stopwords.puts("Oligo")
+        stopwords.flush
+
+        stats = PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 3,
+          min_count: 1,
+          stopwords_path: stopwords.path
+        )
+
+        expect(stats[:filtered_boundary_stopword]).to eq(2)
+        ngrams = File.readlines(temp_output.path).map { |l| JSON.parse(l)["tokens"] }
+        expect(ngrams).not_to include(["cdk10", "oligo"], ["rat", "cdk10", "oligo"])
+        expect(ngrams).to include(["rat", "cdk10"])
+      ensure
+        stopwords&.close!
+      end
+
+      it "caps n-gram frequency and keeps the top_k most frequent" do
+        all = Tempfile.new(["all", ".jsonl"])
+        PhraseKit::Miner.mine(input_path: temp_corpus.path, output_path: all.path, min_n: 2, max_n: 3, min_count: 1)
+        counts = File.readlines(all.path).map { |l| JSON.parse(l)["count"] }
+        cap = counts.max - 1
+
+        stats = PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 3,
+          min_count: 1,
+          max_count: cap,
+          top_k: 2
+        )
+
+        kept = File.readlines(temp_output.path).map { |l| JSON.parse(l)["count"] }
+        expect(kept.size).to eq(2)
+        expect(kept.max).to be <= cap
+        expect(stats[:filtered_max_count]).to eq(counts.count { |c| c > cap })
+        expect(stats[:filtered_top_k]).to eq(counts.count { |c| c <= cap } - 2)
+        expect(stats[:ngrams_after_filter]).to eq(2)
+      ensure
+        all&.close!
+      end
+
+      it "keeps token case with case_sensitive" do
+        temp_corpus.puts('{"tokens":["Rat","CDK10"],"doc_id":"5"}')
+        temp_corpus.flush
+
+        PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 2,
+          min_count: 1,
+          case_sensitive: true
+        )
+
+        counts = File.readlines(temp_output.path).map { |l| JSON.parse(l) }.to_h { |ng| [ng["tokens"], ng["count"]] }
+        expect(counts[["rat", "cdk10"]]).to eq(3)
+        expect(counts[["Rat", "CDK10"]]).to eq(1)
+      end
+
+      it "mines the same frequent n-grams with a count-min sketch" do
+        exact = Tempfile.new(["exact", ".jsonl"])
+        PhraseKit::Miner.mine(input_path: temp_corpus.path, output_path: exact.path, min_n: 2, max_n: 3, min_count: 2)
+
+        stats = PhraseKit::Miner.mine(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          min_n: 2,
+          max_n: 3,
+          min_count: 2,
+          sketch: true,
+          sketch_width: 1024,
+          sketch_depth: 3
+        )
+
+        expect(stats[:sketch_candidates]).to be >= stats[:ngrams_after_filter]
+        expect(File.read(temp_output.path)).to eq(File.read(exact.path))
+      ensure
+        exact&.close!
+      end
+
       it "produces valid output" do
         PhraseKit::Miner.mine(
           input_path: temp_corpus.path,
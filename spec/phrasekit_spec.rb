@@ -26,6 +26,127 @@ RSpec.describe PhraseKit do
       PhraseKit.load!(**test_paths)
       expect { PhraseKit.healthcheck }.not_to raise_error
     end
+
+    it "supports lazy payload loading" do
+      PhraseKit.load!(**test_paths, lazy_payloads: true, payload_cache_size: 2)
+      expect(PhraseKit.stats[:lazy_payloads]).to be true
+
+      matches = PhraseKit.match_tokens(token_ids: [100, 101, 102])
+      expect(matches.first).to include(phrase_id: 300, n: 3)
+    end
+  end
+
+  describe ".reload!" do
+    let(:test_paths) do
+      {
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      }
+    end
+
+    it "returns the new version and keeps the load options" do
+      require "json"
+      PhraseKit.load!(**test_paths, lazy_payloads: true)
+      version = JSON.parse(File.read(test_paths[:manifest_path]))["version"]
+
+      expect(PhraseKit.reload!(**test_paths)).to eq(version)
+      expect(PhraseKit.stats[:lazy_payloads]).to be true
+      expect(PhraseKit.match_tokens(token_ids: [100, 101, 102]).first[:phrase_id]).to eq(300)
+    end
+
+    it "leaves the old artifacts loaded when the new ones fail" do
+      PhraseKit.load!(**test_paths)
+
+      expect {
+        PhraseKit.reload!(**test_paths, automaton_path: "spec/fixtures/missing.daac")
+      }.to raise_error(PhraseKit::Error, /Failed to reload matcher/)
+      expect(PhraseKit.match_tokens(token_ids: [100, 101, 102]).first[:phrase_id]).to eq(300)
+    end
+  end
+
+  describe "categories" do
+    let(:test_paths) do
+      {
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      }
+    end
+
+    it "reports category_id, and the name with categories_path" do
+      PhraseKit.load!(**test_paths)
+      match = PhraseKit.match_tokens(token_ids: [100, 101, 102]).first
+      expect(match[:category_id]).to eq(1)
+      expect(match).not_to have_key(:category)
+
+      PhraseKit.load!(**test_paths, categories_path: "spec/fixtures/categories.json")
+      expect(PhraseKit.match_tokens(token_ids: [100, 101, 102]).first[:category]).to eq("method")
+      expect(PhraseKit.match_tokens(token_ids: [100, 101]).first).not_to have_key(:category_id)
+    end
+  end
+
+  describe "validation modes" do
+    let(:inconsistent_manifest) do
+      require "json"
+      require "tempfile"
+      manifest = JSON.parse(File.read("spec/fixtures/manifest.json"))
+      manifest["num_patterns"] += 1
+      Tempfile.new(["manifest", ".json"]).tap do |file|
+        file.write(JSON.generate(manifest))
+        file.flush
+      end
+    end
+
+    let(:paths) do
+      {
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: inconsistent_manifest.path
+      }
+    end
+
+    after { inconsistent_manifest.close! }
+
+    it "rejects inconsistent artifacts by default" do
+      expect { PhraseKit.load!(**paths) }.to raise_error(PhraseKit::Error, /Payload count mismatch/)
+    end
+
+    it "loads them with diagnostics when lenient" do
+      PhraseKit.load!(**paths, strict: false)
+
+      expect(PhraseKit.diagnostics).to contain_exactly(a_string_matching(/Payload count mismatch/))
+      expect(PhraseKit.match_tokens(token_ids: [100, 101, 102]).first[:phrase_id]).to eq(300)
+    end
+
+    it "has no diagnostics after a clean strict load" do
+      PhraseKit.load!(**paths.merge(manifest_path: "spec/fixtures/manifest.json"), validate_patterns: true)
+      expect(PhraseKit.diagnostics).to be_empty
+    end
+  end
+
+  describe ".estimate_memory" do
+    it "estimates from file sizes without loading" do
+      estimate = PhraseKit.estimate_memory(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+
+      require "json"
+      format_version = JSON.parse(File.read("spec/fixtures/manifest.json")).fetch("payload_format_version", 1)
+      record_size = {1 => 17, 2 => 21, 3 => 23}.fetch(format_version)
+      expect(estimate[:payload_count]).to eq(File.size("spec/fixtures/payloads.bin") / record_size)
+      expect(estimate[:automaton_mb]).to be_within(1e-9).of(File.size("spec/fixtures/phrases.daac") / 1_048_576.0)
+      expect(estimate[:total_mb]).to be_within(1e-9).of(estimate[:automaton_mb] + estimate[:payloads_mb])
+    end
+
+    it "raises for missing files" do
+      expect {
+        PhraseKit.estimate_memory(automaton_path: "spec/fixtures/missing.daac", payloads_path: "spec/fixtures/payloads.bin",
+                                  manifest_path: "spec/fixtures/manifest.json")
+      }.to raise_error(PhraseKit::Error, /estimate memory/)
+    end
   end
 
   describe ".match_tokens" do
@@ -42,17 +163,29 @@ RSpec.describe PhraseKit do
     end
 
     it "accepts policy parameter" do
-      [:leftmost_longest, :leftmost_first, :salience_max].each do |policy|
+      [:leftmost_longest, :leftmost_first, :salience_max, :count_max, :min_spans, :all].each do |policy|
         result = PhraseKit.match_tokens(token_ids: [1, 2, 3], policy: policy)
         expect(result).to be_an(Array)
       end
     end
 
+    it "returns overlapping matches under the all policy" do
+      matches = PhraseKit.match_tokens(token_ids: [100, 101, 102], policy: :all)
+      expect(matches.map { |m| [m[:start], m[:end], m[:phrase_id]] }).to eq([[0, 2, 100], [0, 3, 300]])
+    end
+
     it "accepts max parameter" do
       result = PhraseKit.match_tokens(token_ids: [1, 2, 3], max: 10)
       expect(result).to be_an(Array)
     end
 
+    it "returns nothing for max: 0 and rejects an invalid max" do
+      expect(PhraseKit.match_tokens(token_ids: [100, 101, 102], max: 0)).to eq([])
+      expect(PhraseKit.match_tokens_batch(batch: [[100, 101], []], max: 0)).to eq([[], []])
+      expect { PhraseKit.match_tokens(token_ids: [100, 101], max: -1) }.to raise_error(ArgumentError, /max must be a non-negative Integer/)
+      expect { PhraseKit.match_tokens(token_ids: [100, 101], max: nil) }.to raise_error(ArgumentError, /got nil/)
+    end
+
     it "returns array of matches" do
       result = PhraseKit.match_tokens(token_ids: [1, 2, 3])
       expect(result).to be_an(Array)
@@ -84,6 +217,183 @@ RSpec.describe PhraseKit do
       end
     end
 
+    describe "ranked matches" do
+      it "omits rank fields by default" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 50, 200, 101])
+        expect(matches.first).not_to have_key(:rank)
+      end
+
+      it "adds rank and score_normalized when requested" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 50, 200, 101], rank: true)
+
+        expect(matches.map { |m| m[:rank] }).to contain_exactly(0, 1)
+        expect(matches.map { |m| m[:score_normalized] }).to all(be_between(0.0, 1.0))
+
+        top = matches.find { |m| m[:rank] == 0 }
+        expect(top[:salience]).to eq(matches.map { |m| m[:salience] }.max)
+        expect(top[:score_normalized]).to eq(1.0)
+      end
+    end
+
+    describe "include_pattern_id" do
+      it "omits pattern_id by default" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101])
+        expect(matches.first).not_to have_key(:pattern_id)
+      end
+
+      it "reports the build-order pattern index" do
+        # Fixture patterns in build order: [100, 101], [200, 101], [100, 101, 102]
+        matches = PhraseKit.match_tokens(token_ids: [200, 101, 50, 100, 101, 102], include_pattern_id: true)
+        expect(matches.map { |m| [m[:phrase_id], m[:pattern_id]] }).to eq([[200, 1], [300, 2]])
+      end
+    end
+
+    it "omits idf for artifacts built without document frequencies" do
+      expect(PhraseKit.match_tokens(token_ids: [100, 101]).first).not_to have_key(:idf)
+    end
+
+    describe "confidence" do
+      it "omits confidence by default" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101])
+        expect(matches.first).not_to have_key(:confidence)
+      end
+
+      it "shrinks salience by count" do
+        match = PhraseKit.match_tokens(token_ids: [100, 101, 102], confidence: true).first
+        expected = match[:salience] * match[:count] / (match[:count] + 10.0)
+        expect(match[:confidence]).to be_within(1e-4).of(expected)
+      end
+
+      it "accepts a custom prior" do
+        match = PhraseKit.match_tokens(token_ids: [100, 101, 102], confidence: true, confidence_prior: 0).first
+        expect(match[:confidence]).to be_within(1e-4).of(match[:salience])
+      end
+    end
+
+    describe "pinned" do
+      it "lets a pinned shorter phrase beat a longer one" do
+        expect(PhraseKit.match_tokens(token_ids: [100, 101, 102]).map { |m| m[:phrase_id] }).to eq([300])
+
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 102], pinned: [100])
+        expect(matches.map { |m| m[:phrase_id] }).to eq([100])
+      end
+
+      it "applies under every policy" do
+        [:leftmost_longest, :leftmost_first, :salience_max].each do |policy|
+          matches = PhraseKit.match_tokens(token_ids: [100, 101, 102], policy: policy, pinned: [100])
+          expect(matches.first[:phrase_id]).to eq(100)
+        end
+      end
+    end
+
+    describe ".match_tokens_jsonl" do
+      it "serializes the same matches one per line" do
+        require "json"
+        token_ids = [200, 101, 50, 100, 101, 102]
+        lines = PhraseKit.match_tokens_jsonl(token_ids: token_ids).lines.map { |l| JSON.parse(l, symbolize_names: true) }
+        expected = PhraseKit.match_tokens(token_ids: token_ids)
+
+        expect(lines.map { |l| l.slice(:start, :end, :phrase_id, :count, :n) })
+          .to eq(expected.map { |m| m.slice(:start, :end, :phrase_id, :count, :n) })
+        expect(lines.map { |l| l[:pattern_id] }).to eq([1, 2])
+      end
+
+      it "returns an empty string without matches" do
+        expect(PhraseKit.match_tokens_jsonl(token_ids: [1, 2, 3])).to eq("")
+      end
+    end
+
+    describe "position_frac" do
+      it "reports start as a fraction of document length" do
+        matches = PhraseKit.match_tokens(token_ids: [200, 101, 50, 100, 101, 102, 7, 8], position_frac: true)
+        expect(matches.map { |m| m[:position_frac] }).to eq([0.0, 0.375])
+      end
+
+      it "omits position_frac by default" do
+        expect(PhraseKit.match_tokens(token_ids: [100, 101]).first).not_to have_key(:position_frac)
+      end
+    end
+
+    describe "match_key" do
+      it "keys the same phrase and tokens identically across documents" do
+        first = PhraseKit.match_tokens(token_ids: [100, 101], match_key: true).first
+        second = PhraseKit.match_tokens(token_ids: [7, 8, 100, 101], match_key: true).first
+        expect(first[:match_key]).to be_a(Integer)
+        expect(first[:match_key]).to eq(second[:match_key])
+      end
+
+      it "differs between phrases" do
+        keys = PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101], match_key: true).map { |m| m[:match_key] }
+        expect(keys.uniq.size).to eq(2)
+      end
+    end
+
+    describe "sort_by" do
+      it "orders by salience score while keeping positions" do
+        matches = PhraseKit.match_tokens(token_ids: [200, 101, 9, 100, 101], sort_by: :salience)
+        expect(matches.map { |m| [m[:phrase_id], m[:start], m[:end]] }).to eq([[100, 3, 5], [200, 0, 2]])
+      end
+
+      it "defaults to position order" do
+        matches = PhraseKit.match_tokens(token_ids: [200, 101, 9, 100, 101])
+        expect(matches.map { |m| m[:phrase_id] }).to eq([200, 100])
+      end
+
+      it "rejects unknown orderings" do
+        expect { PhraseKit.match_tokens(token_ids: [100, 101], sort_by: :length) }.to raise_error(ArgumentError, /Invalid sort_by/)
+      end
+    end
+
+    describe "one_per_length" do
+      it "keeps the first match of each length" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101, 9, 100, 101, 102], one_per_length: true)
+        expect(matches.map { |m| m[:phrase_id] }).to eq([100, 300])
+      end
+
+      it "applies max to the distinct lengths" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101, 9, 100, 101, 102], one_per_length: true, max: 2)
+        expect(matches.map { |m| m[:phrase_id] }).to eq([100, 300])
+      end
+    end
+
+    describe "min_salience" do
+      it "keeps every match by default" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101])
+        expect(matches.map { |m| m[:phrase_id] }).to eq([100, 200])
+      end
+
+      it "drops matches below the threshold" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101], min_salience: 2.2)
+        expect(matches.map { |m| m[:phrase_id] }).to eq([100])
+        expect(PhraseKit.match_tokens(token_ids: [100, 101, 102], min_salience: 3.5)).to be_empty
+      end
+    end
+
+    describe "min_n" do
+      it "drops matches shorter than min_n before resolution" do
+        expect(PhraseKit.match_tokens(token_ids: [100, 101, 102], min_n: 3).map { |m| m[:phrase_id] }).to eq([300])
+        expect(PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101], min_n: 3)).to be_empty
+      end
+    end
+
+    describe "inclusive_end" do
+      it "reports exclusive ends by default" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 102])
+        expect(matches.first).to include(start: 0, end: 3)
+      end
+
+      it "reports the last token index for a multi-token span" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 102], inclusive_end: true)
+        expect(matches.first).to include(start: 0, end: 2, n: 3)
+      end
+
+      it "leaves start unchanged and can be combined with rank" do
+        matches = PhraseKit.match_tokens(token_ids: [100, 101, 50, 200, 101], inclusive_end: true, rank: true)
+        expect(matches.map { |m| [m[:start], m[:end]] }).to eq([[0, 1], [3, 4]])
+        expect(matches.map { |m| m[:rank] }).to contain_exactly(0, 1)
+      end
+    end
+
     describe "matching policies" do
       let(:overlapping_tokens) { [100, 101, 102] }
 
@@ -142,6 +452,211 @@ RSpec.describe PhraseKit do
     end
   end
 
+  describe ".match_tokens_batch_each" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    let(:batch) { [[100, 101], [999999], [200, 101, 50, 100, 101, 102]] }
+
+    it "yields each document's matches in input order" do
+      yielded = []
+      PhraseKit.match_tokens_batch_each(batch: batch) { |index, matches| yielded << [index, matches] }
+
+      expect(yielded.map(&:first)).to eq([0, 1, 2])
+      batch.each_with_index do |token_ids, i|
+        expect(yielded[i][1]).to eq(PhraseKit.match_tokens(token_ids: token_ids))
+      end
+    end
+
+    it "returns nil" do
+      expect(PhraseKit.match_tokens_batch_each(batch: batch) { |_, _| }).to be_nil
+    end
+
+    it "requires a block" do
+      expect { PhraseKit.match_tokens_batch_each(batch: batch) }.to raise_error(ArgumentError)
+    end
+  end
+
+  describe ".match_tokens_streaming" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    let(:token_ids) { [200, 101, 50] + [100, 101, 102, 7] * 200 }
+
+    it "yields the same matches as match_tokens" do
+      [:leftmost_longest, :all].each do |policy|
+        yielded = []
+        summary = PhraseKit.match_tokens_streaming(token_ids: token_ids, policy: policy, window: 8) { |match| yielded << match }
+        expect(yielded).to eq(PhraseKit.match_tokens(token_ids: token_ids, policy: policy, max: token_ids.size))
+        expect(summary).to include(emitted: yielded.size, degraded: false)
+      end
+    end
+
+    it "uses the native default window when none is given" do
+      yielded = []
+      summary = PhraseKit.match_tokens_streaming(token_ids: token_ids) { |match| yielded << match }
+      expect(yielded).to eq(PhraseKit.match_tokens(token_ids: token_ids, max: token_ids.size))
+      expect(summary).to include(emitted: yielded.size, window_cuts: 0)
+    end
+
+    it "stops the scan on break" do
+      yielded = []
+      PhraseKit.match_tokens_streaming(token_ids: token_ids) do |match|
+        yielded << match
+        break if yielded.size == 3
+      end
+      expect(yielded.size).to eq(3)
+    end
+
+    it "requires a block and a valid window" do
+      expect { PhraseKit.match_tokens_streaming(token_ids: token_ids) }.to raise_error(ArgumentError, /requires a block/)
+      expect { PhraseKit.match_tokens_streaming(token_ids: token_ids, window: -1) {} }.to raise_error(ArgumentError, /window/)
+    end
+  end
+
+  describe ".match_tokens_batch" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    let(:batch) { [[100, 101], [999999], [200, 101, 50, 100, 101, 102]] }
+
+    it "returns each document's matches in input order" do
+      results = PhraseKit.match_tokens_batch(batch: batch)
+
+      expect(results.size).to eq(3)
+      batch.each_with_index do |token_ids, i|
+        expect(results[i]).to eq(PhraseKit.match_tokens(token_ids: token_ids))
+      end
+    end
+
+    it "counts one match call per document" do
+      before = PhraseKit.stats[:match_calls]
+      PhraseKit.match_tokens_batch(batch: batch)
+      expect(PhraseKit.stats[:match_calls] - before).to eq(3)
+    end
+
+    it "rejects an invalid policy" do
+      expect { PhraseKit.match_tokens_batch(batch: batch, policy: :bogus) }.to raise_error(ArgumentError)
+    end
+  end
+
+  describe ".best_match" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    it "returns the highest-salience match regardless of overlap" do
+      match = PhraseKit.best_match(token_ids: [100, 101, 102, 200, 101])
+      expect(match).to include(phrase_id: 300, start: 0, end: 3)
+    end
+
+    it "returns nil when nothing matches" do
+      expect(PhraseKit.best_match(token_ids: [999999])).to be_nil
+    end
+  end
+
+  describe ".payload_for" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    it "returns the payload of a phrase_id without matching" do
+      expect(PhraseKit.payload_for(phrase_id: 200)).to include(phrase_id: 200, salience: 2.0, count: 100, n: 2)
+      expect(PhraseKit.payload_for(phrase_id: 200)).not_to have_key(:start)
+    end
+
+    it "returns nil for an unknown phrase_id" do
+      expect(PhraseKit.payload_for(phrase_id: 999)).to be_nil
+    end
+  end
+
+  describe ".phrase_frequencies" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    it "counts every occurrence of repeated phrases" do
+      frequencies = PhraseKit.phrase_frequencies(token_ids: [200, 101, 50, 200, 101, 200, 101])
+      expect(frequencies).to eq(200 => 3)
+    end
+
+    it "counts overlapping phrases without resolving them" do
+      frequencies = PhraseKit.phrase_frequencies(token_ids: [100, 101, 102])
+      expect(frequencies.keys).to include(100, 300)
+    end
+
+    it "returns an empty hash when nothing matches" do
+      expect(PhraseKit.phrase_frequencies(token_ids: [999999])).to eq({})
+    end
+  end
+
+  describe ".contains_phrase_ids_batch" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    it "returns the phrase_ids of each document in input order" do
+      batch = [[200, 101, 9, 200, 101], [999999], [100, 101, 102]]
+      expect(PhraseKit.contains_phrase_ids_batch(batch: batch)).to eq([[200], [], [100, 300]])
+    end
+  end
+
+  describe ".phrase_scores" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    # Phrase 200 matches twice
+    let(:token_ids) { [200, 101, 50, 200, 101] }
+    let(:single) { PhraseKit.phrase_scores(token_ids: [200, 101], agg: :max)[200] }
+
+    it "aggregates repeated occurrences by each mode" do
+      expect(PhraseKit.phrase_scores(token_ids: token_ids, agg: :sum)[200]).to be_within(1e-4).of(2 * single)
+      expect(PhraseKit.phrase_scores(token_ids: token_ids, agg: :max)[200]).to be_within(1e-4).of(single)
+      expect(PhraseKit.phrase_scores(token_ids: token_ids, agg: :mean)[200]).to be_within(1e-4).of(single)
+      expect(PhraseKit.phrase_scores(token_ids: token_ids, agg: :count_weighted)[200]).to be_within(1e-4).of(single * Math.log(3))
+    end
+
+    it "rejects unknown aggregations" do
+      expect { PhraseKit.phrase_scores(token_ids: token_ids, agg: :median) }.to raise_error(ArgumentError, /Invalid aggregation/)
+    end
+  end
+
   describe ".stats" do
     context "when not loaded" do
       before { PhraseKit.instance_variable_set(:@matcher, nil) }
@@ -173,10 +688,124 @@ RSpec.describe PhraseKit do
         )
       end
 
+      it "reports the automaton's pattern count alongside the payload count" do
+        stats = PhraseKit.stats
+        expect(stats[:automaton_patterns]).to eq(stats[:num_patterns])
+      end
+
       it "includes manifest version" do
         stats = PhraseKit.stats
         expect(stats[:version]).not_to be_nil
       end
+
+      it "counts match calls and hits" do
+        before = PhraseKit.stats
+        PhraseKit.match_tokens(token_ids: [100, 101, 9, 200, 101])
+        after = PhraseKit.stats
+        expect(after[:match_calls] - before[:match_calls]).to eq(1)
+        expect(after[:hits_total] - before[:hits_total]).to eq(2)
+      end
+
+      it "reports latency percentiles once calls are made" do
+        PhraseKit.match_tokens(token_ids: [100, 101])
+        stats = PhraseKit.stats
+        expect(stats[:p50_us]).to be <= stats[:p95_us]
+        expect(stats[:p95_us]).to be <= stats[:p99_us]
+      end
+
+      it "resets counters" do
+        PhraseKit.match_tokens(token_ids: [100, 101])
+        PhraseKit.reset_stats
+        expect(PhraseKit.stats).to include(match_calls: 0, hits_total: 0, p50_us: 0, p99_us: 0)
+      end
+
+      it "counts hits before and after overlap resolution per policy" do
+        PhraseKit.reset_stats
+        expect(PhraseKit.stats).to include(raw_hits_total: 0, resolved_hits_total: 0, overlap_resolution_ratio: nil, policy_hits: {})
+
+        PhraseKit.match_tokens(token_ids: [100, 101, 102])
+        PhraseKit.match_tokens(token_ids: [100, 101, 102], policy: :all)
+        stats = PhraseKit.stats
+        expect(stats).to include(raw_hits_total: 4, resolved_hits_total: 3, overlap_resolution_ratio: 0.75)
+        expect(stats[:policy_hits]).to eq(leftmost_longest: {raw_hits: 2, resolved_hits: 1}, all: {raw_hits: 2, resolved_hits: 2})
+      end
+    end
+  end
+
+  describe ".clear_cache" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json",
+        result_cache_size: 8
+      )
+    end
+
+    it "serves repeated calls from the result cache" do
+      first = PhraseKit.match_tokens(token_ids: [100, 101, 102])
+      second = PhraseKit.match_tokens(token_ids: [100, 101, 102])
+      PhraseKit.match_tokens(token_ids: [200, 101])
+
+      expect(second).to eq(first)
+      stats = PhraseKit.stats
+      expect(stats[:cache_hits]).to eq(1)
+      expect(stats[:cache_misses]).to eq(2)
+      expect(stats[:cache_entries]).to eq(2)
+    end
+
+    it "empties the cache" do
+      PhraseKit.match_tokens(token_ids: [100, 101])
+      PhraseKit.clear_cache
+      expect(PhraseKit.stats[:cache_entries]).to eq(0)
+
+      PhraseKit.match_tokens(token_ids: [100, 101])
+      expect(PhraseKit.stats[:cache_misses]).to eq(2)
+    end
+
+    it "starts empty after a reload" do
+      PhraseKit.match_tokens(token_ids: [100, 101])
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json",
+        result_cache_size: 8
+      )
+      expect(PhraseKit.stats).to include(cache_hits: 0, cache_misses: 0, cache_entries: 0)
+    end
+  end
+
+  describe ".on_stats" do
+    before do
+      PhraseKit.load!(
+        automaton_path: "spec/fixtures/phrases.daac",
+        payloads_path: "spec/fixtures/payloads.bin",
+        manifest_path: "spec/fixtures/manifest.json"
+      )
+    end
+
+    after { PhraseKit.on_stats }
+
+    it "yields a stats snapshot every N match calls" do
+      snapshots = []
+      PhraseKit.on_stats(every: 2) { |stats| snapshots << stats }
+
+      5.times { PhraseKit.match_tokens(token_ids: [100, 101]) }
+      expect(snapshots.size).to eq(2)
+      expect(snapshots.first).to include(:match_calls, :hits_total)
+    end
+
+    it "stops after unregistering" do
+      calls = 0
+      PhraseKit.on_stats(every: 1) { calls += 1 }
+      PhraseKit.match_tokens(token_ids: [100, 101])
+      PhraseKit.on_stats
+      PhraseKit.match_tokens(token_ids: [100, 101])
+      expect(calls).to eq(1)
+    end
+
+    it "rejects a non-positive threshold" do
+      expect { PhraseKit.on_stats(every: 0) {} }.to raise_error(ArgumentError)
     end
   end
 
@@ -317,6 +946,69 @@ RSpec.describe PhraseKit do
         expect(result).to eq([100, 101, 200])
       end
 
+      it "looks tokens up as given when the build is case-sensitive" do
+        require "json"
+        require "tempfile"
+        manifest = JSON.parse(File.read("spec/fixtures/manifest.json")).merge("normalization" => "none")
+        file = Tempfile.new(["manifest", ".json"])
+        file.write(JSON.generate(manifest))
+        file.flush
+        PhraseKit.load!(**test_paths_with_vocab.merge(manifest_path: file.path))
+
+        expect(PhraseKit.encode_tokens(["machine", "MACHINE", "Learning"])).to eq([100, 0, 0])
+      ensure
+        file&.close!
+      end
+
+      it "brings tokens to the build's Unicode normalization form" do
+        require "json"
+        require "tempfile"
+        manifest = JSON.parse(File.read("spec/fixtures/manifest.json")).merge("unicode_normalization" => "nfkc")
+        file = Tempfile.new(["manifest", ".json"])
+        file.write(JSON.generate(manifest))
+        file.flush
+        PhraseKit.load!(**test_paths_with_vocab.merge(manifest_path: file.path))
+
+        # Full-width letters fold to ASCII under NFKC
+        expect(PhraseKit.encode_tokens(["ｍａｃｈｉｎｅ", "learning"])).to eq([100, 101])
+      ensure
+        file&.close!
+      end
+
+      describe "unk_policy" do
+        # "zzz" is not in the vocabulary; [machine, learning, algorithms] is phrase 300
+        let(:tokens) { ["machine", "zzz", "algorithms"] }
+
+        it "maps unknown tokens to <UNK> by default" do
+          expect(PhraseKit.match_text_tokens(tokens: tokens)).to be_empty
+          expect(PhraseKit.match_text_tokens(tokens: tokens, unk_policy: :map)).to be_empty
+        end
+
+        it "never matches across an unknown token with :break" do
+          expect(PhraseKit.match_text_tokens(tokens: tokens, unk_policy: :break)).to be_empty
+
+          matches = PhraseKit.match_text_tokens(tokens: ["machine", "learning", "zzz"], unk_policy: :break)
+          expect(matches.map { |m| m[:phrase_id] }).to eq([100])
+        end
+
+        it "lets an unknown token stand for any vocabulary token with :wildcard" do
+          matches = PhraseKit.match_text_tokens(tokens: tokens, unk_policy: :wildcard)
+          expect(matches.map { |m| [m[:phrase_id], m[:start], m[:end]] }).to eq([[300, 0, 3]])
+        end
+
+        it "rejects unknown policies" do
+          expect { PhraseKit.match_text_tokens(tokens: tokens, unk_policy: :skip) }.to raise_error(ArgumentError, /Invalid unk_policy/)
+        end
+      end
+
+      it "applies case-sensitive entries before lowercasing" do
+        PhraseKit.vocabulary[:case_sensitive_tokens] = {"COVID" => 900}
+        PhraseKit.vocabulary[:tokens]["testing"] = 901
+
+        expect(PhraseKit.encode_tokens(["COVID", "Testing"])).to eq([900, 901])
+        expect(PhraseKit.encode_tokens(["covid", "testing"])).to eq([0, 901])
+      end
+
       it "raises error when vocabulary not loaded" do
         PhraseKit.instance_variable_set(:@vocabulary, nil)
         expect {
@@ -358,6 +1050,32 @@ RSpec.describe PhraseKit do
         expect(matches).to be_empty
       end
 
+      it "matches through case-sensitive entries before lowercasing" do
+        # "ML" is case-sensitive for machine (100); "Deep" shadows deep (200)
+        PhraseKit.vocabulary[:case_sensitive_tokens] = {"ML" => 100, "Deep" => 100}
+
+        expect(PhraseKit.match_text_tokens(tokens: ["ML", "learning"]).map { |m| m[:phrase_id] }).to eq([100])
+        expect(PhraseKit.match_text_tokens(tokens: ["ml", "learning"])).to be_empty
+        expect(PhraseKit.match_text_tokens(tokens: ["Deep", "learning"]).map { |m| m[:phrase_id] }).to eq([100])
+        expect(PhraseKit.match_text_tokens(tokens: ["DEEP", "learning"]).map { |m| m[:phrase_id] }).to eq([200])
+      end
+
+      it "layers a per-call vocab override over the loaded vocabulary" do
+        override = {"neural" => 200, "Machine" => 200}
+
+        neural = PhraseKit.match_text_tokens(tokens: ["Neural", "learning"], vocab_override: override)
+        expect(neural.first[:phrase_id]).to eq(200)
+
+        shadowed = PhraseKit.match_text_tokens(tokens: ["Machine", "learning"], vocab_override: override)
+        expect(shadowed.first[:phrase_id]).to eq(200)
+
+        base = PhraseKit.match_text_tokens(tokens: ["machine", "learning"], vocab_override: override)
+        expect(base.first[:phrase_id]).to eq(100)
+
+        expect(PhraseKit.encode_tokens(["neural", "unknown"], vocab_override: override)).to eq([200, 0])
+        expect(PhraseKit.encode_tokens(["neural"])).to eq([0])
+      end
+
       it "raises error when vocabulary not loaded" do
         PhraseKit.instance_variable_set(:@vocabulary, nil)
         expect {
@@ -366,6 +1084,33 @@ RSpec.describe PhraseKit do
       end
     end
 
+    describe ".match_strings" do
+      before do
+        PhraseKit.load!(**test_paths_with_vocab)
+      end
+
+      it "encodes string tokens natively and matches them" do
+        matches = PhraseKit.match_strings(tokens: ["Machine", "LEARNING", "algorithms"])
+        expect(matches.first).to include(phrase_id: 300, start: 0, end: 3)
+        expect(matches).to eq(PhraseKit.match_tokens(token_ids: PhraseKit.encode_tokens(["machine", "learning", "algorithms"])))
+      end
+
+      it "maps unknown tokens to <UNK>" do
+        expect(PhraseKit.match_strings(tokens: ["unknown", "tokens"])).to be_empty
+      end
+
+      it "raises error when loaded without vocab_path" do
+        PhraseKit.load!(
+          automaton_path: "spec/fixtures/phrases.daac",
+          payloads_path: "spec/fixtures/payloads.bin",
+          manifest_path: "spec/fixtures/manifest.json"
+        )
+        expect {
+          PhraseKit.match_strings(tokens: ["machine"])
+        }.to raise_error(PhraseKit::Error, /Vocabulary not loaded/)
+      end
+    end
+
     describe "backwards compatibility" do
       it "load! works without vocab_path" do
         PhraseKit.load!(
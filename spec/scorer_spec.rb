@@ -227,6 +227,53 @@ RSpec.describe PhraseKit::Scorer do
         output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
         expect(output).not_to be_empty
       end
+
+      [:dice, :tscore].each do |method|
+        it "scores with #{method} method" do
+          PhraseKit::Scorer.score(
+            domain_path: temp_domain.path,
+            background_path: temp_background.path,
+            output_path: temp_output.path,
+            method: method,
+            min_salience: -100.0,
+            min_domain_count: 1
+          )
+
+          output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
+          expect(output).not_to be_empty
+        end
+      end
+
+      it "passes ratio smoothing and unseen_pmi through" do
+        PhraseKit::Scorer.score(
+          domain_path: temp_domain.path,
+          background_path: temp_background.path,
+          output_path: temp_output.path,
+          method: :pmi,
+          min_salience: -100.0,
+          min_domain_count: 1,
+          unseen_pmi: 42.0
+        )
+
+        output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
+        unseen = output.select { |p| p["background_count"] == 0 }
+        expect(unseen.map { |p| p["salience"] }.uniq).to eq([42.0]) unless unseen.empty?
+      end
+
+      it "scores with llr method" do
+        PhraseKit::Scorer.score(
+          domain_path: temp_domain.path,
+          background_path: temp_background.path,
+          output_path: temp_output.path,
+          method: :llr,
+          min_salience: 0.0,
+          min_domain_count: 1
+        )
+
+        output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
+        expect(output).not_to be_empty
+        expect(output.map { |p| p["salience"] }).to eq(output.map { |p| p["salience"] }.sort.reverse)
+      end
     end
 
     context "with empty domain corpus" do
@@ -19,11 +19,12 @@ RSpec.describe PhraseKit::Tagger do
       FileUtils.rm_rf(artifacts_dir)
     end
 
-    def create_test_artifacts(dir)
+    def create_test_artifacts(dir, extra_phrases: [])
       phrases_file = File.join(dir, "phrases.jsonl")
       File.open(phrases_file, "w") do |f|
         f.puts('{"tokens":["test","phrase"],"phrase_id":100,"salience":2.5,"domain_count":10}')
         f.puts('{"tokens":["another","test"],"phrase_id":101,"salience":3.0,"domain_count":15}')
+        extra_phrases.each { |phrase| f.puts(JSON.generate(phrase)) }
       end
 
       config_file = File.join(dir, "build_config.json")
@@ -56,6 +57,30 @@ RSpec.describe PhraseKit::Tagger do
         }.not_to raise_error
       end
 
+      context "when vocab.json comes from another build" do
+        before do
+          vocab_path = File.join(artifacts_dir, "vocab.json")
+          vocab = JSON.parse(File.read(vocab_path))
+          File.write(vocab_path, JSON.generate(vocab.merge("version" => "stale-v0")))
+        end
+
+        it "refuses to tag" do
+          expect {
+            PhraseKit::Tagger.tag(input_path: temp_corpus.path, output_path: temp_output.path, artifacts_dir: artifacts_dir)
+          }.to raise_error(PhraseKit::Tagger::Error, /does not match manifest version/)
+        end
+
+        it "tags with a warning when allowed" do
+          stats = PhraseKit::Tagger.tag(
+            input_path: temp_corpus.path,
+            output_path: temp_output.path,
+            artifacts_dir: artifacts_dir,
+            allow_version_mismatch: true
+          )
+          expect(stats[:documents]).to eq(3)
+        end
+      end
+
       it "returns statistics" do
         stats = PhraseKit::Tagger.tag(
           input_path: temp_corpus.path,
@@ -128,6 +153,63 @@ RSpec.describe PhraseKit::Tagger do
         expect(matched_tokens).to eq(["test", "phrase"])
       end
 
+      it "adds span text with emit_text" do
+        PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir,
+          emit_text: true
+        )
+
+        output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
+
+        span = output.find { |d| d["doc_id"] == "doc1" }["spans"].first
+        expect(span["text"]).to eq("test phrase")
+        expect(span).to include("salience", "count")
+      end
+
+      it "adds BIO tags with output_format: :bio" do
+        PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir,
+          output_format: :bio
+        )
+
+        output = File.readlines(temp_output.path).map { |line| JSON.parse(line) }
+
+        doc1 = output.find { |d| d["doc_id"] == "doc1" }
+        phrase_id = doc1["spans"].first["phrase_id"]
+        expect(doc1["tags"]).to eq(["O", "O", "O", "B-PHRASE", "I-PHRASE"])
+        expect(doc1["tag_ids"]).to eq([nil, nil, nil, phrase_id, nil])
+
+        doc3 = output.find { |d| d["doc_id"] == "doc3" }
+        expect(doc3["tags"]).to all(eq("O"))
+      end
+
+      it "fails on out-of-vocabulary tokens with unk_mode: :error" do
+        expect {
+          PhraseKit::Tagger.tag(
+            input_path: temp_corpus.path,
+            output_path: temp_output.path,
+            artifacts_dir: artifacts_dir,
+            unk_mode: :error
+          )
+        }.to raise_error(PhraseKit::Tagger::Error, /not in the vocabulary/)
+      end
+
+      it "rejects BIO output with the all policy" do
+        expect {
+          PhraseKit::Tagger.tag(
+            input_path: temp_corpus.path,
+            output_path: temp_output.path,
+            artifacts_dir: artifacts_dir,
+            policy: :all,
+            output_format: :bio
+          )
+        }.to raise_error(PhraseKit::Tagger::Error)
+      end
+
       it "handles documents with no matches" do
         PhraseKit::Tagger.tag(
           input_path: temp_corpus.path,
@@ -177,6 +259,138 @@ RSpec.describe PhraseKit::Tagger do
       end
     end
 
+    context "with phrase categories" do
+      let(:category_artifacts_dir) { Dir.mktmpdir }
+
+      before do
+        create_test_artifacts(category_artifacts_dir, extra_phrases: [
+          {tokens: ["no", "matches"], phrase_id: 102, salience: 1.0, count: 5, category: "negation"}
+        ])
+        temp_corpus.puts('{"doc_id":"doc1","tokens":["no","matches","for","a","test","phrase"]}')
+        temp_corpus.flush
+      end
+
+      after { FileUtils.rm_rf(category_artifacts_dir) }
+
+      it "labels categorized phrases with their category" do
+        PhraseKit::Tagger.tag(input_path: temp_corpus.path, output_path: temp_output.path, artifacts_dir: category_artifacts_dir)
+
+        output = JSON.parse(File.read(temp_output.path))
+        labels = output["spans"].to_h { |span| [span["phrase_id"], span["label"]] }
+        expect(labels).to eq(102 => "negation", 100 => "PHRASE")
+      end
+    end
+
+    context "with inclusive_end" do
+      let(:span_artifacts_dir) { Dir.mktmpdir }
+
+      before do
+        create_test_artifacts(span_artifacts_dir, extra_phrases: [
+          {tokens: ["cancer"], phrase_id: 102, salience: 2.0, domain_count: 12}
+        ])
+        temp_corpus.puts('{"doc_id":"doc1","tokens":["cancer","is","a","test","phrase"]}')
+        temp_corpus.flush
+      end
+
+      after do
+        FileUtils.rm_rf(span_artifacts_dir)
+      end
+
+      def spans_with(inclusive_end:)
+        PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: span_artifacts_dir,
+          inclusive_end: inclusive_end
+        )
+        JSON.parse(File.read(temp_output.path))["spans"].map { |s| [s["phrase_id"], s["start"], s["end"]] }
+      end
+
+      it "reports exclusive ends by default" do
+        expect(spans_with(inclusive_end: false)).to eq([[102, 0, 1], [100, 3, 5]])
+      end
+
+      it "reports the last token index when inclusive_end is set" do
+        expect(spans_with(inclusive_end: true)).to eq([[102, 0, 0], [100, 3, 4]])
+      end
+    end
+
+    context "with multiple threads" do
+      before do
+        200.times do |i|
+          tokens = i.even? ? ["this", "is", "a", "test", "phrase"] : ["another", "test", "example"]
+          temp_corpus.puts(JSON.generate({doc_id: "doc#{i}", tokens: tokens}))
+        end
+        temp_corpus.flush
+      end
+
+      it "matches single-threaded output in input order" do
+        sequential_output = Tempfile.new(["sequential", ".jsonl"])
+
+        PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: sequential_output.path,
+          artifacts_dir: artifacts_dir
+        )
+        stats = PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir,
+          threads: 4,
+          read_ahead: 8
+        )
+
+        expect(stats[:documents]).to eq(200)
+        expect(File.read(temp_output.path)).to eq(File.read(sequential_output.path))
+      ensure
+        sequential_output.close!
+      end
+    end
+
+    context "with OOV reporting" do
+      before do
+        temp_corpus.puts('{"doc_id":"doc1","tokens":["test","phrase","unseen"]}')
+        temp_corpus.puts('{"doc_id":"doc2","tokens":["unseen","words"]}')
+        temp_corpus.flush
+      end
+
+      it "reports OOV statistics" do
+        stats = PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir,
+          oov_report: true
+        )
+
+        expect(stats[:oov_tokens]).to eq(3)
+        expect(stats[:oov_rate]).to be_within(0.001).of(0.6)
+      end
+
+      it "writes the OOV sidecar report" do
+        report_path = File.join(artifacts_dir, "oov.json")
+        PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir,
+          oov_output_path: report_path
+        )
+
+        report = JSON.parse(File.read(report_path))
+        expect(report["unk_tokens"]).to eq(3)
+        expect(report["top_oov"].first).to eq({"token" => "unseen", "count" => 2})
+      end
+
+      it "omits OOV statistics by default" do
+        stats = PhraseKit::Tagger.tag(
+          input_path: temp_corpus.path,
+          output_path: temp_output.path,
+          artifacts_dir: artifacts_dir
+        )
+
+        expect(stats).not_to have_key(:oov_tokens)
+      end
+    end
+
     context "with different matching policies" do
       before do
         temp_corpus.puts('{"doc_id":"doc1","tokens":["test","phrase","test"]}')